@@ -1,5 +1,95 @@
+//! Linker arguments, the memory layout, and the build hash the host checks its
+//! ELF against
+//!
+//! `ld/memory.x` links the image below the flash records (`src/storage.rs`).
+//! Diagnostics and bench builds are too large for that, keep no records and
+//! link against `ld/memory-full.x` instead. Neither sits in the crate root,
+//! where the linker would find it before the copy in `OUT_DIR`.
+//!
+//! The hash covers everything that shapes the image: the device and protocol
+//! sources, the manifests and lock file, the enabled features, the profile and
+//! the `DEFMT_LOG` filter (which decides what goes into the defmt table). Two
+//! builds from the same inputs get the same hash; any edit gets a new one.
+
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
 fn main() {
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let records = env::var_os("CARGO_FEATURE_DIAGNOSTICS").is_none()
+        && env::var_os("CARGO_FEATURE_BENCH").is_none();
+    let memory = if records { "memory.x" } else { "memory-full.x" };
+    fs::copy(Path::new("ld").join(memory), out_dir.join("memory.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out_dir.display());
+
+    let inputs = [
+        "src",
+        "build.rs",
+        "Cargo.toml",
+        "Cargo.lock",
+        "ld",
+        "../protocol/src",
+        "../protocol/Cargo.toml",
+    ];
+    let mut files = Vec::new();
+    for input in inputs {
+        println!("cargo:rerun-if-changed={}", input);
+        collect(Path::new(input), &mut files);
+    }
+    println!("cargo:rerun-if-env-changed=DEFMT_LOG");
+    files.sort();
+
+    let mut hash = Fnv::new();
+    for file in &files {
+        hash.write(file.to_string_lossy().as_bytes());
+        hash.write(&fs::read(file).unwrap_or_default());
+    }
+    let mut vars: Vec<(String, String)> = env::vars()
+        .filter(|(k, _)| {
+            k.starts_with("CARGO_FEATURE_") || k == "PROFILE" || k == "DEFMT_LOG" || k == "TARGET"
+        })
+        .collect();
+    vars.sort();
+    for (k, v) in vars {
+        hash.write(k.as_bytes());
+        hash.write(v.as_bytes());
+    }
+
+    let out = out_dir.join("build_hash.rs");
+    fs::write(
+        out,
+        format!("pub const BUILD_HASH: [u8; 16] = *b\"{:016x}\";\n", hash.0),
+    )
+    .unwrap();
+}
+
+fn collect(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_dir() {
+        for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+            collect(&entry.path(), files);
+        }
+    } else if path.is_file() {
+        files.push(path.to_path_buf());
+    }
+}
+
+/// 64-bit FNV-1a; stable across toolchains, unlike `std`'s hasher
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+        // Length-delimit, so moving bytes between two inputs changes the hash
+        self.0 = (self.0 ^ bytes.len() as u64).wrapping_mul(0x0100_0000_01b3);
+    }
 }
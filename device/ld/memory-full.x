@@ -0,0 +1,7 @@
+/* STM32G431CB: 128K flash, 32K RAM. Diagnostics and bench builds keep no
+   records (storage.rs), so the image may use all of flash. */
+MEMORY
+{
+  FLASH : ORIGIN = 0x08000000, LENGTH = 128K
+  RAM   : ORIGIN = 0x20000000, LENGTH = 32K
+}
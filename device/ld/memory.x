@@ -0,0 +1,7 @@
+/* STM32G431CB: 128K flash, 32K RAM. The last 8 flash pages (16K) hold the
+   records (storage.rs, RESERVED_PAGES), so the image is linked below them. */
+MEMORY
+{
+  FLASH : ORIGIN = 0x08000000, LENGTH = 112K
+  RAM   : ORIGIN = 0x20000000, LENGTH = 32K
+}
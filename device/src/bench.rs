@@ -0,0 +1,68 @@
+//! RTT throughput benchmark (`BenchEndpoint`)
+//!
+//! On request the device broadcasts `BenchFrame`s on `event/bench` as fast as
+//! it can queue them, numbered from 0, for the requested time, then answers
+//! with how many it sent. The frames go without acknowledgement: when the
+//! host does not drain the up channel in time its `NoBlockSkip` mode drops
+//! whole writes, which the host sees as gaps in the sequence. A full outgoing
+//! queue is back-pressure on the device side instead; those attempts are
+//! counted separately, and the frame is retried after the TX worker has run.
+//!
+//! The motor stays out of it: the benchmark needs the device armed, as a
+//! deliberate step, and the motor stopped, since the flood crowds out
+//! telemetry and keepalives while it lasts.
+
+use embassy_futures::yield_now;
+use embassy_time::{Duration, Instant};
+use oxifoc_protocol::constants::MAX_BENCH_DURATION_MS;
+use oxifoc_protocol::{
+    BENCH_MAX_PAYLOAD, BenchFrame, BenchReport, BenchRequest, BenchTopic, MotorState,
+};
+
+use crate::log;
+use crate::motor;
+use crate::net::STACK;
+
+/// Flood `event/bench` as `req` asks, once armed with the motor stopped
+pub async fn run(req: BenchRequest) -> BenchReport {
+    if !motor::get_motor_armed() {
+        log::warn!("Throughput benchmark rejected: not armed");
+        return BenchReport::Disarmed;
+    }
+    if motor::get_motor_state() != MotorState::Stopped {
+        log::warn!("Throughput benchmark rejected: motor not stopped");
+        return BenchReport::MotorRunning;
+    }
+    let duration_ms = req.duration_ms.min(MAX_BENCH_DURATION_MS);
+    let len = (req.payload as usize).min(BENCH_MAX_PAYLOAD);
+    log::info!(
+        "Throughput benchmark: {}-byte frames for {}ms",
+        len,
+        duration_ms
+    );
+
+    let start = Instant::now();
+    let end = start + Duration::from_millis(duration_ms as u64);
+    let (mut frames, mut queue_full) = (0u32, 0u32);
+    while Instant::now() < end {
+        let frame = BenchFrame::new(frames, len);
+        match STACK.topics().broadcast::<BenchTopic>(&frame, None) {
+            Ok(()) => frames += 1,
+            Err(_) => queue_full += 1,
+        }
+        // Let the TX worker move the queue into the RTT buffer
+        yield_now().await;
+    }
+    let elapsed_ms = start.elapsed().as_millis() as u32;
+    log::info!(
+        "Throughput benchmark done: {} frames in {}ms, queue full {} times",
+        frames,
+        elapsed_ms,
+        queue_full
+    );
+    BenchReport::Done {
+        frames,
+        elapsed_ms,
+        queue_full,
+    }
+}
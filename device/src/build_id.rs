@@ -0,0 +1,19 @@
+//! Build identity, reported in `DeviceInfo`
+//!
+//! `build.rs` hashes the inputs of this image into `BUILD_HASH`. The same
+//! bytes sit in the ELF under the symbol `OXIFOC_BUILD_HASH`, so the host can
+//! tell whether the ELF it decodes defmt with is the firmware that is flashed.
+
+include!(concat!(env!("OUT_DIR"), "/build_hash.rs"));
+
+/// Looked up by name in the ELF; reading it in `hash` keeps the linker from dropping it
+#[used]
+#[unsafe(no_mangle)]
+static OXIFOC_BUILD_HASH: [u8; 16] = BUILD_HASH;
+
+/// Build hash as 16 hex digits
+pub fn hash() -> heapless::String<16> {
+    let mut s = heapless::String::new();
+    let _ = s.push_str(core::str::from_utf8(&OXIFOC_BUILD_HASH).unwrap_or(""));
+    s
+}
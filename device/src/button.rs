@@ -0,0 +1,132 @@
+//! User button gesture timing and event coalescing
+//!
+//! `button_handler` in `main` classifies presses into `ButtonEvent`s. Each
+//! event costs a request round trip to the host, so a bouncy contact or a
+//! burst of presses is coalesced here: an event identical to the last one
+//! sent within `min_event_interval_ms` is dropped. A different event is always
+//! sent, so a single click right after a double click still reaches the host.
+//!
+//! The wiring is part of the config: `polarity` says which edge is a press,
+//! and `pull` selects the input's internal resistor. The default matches the
+//! B-G431B-ESC1 (active-low, external pull-up); other boards set
+//! `OXIFOC_BUTTON_POLARITY=low|high` and `OXIFOC_BUTTON_PULL=none|up|down`
+//! at build time.
+
+use oxifoc_protocol::ButtonEvent;
+
+/// Logic level of the input while the button is pressed
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ButtonPolarity {
+    /// Pressed pulls the input low: press = falling edge
+    ActiveLow,
+    /// Pressed drives the input high: press = rising edge
+    ActiveHigh,
+}
+
+/// Internal resistor on the button input
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ButtonPull {
+    /// Board provides the pull resistor
+    None,
+    Up,
+    Down,
+}
+
+/// Polarity from `OXIFOC_BUTTON_POLARITY` at build time, active-low if unset
+const POLARITY: ButtonPolarity = match option_env!("OXIFOC_BUTTON_POLARITY") {
+    Some(s) => match s.as_bytes() {
+        b"low" => ButtonPolarity::ActiveLow,
+        b"high" => ButtonPolarity::ActiveHigh,
+        _ => panic!("OXIFOC_BUTTON_POLARITY must be low or high"),
+    },
+    None => ButtonPolarity::ActiveLow,
+};
+
+/// Pull from `OXIFOC_BUTTON_PULL` at build time; none if unset, as the ESC1
+/// has an external pull-up
+const PULL: ButtonPull = match option_env!("OXIFOC_BUTTON_PULL") {
+    Some(s) => match s.as_bytes() {
+        b"none" => ButtonPull::None,
+        b"up" => ButtonPull::Up,
+        b"down" => ButtonPull::Down,
+        _ => panic!("OXIFOC_BUTTON_PULL must be none, up or down"),
+    },
+    None => ButtonPull::None,
+};
+
+/// Button tunables
+#[derive(Clone, Copy)]
+pub struct ButtonConfig {
+    pub polarity: ButtonPolarity,
+    pub pull: ButtonPull,
+    /// Window after a release in which a second press makes a double click
+    pub double_click_ms: u16,
+    /// Press duration that counts as a hold
+    pub hold_ms: u16,
+    /// Minimum time between two identical events sent to the host (0 = off)
+    pub min_event_interval_ms: u16,
+}
+
+impl Default for ButtonConfig {
+    fn default() -> Self {
+        Self {
+            polarity: POLARITY,
+            pull: PULL,
+            double_click_ms: 250,
+            hold_ms: 1000,
+            min_event_interval_ms: 300,
+        }
+    }
+}
+
+/// Drops repeats of the last sent event within the configured interval
+#[derive(Default)]
+pub struct Coalescer {
+    last_sent: Option<(ButtonEvent, u64)>,
+}
+
+impl Coalescer {
+    /// Whether `event`, classified at `now_ms`, should be sent
+    pub fn admit(&mut self, event: &ButtonEvent, now_ms: u64, min_interval_ms: u16) -> bool {
+        if let Some((last, at)) = &self.last_sent
+            && last == event
+            && now_ms - at < min_interval_ms as u64
+        {
+            return false;
+        }
+        self.last_sent = Some((event.clone(), now_ms));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeats_within_interval_are_dropped() {
+        let mut c = Coalescer::default();
+        assert!(c.admit(&ButtonEvent::SingleClick, 0, 300));
+        assert!(!c.admit(&ButtonEvent::SingleClick, 100, 300));
+        assert!(!c.admit(&ButtonEvent::SingleClick, 299, 300));
+        // Measured from the last event sent, not the last one dropped
+        assert!(c.admit(&ButtonEvent::SingleClick, 300, 300));
+    }
+
+    #[test]
+    fn test_distinct_events_are_never_dropped() {
+        let mut c = Coalescer::default();
+        assert!(c.admit(&ButtonEvent::SingleClick, 0, 300));
+        assert!(c.admit(&ButtonEvent::DoubleClick, 10, 300));
+        assert!(c.admit(&ButtonEvent::SingleClick, 20, 300));
+        assert!(c.admit(&ButtonEvent::Hold, 30, 300));
+        assert!(!c.admit(&ButtonEvent::Hold, 40, 300));
+    }
+
+    #[test]
+    fn test_zero_interval_sends_everything() {
+        let mut c = Coalescer::default();
+        assert!(c.admit(&ButtonEvent::Hold, 5, 0));
+        assert!(c.admit(&ButtonEvent::Hold, 5, 0));
+    }
+}
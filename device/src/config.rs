@@ -0,0 +1,55 @@
+//! Aggregated device configuration snapshots for `ConfigEndpoint`,
+//! `CommutationTableEndpoint` and `CommutationPeriodEndpoint`
+
+use oxifoc_protocol::{Addressing, CommutationPeriod, CommutationTable, DeviceConfig};
+
+use crate::motor::{self, blanking, current, low_speed, profile, self_test, six_step};
+use crate::net;
+
+/// Collect the device's entire configuration into one response
+pub fn capture() -> DeviceConfig {
+    let p = profile::active();
+    DeviceConfig {
+        profile: p.name,
+        pole_pairs: p.pole_pairs,
+        kv: p.kv,
+        pwm_freq: p.pwm_freq,
+        dead_time_ns: p.dead_time_ns,
+        max_duty: p.max_duty,
+        mode: motor::get_commutation_mode(),
+        scan_scheme: motor::get_scan_scheme(),
+        commutation_period_ms: motor::get_commutation_period_ms(),
+        blanking_us: blanking::blanking_us(),
+        self_test_max_duty: self_test::MAX_TEST_DUTY,
+        min_off_ms: motor::get_min_off_ms(),
+        min_rpm: low_speed::min_rpm(),
+        min_rpm_timeout_ms: low_speed::timeout_ms(),
+        max_phase_voltage_mv: motor::get_max_phase_voltage_mv(),
+        regen_current_limit_ma: motor::get_regen_current_limit_ma(),
+        phase_map: motor::get_phase_map(),
+        addressing: Addressing {
+            network_id: net::NETWORK_ID,
+            node_id: net::local_node_id(),
+            peer_node_id: net::peer_node_id(),
+        },
+        current_calibration: current::active_calibration(),
+    }
+}
+
+/// The step table the controller is commutating with, and the phase map it goes through
+pub fn commutation_table() -> CommutationTable {
+    let scheme = motor::get_scan_scheme();
+    CommutationTable {
+        scheme,
+        steps: core::array::from_fn(|n| six_step::table(scheme)[n].0),
+        phase_map: motor::get_phase_map(),
+    }
+}
+
+/// The open-loop run period and the dwell the running motor is at
+pub fn commutation_period() -> CommutationPeriod {
+    CommutationPeriod {
+        period_us: motor::get_run_period_us(),
+        dwell_us: motor::get_step_dwell_us(),
+    }
+}
@@ -0,0 +1,127 @@
+//! Cooling fan driven from the board temperature
+//!
+//! A fan on PB4 (TIM3_CH1, AF2; the UART RX pad, unused by this firmware) is
+//! switched with PWM, off below `min_temp_c` and ramping from `min_duty` at it
+//! to `max_duty` at `max_temp_c`. Many fans do not start at low duty, hence
+//! the jump to `min_duty` rather than a ramp from 0. The curve follows a rise
+//! at once, but a fall only once it is more than `hysteresis_c` below where
+//! the duty was set, so a temperature wobbling around a point of the curve
+//! does not make the fan hunt, and it turns off only at `min_temp_c -
+//! hysteresis_c`.
+//!
+//! The temperature is the one the control task records for telemetry; while
+//! the ADC health check does not trust it, the fan runs at `max_duty`. TIM3 is
+//! free: the embassy time driver takes TIM2.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// Fan tunables
+#[derive(Clone, Copy)]
+pub struct FanConfig {
+    /// Drive a fan at all; false leaves PB4 unconfigured
+    pub enabled: bool,
+    /// Fan off below this, in °C
+    pub min_temp_c: i16,
+    /// Full `max_duty` from this up, in °C
+    pub max_temp_c: i16,
+    /// Duty at `min_temp_c`, in percent
+    pub min_duty: u8,
+    /// Duty at `max_temp_c` and above, in percent
+    pub max_duty: u8,
+    /// Fall needed before the duty follows the temperature down, in °C
+    pub hysteresis_c: u8,
+    /// PWM frequency; 25 kHz is the 4-wire fan standard and inaudible
+    pub pwm_freq_hz: u32,
+    /// Time between duty updates
+    pub period_ms: u16,
+}
+
+impl Default for FanConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_temp_c: 40,
+            max_temp_c: 70,
+            min_duty: 30,
+            max_duty: 100,
+            hysteresis_c: 3,
+            pwm_freq_hz: 25_000,
+            period_ms: 250,
+        }
+    }
+}
+
+/// Fan duty from the last update, `NO_FAN` until the fan task runs
+static DUTY: AtomicU8 = AtomicU8::new(NO_FAN);
+const NO_FAN: u8 = u8::MAX;
+
+/// Duty the fan runs at in percent, `None` without a fan
+pub fn duty() -> Option<u8> {
+    match DUTY.load(Ordering::Relaxed) {
+        NO_FAN => None,
+        d => Some(d),
+    }
+}
+
+/// Record the duty the fan task applied
+pub fn set_duty(duty: u8) {
+    DUTY.store(duty.min(100), Ordering::Relaxed);
+}
+
+/// Temperature-to-duty curve with hysteresis on the way down
+#[derive(Default)]
+pub struct FanCurve {
+    /// Temperature the duty was last set from, in 0.1 °C
+    held_dc: Option<i32>,
+}
+
+impl FanCurve {
+    /// Duty for a reading of `temp_dc` (0.1 °C), in percent
+    pub fn update(&mut self, temp_dc: i16, cfg: &FanConfig) -> u8 {
+        let temp = temp_dc as i32;
+        let hyst = cfg.hysteresis_c as i32 * 10;
+        let held = match self.held_dc {
+            Some(h) if temp <= h && temp >= h - hyst => h,
+            Some(h) if temp < h => temp + hyst,
+            _ => temp,
+        };
+        self.held_dc = Some(held);
+        curve(held, cfg)
+    }
+}
+
+fn curve(temp_dc: i32, cfg: &FanConfig) -> u8 {
+    let (lo, hi) = (cfg.min_temp_c as i32 * 10, cfg.max_temp_c as i32 * 10);
+    let (min, max) = (cfg.min_duty.min(100) as i32, cfg.max_duty.min(100) as i32);
+    if temp_dc < lo {
+        0
+    } else if temp_dc >= hi {
+        max as u8
+    } else {
+        (min + (max - min) * (temp_dc - lo) / (hi - lo)) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ramp_follows_rises_and_lags_falls_by_the_hysteresis() {
+        let cfg = FanConfig::default();
+        let mut fan = FanCurve::default();
+        assert_eq!(fan.update(250, &cfg), 0);
+        assert_eq!(fan.update(400, &cfg), 30);
+        assert_eq!(fan.update(550, &cfg), 65);
+        assert_eq!(fan.update(800, &cfg), 100);
+
+        // Back down: held until 3 °C under 80, then 3 °C above the reading
+        assert_eq!(fan.update(780, &cfg), 100);
+        assert_eq!(fan.update(580, &cfg), 79);
+        assert_eq!(fan.update(600, &cfg), 79);
+        // Off only below 37 °C
+        assert_eq!(fan.update(380, &cfg), 32);
+        assert_eq!(fan.update(369, &cfg), 0);
+        assert_eq!(fan.update(395, &cfg), 0);
+    }
+}
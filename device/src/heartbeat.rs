@@ -0,0 +1,76 @@
+//! Liveness blink driven by the motor control loop
+//!
+//! The status LED's pattern runs in its own task, so it keeps blinking while
+//! the control loop is stuck. The heartbeat output is only updated from the
+//! control loop's iterations: a short pulse every `period_ms`, and a stuck
+//! loop freezes it on or off. The loop runs once per commutation (at least
+//! every 500 ms while stopped at the boot period), and a level only changes
+//! at an iteration, so on a slow loop the pulse stretches to one iteration.
+
+/// Heartbeat tunables
+#[derive(Clone, Copy)]
+pub struct HeartbeatConfig {
+    /// Time from one pulse to the next
+    pub period_ms: u16,
+    /// Pulse length (longer when the loop iterates less often)
+    pub on_ms: u16,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            period_ms: 1000,
+            on_ms: 50,
+        }
+    }
+}
+
+/// Heartbeat output level, advanced once per control loop iteration
+#[derive(Default)]
+pub struct Heartbeat {
+    last_pulse_ms: Option<u64>,
+}
+
+impl Heartbeat {
+    /// Output level for an iteration at `now_ms`
+    pub fn tick(&mut self, now_ms: u64, cfg: &HeartbeatConfig) -> bool {
+        match self.last_pulse_ms {
+            Some(t) if now_ms - t < cfg.on_ms as u64 => true,
+            Some(t) if now_ms - t < cfg.period_ms as u64 => false,
+            _ => {
+                self.last_pulse_ms = Some(now_ms);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_pulse_each_period_on_a_fast_loop() {
+        let cfg = HeartbeatConfig::default();
+        let mut hb = Heartbeat::default();
+        // 100 ms iterations: on for one, off for nine
+        for n in 0..25 {
+            assert_eq!(hb.tick(n * 100, &cfg), n % 10 == 0, "iteration {}", n);
+        }
+    }
+
+    #[test]
+    fn test_slow_loop_stretches_the_pulse() {
+        let cfg = HeartbeatConfig::default();
+        let mut hb = Heartbeat::default();
+        // 500 ms per iteration: on, off, on, off...
+        assert!(hb.tick(0, &cfg));
+        assert!(!hb.tick(500, &cfg));
+        assert!(hb.tick(1000, &cfg));
+        assert!(!hb.tick(1500, &cfg));
+        // A stall shows as a missing pulse, then the next iteration restarts it
+        assert!(hb.tick(9000, &cfg));
+        assert!(hb.tick(9040, &cfg));
+        assert!(!hb.tick(9050, &cfg));
+    }
+}
@@ -0,0 +1,66 @@
+//! Failsafe stop driven by any traffic from the host
+//!
+//! For a tethered setup where the host is in charge: every chunk of bytes the
+//! RX worker reads from the RTT down channel counts as a sign of life, whatever
+//! endpoint it is for. Once nothing has arrived for `timeout_ms` while the
+//! motor runs, the control loop requests a safety stop. Off by default, since
+//! an untethered device (button control) may see no host traffic at all.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Link failsafe tunables
+#[derive(Clone, Copy)]
+pub struct LinkFailsafeConfig {
+    pub enabled: bool,
+    /// Host silence that stops a running motor
+    pub timeout_ms: u32,
+}
+
+impl Default for LinkFailsafeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timeout_ms: 1000,
+        }
+    }
+}
+
+/// Time of the last inbound bytes, in ms since boot (wrapping)
+static LAST_RX_MS: AtomicU32 = AtomicU32::new(0);
+
+/// Record inbound traffic; called by the RTT reader for every non-empty read
+pub fn note_rx() {
+    LAST_RX_MS.store(
+        embassy_time::Instant::now().as_millis() as u32,
+        Ordering::Relaxed,
+    );
+}
+
+/// Time since the last inbound bytes at `now_ms`
+pub fn silence_ms(now_ms: u32) -> u32 {
+    now_ms.wrapping_sub(LAST_RX_MS.load(Ordering::Relaxed))
+}
+
+/// Whether `silence_ms` of host silence must stop the motor
+pub fn expired(silence_ms: u32, cfg: &LinkFailsafeConfig) -> bool {
+    cfg.enabled && silence_ms >= cfg.timeout_ms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expires_only_when_enabled_and_past_timeout() {
+        let on = LinkFailsafeConfig {
+            enabled: true,
+            ..LinkFailsafeConfig::default()
+        };
+        assert!(!expired(999, &on));
+        assert!(expired(1000, &on));
+        assert!(!expired(60_000, &LinkFailsafeConfig::default()));
+        // The ms counter wraps after about 49 days
+        LAST_RX_MS.store(u32::MAX - 499, Ordering::Relaxed);
+        assert_eq!(silence_ms(1500), 2000);
+    }
+}
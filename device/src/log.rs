@@ -0,0 +1,78 @@
+//! Runtime log verbosity, set by the host over `LogLevelEndpoint`
+//!
+//! defmt filters at compile time (`DEFMT_LOG`) and sends everything built in.
+//! Its up channel is `NoBlockSkip`, so a burst the host cannot drain in time
+//! is dropped, usually mid-frame. The macros here wrap defmt's and skip a log
+//! call below the runtime level before anything is encoded, which keeps the
+//! channel quiet at the source instead of thinning it out on the host. The
+//! firmware logs through them (`log::info!` and so on) rather than calling
+//! defmt's directly. The level starts at `Trace` on every boot.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use oxifoc_protocol::LogLevel;
+
+/// Lowest level emitted, as `LogLevel as u8`
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Trace as u8);
+
+/// Emit only calls at `level` or above from now on
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Current runtime level
+pub fn level() -> LogLevel {
+    LogLevel::from_u8(LEVEL.load(Ordering::Relaxed))
+}
+
+/// Whether a call at `level` passes the runtime gate
+#[inline]
+pub fn enabled(level: LogLevel) -> bool {
+    level as u8 >= LEVEL.load(Ordering::Relaxed)
+}
+
+// Nothing logs at trace level yet; kept so every level has its macro
+#[allow(unused_macros)]
+macro_rules! trace_ {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled(oxifoc_protocol::LogLevel::Trace) {
+            defmt::trace!($($arg)*)
+        }
+    };
+}
+
+macro_rules! debug_ {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled(oxifoc_protocol::LogLevel::Debug) {
+            defmt::debug!($($arg)*)
+        }
+    };
+}
+
+macro_rules! info_ {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled(oxifoc_protocol::LogLevel::Info) {
+            defmt::info!($($arg)*)
+        }
+    };
+}
+
+macro_rules! warn_ {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled(oxifoc_protocol::LogLevel::Warn) {
+            defmt::warn!($($arg)*)
+        }
+    };
+}
+
+macro_rules! error_ {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled(oxifoc_protocol::LogLevel::Error) {
+            defmt::error!($($arg)*)
+        }
+    };
+}
+
+// Renamed on export: a `warn` macro would clash with the built-in `#[warn]` attribute
+#[allow(unused_imports)]
+pub(crate) use {debug_ as debug, error_ as error, info_ as info, trace_ as trace, warn_ as warn};
@@ -5,44 +5,74 @@ use core::pin::pin;
 use core::sync::atomic::{AtomicBool, Ordering};
 
 use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
 use embassy_stm32::exti::ExtiInput;
-use embassy_stm32::gpio::{Level, Output, Pull, Speed};
-use embassy_time::{Duration, Timer, with_timeout};
-use ergot::{
-    Address,
-    exports::bbq2::traits::coordination::cas::AtomicCoord,
-    toolkits::embedded_io_async_v0_6::{self as kit, tx_worker},
-};
-use mutex::raw_impls::cs::CriticalSectionRawMutex;
+use embassy_stm32::flash::Flash;
+use embassy_stm32::gpio::{Level, Output, OutputType, Pull, Speed};
+use embassy_stm32::time::Hertz;
+use embassy_stm32::timer::low_level::CountingMode;
+use embassy_stm32::timer::simple_pwm::{PwmPin, SimplePwm};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer, with_deadline, with_timeout};
+use ergot::toolkits::embedded_io_async_v0_6::tx_worker;
 use oxifoc_protocol::{
-    ButtonEndpoint, ButtonEvent, DeviceInfo, InfoEndpoint,
-    MotorCommand, MotorEndpoint,
+    ButtonEndpoint, ButtonEvent, CommandLog, CommandLogEndpoint, CommutationMode,
+    CommutationPeriodEndpoint, CommutationTableEndpoint, ConfigEndpoint,
+    CurrentCalibrationEndpoint, DeviceInfo, FullStatusEndpoint, IdentifyEndpoint, InfoEndpoint,
+    KeepAlive, KeepAliveEndpoint, LogLevel, LogLevelEndpoint, MotorCommand, MotorEndpoint,
+    MotorRequest, MotorState, MotorStatus, PROTOCOL_VERSION, ProfileCommand, ProfileEndpoint,
+    ProtocolVersionEndpoint, RejectReason, SelfTestEndpoint, SelfTestRequest, SelfTestResult,
+    TaskStatsEndpoint, TelemetryEncoder, TelemetryEndpoint,
 };
 use rtt_target::{ChannelMode::*, rtt_init};
 use static_cell::StaticCell;
 
+#[cfg(feature = "bench")]
+mod bench;
+
+mod build_id;
+
+mod button;
+use button::{ButtonConfig, ButtonPolarity, ButtonPull, Coalescer};
+
+mod config;
+
+mod fan;
+use fan::{FanConfig, FanCurve};
+
+mod heartbeat;
+use heartbeat::{Heartbeat, HeartbeatConfig};
+
+mod link_failsafe;
+
+mod log;
+use link_failsafe::LinkFailsafeConfig;
+
 mod rtt_io;
 use rtt_io::RttWriter;
 
 mod motor;
-use motor::MotorController;
-
-// Use panic-probe for panics
-use panic_probe as _;
+use motor::adc_health::{AdcHealth, Signals};
+use motor::auto_disarm::{AutoDisarmConfig, IdleTimer};
+use motor::current::{AdcConfig, CurrentSense, SensePins};
+use motor::ext_trigger::{EdgeFilter, ExtTriggerConfig, TriggerEdge};
+use motor::hall::HallSensors;
+use motor::pwm::PwmPins;
+use motor::six_step::CommutationStep;
+use motor::startup::StartupOutcome;
+use motor::sync_guard::{SyncGuard, Timing};
+use motor::{ControlChannel, ControlMsg, ControlReceiver, ControlSender, MotorController};
 
-const OUT_QUEUE_SIZE: usize = 2048;
-const MAX_PACKET_SIZE: usize = 512;
+mod net;
+use net::{MAX_PACKET_SIZE, OUTQ, RxWorker, STACK};
 
-// Type aliases for our application
-type Queue = kit::Queue<OUT_QUEUE_SIZE, AtomicCoord>;
-type Stack = kit::Stack<&'static Queue, CriticalSectionRawMutex>;
-type RxWorker = kit::RxWorker<&'static Queue, CriticalSectionRawMutex, rtt_io::RttReader>;
+mod storage;
 
-/// Statically store our outgoing packet buffer
-static OUTQ: Queue = kit::Queue::new();
+mod task_stats;
 
-/// Statically store our netstack
-static STACK: Stack = kit::new_target_stack(OUTQ.stream_producer(), MAX_PACKET_SIZE as u16);
+// Use panic-probe for panics
+use panic_probe as _;
 
 /// Buffers for RX worker
 static RECV_BUF: StaticCell<[u8; MAX_PACKET_SIZE]> = StaticCell::new();
@@ -76,6 +106,9 @@ fn get_device_state() -> DeviceState {
     }
 }
 
+/// Identify request from the host: seconds of the identify pattern
+static IDENTIFY: Signal<CriticalSectionRawMutex, u16> = Signal::new();
+
 /// RTT channel storage
 static RTT_UP_CHANNEL: StaticCell<rtt_target::UpChannel> = StaticCell::new();
 static RTT_DOWN_CHANNEL: StaticCell<rtt_target::DownChannel> = StaticCell::new();
@@ -103,8 +136,8 @@ async fn main(spawner: Spawner) {
     let rtt_down = channels.down.0;
     let rtt_down_static = RTT_DOWN_CHANNEL.init_with(|| rtt_down);
 
-    // Create RTT I/O
-    let rtt_io = rtt_io::RttIo::new(rtt_up_static, rtt_down_static);
+    // Create RTT I/O, batching partial writes so each RTT write carries whole frames
+    let rtt_io = rtt_io::RttIo::new(rtt_up_static, rtt_down_static).with_batching();
     let (rtt_rx, rtt_tx) = rtt_io.split();
 
     // Initialize STM32 with HSE=8MHz feeding PLL to 170MHz SYSCLK
@@ -130,31 +163,107 @@ async fn main(spawner: Spawner) {
             config.rcc.sys = Sysclk::PLL1_R;
             // Above 150MHz, enable Range1 boost mode per RM0440 guidance
             config.rcc.boost = true;
+            // ADC1/2 kernel clock from SYSCLK (the driver applies the prescaler)
+            config.rcc.mux.adc12sel = mux::Adcsel::SYS;
         }
         embassy_stm32::init(config)
     };
 
-    defmt::info!("Oxifoc starting - ergot over RTT");
+    // Cycle counter for µs-resolution sense blanking
+    let mut core_p = cortex_m::Peripherals::take().unwrap();
+    motor::blanking::init(
+        &mut core_p.DCB,
+        &mut core_p.DWT,
+        &motor::blanking::SenseConfig::default(),
+    );
 
-    // Create RX worker for incoming ergot messages (it will set interface to Inactive, then Active after first frame)
-    let rx_worker = RxWorker::new_target(&STACK, rtt_rx, ());
+    log::info!(
+        "Oxifoc starting - ergot over RTT (node={}.{})",
+        net::NETWORK_ID,
+        net::local_node_id()
+    );
+    log::info!("Board UID: {}", unique_id().as_str());
+    log::info!("Build: {}", build_id::hash().as_str());
+    motor::safe_mode::banner();
 
+    // Create RX worker for incoming ergot messages
+    let rx_worker = net::new_rx_worker(rtt_rx);
+    // Button: PC10, active-low to GND with an external pull-up unless built for other wiring
     // Button: PC10, external pull-up, active-low to GND
-    let button = ExtiInput::new(p.PC10, p.EXTI10, Pull::None);
-    defmt::info!("Button configured on PC10 (active-low)");
+    let button_cfg = ButtonConfig::default();
+    let pull = match button_cfg.pull {
+        ButtonPull::None => Pull::None,
+        ButtonPull::Up => Pull::Up,
+        ButtonPull::Down => Pull::Down,
+    };
+    let button = ExtiInput::new(p.PC10, p.EXTI10, pull);
+    log::info!(
+        "Button configured on PC10 ({}, pull {})",
+        button_cfg.polarity,
+        button_cfg.pull
+    );
+
+    // Internal flash for persistent parameters
+    storage::init(Flash::new_blocking(p.FLASH)).await;
 
     // LED on PC6
     let mut led = Output::new(p.PC6, Level::Low, Speed::Low);
 
+    // Control loop heartbeat on PA15 (the PWM input pad, unused by this firmware);
+    // wire an LED with a series resistor to it
+    let heartbeat_led = Output::new(p.PA15, Level::Low, Speed::Low);
+
+    // External commutation trigger on PB3 (the UART TX pad), followed in
+    // `CommutationMode::External`
+    let trigger_cfg = ExtTriggerConfig::default();
+    let trigger = ExtiInput::new(p.PB3, p.EXTI3, Pull::Down);
+
+    // Cooling fan on PB4 (TIM3_CH1), only if one is configured
+    let fan_cfg = FanConfig::default();
+    let fan_pwm = fan_cfg.enabled.then(|| {
+        SimplePwm::new(
+            p.TIM3,
+            Some(PwmPin::new(p.PB4, OutputType::PushPull)),
+            None,
+            None,
+            None,
+            Hertz(fan_cfg.pwm_freq_hz),
+            CountingMode::EdgeAlignedUp,
+        )
+    });
+
     // Initialize motor controller with TIM1 and motor pins
-    let motor_ctrl = MotorController::init(
-        p.TIM1,
-        p.PA8,   // Phase A high
-        p.PC13,  // Phase A low
-        p.PA9,   // Phase B high
-        p.PA12,  // Phase B low
-        p.PA10,  // Phase C high
-        p.PB15,  // Phase C low
+    let motor_ctrl = MotorController::init(PwmPins {
+        tim1: p.TIM1,
+        a_high: p.PA8,
+        a_low: p.PC13,
+        b_high: p.PA9,
+        b_low: p.PA12,
+        c_high: p.PA10,
+        c_low: p.PB15,
+        bkin: Some(p.PB12),
+    });
+
+    // Hall sensor inputs on the J8 connector (used by the direction self-test)
+    let hall = HallSensors::new(p.PB6, p.PB7, p.PB8);
+
+    // Phase current sense (op-amp outputs PA2/PA6/PB1), VBUS on PA0, NTC on PB14,
+    // phase voltages on PA4/PC4/PB11 with the divider enable on PB5
+    let current = CurrentSense::new(
+        SensePins {
+            adc1: p.ADC1,
+            adc2: p.ADC2,
+            shunt_a: p.PA2,
+            shunt_b: p.PA6,
+            shunt_c: p.PB1,
+            vbus: p.PA0,
+            temp: p.PB14,
+            phase_v_a: p.PA4,
+            phase_v_b: p.PC4,
+            phase_v_c: p.PB11,
+            bemf_enable: p.PB5,
+        },
+        &AdcConfig::default(),
     );
 
     // Spawn I/O workers
@@ -172,54 +281,130 @@ async fn main(spawner: Spawner) {
     let motor_cmd_receiver = motor_cmd_channel.receiver();
     let motor_cmd_sender = motor_cmd_channel.sender();
 
-    spawner.spawn(button_handler(button)).unwrap();
+    spawner.spawn(button_handler(button, button_cfg)).unwrap();
     spawner.spawn(status_reporter()).unwrap();
     spawner.spawn(info_server()).unwrap();
-    spawner.spawn(motor_control_task(motor_ctrl, motor_cmd_receiver)).unwrap();
-    spawner.spawn(motor_command_server(motor_cmd_sender)).unwrap();
+    spawner.spawn(identify_server()).unwrap();
+    spawner.spawn(log_level_server()).unwrap();
+    spawner.spawn(protocol_server()).unwrap();
+    spawner.spawn(keepalive_task()).unwrap();
+    spawner.spawn(telemetry_task()).unwrap();
+    #[cfg(feature = "diagnostics")]
+    spawner.spawn(bemf_stream_task()).unwrap();
+    #[cfg(feature = "diagnostics")]
+    spawner.spawn(cogging_stream_task()).unwrap();
+    spawner.spawn(command_log_task()).unwrap();
+    spawner
+        .spawn(ext_trigger_task(trigger, trigger_cfg.edge))
+        .unwrap();
+    if let Some(pwm) = fan_pwm {
+        spawner.spawn(fan_task(pwm, fan_cfg)).unwrap();
+    }
+    spawner.spawn(config_server()).unwrap();
+    spawner.spawn(comm_table_server()).unwrap();
+    spawner.spawn(comm_period_server()).unwrap();
+    #[cfg(feature = "diagnostics")]
+    spawner.spawn(electrical_params_server()).unwrap();
+    spawner.spawn(current_calibration_server()).unwrap();
+    #[cfg(feature = "diagnostics")]
+    spawner.spawn(extremes_server()).unwrap();
+    #[cfg(feature = "diagnostics")]
+    spawner.spawn(reset_extremes_server()).unwrap();
+    #[cfg(feature = "bench")]
+    spawner.spawn(bench_server()).unwrap();
+    spawner.spawn(task_stats_server()).unwrap();
+    spawner.spawn(full_status_server()).unwrap();
+    spawner
+        .spawn(motor_control_task(
+            motor_ctrl,
+            hall,
+            current,
+            heartbeat_led,
+            trigger_cfg,
+            motor_cmd_receiver,
+        ))
+        .unwrap();
+    spawner
+        .spawn(motor_command_server(motor_cmd_sender))
+        .unwrap();
+    spawner.spawn(profile_server(motor_cmd_sender)).unwrap();
+    spawner.spawn(self_test_server(motor_cmd_sender)).unwrap();
 
     // Transition to "waiting for link" once tasks are up
     set_device_state(DeviceState::WaitingLink);
 
-    defmt::info!("All tasks spawned, entering LED status loop");
+    log::info!("All tasks spawned, entering LED status loop");
 
-    // LED status loop - shows device state via blink patterns
+    // LED status loop - shows device state via blink patterns, interrupted by identify requests
     loop {
-        match get_device_state() {
-            DeviceState::Boot => {
-                // Quick double blink
-                for _ in 0..2 {
-                    led.set_high();
-                    Timer::after(Duration::from_millis(100)).await;
-                    led.set_low();
-                    Timer::after(Duration::from_millis(100)).await;
-                }
-                Timer::after(Duration::from_millis(600)).await;
-            }
-            DeviceState::WaitingLink => {
-                // Slow blink (1 Hz, 10% duty)
+        if let Either::Second(secs) = select(status_blink(&mut led), IDENTIFY.wait()).await {
+            identify_blink(&mut led, secs).await;
+        }
+    }
+}
+
+/// One period of the blink pattern for the current device state
+async fn status_blink(led: &mut Output<'static>) {
+    match get_device_state() {
+        DeviceState::Boot => {
+            // Quick double blink
+            for _ in 0..2 {
                 led.set_high();
                 Timer::after(Duration::from_millis(100)).await;
                 led.set_low();
-                Timer::after(Duration::from_millis(900)).await;
+                Timer::after(Duration::from_millis(100)).await;
             }
-            DeviceState::Linked => {
-                // Solid ON with periodic short delay to allow state changes
+            Timer::after(Duration::from_millis(600)).await;
+        }
+        DeviceState::WaitingLink => {
+            // Slow blink (1 Hz, 10% duty)
+            led.set_high();
+            Timer::after(Duration::from_millis(100)).await;
+            led.set_low();
+            Timer::after(Duration::from_millis(900)).await;
+        }
+        DeviceState::Linked => {
+            // Solid ON with periodic short delay to allow state changes
+            led.set_high();
+            Timer::after(Duration::from_millis(500)).await;
+        }
+        DeviceState::Error => {
+            // Triple blink pattern
+            for _ in 0..3 {
                 led.set_high();
-                Timer::after(Duration::from_millis(500)).await;
+                Timer::after(Duration::from_millis(120)).await;
+                led.set_low();
+                Timer::after(Duration::from_millis(120)).await;
             }
-            DeviceState::Error => {
-                // Triple blink pattern
-                for _ in 0..3 {
+            Timer::after(Duration::from_millis(800)).await;
+        }
+    }
+}
+
+/// Rapid bursts of four flashes for `secs` seconds so a board can be located
+///
+/// A new request during the pattern restarts it with its own duration.
+async fn identify_blink(led: &mut Output<'static>, mut secs: u16) {
+    log::info!("Identify: blinking for {}s", secs);
+    loop {
+        let bursts = async {
+            loop {
+                for _ in 0..4 {
                     led.set_high();
-                    Timer::after(Duration::from_millis(120)).await;
+                    Timer::after(Duration::from_millis(50)).await;
                     led.set_low();
-                    Timer::after(Duration::from_millis(120)).await;
+                    Timer::after(Duration::from_millis(50)).await;
                 }
-                Timer::after(Duration::from_millis(800)).await;
+                Timer::after(Duration::from_millis(300)).await;
             }
+        };
+        let done = Timer::after(Duration::from_secs(secs as u64));
+        match select(bursts, select(done, IDENTIFY.wait())).await {
+            Either::Second(Either::Second(next)) => secs = next,
+            _ => break,
         }
     }
+    led.set_low();
 }
 
 /// Worker task for incoming ergot data via RTT
@@ -239,65 +424,114 @@ async fn run_tx(mut tx: RttWriter) {
 }
 
 #[embassy_executor::task]
-async fn button_handler(mut button: ExtiInput<'static>) {
-    const DOUBLE_CLICK_DELAY: u64 = 250;
-    const HOLD_DELAY: u64 = 1000;
+async fn button_handler(mut button: ExtiInput<'static>, cfg: ButtonConfig) {
+    log::info!("Button handler started");
 
-    defmt::info!("Button handler started");
-
-    // Target host router at network 1, node 1 (like rp2040-serial-pair target.rs:89-95)
-    let host_addr = Address {
-        network_id: 1,
-        node_id: 1,
-        port_id: 0,
-    };
+    // Target the peer at the other end of the link (host in the default edge role)
+    let host_addr = net::peer_addr();
     let client = STACK
         .endpoints()
         .client::<ButtonEndpoint>(host_addr, Some("button"));
+    let mut coalescer = Coalescer::default();
+    // Rapid repeats of one gesture are sent once per interval
+    let mut send = async |event: ButtonEvent| {
+        let now_ms = embassy_time::Instant::now().as_millis();
+        if coalescer.admit(&event, now_ms, cfg.min_event_interval_ms) {
+            let _ = client.request(&event).await;
+        } else {
+            log::debug!("Button: repeat coalesced");
+        }
+    };
 
-    defmt::info!("Button ready (active-low)");
+    log::info!("Button ready ({})", cfg.polarity);
+    let polarity = cfg.polarity;
 
     loop {
-        // Wait for press (active-low => falling edge)
-        button.wait_for_falling_edge().await;
+        wait_for_press(&mut button, polarity).await;
 
-        // If release does not happen within HOLD_DELAY, it's a hold
+        // If release does not happen within hold_ms, it's a hold
         if with_timeout(
-            Duration::from_millis(HOLD_DELAY),
-            button.wait_for_rising_edge(),
+            Duration::from_millis(cfg.hold_ms as u64),
+            wait_for_release(&mut button, polarity),
         )
         .await
         .is_err()
         {
-            defmt::info!("Button: HOLD");
-            let _ = client.request(&ButtonEvent::Hold).await;
+            log::info!("Button: HOLD");
+            send(ButtonEvent::Hold).await;
             // Ensure we're released before next iteration
-            button.wait_for_rising_edge().await;
+            wait_for_release(&mut button, polarity).await;
             continue;
         }
 
-        // Released within hold window: check for a second press within DOUBLE_CLICK_DELAY
+        // Released within hold window: check for a second press within double_click_ms
         if with_timeout(
-            Duration::from_millis(DOUBLE_CLICK_DELAY),
-            button.wait_for_falling_edge(),
+            Duration::from_millis(cfg.double_click_ms as u64),
+            wait_for_press(&mut button, polarity),
         )
         .await
         .is_ok()
         {
-            defmt::info!("Button: DOUBLE CLICK");
-            let _ = client.request(&ButtonEvent::DoubleClick).await;
+            log::info!("Button: DOUBLE CLICK");
+            send(ButtonEvent::DoubleClick).await;
             // Wait for final release
-            button.wait_for_rising_edge().await;
+            wait_for_release(&mut button, polarity).await;
+        } else {
+            log::info!("Button: SINGLE CLICK");
+            send(ButtonEvent::SingleClick).await;
+        }
+    }
+}
+
+/// Wait for the edge that presses a button wired with `polarity`
+async fn wait_for_press(button: &mut ExtiInput<'static>, polarity: ButtonPolarity) {
+    match polarity {
+        ButtonPolarity::ActiveLow => button.wait_for_falling_edge().await,
+        ButtonPolarity::ActiveHigh => button.wait_for_rising_edge().await,
+    }
+}
+
+/// Wait for the edge that releases a button wired with `polarity`
+async fn wait_for_release(button: &mut ExtiInput<'static>, polarity: ButtonPolarity) {
+    match polarity {
+        ButtonPolarity::ActiveLow => button.wait_for_rising_edge().await,
+        ButtonPolarity::ActiveHigh => button.wait_for_falling_edge().await,
+    }
+}
+
+/// Drive the cooling fan from the board temperature the control task records
+#[embassy_executor::task]
+async fn fan_task(mut pwm: SimplePwm<'static, embassy_stm32::peripherals::TIM3>, cfg: FanConfig) {
+    log::info!(
+        "Fan on PB4: {}% at {}C up to {}% at {}C",
+        cfg.min_duty,
+        cfg.min_temp_c,
+        cfg.max_duty,
+        cfg.max_temp_c
+    );
+    let mut curve = FanCurve::default();
+    let mut ch = pwm.ch1();
+    ch.enable();
+    let mut last = None;
+    loop {
+        let duty = if motor::adc_health::trusted(Signals::TEMP) {
+            curve.update(motor::telemetry::temp_dc(), &cfg)
         } else {
-            defmt::info!("Button: SINGLE CLICK");
-            let _ = client.request(&ButtonEvent::SingleClick).await;
+            cfg.max_duty.min(100)
+        };
+        if last != Some(duty) {
+            ch.set_duty_cycle_percent(duty);
+            fan::set_duty(duty);
+            log::debug!("Fan: {}% at {}dC", duty, motor::telemetry::temp_dc());
+            last = Some(duty);
         }
+        Timer::after(Duration::from_millis(cfg.period_ms as u64)).await;
     }
 }
 
 #[embassy_executor::task]
 async fn status_reporter() {
-    defmt::info!("Status reporter started");
+    log::info!("Status reporter started");
 
     // Create server to handle incoming button requests from the network
     let button_socket = STACK
@@ -306,7 +540,7 @@ async fn status_reporter() {
     let button_socket = pin!(button_socket);
     let mut button_hdl = button_socket.attach();
 
-    defmt::info!("Ergot button endpoint ready");
+    log::info!("Ergot button endpoint ready");
 
     loop {
         // Handle button events from network with timeout
@@ -314,13 +548,13 @@ async fn status_reporter() {
             Duration::from_secs(5),
             button_hdl.serve(async |event| match event {
                 ButtonEvent::SingleClick => {
-                    defmt::info!("Network: SINGLE CLICK");
+                    log::info!("Network: SINGLE CLICK");
                 }
                 ButtonEvent::DoubleClick => {
-                    defmt::info!("Network: DOUBLE CLICK");
+                    log::info!("Network: DOUBLE CLICK");
                 }
                 ButtonEvent::Hold => {
-                    defmt::info!("Network: HOLD");
+                    log::info!("Network: HOLD");
                 }
             }),
         )
@@ -328,11 +562,26 @@ async fn status_reporter() {
 
         // Periodic status when no network activity
         if result.is_err() {
-            defmt::debug!("Waiting for network events...");
+            log::debug!("Waiting for network events...");
         }
     }
 }
 
+/// Base of the 96-bit unique device ID (RM0440 §48.1), lowest word first
+const UID_BASE: usize = 0x1FFF_7590;
+
+/// Unique device ID as 24 hex digits, most significant word first
+fn unique_id() -> heapless::String<24> {
+    use core::fmt::Write;
+    // SAFETY: UID_BASE is the read-only 96-bit factory UID on the G4, three
+    // aligned words of system memory that are always mapped and readable
+    let word = |i: usize| unsafe { core::ptr::read_volatile((UID_BASE + 4 * i) as *const u32) };
+    let mut s = heapless::String::new();
+    // 24 digits fill the capacity exactly
+    let _ = write!(s, "{:08X}{:08X}{:08X}", word(2), word(1), word(0));
+    s
+}
+
 /// Respond to info requests from host
 #[embassy_executor::task]
 async fn info_server() {
@@ -351,56 +600,713 @@ async fn info_server() {
                 let mut sw: heapless::String<32> = heapless::String::new();
                 let _ = hw.push_str("B-G431B-ESC1");
                 let _ = sw.push_str("oxifoc-0.1.0");
-                DeviceInfo { hw, sw }
+                DeviceInfo {
+                    hw,
+                    sw,
+                    uid: unique_id(),
+                    build: build_id::hash(),
+                }
             })
             .await;
     }
 }
 
-/// Static channel for motor commands
-static MOTOR_CMD_CHANNEL: StaticCell<
-    embassy_sync::channel::Channel<embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, MotorCommand, 4>,
-> = StaticCell::new();
+/// Start (or end, with 0 seconds) the identify LED pattern on host request
+#[embassy_executor::task]
+async fn identify_server() {
+    let server = STACK
+        .endpoints()
+        .bounded_server::<IdentifyEndpoint, 2>(Some("identify"));
+    let server = pin!(server);
+    let mut h = server.attach();
+    loop {
+        let _ = h
+            .serve(|secs: &u16| {
+                IDENTIFY.signal(*secs);
+                async {}
+            })
+            .await;
+    }
+}
+
+/// Apply the runtime log level the host asks for
+#[embassy_executor::task]
+async fn log_level_server() {
+    let server = STACK
+        .endpoints()
+        .bounded_server::<LogLevelEndpoint, 2>(Some("log_level"));
+    let server = pin!(server);
+    let mut h = server.attach();
+    loop {
+        let _ = h
+            .serve(|level: &LogLevel| {
+                // Past the gate on purpose: the change itself is always on record
+                defmt::info!(
+                    "Log level: {} -> {}",
+                    defmt::Debug2Format(&log::level()),
+                    defmt::Debug2Format(level)
+                );
+                log::set_level(*level);
+                async {}
+            })
+            .await;
+    }
+}
+
+/// Report the protocol revision this firmware was built with
+#[embassy_executor::task]
+async fn protocol_server() {
+    let server = STACK
+        .endpoints()
+        .bounded_server::<ProtocolVersionEndpoint, 2>(Some("protocol_version"));
+    let server = pin!(server);
+    let mut h = server.attach();
+    loop {
+        let _ = h.serve(|_req: &()| async move { PROTOCOL_VERSION }).await;
+    }
+}
+
+/// Send a sequenced keepalive to the peer every second once the link is up
+///
+/// The sequence restarts at 0 on every boot, which is how the host tells a
+/// device reset apart from a quiet link.
+#[embassy_executor::task]
+async fn keepalive_task() {
+    const PERIOD: Duration = Duration::from_secs(1);
+
+    let client = STACK
+        .endpoints()
+        .client::<KeepAliveEndpoint>(net::peer_addr(), Some("keepalive"));
+    let mut seq: u32 = 0;
+    loop {
+        Timer::after(PERIOD).await;
+        // Stay quiet until the host has talked to us; avoids NoRoute noise
+        if !LINK_ACTIVE.load(Ordering::Relaxed) {
+            continue;
+        }
+        let ka = KeepAlive {
+            seq,
+            uptime_ms: embassy_time::Instant::now().as_millis() as u32,
+        };
+        if with_timeout(PERIOD, client.request(&ka)).await.is_err() {
+            log::debug!("Keepalive {} not acknowledged", seq);
+        }
+        seq = seq.wrapping_add(1);
+    }
+}
+
+/// Stream the telemetry fields selected by the host once the link is up
+#[embassy_executor::task]
+async fn telemetry_task() {
+    const PERIOD: Duration = Duration::from_millis(100);
+
+    let client = STACK
+        .endpoints()
+        .client::<TelemetryEndpoint>(net::peer_addr(), Some("telemetry"));
+    let mut encoder = TelemetryEncoder::default();
+    loop {
+        // Faster while a freewheel coast-down is being tracked, for the host's decay curve
+        if motor::freewheel::coasting() {
+            Timer::after(Duration::from_millis(motor::freewheel::FAST_TELEMETRY_MS)).await;
+        } else {
+            Timer::after(PERIOD).await;
+        }
+        task_stats::TELEMETRY.tick();
+        let mask = motor::telemetry::mask();
+        if !LINK_ACTIVE.load(Ordering::Relaxed) || mask == 0 {
+            continue;
+        }
+        encoder.set_key_interval(motor::telemetry::key_interval());
+        let frame = encoder.encode(&motor::telemetry::sample(), mask);
+        if with_timeout(PERIOD, client.request(&frame)).await.is_err() {
+            log::debug!("Telemetry frame not acknowledged");
+        }
+    }
+}
+
+/// Send each completed BEMF capture to the host in chunks
+#[cfg(feature = "diagnostics")]
+#[embassy_executor::task]
+async fn bemf_stream_task() {
+    let client = STACK
+        .endpoints()
+        .client::<oxifoc_protocol::BemfEndpoint>(net::peer_addr(), Some("bemf"));
+    loop {
+        motor::bemf::DONE.wait().await;
+        let total = motor::bemf::len();
+        let mut offset = 0;
+        while offset < total {
+            let chunk = motor::bemf::chunk(offset);
+            if chunk.samples.is_empty() {
+                break;
+            }
+            if with_timeout(Duration::from_millis(500), client.request(&chunk))
+                .await
+                .is_err()
+            {
+                log::warn!("BEMF chunk at {} not acknowledged; capture dropped", offset);
+                break;
+            }
+            offset += chunk.samples.len() as u16;
+        }
+    }
+}
+
+/// Send each cogging scan position to the host as it is measured
+#[cfg(feature = "diagnostics")]
+#[embassy_executor::task]
+async fn cogging_stream_task() {
+    let client = STACK
+        .endpoints()
+        .client::<oxifoc_protocol::CoggingEndpoint>(net::peer_addr(), Some("cogging"));
+    loop {
+        let sample = motor::cogging::next_sample().await;
+        if with_timeout(Duration::from_millis(500), client.request(&sample))
+            .await
+            .is_err()
+        {
+            log::warn!("Cogging sample {} not acknowledged", sample.index);
+        }
+    }
+}
+
+/// Send the outcome of each processed motor command to the host
+#[embassy_executor::task]
+async fn command_log_task() {
+    let client = STACK
+        .endpoints()
+        .client::<CommandLogEndpoint>(net::peer_addr(), Some("command_log"));
+    loop {
+        let entry = motor::COMMAND_LOG.receive().await;
+        // Nobody to tell before the host has connected
+        if !LINK_ACTIVE.load(Ordering::Relaxed) {
+            continue;
+        }
+        if with_timeout(Duration::from_millis(500), client.request(&entry))
+            .await
+            .is_err()
+        {
+            log::debug!("Command log for command {} not acknowledged", entry.cmd_id);
+        }
+    }
+}
+
+/// Respond to full-configuration queries from host
+#[embassy_executor::task]
+async fn config_server() {
+    let server = STACK
+        .endpoints()
+        .bounded_server::<ConfigEndpoint, 2>(Some("config"));
+    let server = pin!(server);
+    let mut h = server.attach();
+    loop {
+        let _ = h.serve(|_req: &()| async move { config::capture() }).await;
+    }
+}
+
+/// Respond to commutation table queries from host
+#[embassy_executor::task]
+async fn comm_table_server() {
+    let server = STACK
+        .endpoints()
+        .bounded_server::<CommutationTableEndpoint, 2>(Some("comm_table"));
+    let server = pin!(server);
+    let mut h = server.attach();
+    loop {
+        let _ = h
+            .serve(|_req: &()| async move { config::commutation_table() })
+            .await;
+    }
+}
+
+/// Respond to commutation period queries from host
+#[embassy_executor::task]
+async fn comm_period_server() {
+    let server = STACK
+        .endpoints()
+        .bounded_server::<CommutationPeriodEndpoint, 2>(Some("comm_period"));
+    let server = pin!(server);
+    let mut h = server.attach();
+    loop {
+        let _ = h
+            .serve(|_req: &()| async move { config::commutation_period() })
+            .await;
+    }
+}
+
+/// Respond to resistance/inductance measurement queries from host
+#[cfg(feature = "diagnostics")]
+#[embassy_executor::task]
+async fn electrical_params_server() {
+    let server = STACK
+        .endpoints()
+        .bounded_server::<oxifoc_protocol::ElectricalParamsEndpoint, 2>(Some("electrical_params"));
+    let server = pin!(server);
+    let mut h = server.attach();
+    loop {
+        let _ = h
+            .serve(|_req: &()| async move { motor::rl::latest() })
+            .await;
+    }
+}
+
+/// Respond to current calibration queries from host
+#[embassy_executor::task]
+async fn current_calibration_server() {
+    let server = STACK
+        .endpoints()
+        .bounded_server::<CurrentCalibrationEndpoint, 2>(Some("current_calibration"));
+    let server = pin!(server);
+    let mut h = server.attach();
+    loop {
+        let _ = h
+            .serve(|_req: &()| async move { motor::current_cal::latest() })
+            .await;
+    }
+}
+
+/// Respond to telemetry extremes queries from host
+#[cfg(feature = "diagnostics")]
+#[embassy_executor::task]
+async fn extremes_server() {
+    let server = STACK
+        .endpoints()
+        .bounded_server::<oxifoc_protocol::ExtremesEndpoint, 2>(Some("extremes"));
+    let server = pin!(server);
+    let mut h = server.attach();
+    loop {
+        let _ = h
+            .serve(|_req: &()| async move { motor::telemetry::extremes() })
+            .await;
+    }
+}
+
+/// Start the telemetry extremes over on host request
+#[cfg(feature = "diagnostics")]
+#[embassy_executor::task]
+async fn reset_extremes_server() {
+    let server = STACK
+        .endpoints()
+        .bounded_server::<oxifoc_protocol::ResetExtremesEndpoint, 2>(Some("reset_extremes"));
+    let server = pin!(server);
+    let mut h = server.attach();
+    loop {
+        let _ = h
+            .serve(|_req: &()| async move { motor::telemetry::reset_extremes() })
+            .await;
+    }
+}
+
+/// Respond to task scheduling queries from host, starting the figures over
+#[embassy_executor::task]
+async fn task_stats_server() {
+    let server = STACK
+        .endpoints()
+        .bounded_server::<TaskStatsEndpoint, 2>(Some("task_stats"));
+    let server = pin!(server);
+    let mut h = server.attach();
+    loop {
+        let _ = h.serve(|_req: &()| async move { task_stats::take() }).await;
+    }
+}
+
+/// Run a throughput benchmark on host request; answers once the flood is over
+#[cfg(feature = "bench")]
+#[embassy_executor::task]
+async fn bench_server() {
+    let server = STACK
+        .endpoints()
+        .bounded_server::<oxifoc_protocol::BenchEndpoint, 2>(Some("bench"));
+    let server = pin!(server);
+    let mut h = server.attach();
+    loop {
+        let _ = h
+            .serve(|req: &oxifoc_protocol::BenchRequest| {
+                let req = *req;
+                async move { bench::run(req).await }
+            })
+            .await;
+    }
+}
+
+/// Respond to full status queries from host
+#[embassy_executor::task]
+async fn full_status_server() {
+    let server = STACK
+        .endpoints()
+        .bounded_server::<FullStatusEndpoint, 2>(Some("full_status"));
+    let server = pin!(server);
+    let mut h = server.attach();
+    loop {
+        let _ = h
+            .serve(|_req: &()| async move { motor::capture_full_status() })
+            .await;
+    }
+}
+
+/// Static channel for motor control messages
+static MOTOR_CMD_CHANNEL: StaticCell<ControlChannel> = StaticCell::new();
 
 /// Motor control task - performs 6-step commutation and handles commands
 #[embassy_executor::task]
 async fn motor_control_task(
     mut motor: MotorController<'static>,
-    cmd_receiver: embassy_sync::channel::Receiver<
-        'static,
-        embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
-        MotorCommand,
-        4,
-    >,
+    hall: HallSensors<'static>,
+    mut current: CurrentSense<'static>,
+    mut heartbeat_led: Output<'static>,
+    trigger_cfg: ExtTriggerConfig,
+    cmd_receiver: ControlReceiver,
 ) {
-    defmt::info!("Motor control task started");
+    log::info!("Motor control task started");
+    let heartbeat_cfg = HeartbeatConfig::default();
+    let mut heartbeat = Heartbeat::default();
+    let failsafe_cfg = LinkFailsafeConfig::default();
+    let auto_disarm_cfg = AutoDisarmConfig::default();
+    let mut idle = IdleTimer::default();
+    let mut adc_health = AdcHealth::default();
+    let mut sync = SyncGuard::default();
+
+    // Restore a stored current calibration; otherwise the phases are off here,
+    // so the op-amp outputs sit at their zero-current bias
+    match storage::load(storage::RecordKey::CurrentCalibration).await {
+        Some(cal) => {
+            current.set_calibration(cal);
+            log::info!("Current calibration restored: offsets {}", cal.offset);
+        }
+        None => {
+            current.calibrate();
+            motor::adc_health::check_offsets(
+                current.calibration().offset,
+                motor.adc_health_config(),
+            );
+        }
+    }
+
+    // Restore a phase order corrected by an earlier self-test
+    if let Some(map) = storage::load(storage::RecordKey::PhaseMap).await {
+        motor.set_phase_map(map);
+    }
 
+    // A message that aborted a running sequence is handled before the next tick
+    let mut pending: Option<ControlMsg> = None;
     loop {
+        // A long sequence or a starved loop shows up as a long gap in `req/task_stats`
+        task_stats::CONTROL.tick();
+
+        // A host that went quiet stops the motor like any other safety stop
+        let silence_ms = link_failsafe::silence_ms(embassy_time::Instant::now().as_millis() as u32);
+        if motor::get_motor_state() == MotorState::Running
+            && link_failsafe::expired(silence_ms, &failsafe_cfg)
+        {
+            log::warn!("No host traffic for {}ms; stopping the motor", silence_ms);
+            motor::request_internal_stop();
+        }
+
+        // An armed motor left stopped with no commands goes back to disarmed,
+        // through the same path as a host command so the host sees it logged
+        let idle_now_ms = embassy_time::Instant::now().as_millis() as u32;
+        if motor::get_motor_state() != MotorState::Stopped {
+            idle.activity(idle_now_ms);
+        }
+        if idle.expired(idle_now_ms, motor.is_armed(), &auto_disarm_cfg) {
+            log::warn!(
+                "Armed but idle for {}ms; disarming",
+                idle.idle_ms(idle_now_ms)
+            );
+            let msg = ControlMsg::Internal(MotorCommand::Disarm);
+            process_msg(&mut motor, &hall, &mut current, &cmd_receiver, msg).await;
+        }
+
+        // A safety stop goes first, ahead of the pending message and the queue
+        if let Some(msg) = motor::take_safety_stop() {
+            process_msg(&mut motor, &hall, &mut current, &cmd_receiver, msg).await;
+            idle.activity(embassy_time::Instant::now().as_millis() as u32);
+        }
+
         // Check for commands (non-blocking)
-        if let Ok(cmd) = cmd_receiver.try_receive() {
-            motor.handle_command(&cmd);
+        if let Some(msg) = pending.take().or_else(|| cmd_receiver.try_receive().ok()) {
+            pending = process_msg(&mut motor, &hall, &mut current, &cmd_receiver, msg).await;
+            idle.activity(embassy_time::Instant::now().as_millis() as u32);
+            if pending.is_some() {
+                continue;
+            }
         }
 
+        // Sample analog telemetry at the end of the step, clear of blanking
+        motor::telemetry::record_analog(&mut current);
+        motor::adc_health::record(
+            &mut adc_health,
+            &motor::telemetry::readings(),
+            motor.adc_health_config(),
+        );
+        motor.limit_phase_voltage(motor::telemetry::vbus_mv());
+
         // Perform commutation step
         motor.commutate();
 
-        // Wait for next commutation based on speed
-        let period = motor.get_commutation_period();
-        Timer::after(period).await;
+        // A reversal reaching its dwell brakes here, where the current is sensed
+        if let Some(ms) = motor.take_brake_request() {
+            let cfg = *motor.regen_config();
+            pending = motor::regen::run(
+                &mut motor,
+                &mut current,
+                &cfg,
+                ms,
+                motor::Trace::NONE,
+                &cmd_receiver,
+            )
+            .await;
+            if pending.is_some() {
+                continue;
+            }
+        }
+
+        // A freewheel test whose spin time is over cuts the drive and tracks the coast here
+        if let Some(plan) = motor.take_freewheel_due() {
+            let cfg = *motor.freewheel_config();
+            pending =
+                motor::freewheel::coast(&mut motor, &mut current, plan, &cfg, &cmd_receiver).await;
+            if pending.is_some() {
+                continue;
+            }
+        }
+
+        // Pulses only while this loop keeps iterating
+        let now_ms = embassy_time::Instant::now().as_millis();
+        heartbeat_led.set_level(heartbeat.tick(now_ms, &heartbeat_cfg).into());
+
+        // Wait for next commutation based on speed (sampling BEMF if a capture
+        // is armed), or for the external trigger; a safety stop cuts the wait short
+        if motor.commutation_mode() == CommutationMode::External
+            && motor::get_motor_state() == MotorState::Running
+        {
+            wait_for_trigger(&mut motor, &mut sync, &trigger_cfg).await;
+        } else {
+            sync.reset();
+            let period = motor.get_commutation_period();
+            #[cfg(feature = "diagnostics")]
+            let wait = motor::bemf::wait_step(&mut current, motor.scan_scheme(), period);
+            #[cfg(not(feature = "diagnostics"))]
+            let wait = Timer::after(period);
+            select(wait, motor::SAFETY_WAKE.wait()).await;
+        }
+    }
+}
+
+/// Wait for the trigger edge that times the next step, or for a safety stop
+///
+/// An edge well ahead of the expected step interval is ignored, and a step
+/// whose edge is overdue goes without it; after too many of those in a row
+/// the motor stops (see `motor::sync_guard`). Without an estimate yet, an
+/// edge is waited for up to the trigger timeout.
+async fn wait_for_trigger(
+    motor: &mut MotorController<'static>,
+    sync: &mut SyncGuard,
+    trigger_cfg: &ExtTriggerConfig,
+) {
+    let cfg = *motor.sync_guard_config();
+    let timeout_at = Instant::now() + Duration::from_millis(trigger_cfg.timeout_ms as u64);
+    loop {
+        let late_at = sync.late_deadline_us(&cfg).map(Instant::from_micros);
+        let deadline = late_at.map_or(timeout_at, |at| at.min(timeout_at));
+        let edge = select(motor::ext_trigger::wait(), motor::SAFETY_WAKE.wait());
+        let timing = match with_deadline(deadline, edge).await {
+            Ok(Either::First(())) => sync.on_edge(Instant::now().as_micros(), &cfg),
+            Ok(Either::Second(())) => return,
+            Err(_) if deadline < timeout_at => sync.on_late(Instant::now().as_micros(), &cfg),
+            Err(_) => {
+                log::warn!(
+                    "No commutation trigger for {}ms; stopping the motor",
+                    trigger_cfg.timeout_ms
+                );
+                motor::request_internal_stop();
+                return;
+            }
+        };
+        match timing {
+            Timing::OnTime => return,
+            Timing::Early => {}
+            Timing::Late => {
+                log::warn!(
+                    "Commutation trigger overdue (expected every {}us): stepping without it ({}/{})",
+                    sync.expected_us().unwrap_or(0),
+                    sync.corrections(),
+                    cfg.max_corrections
+                );
+                return;
+            }
+            Timing::Lost => {
+                motor.lose_sync();
+                return;
+            }
+        }
+    }
+}
+
+/// Pass accepted edges of the external commutation trigger to the control task
+#[embassy_executor::task]
+async fn ext_trigger_task(mut input: ExtiInput<'static>, edge: TriggerEdge) {
+    let mut filter = EdgeFilter::default();
+    loop {
+        match edge {
+            TriggerEdge::Rising => input.wait_for_rising_edge().await,
+            TriggerEdge::Falling => input.wait_for_falling_edge().await,
+            TriggerEdge::Both => input.wait_for_any_edge().await,
+        }
+        motor::ext_trigger::edge(&mut filter, embassy_time::Instant::now().as_micros());
+    }
+}
+
+/// Act on one control message; returns a message that aborted a sequence
+///
+/// Motor commands are admitted or rejected first; the verdict is logged to the
+/// host as a `CommandLog`, and for a host command also published on
+/// `motor::COMMAND_RESULT` for the endpoint server waiting on it. An
+/// `Internal` command is handled as a host one from here on.
+async fn process_msg(
+    motor: &mut MotorController<'static>,
+    hall: &HallSensors<'static>,
+    current: &mut CurrentSense<'static>,
+    cmd_receiver: &ControlReceiver,
+    msg: ControlMsg,
+) -> Option<ControlMsg> {
+    let (msg, awaited) = match msg {
+        ControlMsg::Internal(cmd) => (ControlMsg::Command(cmd, motor::Trace::NONE), false),
+        msg => (msg, true),
+    };
+    if let ControlMsg::Command(cmd, trace) = &msg {
+        let verdict = motor.admit(cmd);
+        if awaited {
+            motor::COMMAND_RESULT.signal(verdict.clone());
+        }
+        motor::log_command(CommandLog::new(cmd, &verdict));
+        if let Err(reason) = verdict {
+            log::warn!(
+                "Motor command{} rejected: {}",
+                trace,
+                defmt::Debug2Format(&reason)
+            );
+            return None;
+        }
+    }
+
+    match msg {
+        ControlMsg::SelfTest(req) => {
+            motor.precharge_bootstrap_once();
+            let (report, pending) = motor::self_test::run(motor, hall, &req, cmd_receiver).await;
+            motor::self_test::RESULT.signal(report);
+            pending
+        }
+        ControlMsg::Command(MotorCommand::Start { duty, step }, trace)
+            if motor::get_motor_state() == MotorState::Stopped =>
+        {
+            log::info!(
+                "Motor command{}: START duty={} step={} from standstill",
+                trace,
+                duty,
+                step
+            );
+            let step = step.map(CommutationStep::from_u8);
+            motor.precharge_bootstrap_once();
+            match motor::startup::run(motor, current, duty, step, cmd_receiver).await {
+                StartupOutcome::Running(next) => {
+                    motor.start_at(duty, next);
+                    None
+                }
+                StartupOutcome::Aborted(msg) => Some(msg),
+            }
+        }
+        ControlMsg::Command(
+            MotorCommand::Freewheel {
+                duty,
+                spin_ms,
+                coast_ms,
+            },
+            trace,
+        ) if motor::get_motor_state() == MotorState::Stopped => {
+            log::info!(
+                "Motor command{}: FREEWHEEL duty={} from standstill",
+                trace,
+                duty
+            );
+            motor.precharge_bootstrap_once();
+            match motor::startup::run(motor, current, duty, None, cmd_receiver).await {
+                StartupOutcome::Running(next) => {
+                    motor.start_at(duty, next);
+                    motor.plan_freewheel(spin_ms, coast_ms, trace);
+                    None
+                }
+                StartupOutcome::Aborted(msg) => Some(msg),
+            }
+        }
+        ControlMsg::Command(MotorCommand::Stop, trace)
+            if motor::get_motor_state() == MotorState::Running && motor.regen_enabled() =>
+        {
+            let cfg = *motor.regen_config();
+            let ms = cfg.max_brake_ms as u32;
+            let pending = motor::regen::run(motor, current, &cfg, ms, trace, cmd_receiver).await;
+            motor.handle_command(&MotorCommand::Stop, trace);
+            pending
+        }
+        ControlMsg::Command(MotorCommand::Nudge { steps, duty }, trace) => {
+            motor.precharge_bootstrap_once();
+            motor::nudge::run(motor, steps, duty, trace, cmd_receiver).await
+        }
+        #[cfg(feature = "diagnostics")]
+        ControlMsg::Command(MotorCommand::TestPattern { pattern }, trace) => {
+            motor.precharge_bootstrap_once();
+            motor::test_pattern::run(motor, pattern, trace, cmd_receiver).await
+        }
+        #[cfg(feature = "diagnostics")]
+        ControlMsg::Command(MotorCommand::SetPhaseDuties { a, b, c }, trace) => {
+            motor.precharge_bootstrap_once();
+            motor::phase_duty::run(motor, [a, b, c], trace, cmd_receiver).await
+        }
+        ControlMsg::Command(
+            MotorCommand::CalibrateCurrent {
+                gain_num,
+                gain_den,
+                persist,
+            },
+            trace,
+        ) => {
+            let gain = (gain_num != 0).then_some((gain_num, gain_den));
+            motor::current_cal::run(motor, current, gain, persist, trace).await;
+            None
+        }
+        #[cfg(feature = "diagnostics")]
+        ControlMsg::Command(MotorCommand::MeasureRL, trace) => {
+            motor.precharge_bootstrap_once();
+            let cfg = *motor.rl_config();
+            motor::rl::run(motor, current, &cfg, trace, cmd_receiver).await
+        }
+        #[cfg(feature = "diagnostics")]
+        ControlMsg::Command(
+            MotorCommand::CoggingScan {
+                current_ma,
+                settle_ms,
+            },
+            trace,
+        ) => {
+            motor.precharge_bootstrap_once();
+            let cfg = *motor.cogging_config();
+            let scan = motor::cogging::ScanParams {
+                current_ma,
+                settle_ms,
+            };
+            motor::cogging::run(motor, current, hall, scan, &cfg, trace, cmd_receiver).await
+        }
+        msg => {
+            motor.handle_msg(msg);
+            None
+        }
     }
 }
 
 /// Motor command server - handles motor control commands via ergot
 #[embassy_executor::task]
-async fn motor_command_server(
-    motor_cmd_sender: embassy_sync::channel::Sender<
-        'static,
-        embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
-        MotorCommand,
-        4,
-    >,
-) {
-    defmt::info!("Motor command server started");
+async fn motor_command_server(motor_cmd_sender: ControlSender) {
+    log::info!("Motor command server started");
 
     let server = STACK
         .endpoints()
@@ -410,17 +1316,94 @@ async fn motor_command_server(
 
     loop {
         let _ = h
-            .serve(|cmd: &MotorCommand| {
-                let cmd_clone = cmd.clone();
+            .serve(|req: &MotorRequest| {
+                let cmd_clone = req.cmd.clone();
+                let trace = motor::Trace(req.trace);
                 let sender_clone = motor_cmd_sender.clone();
                 async move {
-                    // Send command to motor task
-                    let _ = sender_clone.try_send(cmd_clone);
-                    // Return current motor status
-                    motor::get_motor_status()
+                    // Send command to motor task and wait for it to be admitted
+                    motor::COMMAND_RESULT.reset();
+                    if motor::is_safety_command(&cmd_clone) {
+                        // Bypass the queue so a stop never waits behind normal commands;
+                        // the trace id does not travel with it, so it is logged here
+                        let name = MotorCommand::name_of(cmd_clone.id());
+                        log::info!("Motor command{}: {} (safety path)", trace, name);
+                        motor::request_safety_stop(&cmd_clone);
+                    } else {
+                        let cmd_id = cmd_clone.id();
+                        if sender_clone
+                            .try_send(ControlMsg::Command(cmd_clone, trace))
+                            .is_err()
+                        {
+                            log::warn!("Motor command{} dropped: control queue full", trace);
+                            motor::log_command(CommandLog {
+                                cmd_id,
+                                accepted: false,
+                                reason: RejectReason::RateLimited,
+                            });
+                        }
+                    }
+                    let verdict =
+                        with_timeout(Duration::from_secs(1), motor::COMMAND_RESULT.wait()).await;
+                    // Return current motor status, with the reason if refused
+                    MotorStatus {
+                        rejected: verdict.ok().and_then(Result::err),
+                        ..motor::get_motor_status()
+                    }
                 }
             })
             .await;
     }
 }
 
+/// Profile server - save/load/list motor presets in flash
+#[embassy_executor::task]
+async fn profile_server(control: ControlSender) {
+    let server = STACK
+        .endpoints()
+        .bounded_server::<ProfileEndpoint, 2>(Some("profile"));
+    let server = pin!(server);
+    let mut h = server.attach();
+
+    loop {
+        let _ = h
+            .serve(|cmd: &ProfileCommand| {
+                let cmd = cmd.clone();
+                async move { motor::profile::handle(&cmd, &control).await }
+            })
+            .await;
+    }
+}
+
+/// Self-test server - runs the direction check in the motor task and returns its report
+#[embassy_executor::task]
+async fn self_test_server(control: ControlSender) {
+    let server = STACK
+        .endpoints()
+        .bounded_server::<SelfTestEndpoint, 1>(Some("self_test"));
+    let server = pin!(server);
+    let mut h = server.attach();
+
+    loop {
+        let _ = h
+            .serve(|req: &SelfTestRequest| {
+                let req = req.clone();
+                async move {
+                    motor::self_test::RESULT.reset();
+                    control.send(ControlMsg::SelfTest(req)).await;
+                    match with_timeout(Duration::from_secs(10), motor::self_test::RESULT.wait())
+                        .await
+                    {
+                        Ok(report) => report,
+                        Err(_) => oxifoc_protocol::SelfTestReport {
+                            result: SelfTestResult::Aborted,
+                            observed: None,
+                            transitions: 0,
+                            phase_map: oxifoc_protocol::PhaseMap::IDENTITY,
+                        },
+                    }
+                }
+            })
+            .await;
+    }
+}
@@ -0,0 +1,99 @@
+//! Open-loop acceleration: per-step dwell schedule of the running sequencer
+//!
+//! Jumping straight to a shorter step period asks the rotor to catch up in a
+//! single step, which is where open-loop drive loses sync. Instead the
+//! controller keeps the dwell it is actually commutating with and moves it
+//! one step at a time towards the target period, raising the step rate
+//! linearly at `AccelConfig::steps_per_s2`.
+//! Slowing down needs no schedule: a longer dwell takes effect at once.
+
+/// Acceleration tunables
+#[derive(Clone, Copy)]
+pub struct AccelConfig {
+    /// Rise of the step rate while accelerating, in steps per second per second
+    pub steps_per_s2: u16,
+}
+
+impl Default for AccelConfig {
+    fn default() -> Self {
+        Self { steps_per_s2: 100 }
+    }
+}
+
+/// Dwell the sequencer is currently commutating with
+pub struct DwellRamp {
+    dwell_us: u32,
+}
+
+impl DwellRamp {
+    pub fn new(dwell_us: u32) -> Self {
+        Self {
+            dwell_us: dwell_us.max(1),
+        }
+    }
+
+    /// Current dwell, in microseconds
+    pub fn dwell_us(&self) -> u32 {
+        self.dwell_us
+    }
+
+    /// Restart from `dwell_us` (e.g. the last period of the startup ramp)
+    pub fn reset(&mut self, dwell_us: u32) {
+        self.dwell_us = dwell_us.max(1);
+    }
+
+    /// Dwell for the next step, moving one step from the current dwell towards `target_us`
+    pub fn advance(&mut self, target_us: u32, steps_per_s2: u16) -> u32 {
+        let target_us = target_us.max(1);
+        if target_us >= self.dwell_us {
+            self.dwell_us = target_us;
+            return self.dwell_us;
+        }
+        let dwell = self.dwell_us as u64;
+        // Rate in millisteps/s, plus what `steps_per_s2` adds over one dwell
+        let rate = 1_000_000_000 / dwell;
+        let gain = steps_per_s2 as u64 * dwell / 1000;
+        let next = 1_000_000_000 / (rate + gain).max(1);
+        // Always make progress, never overshoot
+        let next = (next as u32).min(self.dwell_us - 1);
+        self.dwell_us = next.max(target_us);
+        self.dwell_us
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Steps taken to go from `from_us` to `to_us`
+    fn steps_to(from_us: u32, to_us: u32, steps_per_s2: u16) -> u32 {
+        let mut ramp = DwellRamp::new(from_us);
+        let mut steps = 0;
+        let mut last = from_us;
+        while ramp.dwell_us() != to_us {
+            let dwell = ramp.advance(to_us, steps_per_s2);
+            assert!(dwell < last && dwell >= to_us);
+            last = dwell;
+            steps += 1;
+        }
+        steps
+    }
+
+    #[test]
+    fn test_linear_rate_rises_by_rate_times_dwell() {
+        let mut ramp = DwellRamp::new(100_000);
+        // 10 steps/s plus 100 steps/s² over 0.1 s = 20 steps/s
+        assert_eq!(ramp.advance(10_000, 100), 50_000);
+        // 20 + 100 * 0.05 = 25 steps/s
+        assert_eq!(ramp.advance(10_000, 100), 40_000);
+        assert!(steps_to(100_000, 10_000, 100) > 5);
+    }
+
+    #[test]
+    fn test_slowing_down_jumps_to_target() {
+        let mut ramp = DwellRamp::new(10_000);
+        assert_eq!(ramp.advance(40_000, 100), 40_000);
+        // A rate too small to register still moves the dwell
+        assert_eq!(ramp.advance(1_000, 0), 39_999);
+    }
+}
@@ -0,0 +1,272 @@
+//! ADC health check and the degraded mode it falls back to
+//!
+//! Protection, the align current regulation, the phase voltage limit and the
+//! fan all act on ADC readings. A converter or shunt op-amp that failed to
+//! come up, or a broken divider or NTC, should cost those features rather
+//! than open-loop control, so the control task checks every set of readings
+//! and the users of an untrusted signal fall back to fixed limits instead of
+//! acting on garbage. A reading is implausible when:
+//! - current: the winding current is past `current_max_ma`, near the
+//!   converter's rails; calibration also fails when a zero-current offset is
+//!   more than `offset_tolerance` from mid-rail (op-amp off, ADC stuck)
+//! - bus voltage: above `vbus_max_mv`, more than the board takes (a low one
+//!   is a bench without a supply, not a fault)
+//! - temperature: outside `temp_min_dc..=temp_max_dc`, which the NTC only
+//!   leaves when it is open or shorted
+//!
+//! A signal becomes untrusted after `fault_after` implausible readings in a
+//! row and trusted again after `clear_after` plausible ones; a failed
+//! calibration leaves the current untrusted until one succeeds. While any
+//! signal is, telemetry and the status report `adc_fault`, and:
+//! - protection skips the limits that depend on it and clamps the duty to
+//!   `limp_duty` (`Fault::Adc`); the break input, if armed, still guards the
+//!   bridge
+//! - without current, the align holds `align_duty` instead of regulating,
+//!   regen braking coasts instead, and R/L measurement and cogging scans are
+//!   refused
+//! - without bus voltage, the phase voltage limit assumes `assumed_vbus_mv`
+//! - without temperature, the fan runs at its full duty
+
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use super::protection::Readings;
+use crate::log;
+
+/// ADC health tunables
+#[derive(Clone, Copy)]
+pub struct AdcHealthConfig {
+    /// Largest distance of a zero-current offset from mid-rail (2048), in counts
+    pub offset_tolerance: u16,
+    /// Winding current past which the shunt reading counts as railed, in mA
+    pub current_max_ma: u16,
+    /// Bus voltage above which the reading counts as railed, in mV
+    pub vbus_max_mv: u16,
+    /// Plausible board temperature range, in 0.1 °C
+    pub temp_min_dc: i16,
+    pub temp_max_dc: i16,
+    /// Implausible readings in a row before a signal is untrusted
+    pub fault_after: u8,
+    /// Plausible readings in a row before it is trusted again
+    pub clear_after: u16,
+    /// Bus voltage the phase voltage limit assumes without a trusted reading, in mV
+    pub assumed_vbus_mv: u16,
+    /// Fixed align duty without a trusted current, in percent
+    pub align_duty: u8,
+}
+
+impl Default for AdcHealthConfig {
+    fn default() -> Self {
+        Self {
+            offset_tolerance: 300,
+            current_max_ma: 45_000, // the rails are ±60 A from mid-rail
+            vbus_max_mv: 30_000,    // full scale is 34.3 V
+            temp_min_dc: -200,      // the rails read -21 °C and 124 °C
+            temp_max_dc: 1200,
+            fault_after: 3,
+            clear_after: 100,
+            assumed_vbus_mv: 25_000, // the board's rated maximum, the lowest duty ceiling
+            align_duty: 4,
+        }
+    }
+}
+
+/// Set of analog signals
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Signals(u8);
+
+impl Signals {
+    pub const NONE: Self = Self(0);
+    pub const CURRENT: Self = Self(1 << 0);
+    pub const VBUS: Self = Self(1 << 1);
+    pub const TEMP: Self = Self(1 << 2);
+
+    /// Whether any signal of `signal` is in this set
+    pub fn contains(self, signal: Self) -> bool {
+        self.0 & signal.0 != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// This set with `signal` added if `on`, removed otherwise
+    pub fn with(self, signal: Self, on: bool) -> Self {
+        if on {
+            Self(self.0 | signal.0)
+        } else {
+            Self(self.0 & !signal.0)
+        }
+    }
+}
+
+impl defmt::Format for Signals {
+    fn format(&self, f: defmt::Formatter) {
+        let names = [
+            (Self::CURRENT, "current"),
+            (Self::VBUS, "vbus"),
+            (Self::TEMP, "temp"),
+        ];
+        let mut first = true;
+        for (signal, name) in names {
+            if self.contains(signal) {
+                defmt::write!(f, "{=str}{=str}", if first { "" } else { "," }, name);
+                first = false;
+            }
+        }
+        if first {
+            defmt::write!(f, "none");
+        }
+    }
+}
+
+/// Signals untrusted after the last check
+static UNTRUSTED: AtomicU8 = AtomicU8::new(0);
+/// The last current calibration found an offset far from mid-rail
+static OFFSETS_BAD: AtomicBool = AtomicBool::new(false);
+
+/// Signals the fallbacks are in use for
+pub fn untrusted() -> Signals {
+    Signals(UNTRUSTED.load(Ordering::Relaxed))
+        .with(Signals::CURRENT, OFFSETS_BAD.load(Ordering::Relaxed))
+}
+
+/// Whether `signal` can be acted on
+pub fn trusted(signal: Signals) -> bool {
+    !untrusted().contains(signal)
+}
+
+/// Any signal untrusted, as reported in telemetry and the status
+pub fn adc_fault() -> bool {
+    !untrusted().is_empty()
+}
+
+/// Check the zero-current offsets of a calibration; returns whether they are plausible
+pub fn check_offsets(offset: [i16; 3], cfg: &AdcHealthConfig) -> bool {
+    let ok = offset
+        .iter()
+        .all(|o| (*o as i32 - 2048).unsigned_abs() <= cfg.offset_tolerance as u32);
+    if !ok {
+        log::error!(
+            "ADC: current offsets {} far from mid-rail; current sensing untrusted",
+            offset
+        );
+    } else if OFFSETS_BAD.load(Ordering::Relaxed) {
+        log::info!("ADC: current offsets plausible again");
+    }
+    OFFSETS_BAD.store(!ok, Ordering::Relaxed);
+    ok
+}
+
+/// Run of plausible or implausible readings of one signal
+#[derive(Default)]
+struct Debounce {
+    bad: u8,
+    good: u16,
+    untrusted: bool,
+}
+
+impl Debounce {
+    fn update(&mut self, plausible: bool, cfg: &AdcHealthConfig) -> bool {
+        if plausible {
+            self.bad = 0;
+            self.good = self.good.saturating_add(1);
+            if self.good >= cfg.clear_after {
+                self.untrusted = false;
+            }
+        } else {
+            self.good = 0;
+            self.bad = self.bad.saturating_add(1);
+            if self.bad >= cfg.fault_after {
+                self.untrusted = true;
+            }
+        }
+        self.untrusted
+    }
+}
+
+/// Plausibility of each signal, kept between readings
+#[derive(Default)]
+pub struct AdcHealth {
+    current: Debounce,
+    vbus: Debounce,
+    temp: Debounce,
+}
+
+impl AdcHealth {
+    /// Fold in one set of readings; returns the signals untrusted from now on
+    pub fn update(&mut self, r: &Readings, cfg: &AdcHealthConfig) -> Signals {
+        let temp_ok = (cfg.temp_min_dc..=cfg.temp_max_dc).contains(&r.temp_dc);
+        Signals::NONE
+            .with(
+                Signals::CURRENT,
+                self.current.update(r.current_ma <= cfg.current_max_ma, cfg),
+            )
+            .with(
+                Signals::VBUS,
+                self.vbus.update(r.vbus_mv <= cfg.vbus_max_mv, cfg),
+            )
+            .with(Signals::TEMP, self.temp.update(temp_ok, cfg))
+    }
+}
+
+/// Check the latest readings and publish the result; call from the control task
+pub fn record(health: &mut AdcHealth, r: &Readings, cfg: &AdcHealthConfig) {
+    let now = health.update(r, cfg);
+    let before = Signals(UNTRUSTED.swap(now.0, Ordering::Relaxed));
+    if now == before {
+        return;
+    }
+    if now.is_empty() {
+        log::info!("ADC: readings plausible again");
+    } else {
+        log::error!("ADC: implausible {} readings; using fixed limits", now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NORMAL: Readings = Readings {
+        current_ma: 1000,
+        temp_dc: 300,
+        vbus_mv: 12_000,
+        untrusted: Signals::NONE,
+    };
+
+    #[test]
+    fn test_a_railed_signal_is_untrusted_after_a_run_and_trusted_after_a_longer_one() {
+        let cfg = AdcHealthConfig::default();
+        let mut health = AdcHealth::default();
+        // NTC open: reads at the top rail; a bench without a supply is fine
+        let open_ntc = Readings {
+            temp_dc: 1239,
+            vbus_mv: 0,
+            ..NORMAL
+        };
+        assert_eq!(health.update(&open_ntc, &cfg), Signals::NONE);
+        assert_eq!(health.update(&NORMAL, &cfg), Signals::NONE);
+        for _ in 0..2 {
+            assert_eq!(health.update(&open_ntc, &cfg), Signals::NONE);
+        }
+        assert_eq!(health.update(&open_ntc, &cfg), Signals::TEMP);
+
+        let railed = Readings {
+            current_ma: 60_000,
+            vbus_mv: 34_300,
+            ..open_ntc
+        };
+        for _ in 0..3 {
+            health.update(&railed, &cfg);
+        }
+        let all = Signals::CURRENT
+            .with(Signals::VBUS, true)
+            .with(Signals::TEMP, true);
+        assert_eq!(health.update(&railed, &cfg), all);
+
+        for _ in 0..99 {
+            assert_eq!(health.update(&NORMAL, &cfg), all);
+        }
+        assert_eq!(health.update(&NORMAL, &cfg), Signals::NONE);
+    }
+}
@@ -0,0 +1,63 @@
+//! Inactivity auto-disarm: an armed board left idle returns to disarmed
+//!
+//! Arming opens the window in which test and diagnostic motion is accepted.
+//! Left open on an idle bench, a stray command can still spin the motor, so
+//! the control task disarms once the motor has been stopped and no command
+//! has arrived for `timeout_ms`.
+
+/// Auto-disarm tunables
+#[derive(Clone, Copy)]
+pub struct AutoDisarmConfig {
+    /// Idle time after which an armed motor is disarmed, in ms (0 disables)
+    pub timeout_ms: u32,
+}
+
+impl Default for AutoDisarmConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 5 * 60_000,
+        }
+    }
+}
+
+/// Time of the last motor activity, kept by the control task
+#[derive(Default)]
+pub struct IdleTimer {
+    last_activity_ms: u32,
+}
+
+impl IdleTimer {
+    /// Record activity at `now_ms`: a command, or the motor not being stopped
+    pub fn activity(&mut self, now_ms: u32) {
+        self.last_activity_ms = now_ms;
+    }
+
+    /// Time since the last activity at `now_ms` (wrapping ms counter)
+    pub fn idle_ms(&self, now_ms: u32) -> u32 {
+        now_ms.wrapping_sub(self.last_activity_ms)
+    }
+
+    /// Whether an `armed` motor has been idle long enough to disarm at `now_ms`
+    pub fn expired(&self, now_ms: u32, armed: bool, cfg: &AutoDisarmConfig) -> bool {
+        armed && cfg.timeout_ms != 0 && self.idle_ms(now_ms) >= cfg.timeout_ms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disarms_only_armed_after_idle_timeout() {
+        let cfg = AutoDisarmConfig { timeout_ms: 1000 };
+        let mut idle = IdleTimer::default();
+        idle.activity(5000);
+        assert!(!idle.expired(5999, true, &cfg));
+        assert!(idle.expired(6000, true, &cfg));
+        assert!(!idle.expired(6000, false, &cfg));
+        assert!(!idle.expired(u32::MAX, true, &AutoDisarmConfig { timeout_ms: 0 }));
+        // Activity just before the ms counter wraps
+        idle.activity(u32::MAX - 499);
+        assert_eq!(idle.idle_ms(500), 1000);
+    }
+}
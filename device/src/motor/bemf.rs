@@ -0,0 +1,122 @@
+//! Floating-phase voltage capture for `MotorCommand::CaptureBemf`
+//!
+//! While a capture is armed and the motor is running, the control task samples
+//! the phase left floating by the applied step every `SAMPLE_INTERVAL` during
+//! its commutation wait, instead of sleeping through it (schemes without a
+//! floating phase capture nothing). Once the requested number of samples is
+//! stored, `DONE` fires and `bemf_stream_task` in `main` sends the buffer to
+//! the host as `BemfChunk`s. Arming a new capture discards the previous one,
+//! even if it is still being sent.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use oxifoc_protocol::{
+    BEMF_CHUNK_SAMPLES, BEMF_MAX_SAMPLES, BemfChunk, BemfSample, MotorState, ScanScheme,
+};
+
+use super::current::CurrentSense;
+use super::six_step::CommutationStep;
+use super::{get_motor_state, get_motor_step, get_phase_map};
+
+/// Time between samples within a commutation step
+const SAMPLE_INTERVAL: Duration = Duration::from_micros(100);
+
+struct Capture {
+    requested: u16,
+    started: Option<Instant>,
+    samples: heapless::Vec<BemfSample, { BEMF_MAX_SAMPLES as usize }>,
+}
+
+static CAPTURE: Mutex<CriticalSectionRawMutex, RefCell<Capture>> =
+    Mutex::new(RefCell::new(Capture {
+        requested: 0,
+        started: None,
+        samples: heapless::Vec::new(),
+    }));
+
+/// Fires when a capture is full and ready to send
+pub static DONE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Start a capture of `samples` readings (clamped to `BEMF_MAX_SAMPLES`)
+pub fn arm(samples: u16) {
+    CAPTURE.lock(|c| {
+        let mut c = c.borrow_mut();
+        c.requested = samples.min(BEMF_MAX_SAMPLES);
+        c.started = None;
+        c.samples.clear();
+    });
+}
+
+/// Whether an armed capture still needs samples
+fn wants_samples() -> bool {
+    CAPTURE.lock(|c| {
+        let c = c.borrow();
+        c.samples.len() < c.requested as usize
+    })
+}
+
+/// Store one reading; returns false once the capture is full
+fn record(sample: impl FnOnce(u32) -> BemfSample) -> bool {
+    let full = CAPTURE.lock(|c| {
+        let mut c = c.borrow_mut();
+        let now = Instant::now();
+        let started = *c.started.get_or_insert(now);
+        let _ = c.samples.push(sample((now - started).as_micros() as u32));
+        c.samples.len() >= c.requested as usize
+    });
+    if full {
+        DONE.signal(());
+    }
+    !full
+}
+
+/// Wait out one commutation period, sampling the floating phase if a capture is armed
+pub async fn wait_step(current: &mut CurrentSense<'_>, scheme: ScanScheme, period: Duration) {
+    let deadline = Instant::now() + period;
+    let step = CommutationStep::from_u8(get_motor_step());
+    let floating = step.phase_state(scheme).floating_phase();
+    if let Some(phase) = floating
+        && get_motor_state() == MotorState::Running
+        && wants_samples()
+    {
+        // The ADC pins follow the board phases, so go through the phase map
+        let board_phase = get_phase_map().channels[phase] as usize;
+        while Instant::now() + SAMPLE_INTERVAL < deadline {
+            let mv = current.phase_voltage_mv(board_phase).min(u16::MAX as u32) as u16;
+            let more = record(|t_us| BemfSample {
+                t_us,
+                step: step.as_u8(),
+                phase: phase as u8,
+                mv,
+            });
+            if !more {
+                break;
+            }
+            Timer::after(SAMPLE_INTERVAL).await;
+        }
+    }
+    Timer::at(deadline).await;
+}
+
+/// Samples captured so far
+pub fn len() -> u16 {
+    CAPTURE.lock(|c| c.borrow().samples.len() as u16)
+}
+
+/// Up to `BEMF_CHUNK_SAMPLES` samples starting at `offset`
+pub fn chunk(offset: u16) -> BemfChunk {
+    CAPTURE.lock(|c| {
+        let c = c.borrow();
+        let start = (offset as usize).min(c.samples.len());
+        let end = (start + BEMF_CHUNK_SAMPLES).min(c.samples.len());
+        BemfChunk {
+            offset,
+            total: c.samples.len() as u16,
+            samples: heapless::Vec::from_slice(&c.samples[start..end]).unwrap_or_default(),
+        }
+    })
+}
@@ -0,0 +1,75 @@
+//! Post-commutation blanking window
+//!
+//! Switching transients after a commutation corrupt BEMF and current readings
+//! for a few microseconds. Every phase-pattern change marks the window start;
+//! samplers check `in_blanking()` (or await `wait_clear()`) and discard readings
+//! taken inside it.
+//!
+//! The embassy tick (32.768 kHz) is far too coarse for µs windows, so the window
+//! is timed with the DWT cycle counter at SYSCLK (170 MHz).
+
+use core::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+
+use cortex_m::peripheral::{DCB, DWT};
+
+use crate::log;
+
+/// SYSCLK cycles per microsecond (see clock setup in `main`)
+pub const CYCLES_PER_US: u32 = 170;
+
+/// Sensing configuration
+#[derive(Clone, Copy)]
+pub struct SenseConfig {
+    /// Suppression window after each commutation, in microseconds
+    pub blanking_us: u16,
+}
+
+impl Default for SenseConfig {
+    fn default() -> Self {
+        Self { blanking_us: 5 }
+    }
+}
+
+static BLANKING_US: AtomicU16 = AtomicU16::new(5);
+static BLANKING_CYCLES: AtomicU32 = AtomicU32::new(5 * CYCLES_PER_US);
+static LAST_SWITCH: AtomicU32 = AtomicU32::new(0);
+
+/// Enable the cycle counter and apply the sensing config
+pub fn init(dcb: &mut DCB, dwt: &mut DWT, config: &SenseConfig) {
+    dcb.enable_trace();
+    dwt.enable_cycle_counter();
+    set_blanking_us(config.blanking_us);
+    log::info!(
+        "Sense blanking: {}us after each commutation",
+        config.blanking_us
+    );
+}
+
+/// Set the blanking window length
+pub fn set_blanking_us(us: u16) {
+    BLANKING_US.store(us, Ordering::Relaxed);
+    BLANKING_CYCLES.store(us as u32 * CYCLES_PER_US, Ordering::Relaxed);
+}
+
+/// Current blanking window length
+pub fn blanking_us() -> u16 {
+    BLANKING_US.load(Ordering::Relaxed)
+}
+
+/// Mark a phase-pattern change; starts a new blanking window
+pub fn mark_commutation() {
+    LAST_SWITCH.store(DWT::cycle_count(), Ordering::Release);
+}
+
+/// True while inside the blanking window of the most recent commutation
+pub fn in_blanking() -> bool {
+    let elapsed = DWT::cycle_count().wrapping_sub(LAST_SWITCH.load(Ordering::Acquire));
+    elapsed < BLANKING_CYCLES.load(Ordering::Relaxed)
+}
+
+/// Yield until the current blanking window has passed
+pub async fn wait_clear() {
+    while in_blanking() {
+        embassy_futures::yield_now().await;
+    }
+}
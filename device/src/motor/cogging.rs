@@ -0,0 +1,157 @@
+//! Cogging-torque scan (`MotorCommand::CoggingScan`)
+//!
+//! The rotor is walked through one mechanical revolution one commutation
+//! step at a time, `6 × pole_pairs` positions, each held at the scan current
+//! for `settle_ms`. The duty is integrated so the filtered current sits at
+//! the target, carried over from one position to the next like the align
+//! current at startup, so every position is held with the same torque. At
+//! the end of each hold the device sends a `CoggingSample` with the duty it
+//! took, the current it reached and the hall state, if sensors are fitted.
+//! Cogging shows as positions where the rotor settles off the step's field
+//! axis: with halls, as sectors out of step with the held step, and in the
+//! current-to-duty relationship along the way. The outputs are off afterwards.
+//!
+//! Like the R/L measurement it needs the motor armed and starts from a
+//! standstill; the duty is capped by `CoggingConfig::max_duty_permille` and
+//! the PWM limits, and any control message or a current past the abort level
+//! ends the scan.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Timer};
+use oxifoc_protocol::{CoggingSample, MotorCommand};
+
+use super::current::{CurrentIntegrator, CurrentSense};
+use super::hall::HallSensors;
+use super::six_step::CommutationStep;
+use super::{ControlMsg, ControlReceiver, MotorController, Trace, blanking, poll_control};
+use crate::log;
+
+/// Scan tunables beyond what the command sets
+#[derive(Clone, Copy)]
+pub struct CoggingConfig {
+    /// Highest duty the regulation may reach, in 0.1% steps (the PWM limits apply too)
+    pub max_duty_permille: u16,
+    /// End the scan once the filtered current passes this, in milliamps
+    pub abort_current_ma: u16,
+    /// The first position is held this many times `settle_ms`, so the rotor
+    /// has aligned and the regulation has wound up before the first sample
+    pub first_hold_factor: u8,
+}
+
+impl Default for CoggingConfig {
+    fn default() -> Self {
+        Self {
+            max_duty_permille: 150,
+            abort_current_ma: 4000,
+            first_hold_factor: 5,
+        }
+    }
+}
+
+/// What the `CoggingScan` command asks for
+#[derive(Clone, Copy)]
+pub struct ScanParams {
+    /// Current held at each position, in milliamps
+    pub current_ma: u16,
+    /// How long each position is held
+    pub settle_ms: u16,
+}
+
+/// Regulation update interval
+const TICK: Duration = Duration::from_millis(1);
+/// Integral gain: duty (0.1% steps) per mA of error per tick, as 1/`KI_DIV`
+const KI_DIV: i32 = 200;
+
+/// Samples waiting for the stream task; a full queue drops the newest
+static SAMPLES: Channel<CriticalSectionRawMutex, CoggingSample, 8> = Channel::new();
+
+/// Next sample to send to the host
+pub async fn next_sample() -> CoggingSample {
+    SAMPLES.receive().await
+}
+
+/// Scan one mechanical revolution, holding `scan.current_ma` for `scan.settle_ms` at each step
+///
+/// Any control message ends the scan and is returned so the caller can still
+/// act on it (e.g. a `Stop`).
+pub async fn run(
+    motor: &mut MotorController<'_>,
+    sense: &mut CurrentSense<'_>,
+    hall: &HallSensors<'_>,
+    scan: ScanParams,
+    cfg: &CoggingConfig,
+    trace: Trace,
+    control: &ControlReceiver,
+) -> Option<ControlMsg> {
+    if !motor.is_armed() {
+        log::warn!("Cogging scan{} rejected: not armed", trace);
+        return None;
+    }
+    let ScanParams {
+        current_ma,
+        settle_ms,
+    } = scan;
+    motor.handle_command(&MotorCommand::Stop, trace);
+    let total = 6 * motor.params().pole_pairs.max(1) as u16;
+    let max_permille = cfg.max_duty_permille.min(motor.duty_ceiling_permille());
+    log::info!(
+        "Cogging scan{}: {} positions at {}mA, {}ms each",
+        trace,
+        total,
+        current_ma,
+        settle_ms
+    );
+
+    let mut reg = CurrentIntegrator::new(KI_DIV, max_permille);
+    let mut step = CommutationStep::from_u8(0);
+    for index in 0..total {
+        let hold_ms = match index {
+            0 => settle_ms as u32 * cfg.first_hold_factor.max(1) as u32,
+            _ => settle_ms as u32,
+        };
+        let end = Instant::now() + Duration::from_millis(hold_ms as u64);
+        while Instant::now() < end {
+            motor.apply_step_permille(step, reg.duty_permille());
+            Timer::after(TICK).await;
+            if let Some(msg) = poll_control(control) {
+                motor.all_phases_off();
+                log::warn!(
+                    "Cogging scan{} aborted by incoming command at {}/{}",
+                    trace,
+                    index,
+                    total
+                );
+                return Some(msg);
+            }
+            blanking::wait_clear().await;
+            reg.update(sense.magnitude(), current_ma);
+            if reg.current_ma() > cfg.abort_current_ma as u32 {
+                motor.all_phases_off();
+                log::warn!(
+                    "Cogging scan{} aborted: {}mA at {}/{}",
+                    trace,
+                    reg.current_ma(),
+                    index,
+                    total
+                );
+                return None;
+            }
+        }
+        let sample = CoggingSample {
+            index,
+            total,
+            step: step as u8,
+            hall: hall.read(),
+            duty_permille: reg.duty_permille(),
+            current_ma: reg.current_ma().min(u16::MAX as u32) as u16,
+        };
+        if SAMPLES.try_send(sample).is_err() {
+            log::warn!("Cogging sample {} dropped: host not keeping up", index);
+        }
+        step = step.next();
+    }
+    motor.all_phases_off();
+    log::info!("Cogging scan{} done: {} positions", trace, total);
+    None
+}
@@ -0,0 +1,225 @@
+//! Commutation strategies behind a common trait
+//!
+//! A `Commutator` decides which step to energize on each sequencer tick; it
+//! drives the outputs only through `MotorContext`, which looks the step up in
+//! the selected scheme's table, so a strategy can be run against a mock
+//! `PhaseDriver` without the timer hardware. The controller
+//! holds a `Sequencer`, an enum over the available strategies (no allocator on
+//! the device, so no `Box<dyn>`).
+//!
+//! Both commutation modes tick the same `Sequencer`: `CommutationMode::External`
+//! only changes what the control task waits for between ticks (a trigger edge
+//! instead of the run period), so it is not a strategy of its own.
+//!
+//! The tests below run the strategies against a mock driver, but like every
+//! device test they are not run by `cargo test`: the firmware is a `no_std`
+//! binary with `test = false`, so they only document the expected sequences.
+
+use oxifoc_protocol::Direction;
+
+use super::six_step::{CommutationStep, PhaseState};
+
+/// Output stage as seen by a commutator
+pub trait PhaseDriver {
+    /// Drive the phases as `state` says, PWM phases at `duty_percent`
+    fn energize(&mut self, state: &PhaseState, duty_percent: u8);
+}
+
+/// Everything a commutator may use during one tick
+pub struct MotorContext<'a> {
+    driver: &'a mut dyn PhaseDriver,
+    table: &'static [PhaseState; 6],
+    duty: u8,
+    applied: Option<CommutationStep>,
+}
+
+impl<'a> MotorContext<'a> {
+    pub fn new(driver: &'a mut dyn PhaseDriver, table: &'static [PhaseState; 6], duty: u8) -> Self {
+        Self {
+            driver,
+            table,
+            duty,
+            applied: None,
+        }
+    }
+
+    /// Energize `step` at the commanded duty
+    pub fn energize(&mut self, step: CommutationStep) {
+        self.driver
+            .energize(&self.table[step.as_u8() as usize], self.duty);
+        self.applied = Some(step);
+    }
+
+    /// Step energized during this tick, if any
+    pub fn applied(&self) -> Option<CommutationStep> {
+        self.applied
+    }
+}
+
+/// A commutation strategy
+pub trait Commutator {
+    /// Advance one sequencer tick
+    fn step(&mut self, ctx: &mut MotorContext);
+    /// Restart so the next tick energizes `next`
+    fn reset(&mut self, next: CommutationStep);
+}
+
+/// Run one tick of `commutator` with step table `table`; returns the step it energized
+pub fn tick(
+    commutator: &mut impl Commutator,
+    driver: &mut dyn PhaseDriver,
+    table: &'static [PhaseState; 6],
+    duty: u8,
+) -> Option<CommutationStep> {
+    let mut ctx = MotorContext::new(driver, table, duty);
+    commutator.step(&mut ctx);
+    ctx.applied()
+}
+
+/// Fixed-rate 6-step sequence without feedback
+pub struct OpenLoop {
+    next: CommutationStep,
+    direction: Direction,
+}
+
+impl Default for OpenLoop {
+    fn default() -> Self {
+        Self {
+            next: CommutationStep::Step0,
+            direction: Direction::Forward,
+        }
+    }
+}
+
+impl Commutator for OpenLoop {
+    fn step(&mut self, ctx: &mut MotorContext) {
+        ctx.energize(self.next);
+        self.next = match self.direction {
+            Direction::Forward => self.next.next(),
+            Direction::Reverse => self.next.prev(),
+        };
+    }
+
+    fn reset(&mut self, next: CommutationStep) {
+        self.next = next;
+    }
+}
+
+/// Strategy selected for the running sequencer
+pub enum Sequencer {
+    OpenLoop(OpenLoop),
+}
+
+impl Default for Sequencer {
+    fn default() -> Self {
+        Self::OpenLoop(OpenLoop::default())
+    }
+}
+
+impl Sequencer {
+    /// Way the sequence runs through the steps from the next tick on
+    pub fn set_direction(&mut self, direction: Direction) {
+        match self {
+            Self::OpenLoop(c) => c.direction = direction,
+        }
+    }
+}
+
+impl Commutator for Sequencer {
+    fn step(&mut self, ctx: &mut MotorContext) {
+        match self {
+            Self::OpenLoop(c) => c.step(ctx),
+        }
+    }
+
+    fn reset(&mut self, next: CommutationStep) {
+        match self {
+            Self::OpenLoop(c) => c.reset(next),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::motor::six_step::table;
+    use oxifoc_protocol::ScanScheme;
+
+    const TABLE: &[PhaseState; 6] = table(ScanScheme::Conduction120);
+
+    /// Records every output change
+    #[derive(Default)]
+    struct MockDriver {
+        log: heapless::Vec<(PhaseState, u8), 16>,
+    }
+
+    impl PhaseDriver for MockDriver {
+        fn energize(&mut self, state: &PhaseState, duty_percent: u8) {
+            let _ = self.log.push((*state, duty_percent));
+        }
+    }
+
+    /// Energizes a fixed step every other tick, leaving the outputs alone in between
+    struct Blink {
+        on: bool,
+    }
+
+    impl Commutator for Blink {
+        fn step(&mut self, ctx: &mut MotorContext) {
+            if self.on {
+                ctx.energize(CommutationStep::Step3);
+            }
+            self.on = !self.on;
+        }
+
+        fn reset(&mut self, _next: CommutationStep) {
+            self.on = true;
+        }
+    }
+
+    #[test]
+    fn test_fake_commutator_drives_outputs() {
+        let mut driver = MockDriver::default();
+        let mut fake = Blink { on: false };
+        fake.reset(CommutationStep::Step0);
+        assert_eq!(
+            tick(&mut fake, &mut driver, TABLE, 7),
+            Some(CommutationStep::Step3)
+        );
+        assert_eq!(tick(&mut fake, &mut driver, TABLE, 7), None);
+        assert_eq!(driver.log.as_slice(), &[(TABLE[3], 7)]);
+    }
+
+    #[test]
+    fn test_open_loop_follows_six_step_sequence() {
+        let mut driver = MockDriver::default();
+        let mut seq = Sequencer::default();
+        seq.reset(CommutationStep::Step4);
+        for expected in [4, 5, 0, 1] {
+            let applied = tick(&mut seq, &mut driver, TABLE, 10).map(CommutationStep::as_u8);
+            assert_eq!(applied, Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_open_loop_runs_backwards_when_reversed() {
+        let mut driver = MockDriver::default();
+        let mut seq = Sequencer::default();
+        seq.reset(CommutationStep::Step1);
+        seq.set_direction(Direction::Reverse);
+        for expected in [1, 0, 5, 4] {
+            let applied = tick(&mut seq, &mut driver, TABLE, 10).map(CommutationStep::as_u8);
+            assert_eq!(applied, Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_context_energizes_selected_table() {
+        let table_180 = table(ScanScheme::Conduction180);
+        let mut driver = MockDriver::default();
+        let mut seq = Sequencer::default();
+        seq.reset(CommutationStep::Step1);
+        tick(&mut seq, &mut driver, table_180, 10);
+        assert_eq!(driver.log.as_slice(), &[(table_180[1], 10)]);
+    }
+}
@@ -0,0 +1,529 @@
+//! Phase current and voltage, bus voltage and temperature sensing (B-G431B-ESC1)
+//!
+//! Each phase has a 3 mΩ low-side shunt amplified by an internal op-amp in
+//! standalone mode with the board's external gain network (×9.14, biased to
+//! mid-rail). The op-amp outputs are read back on their VOUT pins:
+//! - A: OPAMP1 VOUT = PA2 (ADC1_IN3)
+//! - B: OPAMP2 VOUT = PA6 (ADC2_IN3)
+//! - C: OPAMP3 VOUT = PB1 (ADC1_IN12)
+//!
+//! The same ADC1 also reads the bus voltage divider on PA0 (ADC1_IN1) and the
+//! on-board NTC on PB14 (ADC1_IN5). ADC2 reads the phase voltage dividers
+//! used for BEMF sensing, which PB5 (GPIO_BEMF) connects to the phases:
+//! - A: PA4 (ADC2_IN17)
+//! - B: PC4 (ADC2_IN5)
+//! - C: PB11 (ADC2_IN14)
+//!
+//! With `CurrentSampling::Free` the shunts are read whenever a reading is
+//! asked for, asynchronously to the PWM, so the result is only meaningful as
+//! a filtered magnitude (e.g. for regulating alignment current), not as an
+//! instantaneous phase current. `CurrentSampling::DualEdge` instead converts
+//! them as injected sequences triggered by TIM1's update event (TRGO), which
+//! in center-aligned mode comes at both the top and the bottom of every PWM
+//! period. A reading waits for the next two sequences, one at each edge, and
+//! averages them: the two sit half a period apart on either side of the
+//! ripple, so their mean is close to the period's average current, as FOC
+//! needs. ADC1 converts A then C, ADC2 converts B, both on the same trigger.
+//! If no sequence arrives (the timer stopped), a free-running read is used.
+//!
+//! `AdcConfig` sets the sample time of each signal group and the hardware
+//! oversampling ratio. The oversampler averages 2..256 conversions into one
+//! 12-bit result, which takes the shunt noise down by √ratio but makes every
+//! read that many conversions long. Injected conversions are not
+//! oversampled, so a dual-edge reading is two single conversions.
+//!
+//! Readings become milliamps through a `CurrentCalibration`: the zero-current
+//! offset of each shunt channel and a gain correction on top of the nominal
+//! ×9.14 / 3 mΩ scale. `calibrate` measures the offsets, at boot unless a
+//! stored calibration is restored, and on `MotorCommand::CalibrateCurrent`.
+
+use core::cell::Cell;
+
+use cortex_m::peripheral::DWT;
+use embassy_stm32::Peri;
+use embassy_stm32::adc::{Adc, AdcChannel, AnyAdcChannel, Instance, SampleTime};
+use embassy_stm32::gpio::{Level, Output, Speed};
+use embassy_stm32::pac::adc::vals::{Rovsm, Trovs};
+use embassy_stm32::peripherals::{ADC1, ADC2, PA0, PA2, PA4, PA6, PB1, PB5, PB11, PB14, PC4};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use oxifoc_protocol::CurrentCalibration;
+
+use super::blanking::CYCLES_PER_US;
+use crate::log;
+
+/// Output current conversion: 3.3 V / 4096 LSB / (9.14 × 3 mΩ) ≈ 29.38 mA per LSB
+const UA_PER_LSB: i32 = 29_380;
+/// Samples averaged for the zero-current offset
+const CALIBRATION_SAMPLES: i32 = 64;
+
+/// ADC full scale in millivolts (VDDA = VREF+ = 3.3 V)
+const ADC_FULL_SCALE_MV: u32 = 3300;
+/// Bus voltage divider 169 kΩ / 18 kΩ, as (R1 + R2) / R2 ×1000
+const VBUS_DIVIDER_X1000: u32 = 10_389;
+/// Phase dividers use the bus divider's ratio, so a phase at VBUS reads as VBUS
+const PHASE_DIVIDER_X1000: u32 = VBUS_DIVIDER_X1000;
+/// NTC output at 25 °C and its slope, from the board's linear approximation
+const NTC_V25_MV: i32 = 1055;
+const NTC_UV_PER_C: i32 = 22_700;
+
+/// ADC kernel clock: SYSCLK (170 MHz) through the driver's /4 prescaler
+const ADC_CLOCK_KHZ: u32 = 42_500;
+/// Successive-approximation time of a 12-bit conversion, in tenths of a cycle
+const CONVERSION_CYCLES_X10: u32 = 125;
+/// Largest oversampling ratio the hardware supports, as a power of two
+const MAX_OVERSAMPLING_LOG2: u8 = 8;
+
+/// When the shunt currents are converted
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum CurrentSampling {
+    /// On demand, asynchronously to the PWM
+    Free,
+    /// At the top and the bottom of each PWM period (TIM1 TRGO), averaged
+    DualEdge,
+}
+
+/// ADC tunables
+#[derive(Clone, Copy)]
+pub struct AdcConfig {
+    /// Sample time of the shunt op-amp outputs
+    pub current_sample_time: SampleTime,
+    /// Sample time of the bus voltage divider
+    pub vbus_sample_time: SampleTime,
+    /// Sample time of the NTC (high source impedance, needs a long one)
+    pub temp_sample_time: SampleTime,
+    /// Sample time of the phase voltage dividers
+    pub phase_sample_time: SampleTime,
+    /// Conversions averaged per reading, as a power of two (0 = off, max 8 = ×256)
+    pub oversampling_log2: u8,
+    /// When the shunt currents are converted
+    pub current_sampling: CurrentSampling,
+}
+
+impl Default for AdcConfig {
+    fn default() -> Self {
+        Self {
+            current_sample_time: SampleTime::CYCLES24_5,
+            vbus_sample_time: SampleTime::CYCLES47_5,
+            temp_sample_time: SampleTime::CYCLES247_5,
+            phase_sample_time: SampleTime::CYCLES24_5,
+            oversampling_log2: 2,
+            current_sampling: CurrentSampling::Free,
+        }
+    }
+}
+
+/// Sample time in tenths of an ADC clock cycle
+fn sample_cycles_x10(t: SampleTime) -> u32 {
+    match t {
+        SampleTime::CYCLES2_5 => 25,
+        SampleTime::CYCLES6_5 => 65,
+        SampleTime::CYCLES12_5 => 125,
+        SampleTime::CYCLES24_5 => 245,
+        SampleTime::CYCLES47_5 => 475,
+        SampleTime::CYCLES92_5 => 925,
+        SampleTime::CYCLES247_5 => 2475,
+        SampleTime::CYCLES640_5 => 6405,
+    }
+}
+
+/// Duration of one reading (all oversampled conversions), in nanoseconds
+fn reading_ns(t: SampleTime, oversampling_log2: u8) -> u32 {
+    let cycles_x10 = (sample_cycles_x10(t) + CONVERSION_CYCLES_X10) << oversampling_log2;
+    cycles_x10 * 100_000 / ADC_CLOCK_KHZ
+}
+
+/// Average `1 << log2` conversions into each 12-bit result (0 turns it off)
+fn set_oversampling<T: Instance>(adc: &mut Adc<'_, T>, log2: u8) {
+    if log2 == 0 {
+        adc.enable_regular_oversampling_mode(Rovsm::CONTINUED, Trovs::AUTOMATIC, false);
+        return;
+    }
+    // OVSR 0 = ×2 .. 7 = ×256; shifting by the same power keeps the 12-bit scale
+    adc.set_oversampling_ratio(log2 - 1);
+    adc.set_oversampling_shift(log2);
+    adc.enable_regular_oversampling_mode(Rovsm::CONTINUED, Trovs::AUTOMATIC, true);
+}
+
+/// OPAMP1..3 control/status registers (RM0440 §25.5)
+const OPAMP_CSR: [usize; 3] = [0x4001_0300, 0x4001_0304, 0x4001_0308];
+/// OPAEN | OPAHSM, VINP0 / VINM0 (external gain network), output on VOUT pin
+const OPAMP_CSR_STANDALONE: u32 = (1 << 0) | (1 << 7);
+
+/// ADC1/ADC2 register blocks and offsets (RM0440 §21.7)
+const ADC_BASE: [usize; 2] = [0x5000_0000, 0x5000_0100];
+const ADC_ISR: usize = 0x00;
+const ADC_CR: usize = 0x08;
+const ADC_JSQR: usize = 0x4C;
+const ADC_JDR1: usize = 0x80;
+/// ISR end of injected sequence (write 1 to clear)
+const ISR_JEOS: u32 = 1 << 6;
+/// CR start of injected conversions
+const CR_JADSTART: u32 = 1 << 3;
+/// JSQR trigger: JEXTSEL 0 = TIM1_TRGO, JEXTEN 01 = rising edge
+const JSQR_TIM1_TRGO: u32 = 0b01 << 7;
+/// Injected sequences: ADC1 converts A (IN3) then C (IN12), ADC2 converts B (IN3)
+const JSQR: [u32; 2] = [
+    JSQR_TIM1_TRGO | 1 | (3 << 9) | (12 << 15),
+    JSQR_TIM1_TRGO | (3 << 9),
+];
+/// Longest wait for one injected sequence before falling back to a free read
+const SEQUENCE_TIMEOUT_US: u32 = 1000;
+
+fn adc_reg(adc: usize, offset: usize) -> *mut u32 {
+    (ADC_BASE[adc] + offset) as *mut u32
+}
+
+/// Program and start the TIM1-triggered injected sequences of both ADCs
+///
+/// JSQR may only be written while no injected conversion is running, which
+/// holds right after `Adc::new`.
+fn start_injected() {
+    for (adc, jsqr) in JSQR.into_iter().enumerate() {
+        // SAFETY: the HAL driver only uses the regular group; nothing else touches JSQR
+        unsafe {
+            core::ptr::write_volatile(adc_reg(adc, ADC_JSQR), jsqr);
+            core::ptr::write_volatile(adc_reg(adc, ADC_ISR), ISR_JEOS);
+        }
+    }
+    rearm_injected();
+}
+
+/// Set JADSTART on any ADC where it is clear, e.g. after the driver disabled the ADC
+fn rearm_injected() {
+    for adc in 0..2 {
+        // SAFETY: only JADSTART changes; the start/stop bits act on a written 1,
+        // so writing back the ones that read as set repeats a request in progress
+        unsafe {
+            let cr = core::ptr::read_volatile(adc_reg(adc, ADC_CR));
+            if cr & CR_JADSTART == 0 {
+                core::ptr::write_volatile(adc_reg(adc, ADC_CR), cr | CR_JADSTART);
+            }
+        }
+    }
+}
+
+/// Wait for the next injected sequence; returns its A, B, C results
+///
+/// Only ADC1's end of sequence is waited for: ADC2 converts one channel on
+/// the same trigger, so by then it has finished too and its result is from
+/// the same edge.
+fn next_sequence() -> Option<[i32; 3]> {
+    // SAFETY: ISR is write-1-to-clear, so only JEOS is affected
+    unsafe { core::ptr::write_volatile(adc_reg(0, ADC_ISR), ISR_JEOS) };
+    let start = DWT::cycle_count();
+    // SAFETY: plain reads of status and data registers
+    while unsafe { core::ptr::read_volatile(adc_reg(0, ADC_ISR)) } & ISR_JEOS == 0 {
+        if DWT::cycle_count().wrapping_sub(start) > SEQUENCE_TIMEOUT_US * CYCLES_PER_US {
+            return None;
+        }
+    }
+    // SAFETY: as above
+    let jdr = |adc: usize, n: usize| unsafe {
+        core::ptr::read_volatile(adc_reg(adc, ADC_JDR1 + 4 * n)) as i32
+    };
+    Some([jdr(0, 0), jdr(1, 0), jdr(0, 1)])
+}
+
+/// Phase currents in milliamps (A, B, C)
+pub type PhaseCurrents = [i32; 3];
+
+/// Calibration in use, for the config endpoint
+static CALIBRATION: Mutex<CriticalSectionRawMutex, Cell<CurrentCalibration>> =
+    Mutex::new(Cell::new(CurrentCalibration {
+        offset: [2048; 3],
+        gain_num: 1,
+        gain_den: 1,
+    }));
+
+/// Calibration the current readings are converted with
+pub fn active_calibration() -> CurrentCalibration {
+    CALIBRATION.lock(|c| c.get())
+}
+
+/// Milliamps from a raw shunt reading with zero-current `offset`
+fn to_ma(raw: i32, offset: i16, cal: &CurrentCalibration) -> i32 {
+    let ma = (raw - offset as i32) * UA_PER_LSB / 1000;
+    (ma as i64 * cal.gain_num as i64 / cal.gain_den as i64) as i32
+}
+
+/// Peripherals and pins `CurrentSense` takes over
+pub struct SensePins<'d> {
+    pub adc1: Peri<'d, ADC1>,
+    pub adc2: Peri<'d, ADC2>,
+    /// Op-amp outputs of the phase A, B and C shunts
+    pub shunt_a: Peri<'d, PA2>,
+    pub shunt_b: Peri<'d, PA6>,
+    pub shunt_c: Peri<'d, PB1>,
+    /// Bus voltage divider
+    pub vbus: Peri<'d, PA0>,
+    /// Board NTC
+    pub temp: Peri<'d, PB14>,
+    /// Phase voltage dividers of phases A, B and C
+    pub phase_v_a: Peri<'d, PA4>,
+    pub phase_v_b: Peri<'d, PC4>,
+    pub phase_v_c: Peri<'d, PB11>,
+    /// Enable of the phase voltage dividers
+    pub bemf_enable: Peri<'d, PB5>,
+}
+
+pub struct CurrentSense<'d> {
+    adc1: Adc<'d, ADC1>,
+    adc2: Adc<'d, ADC2>,
+    ch_a: AnyAdcChannel<ADC1>,
+    ch_b: AnyAdcChannel<ADC2>,
+    ch_c: AnyAdcChannel<ADC1>,
+    ch_vbus: AnyAdcChannel<ADC1>,
+    ch_temp: AnyAdcChannel<ADC1>,
+    ch_phase_v: [AnyAdcChannel<ADC2>; 3],
+    _bemf_enable: Output<'d>,
+    cal: CurrentCalibration,
+    config: AdcConfig,
+    /// Dual-edge sampling fell back to free reads (warned once)
+    sync_lost: bool,
+}
+
+impl<'d> CurrentSense<'d> {
+    /// Enable the op-amps and ADCs
+    ///
+    /// The op-amp input pins (PA1/PA3, PA7/PA5, PB0/PB2) stay in their reset
+    /// analog mode. Call `calibrate` with all phases off, or `set_calibration`,
+    /// before use.
+    pub fn new(pins: SensePins<'d>, config: &AdcConfig) -> Self {
+        for csr in OPAMP_CSR {
+            // SAFETY: the op-amps are not owned by any other driver in this firmware
+            unsafe { core::ptr::write_volatile(csr as *mut u32, OPAMP_CSR_STANDALONE) };
+        }
+
+        let mut config = *config;
+        config.oversampling_log2 = config.oversampling_log2.min(MAX_OVERSAMPLING_LOG2);
+        let mut adc1 = Adc::new(pins.adc1);
+        let mut adc2 = Adc::new(pins.adc2);
+        set_oversampling(&mut adc1, config.oversampling_log2);
+        set_oversampling(&mut adc2, config.oversampling_log2);
+
+        let ratio = 1u32 << config.oversampling_log2;
+        log::info!(
+            "ADC: oversampling x{} (noise /{}), reading takes current {}ns, vbus {}ns, temp {}ns, phase {}ns",
+            ratio,
+            // √ratio, rounded down
+            1u32 << (config.oversampling_log2 / 2),
+            reading_ns(config.current_sample_time, config.oversampling_log2),
+            reading_ns(config.vbus_sample_time, config.oversampling_log2),
+            reading_ns(config.temp_sample_time, config.oversampling_log2),
+            reading_ns(config.phase_sample_time, config.oversampling_log2),
+        );
+
+        let mut sense = Self {
+            adc1,
+            adc2,
+            ch_a: pins.shunt_a.degrade_adc(),
+            ch_b: pins.shunt_b.degrade_adc(),
+            ch_c: pins.shunt_c.degrade_adc(),
+            ch_vbus: pins.vbus.degrade_adc(),
+            ch_temp: pins.temp.degrade_adc(),
+            ch_phase_v: [
+                pins.phase_v_a.degrade_adc(),
+                pins.phase_v_b.degrade_adc(),
+                pins.phase_v_c.degrade_adc(),
+            ],
+            _bemf_enable: Output::new(pins.bemf_enable, Level::High, Speed::Low),
+            cal: active_calibration(),
+            config,
+            sync_lost: false,
+        };
+        if config.current_sampling == CurrentSampling::DualEdge {
+            // One regular read of each shunt leaves its sample time in SMPR, which
+            // the injected conversions use too
+            sense.read_raw_free();
+            start_injected();
+            log::info!("ADC: shunt currents sampled at both PWM edges");
+        }
+        sense
+    }
+
+    fn read_raw(&mut self) -> [i32; 3] {
+        if self.config.current_sampling == CurrentSampling::DualEdge {
+            rearm_injected();
+            if let Some(top) = next_sequence()
+                && let Some(bottom) = next_sequence()
+            {
+                return core::array::from_fn(|i| (top[i] + bottom[i]) / 2);
+            }
+            if !self.sync_lost {
+                log::warn!("ADC: no TIM1-triggered current samples; reading free-running");
+                self.sync_lost = true;
+            }
+        }
+        self.read_raw_free()
+    }
+
+    fn read_raw_free(&mut self) -> [i32; 3] {
+        self.adc1.set_sample_time(self.config.current_sample_time);
+        self.adc2.set_sample_time(self.config.current_sample_time);
+        [
+            self.adc1.blocking_read(&mut self.ch_a) as i32,
+            self.adc2.blocking_read(&mut self.ch_b) as i32,
+            self.adc1.blocking_read(&mut self.ch_c) as i32,
+        ]
+    }
+
+    /// Measure the zero-current offsets, keeping the gain; all phases must be off
+    pub fn calibrate(&mut self) {
+        let mut sum = [0i32; 3];
+        for _ in 0..CALIBRATION_SAMPLES {
+            let raw = self.read_raw();
+            for (s, r) in sum.iter_mut().zip(raw) {
+                *s += r;
+            }
+        }
+        let offset = sum.map(|s| (s / CALIBRATION_SAMPLES) as i16);
+        self.set_calibration(CurrentCalibration { offset, ..self.cal });
+        log::info!("Current sense offsets: {}", offset);
+    }
+
+    /// Calibration the readings are converted with
+    pub fn calibration(&self) -> CurrentCalibration {
+        self.cal
+    }
+
+    /// Convert with `cal` from now on; a gain with a zero term falls back to the nominal one
+    pub fn set_calibration(&mut self, cal: CurrentCalibration) {
+        self.cal = match (cal.gain_num, cal.gain_den) {
+            (0, _) | (_, 0) => CurrentCalibration {
+                gain_num: 1,
+                gain_den: 1,
+                ..cal
+            },
+            _ => cal,
+        };
+        CALIBRATION.lock(|c| c.set(self.cal));
+    }
+
+    /// Current in each phase, in milliamps
+    pub fn read(&mut self) -> PhaseCurrents {
+        let raw = self.read_raw();
+        core::array::from_fn(|i| to_ma(raw[i], self.cal.offset[i], &self.cal))
+    }
+
+    /// Largest absolute phase current, in milliamps
+    ///
+    /// With two phases conducting this is the winding current regardless of
+    /// which pair is energized or how the phase map is set.
+    pub fn magnitude(&mut self) -> u32 {
+        self.read()
+            .iter()
+            .map(|i| i.unsigned_abs())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Bus voltage in millivolts
+    pub fn vbus_mv(&mut self) -> u32 {
+        self.adc1.set_sample_time(self.config.vbus_sample_time);
+        let raw = self.adc1.blocking_read(&mut self.ch_vbus) as u32;
+        raw * ADC_FULL_SCALE_MV / 4096 * VBUS_DIVIDER_X1000 / 1000
+    }
+
+    /// Board temperature in 0.1 °C
+    pub fn temperature_dc(&mut self) -> i32 {
+        self.adc1.set_sample_time(self.config.temp_sample_time);
+        let raw = self.adc1.blocking_read(&mut self.ch_temp) as u32;
+        let mv = (raw * ADC_FULL_SCALE_MV / 4096) as i32;
+        250 + (mv - NTC_V25_MV) * 10_000 / NTC_UV_PER_C
+    }
+
+    /// Voltage of board phase `phase` (TIM1 channel order) to ground, in millivolts
+    ///
+    /// Only meaningful for the floating phase; a driven phase reads the PWM.
+    pub fn phase_voltage_mv(&mut self, phase: usize) -> u32 {
+        self.adc2.set_sample_time(self.config.phase_sample_time);
+        let raw = self.adc2.blocking_read(&mut self.ch_phase_v[phase]) as u32;
+        raw * ADC_FULL_SCALE_MV / 4096 * PHASE_DIVIDER_X1000 / 1000
+    }
+}
+
+/// Integrator from a filtered current to a duty, in 0.1% steps
+///
+/// Each sample is low-passed with weight 1/`FILTER_DIV`, and the error to the
+/// target moves the duty by one step per `ki_div` mA of error per tick. It
+/// stops integrating at `ceiling`, so an open winding does not wind it up.
+pub struct CurrentIntegrator {
+    ki_div: i32,
+    ceiling: u16,
+    acc: i32,
+    filtered: u32,
+}
+
+impl CurrentIntegrator {
+    const FILTER_DIV: u32 = 4;
+
+    pub const fn new(ki_div: i32, ceiling: u16) -> Self {
+        Self {
+            ki_div,
+            ceiling,
+            acc: 0,
+            filtered: 0,
+        }
+    }
+
+    /// Duty to apply next, in 0.1% steps
+    pub fn duty_permille(&self) -> u16 {
+        (self.acc / self.ki_div).clamp(0, self.ceiling as i32) as u16
+    }
+
+    /// Filtered current, in milliamps
+    pub fn current_ma(&self) -> u32 {
+        self.filtered
+    }
+
+    /// Low-pass a current sample without integrating, for a caller that sets the duty itself
+    pub fn filter(&mut self, sample_ma: u32) -> u32 {
+        self.filtered = (self.filtered * (Self::FILTER_DIV - 1) + sample_ma) / Self::FILTER_DIV;
+        self.filtered
+    }
+
+    /// Take a current sample and move the duty towards `target_ma`
+    pub fn update(&mut self, sample_ma: u32, target_ma: u16) -> u16 {
+        self.filter(sample_ma);
+        let err = target_ma as i32 - self.filtered as i32;
+        if err < 0 || self.acc / self.ki_div < self.ceiling as i32 {
+            self.acc = (self.acc + err).max(0);
+        }
+        self.duty_permille()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duty_settles_at_target_and_never_passes_the_ceiling() {
+        let mut reg = CurrentIntegrator::new(200, 150);
+        // A winding that draws 20 mA per 0.1% of duty
+        for _ in 0..2000 {
+            let duty = reg.duty_permille();
+            reg.update(duty as u32 * 20, 1000);
+        }
+        assert!(
+            (45..=55).contains(&reg.duty_permille()),
+            "duty {}",
+            reg.duty_permille()
+        );
+        assert!(
+            (900..=1100).contains(&reg.current_ma()),
+            "current {}",
+            reg.current_ma()
+        );
+
+        // Open winding: wound up only to the ceiling, and back down at once on current
+        let mut open = CurrentIntegrator::new(200, 150);
+        for _ in 0..5000 {
+            open.update(0, 1000);
+        }
+        assert_eq!(open.duty_permille(), 150);
+        open.update(8000, 1000);
+        assert!(open.duty_permille() < 150);
+    }
+}
@@ -0,0 +1,82 @@
+//! Shunt current-sense calibration on command (`MotorCommand::CalibrateCurrent`)
+//!
+//! The offsets are measured as at boot, with every switch off so no current
+//! flows, after `SETTLE` for a winding current to die away. A gain given with
+//! the command replaces the stored one; otherwise the gain is kept. With
+//! `persist` the result goes to flash and is restored at the next boot in
+//! place of the boot-time offset measurement. Offsets the ADC health check
+//! rejects leave the current untrusted and are not stored. The command is
+//! refused while the motor runs, whose current would read as offset; the
+//! host reads the outcome on `CurrentCalibrationEndpoint`.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Timer};
+use oxifoc_protocol::{CalibrationFailure, CalibrationRun, CurrentCalibration};
+
+use super::adc_health;
+use super::current::CurrentSense;
+use super::{MotorController, Trace};
+use crate::log;
+use crate::storage::{self, RecordKey};
+
+/// Wait with the bridge off before measuring
+const SETTLE: Duration = Duration::from_millis(10);
+
+/// Latest calibration, for the endpoint server
+static LATEST: Mutex<CriticalSectionRawMutex, Cell<CalibrationRun>> =
+    Mutex::new(Cell::new(CalibrationRun::Idle));
+
+/// State of the latest calibration
+pub fn latest() -> CalibrationRun {
+    LATEST.lock(|l| l.get())
+}
+
+fn set_latest(run: CalibrationRun) {
+    LATEST.lock(|l| l.set(run));
+}
+
+/// Measure the offsets, apply `gain` (numerator, denominator) if given, and store if `persist`
+pub async fn run(
+    motor: &mut MotorController<'_>,
+    sense: &mut CurrentSense<'_>,
+    gain: Option<(u16, u16)>,
+    persist: bool,
+    trace: Trace,
+) {
+    // Before any await, so a host polling right after the command never sees a stale result
+    set_latest(CalibrationRun::Measuring);
+    motor.all_phases_off();
+    Timer::after(SETTLE).await;
+    sense.calibrate();
+    if let Some((gain_num, gain_den)) = gain {
+        sense.set_calibration(CurrentCalibration {
+            gain_num,
+            gain_den,
+            ..sense.calibration()
+        });
+    }
+    let cal = sense.calibration();
+    log::info!(
+        "Current calibration{}: offsets {} gain {}/{}",
+        trace,
+        cal.offset,
+        cal.gain_num,
+        cal.gain_den
+    );
+    if !adc_health::check_offsets(cal.offset, motor.adc_health_config()) {
+        if persist {
+            log::warn!("Current calibration{}: not persisted", trace);
+        }
+        set_latest(CalibrationRun::Failed(CalibrationFailure::BadOffsets));
+        return;
+    }
+    if persist && let Err(e) = storage::store(RecordKey::CurrentCalibration, &cal).await {
+        log::error!("Current calibration: failed to persist: {}", e);
+        set_latest(CalibrationRun::Failed(CalibrationFailure::PersistFailed));
+        return;
+    }
+    set_latest(CalibrationRun::Done(cal));
+}
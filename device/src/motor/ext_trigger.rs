@@ -0,0 +1,127 @@
+//! External commutation trigger (`CommutationMode::External`)
+//!
+//! In external mode the running sequence advances one step per edge on the
+//! trigger input instead of once per run period, so another controller (or a
+//! master board driving several of these in lockstep) sets the step timing.
+//! The input is PB3 (the UART TX pad, unused by this firmware) on EXTI line
+//! 3, pulled down; `ExtTriggerConfig::edge` picks the edges that count,
+//! rising unless built with `OXIFOC_TRIGGER_EDGE=rising|falling|both`.
+//!
+//! Startup still aligns and ramps on its own timing; the trigger takes over
+//! once the motor runs. Edges closer together than `safe_mode::MIN_PERIOD_US`
+//! are ignored, so noise or a runaway source cannot step faster than the
+//! open-loop minimum period. If no edge arrives for `timeout_ms` while
+//! running, the control task stops the motor through the safety path, as
+//! when the host goes quiet. Edges that come too early or too late for the
+//! step interval so far are handled by `sync_guard`. The reported speed
+//! follows the interval between the last two edges; BEMF capture samples only
+//! in open-loop mode.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+
+use super::safe_mode;
+
+/// Edges of the trigger input that advance the sequence
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum TriggerEdge {
+    Rising,
+    Falling,
+    Both,
+}
+
+/// Edge from `OXIFOC_TRIGGER_EDGE` at build time, rising if unset
+const TRIGGER_EDGE: TriggerEdge = match option_env!("OXIFOC_TRIGGER_EDGE") {
+    Some(s) => match s.as_bytes() {
+        b"rising" => TriggerEdge::Rising,
+        b"falling" => TriggerEdge::Falling,
+        b"both" => TriggerEdge::Both,
+        _ => panic!("OXIFOC_TRIGGER_EDGE must be rising, falling or both"),
+    },
+    None => TriggerEdge::Rising,
+};
+
+/// Trigger tunables
+#[derive(Clone, Copy)]
+pub struct ExtTriggerConfig {
+    pub edge: TriggerEdge,
+    /// Stop a running motor after this long without an edge, in milliseconds
+    pub timeout_ms: u16,
+}
+
+impl Default for ExtTriggerConfig {
+    fn default() -> Self {
+        Self {
+            edge: TRIGGER_EDGE,
+            timeout_ms: 500,
+        }
+    }
+}
+
+/// An accepted edge, waiting for the control task
+static EDGE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Time between the last two accepted edges in µs, 0 until there are two
+static INTERVAL_US: AtomicU32 = AtomicU32::new(0);
+
+/// Drops edges that come too soon after the last one accepted
+#[derive(Default)]
+pub struct EdgeFilter {
+    last_us: Option<u64>,
+}
+
+impl EdgeFilter {
+    /// Whether an edge at `now_us` counts; returns the interval since the last one that did
+    pub fn accept(&mut self, now_us: u64, min_interval_us: u32) -> Option<u32> {
+        let interval = match self.last_us {
+            Some(last) if now_us.saturating_sub(last) < min_interval_us as u64 => return None,
+            Some(last) => (now_us - last).min(u32::MAX as u64) as u32,
+            None => 0,
+        };
+        self.last_us = Some(now_us);
+        Some(interval)
+    }
+}
+
+/// Record an edge seen at `now_us`; called by the trigger input task
+pub fn edge(filter: &mut EdgeFilter, now_us: u64) {
+    if let Some(interval) = filter.accept(now_us, safe_mode::MIN_PERIOD_US) {
+        INTERVAL_US.store(interval, Ordering::Relaxed);
+        EDGE.signal(());
+    }
+}
+
+/// Wait for the next accepted edge
+pub async fn wait() {
+    EDGE.wait().await;
+}
+
+/// Forget an edge that arrived while the trigger was not followed
+pub fn clear() {
+    EDGE.reset();
+    INTERVAL_US.store(0, Ordering::Relaxed);
+}
+
+/// Time between the last two accepted edges in µs, `None` until there are two
+pub fn interval_us() -> Option<u32> {
+    match INTERVAL_US.load(Ordering::Relaxed) {
+        0 => None,
+        us => Some(us),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edges_inside_the_minimum_period_are_dropped() {
+        let mut filter = EdgeFilter::default();
+        assert_eq!(filter.accept(1_000, 200), Some(0));
+        assert_eq!(filter.accept(1_150, 200), None);
+        // Measured from the last accepted edge, not the dropped one
+        assert_eq!(filter.accept(1_250, 200), Some(250));
+        assert_eq!(filter.accept(11_250, 200), Some(10_000));
+    }
+}
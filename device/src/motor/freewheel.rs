@@ -0,0 +1,227 @@
+//! Freewheel coast-down test (`MotorCommand::Freewheel`)
+//!
+//! The motor is started (or kept running) at the commanded duty and left at
+//! the run period for `spin_ms`, so it reaches a steady speed. Then every
+//! switch is turned off at once, with no discharge or braking, and the rotor
+//! coasts. Its speed now falls only to friction and windage (and, with no
+//! load, the rotor's own inertia sets how fast), which is what the host
+//! fits the decay curve to.
+//!
+//! With all three phases floating, the spinning magnets still induce BEMF in
+//! every winding. The control task reads the three phase voltages back to
+//! back and watches each cross the mean of the three (the star point as seen
+//! from the terminals), with `hysteresis_mv` against noise. Each phase
+//! crosses twice per electrical revolution, so the three give six crossings
+//! per revolution, and the time spanned by the last six gives the speed.
+//! Between crossings the speed is bounded by the time since the last one, so
+//! the estimate keeps falling when they stop coming.
+//!
+//! While coasting, that speed is what telemetry reports as rpm, streamed
+//! every `FAST_TELEMETRY_MS` instead of the usual period. The test ends
+//! once no crossing has come for `stopped_after_ms` (the BEMF has sunk into
+//! the hysteresis), `coast_ms` has passed, or any control message arrives.
+
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+use embassy_time::{Duration, Instant, Timer};
+
+use super::current::CurrentSense;
+use super::{ControlMsg, ControlReceiver, MotorController, Trace, get_rpm_estimate, poll_control};
+use crate::log;
+
+/// Coast-down tunables
+#[derive(Clone, Copy)]
+pub struct FreewheelConfig {
+    /// Distance from the mean a phase has to pass to count as crossed, in mV
+    pub hysteresis_mv: u16,
+    /// The rotor counts as stopped after this long without a crossing, in ms
+    pub stopped_after_ms: u16,
+}
+
+impl Default for FreewheelConfig {
+    fn default() -> Self {
+        Self {
+            hysteresis_mv: 60,
+            stopped_after_ms: 300,
+        }
+    }
+}
+
+/// Telemetry period while coasting, in milliseconds
+pub const FAST_TELEMETRY_MS: u64 = 10;
+/// Time between reads of the three phase voltages
+const SAMPLE_INTERVAL: Duration = Duration::from_micros(50);
+/// Crossings per electrical revolution, two for each phase
+const CROSSINGS_PER_REV: usize = 6;
+
+static COASTING: AtomicBool = AtomicBool::new(false);
+static COAST_RPM: AtomicU16 = AtomicU16::new(0);
+
+/// Whether a coast-down is being tracked
+pub fn coasting() -> bool {
+    COASTING.load(Ordering::Relaxed)
+}
+
+/// BEMF-measured speed of the coasting rotor, while `coasting`
+pub fn coast_rpm() -> Option<u16> {
+    coasting().then(|| COAST_RPM.load(Ordering::Relaxed))
+}
+
+/// Drive phase of a freewheel test, waiting for the spin time to pass
+#[derive(Clone, Copy)]
+pub struct Plan {
+    pub float_at: Instant,
+    pub coast_ms: u16,
+    pub trace: Trace,
+}
+
+/// Speed from BEMF crossings of the floating phases
+#[derive(Default)]
+pub struct CoastTracker {
+    /// Each phase's side of the mean, once it was clearly on one
+    above: [Option<bool>; 3],
+    /// Times of the latest crossings, oldest first, in µs
+    crossings: heapless::Deque<u64, { CROSSINGS_PER_REV + 1 }>,
+    count: u32,
+}
+
+impl CoastTracker {
+    /// Take one reading of the three phase voltages at `now_us`
+    pub fn sample(&mut self, now_us: u64, mv: [u32; 3], hysteresis_mv: u16) {
+        let mean = mv.iter().sum::<u32>() / 3;
+        let h = hysteresis_mv as u32;
+        for (above, &v) in self.above.iter_mut().zip(mv.iter()) {
+            let side = if v > mean + h {
+                true
+            } else if v + h < mean {
+                false
+            } else {
+                continue;
+            };
+            if above.is_some_and(|was| was != side) {
+                if self.crossings.is_full() {
+                    self.crossings.pop_front();
+                }
+                let _ = self.crossings.push_back(now_us);
+                self.count += 1;
+            }
+            *above = Some(side);
+        }
+    }
+
+    /// Crossings seen so far
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Time of the latest crossing, in µs
+    pub fn last_us(&self) -> Option<u64> {
+        self.crossings.back().copied()
+    }
+
+    /// Mechanical speed at `now_us`; 0 before two crossings
+    pub fn rpm(&self, now_us: u64, pole_pairs: u8) -> u16 {
+        let (Some(&first), Some(&last)) = (self.crossings.front(), self.crossings.back()) else {
+            return 0;
+        };
+        let spans = self.crossings.len() as u64 - 1;
+        if spans == 0 {
+            return 0;
+        }
+        // The next crossing is at least this far off
+        let interval_us = ((last - first) / spans).max(now_us.saturating_sub(last));
+        let per_rev_us = interval_us * CROSSINGS_PER_REV as u64 * pole_pairs.max(1) as u64;
+        (60_000_000 / per_rev_us.max(1)).min(u16::MAX as u64) as u16
+    }
+}
+
+/// Cut the drive and track the coast-down as `plan` asks
+///
+/// Any control message ends the tracking and is returned so the caller can
+/// still act on it. The outputs stay off afterwards.
+pub async fn coast(
+    motor: &mut MotorController<'_>,
+    sense: &mut CurrentSense<'_>,
+    plan: Plan,
+    cfg: &FreewheelConfig,
+    control: &ControlReceiver,
+) -> Option<ControlMsg> {
+    let pole_pairs = motor.params().pole_pairs;
+    let from_rpm = get_rpm_estimate();
+    let start = Instant::now();
+    COAST_RPM.store(from_rpm, Ordering::Relaxed);
+    COASTING.store(true, Ordering::Relaxed);
+    motor.stop_coasting();
+    log::info!(
+        "Freewheel{}: drive cut at {} rpm, tracking the coast-down",
+        plan.trace,
+        from_rpm
+    );
+
+    let end = start + Duration::from_millis(plan.coast_ms as u64);
+    let stopped_after_us = cfg.stopped_after_ms as u64 * 1000;
+    let mut tracker = CoastTracker::default();
+    let pending = loop {
+        if let Some(msg) = poll_control(control) {
+            log::warn!("Freewheel{} ended by incoming command", plan.trace);
+            break Some(msg);
+        }
+        let now = Instant::now();
+        if now >= end {
+            break None;
+        }
+        // The ADC pins follow the board phases; which is which does not matter here
+        let mv = [0, 1, 2].map(|phase| sense.phase_voltage_mv(phase));
+        let now_us = now.as_micros();
+        tracker.sample(now_us, mv, cfg.hysteresis_mv);
+        COAST_RPM.store(tracker.rpm(now_us, pole_pairs), Ordering::Relaxed);
+        let quiet_since = tracker.last_us().unwrap_or(start.as_micros());
+        if now_us.saturating_sub(quiet_since) > stopped_after_us {
+            COAST_RPM.store(0, Ordering::Relaxed);
+            break None;
+        }
+        Timer::after(SAMPLE_INTERVAL).await;
+    };
+    COASTING.store(false, Ordering::Relaxed);
+    log::info!(
+        "Freewheel{}: coasted {}ms from {} rpm, {} BEMF crossings",
+        plan.trace,
+        start.elapsed().as_millis(),
+        from_rpm,
+        tracker.count()
+    );
+    pending
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speed_follows_the_crossings_and_decays_between_them() {
+        let mut tracker = CoastTracker::default();
+        // Three triangle-shaped phases 120° apart, one electrical revolution per 6 ms
+        let phase = |t_us: u64, n: u64| {
+            let x = (t_us + n * 2000) % 6000;
+            (500 + x.abs_diff(3000) * 2000 / 6000) as u32
+        };
+        for t in (0..18_000).step_by(50) {
+            tracker.sample(t, [phase(t, 0), phase(t, 1), phase(t, 2)], 60);
+        }
+        assert!(
+            (16..=18).contains(&tracker.count()),
+            "crossings {}",
+            tracker.count()
+        );
+        // 6 ms per electrical revolution, 7 pole pairs: 60e6 / 42000 ≈ 1428 rpm
+        let rpm = tracker.rpm(18_000, 7);
+        assert!((1380..=1480).contains(&rpm), "rpm {}", rpm);
+
+        // Nothing more for 10 ms: at most one crossing per 10 ms from here
+        let last = tracker.last_us().unwrap();
+        assert_eq!(tracker.rpm(last + 10_000, 7), 142);
+        // Flat phases count nothing
+        tracker.sample(30_000, [1000, 1010, 990], 60);
+        assert_eq!(tracker.last_us(), Some(last));
+    }
+}
@@ -0,0 +1,118 @@
+//! Hall sensor inputs (B-G431B-ESC1 J8: H1=PB6, H2=PB7, H3=PB8)
+//!
+//! Standard 120° halls produce the Gray-code sequence 1→3→2→6→4→5 in one
+//! mechanical direction. Sector deltas between samples give the rotation
+//! direction independently of how the motor phases are wired.
+
+use embassy_stm32::gpio::{Input, Pull};
+use oxifoc_protocol::Direction;
+
+/// Hall states in forward order; index = electrical sector 0-5
+const SEQUENCE: [u8; 6] = [0b001, 0b011, 0b010, 0b110, 0b100, 0b101];
+
+/// Map a raw hall state (H3 H2 H1 bits) to its sector, `None` for 0b000/0b111
+pub fn sector(state: u8) -> Option<u8> {
+    SEQUENCE.iter().position(|&s| s == state).map(|i| i as u8)
+}
+
+/// Direction implied by moving from sector `from` to sector `to`
+///
+/// Only adjacent moves count; a jump of two or more sectors is ambiguous.
+pub fn step_direction(from: u8, to: u8) -> Option<Direction> {
+    match (to + 6 - from) % 6 {
+        1 => Some(Direction::Forward),
+        5 => Some(Direction::Reverse),
+        _ => None,
+    }
+}
+
+/// Net direction accumulator over a series of hall samples
+#[derive(Default)]
+pub struct DirectionTracker {
+    last: Option<u8>,
+    forward: u8,
+    reverse: u8,
+}
+
+impl DirectionTracker {
+    /// Feed one raw hall state
+    pub fn sample(&mut self, state: u8) {
+        let Some(s) = sector(state) else {
+            return;
+        };
+        if let Some(last) = self.last
+            && last != s
+        {
+            match step_direction(last, s) {
+                Some(Direction::Forward) => self.forward = self.forward.saturating_add(1),
+                Some(Direction::Reverse) => self.reverse = self.reverse.saturating_add(1),
+                None => {}
+            }
+        }
+        self.last = Some(s);
+    }
+
+    /// Valid transitions counted so far
+    pub fn transitions(&self) -> u8 {
+        self.forward.saturating_add(self.reverse)
+    }
+
+    /// Dominant direction once at least `min_transitions` were seen
+    pub fn direction(&self, min_transitions: u8) -> Option<Direction> {
+        if self.transitions() < min_transitions || self.forward == self.reverse {
+            return None;
+        }
+        if self.forward > self.reverse {
+            Some(Direction::Forward)
+        } else {
+            Some(Direction::Reverse)
+        }
+    }
+}
+
+/// Three hall inputs with internal pull-ups (open-drain sensors)
+pub struct HallSensors<'d> {
+    h1: Input<'d>,
+    h2: Input<'d>,
+    h3: Input<'d>,
+}
+
+impl<'d> HallSensors<'d> {
+    pub fn new(
+        pb6: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::PB6>>,
+        pb7: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::PB7>>,
+        pb8: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::PB8>>,
+    ) -> Self {
+        Self {
+            h1: Input::new(pb6.into(), Pull::Up),
+            h2: Input::new(pb7.into(), Pull::Up),
+            h3: Input::new(pb8.into(), Pull::Up),
+        }
+    }
+
+    /// Raw state as H3 H2 H1 bits
+    pub fn read(&self) -> u8 {
+        (self.h1.is_high() as u8) | (self.h2.is_high() as u8) << 1 | (self.h3.is_high() as u8) << 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_direction_from_sequence() {
+        let mut fwd = DirectionTracker::default();
+        for &s in SEQUENCE.iter().chain(SEQUENCE.iter()) {
+            fwd.sample(s);
+        }
+        assert_eq!(fwd.direction(6), Some(Direction::Forward));
+
+        let mut rev = DirectionTracker::default();
+        for &s in SEQUENCE.iter().rev() {
+            rev.sample(s);
+        }
+        assert_eq!(rev.direction(5), Some(Direction::Reverse));
+        assert_eq!(sector(0b111), None);
+    }
+}
@@ -0,0 +1,124 @@
+//! Low-speed cutoff: protect the windings from sustained near-stall current
+//!
+//! At a very long open-loop step period the rotor barely moves while the
+//! energized pair carries close to its stall current. When the speed estimate
+//! stays below `min_rpm` for `timeout_ms` while running, the guard halves the
+//! duty and starts timing again; once the duty is down to `MIN_DUTY` it stops
+//! the motor. This targets slow, high-current running, not a rotor that has
+//! stopped following the field altogether.
+
+use core::sync::atomic::{AtomicU16, Ordering};
+
+/// Duty at or below which the guard stops instead of reducing further
+pub const MIN_DUTY: u8 = 2;
+
+/// Default threshold; 0 disables the guard
+pub const DEFAULT_MIN_RPM: u16 = 0;
+/// Default time below the threshold before acting
+pub const DEFAULT_TIMEOUT_MS: u16 = 2000;
+
+static MIN_RPM: AtomicU16 = AtomicU16::new(DEFAULT_MIN_RPM);
+static TIMEOUT_MS: AtomicU16 = AtomicU16::new(DEFAULT_TIMEOUT_MS);
+
+/// Set the threshold (0 disables) and the time allowed below it
+pub fn configure(min_rpm: u16, timeout_ms: u16) {
+    MIN_RPM.store(min_rpm, Ordering::Relaxed);
+    TIMEOUT_MS.store(timeout_ms, Ordering::Relaxed);
+}
+
+/// Speed below which the guard starts timing
+pub fn min_rpm() -> u16 {
+    MIN_RPM.load(Ordering::Relaxed)
+}
+
+/// Time allowed below `min_rpm` before each reduction
+pub fn timeout_ms() -> u16 {
+    TIMEOUT_MS.load(Ordering::Relaxed)
+}
+
+/// What the controller must do after a check
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Ok,
+    /// Continue at this lower duty
+    ReduceDuty(u8),
+    /// Stop the motor
+    Stop,
+}
+
+/// Tracks how long the speed has been below the threshold
+#[derive(Default)]
+pub struct LowSpeedGuard {
+    below_since_ms: Option<u64>,
+}
+
+impl LowSpeedGuard {
+    /// Forget the running timer (call when the motor starts)
+    pub fn reset(&mut self) {
+        self.below_since_ms = None;
+    }
+
+    /// Check one speed estimate taken at `now_ms` while running at `duty`
+    pub fn check(
+        &mut self,
+        rpm: u16,
+        duty: u8,
+        min_rpm: u16,
+        timeout_ms: u16,
+        now_ms: u64,
+    ) -> Verdict {
+        if min_rpm == 0 || rpm >= min_rpm {
+            self.below_since_ms = None;
+            return Verdict::Ok;
+        }
+        let since = *self.below_since_ms.get_or_insert(now_ms);
+        if now_ms - since < timeout_ms as u64 {
+            return Verdict::Ok;
+        }
+        // Give the reduced duty a full timeout of its own
+        self.below_since_ms = Some(now_ms);
+        if duty <= MIN_DUTY {
+            Verdict::Stop
+        } else {
+            Verdict::ReduceDuty((duty / 2).max(MIN_DUTY))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_or_fast_enough_never_acts() {
+        let mut guard = LowSpeedGuard::default();
+        assert_eq!(guard.check(1, 20, 0, 100, 0), Verdict::Ok);
+        assert_eq!(guard.check(1, 20, 0, 100, 10_000), Verdict::Ok);
+        assert_eq!(guard.check(300, 20, 200, 100, 20_000), Verdict::Ok);
+    }
+
+    #[test]
+    fn test_reduces_then_stops_while_slow() {
+        let mut guard = LowSpeedGuard::default();
+        assert_eq!(guard.check(50, 12, 200, 1000, 0), Verdict::Ok);
+        assert_eq!(guard.check(50, 12, 200, 1000, 999), Verdict::Ok);
+        assert_eq!(guard.check(50, 12, 200, 1000, 1000), Verdict::ReduceDuty(6));
+        assert_eq!(guard.check(50, 6, 200, 1000, 1500), Verdict::Ok);
+        assert_eq!(guard.check(50, 6, 200, 1000, 2000), Verdict::ReduceDuty(3));
+        assert_eq!(
+            guard.check(50, 3, 200, 1000, 3000),
+            Verdict::ReduceDuty(MIN_DUTY)
+        );
+        assert_eq!(guard.check(50, MIN_DUTY, 200, 1000, 4000), Verdict::Stop);
+    }
+
+    #[test]
+    fn test_speed_recovery_restarts_timer() {
+        let mut guard = LowSpeedGuard::default();
+        assert_eq!(guard.check(50, 10, 200, 1000, 0), Verdict::Ok);
+        assert_eq!(guard.check(250, 10, 200, 1000, 900), Verdict::Ok);
+        assert_eq!(guard.check(50, 10, 200, 1000, 1000), Verdict::Ok);
+        assert_eq!(guard.check(50, 10, 200, 1000, 1999), Verdict::Ok);
+        assert_eq!(guard.check(50, 10, 200, 1000, 2000), Verdict::ReduceDuty(5));
+    }
+}
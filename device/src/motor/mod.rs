@@ -6,17 +6,137 @@
 //! - Voltage: 3S-4S LiPo (11.1-14.8V)
 //! - Type: Outrunner disc motor
 
+pub mod accel;
+pub mod adc_health;
+pub mod auto_disarm;
+#[cfg(feature = "diagnostics")]
+pub mod bemf;
+pub mod blanking;
+#[cfg(feature = "diagnostics")]
+pub mod cogging;
+pub mod commutator;
+pub mod current;
+pub mod current_cal;
+pub mod ext_trigger;
+pub mod freewheel;
+pub mod hall;
+pub mod low_speed;
+pub mod nudge;
+#[cfg(feature = "diagnostics")]
+pub mod phase_duty;
+pub mod profile;
+pub mod protection;
 pub mod pwm;
+pub mod regen;
+pub mod reverse;
+#[cfg(feature = "diagnostics")]
+pub mod rl;
+pub mod safe_mode;
+pub mod self_test;
 pub mod six_step;
+pub mod startup;
+pub mod sync_guard;
+pub mod telemetry;
+#[cfg(feature = "diagnostics")]
+pub mod test_pattern;
 
-use core::sync::atomic::{AtomicU8, Ordering};
-use embassy_time::Duration;
-use oxifoc_protocol::{MotorCommand, MotorState, MotorStatus};
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU16, AtomicU32, Ordering};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::{Channel, Receiver, Sender};
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant};
+use oxifoc_protocol::constants::MAX_COMMUTATION_PERIOD_US;
+use oxifoc_protocol::{
+    CommandLog, CommandRejection, CommutationMode, Direction, FaultKind, FullStatus, MotorCommand,
+    MotorProfile, MotorState, MotorStatus, PhaseMap, ScanScheme, SelfTestRequest,
+};
 
-use self::pwm::{MotorPwm, MotorPwmConfig};
+use self::accel::{AccelConfig, DwellRamp};
+use self::adc_health::{AdcHealthConfig, Signals};
+#[cfg(feature = "diagnostics")]
+use self::cogging::CoggingConfig;
+use self::commutator::{Commutator, Sequencer};
+use self::freewheel::FreewheelConfig;
+use self::low_speed::{LowSpeedGuard, Verdict};
+use self::protection::{Fault, Protection, ProtectionConfig};
+use self::pwm::{MotorPwm, MotorPwmConfig, PwmPins};
+use self::regen::RegenConfig;
+use self::reverse::{Phase, Reversal, ReverseConfig, StallRecovery};
+#[cfg(feature = "diagnostics")]
+use self::rl::RlConfig;
 use self::six_step::CommutationStep;
+#[cfg(feature = "diagnostics")]
+use self::six_step::PhaseState;
+use self::startup::StartupConfig;
+use self::sync_guard::SyncGuardConfig;
+use crate::log;
+
+/// Host correlation id of a command, shown in its log lines as ` [id=42]`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Trace(pub Option<u16>);
+
+impl Trace {
+    /// Not sent by the host, or raised on the device itself
+    pub const NONE: Self = Self(None);
+}
+
+impl defmt::Format for Trace {
+    fn format(&self, f: defmt::Formatter) {
+        if let Some(id) = self.0 {
+            defmt::write!(f, " [id={}]", id);
+        }
+    }
+}
+
+/// Messages accepted by the motor control task
+pub enum ControlMsg {
+    /// Command received from the host over `MotorEndpoint`, with its trace id
+    Command(MotorCommand, Trace),
+    /// Command raised by the device itself (link failsafe, idle disarm, trigger
+    /// timeout); logged to the host, but no endpoint waits for its verdict
+    Internal(MotorCommand),
+    /// Stop and apply a complete parameter set (from `ProfileCommand::Load`)
+    ApplyProfile(MotorProfile),
+    /// Run the direction self-test (result is published via `self_test_result`)
+    SelfTest(SelfTestRequest),
+}
+
+/// Depth of the motor control message queue
+pub const CONTROL_QUEUE_DEPTH: usize = 4;
+
+pub type ControlChannel = Channel<CriticalSectionRawMutex, ControlMsg, CONTROL_QUEUE_DEPTH>;
+pub type ControlSender = Sender<'static, CriticalSectionRawMutex, ControlMsg, CONTROL_QUEUE_DEPTH>;
+pub type ControlReceiver =
+    Receiver<'static, CriticalSectionRawMutex, ControlMsg, CONTROL_QUEUE_DEPTH>;
+
+/// Admission verdict for the most recent motor command, for `motor_command_server`
+pub static COMMAND_RESULT: Signal<CriticalSectionRawMutex, Result<(), CommandRejection>> =
+    Signal::new();
+
+/// Depth of the command log queue drained by `command_log_task`
+pub const COMMAND_LOG_DEPTH: usize = 8;
+
+/// Outcomes of processed motor commands, waiting to be sent to the host
+pub static COMMAND_LOG: Channel<CriticalSectionRawMutex, CommandLog, COMMAND_LOG_DEPTH> =
+    Channel::new();
+
+/// Queue a command log entry; dropped if the host is not draining the queue
+pub fn log_command(entry: CommandLog) {
+    if COMMAND_LOG.try_send(entry).is_err() {
+        log::debug!("Command log full; entry dropped");
+    }
+}
+
+/// Wakes the control task out of its commutation wait for a safety stop
+pub static SAFETY_WAKE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Default minimum time in `Stopped` before a new start is honored
+pub const DEFAULT_MIN_OFF_MS: u16 = 100;
 
 /// Motor physical parameters
+#[derive(Clone, Copy)]
 pub struct MotorParams {
     /// Number of pole pairs (14 poles = 7 pole pairs)
     pub pole_pairs: u8,
@@ -27,16 +147,48 @@ pub struct MotorParams {
 impl Default for MotorParams {
     fn default() -> Self {
         Self {
-            pole_pairs: 7,      // ZD2808-V1.9: 14 poles = 7 pole pairs
-            kv_rating: 700,     // 700 KV
+            pole_pairs: 7,  // ZD2808-V1.9: 14 poles = 7 pole pairs
+            kv_rating: 700, // 700 KV
         }
     }
 }
 
 /// Global motor state
 static MOTOR_STATE: AtomicU8 = AtomicU8::new(MotorState::Stopped as u8);
-static MOTOR_DUTY: AtomicU8 = AtomicU8::new(0);
-static MOTOR_STEP: AtomicU8 = AtomicU8::new(0);
+/// Duty and step applied to the bridge, packed as `duty << 8 | step`
+///
+/// A single atomic so a reader never pairs the duty of one update with the
+/// step of another. Only the control task writes it, right after the PWM
+/// registers change, with `Release`; readers load with `Acquire`, so a status
+/// never reports outputs ahead of the hardware.
+static MOTOR_OUTPUT: AtomicU16 = AtomicU16::new(0);
+static MOTOR_ARMED: AtomicBool = AtomicBool::new(false);
+static COMMUTATION_PERIOD_MS: AtomicU32 = AtomicU32::new(0);
+/// Run period the controller accelerates to, and the dwell of the current step
+static RUN_PERIOD_US: AtomicU32 = AtomicU32::new(0);
+static STEP_DWELL_US: AtomicU32 = AtomicU32::new(0);
+static ESTOP_LATCHED: AtomicBool = AtomicBool::new(false);
+/// Running with duty clamped by a soft fault (see `protection`)
+static LIMP_ACTIVE: AtomicBool = AtomicBool::new(false);
+/// Fault behind the limp mode or the last protection stop, 0 = none, else `Fault as u8 + 1`
+static LAST_FAULT: AtomicU8 = AtomicU8::new(0);
+/// Phase voltage ceiling of `MotorPwm::set_voltage_limit`, 0 = disabled
+static MAX_PHASE_MV: AtomicU16 = AtomicU16::new(0);
+/// `RegenConfig::regen_current_limit_ma` in use, for the config report
+static REGEN_LIMIT_MA: AtomicU16 = AtomicU16::new(0);
+/// Pending safety stop as `SAFETY_*` bits, 0 = none (an `Estop` wins over a `Stop`)
+static SAFETY_STOP: AtomicU8 = AtomicU8::new(0);
+const SAFETY_STOP_BIT: u8 = 1 << 0;
+const SAFETY_ESTOP_BIT: u8 = 1 << 1;
+/// Requested by the host, so `motor_command_server` waits for the verdict
+const SAFETY_HOST_BIT: u8 = 1 << 2;
+static MIN_OFF_MS: AtomicU16 = AtomicU16::new(DEFAULT_MIN_OFF_MS);
+static PHASE_MAP: Mutex<CriticalSectionRawMutex, Cell<PhaseMap>> =
+    Mutex::new(Cell::new(PhaseMap::IDENTITY));
+static SCAN_SCHEME: Mutex<CriticalSectionRawMutex, Cell<ScanScheme>> =
+    Mutex::new(Cell::new(ScanScheme::Conduction120));
+static COMMUTATION_MODE: Mutex<CriticalSectionRawMutex, Cell<CommutationMode>> =
+    Mutex::new(Cell::new(CommutationMode::OpenLoop));
 
 /// Set motor state
 pub fn set_motor_state(state: MotorState) {
@@ -45,127 +197,906 @@ pub fn set_motor_state(state: MotorState) {
 
 /// Get motor state
 pub fn get_motor_state() -> MotorState {
-    match MOTOR_STATE.load(Ordering::Relaxed) {
-        0 => MotorState::Stopped,
-        1 => MotorState::Running,
-        _ => MotorState::Error,
+    MotorState::from_u8(MOTOR_STATE.load(Ordering::Relaxed))
+}
+
+/// Publish the duty and step just applied to the PWM
+fn set_motor_output(duty: u8, step: u8) {
+    MOTOR_OUTPUT.store((duty as u16) << 8 | step as u16, Ordering::Release);
+}
+
+/// Duty and step applied to the bridge, as one consistent pair
+pub fn get_motor_output() -> (u8, u8) {
+    let packed = MOTOR_OUTPUT.load(Ordering::Acquire);
+    ((packed >> 8) as u8, packed as u8)
+}
+
+/// Get motor commutation step
+pub fn get_motor_step() -> u8 {
+    get_motor_output().1
+}
+
+/// Set motor armed flag
+pub fn set_motor_armed(armed: bool) {
+    MOTOR_ARMED.store(armed, Ordering::Relaxed);
+}
+
+/// Get motor armed flag
+pub fn get_motor_armed() -> bool {
+    MOTOR_ARMED.load(Ordering::Relaxed)
+}
+
+/// Whether a soft fault is clamping the applied duty
+pub fn get_limp_active() -> bool {
+    LIMP_ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Fault behind the limp mode, or the one that stopped the motor since the last start
+pub fn get_fault() -> Option<FaultKind> {
+    match LAST_FAULT.load(Ordering::Relaxed) {
+        0 => None,
+        1 => Some(Fault::Overcurrent.into()),
+        2 => Some(Fault::Overtemperature.into()),
+        3 => Some(Fault::Brownout.into()),
+        4 => Some(Fault::GateDriver.into()),
+        5 => Some(Fault::Stall.into()),
+        6 => Some(Fault::Adc.into()),
+        7 => Some(Fault::LostSync.into()),
+        code => {
+            debug_assert!(false, "unknown fault code {}", code);
+            None
+        }
     }
 }
 
-/// Set motor duty cycle
-pub fn set_motor_duty(duty: u8) {
-    MOTOR_DUTY.store(duty, Ordering::Relaxed);
+fn set_fault(fault: Option<Fault>) {
+    LAST_FAULT.store(fault.map_or(0, |f| f as u8 + 1), Ordering::Relaxed);
 }
 
-/// Get motor duty cycle
-pub fn get_motor_duty() -> u8 {
-    MOTOR_DUTY.load(Ordering::Relaxed)
+/// Get open-loop commutation period, rounded to ms for display
+pub fn get_commutation_period_ms() -> u32 {
+    COMMUTATION_PERIOD_MS.load(Ordering::Relaxed)
 }
 
-/// Set motor commutation step
-pub fn set_motor_step(step: u8) {
-    MOTOR_STEP.store(step, Ordering::Relaxed);
+/// Get the open-loop run period, in microseconds
+pub fn get_run_period_us() -> u32 {
+    RUN_PERIOD_US.load(Ordering::Relaxed)
 }
 
-/// Get motor commutation step
-pub fn get_motor_step() -> u8 {
-    MOTOR_STEP.load(Ordering::Relaxed)
+/// Get the dwell of the step being commutated, 0 unless running
+pub fn get_step_dwell_us() -> u32 {
+    if get_motor_state() != MotorState::Running {
+        return 0;
+    }
+    STEP_DWELL_US.load(Ordering::Relaxed)
+}
+
+/// Mechanical speed implied by the dwell of the current step, 0 unless running
+///
+/// During a freewheel coast-down it is the BEMF-measured speed instead.
+pub fn get_rpm_estimate() -> u16 {
+    if let Some(rpm) = freewheel::coast_rpm() {
+        return rpm;
+    }
+    let dwell_us = get_step_dwell_us();
+    if dwell_us == 0 {
+        return 0;
+    }
+    // Six steps per electrical revolution, pole_pairs electrical per mechanical
+    let steps_per_rev = 6 * profile::active().pole_pairs.max(1) as u32;
+    (60_000_000 / dwell_us.saturating_mul(steps_per_rev)).min(u16::MAX as u32) as u16
+}
+
+/// Get minimum stop-to-start dwell
+pub fn get_min_off_ms() -> u16 {
+    MIN_OFF_MS.load(Ordering::Relaxed)
+}
+
+/// Get VBUS-scaled phase voltage ceiling (0 = disabled)
+pub fn get_max_phase_voltage_mv() -> u16 {
+    MAX_PHASE_MV.load(Ordering::Relaxed)
+}
+
+/// Get regenerative braking current limit (0 = disabled)
+pub fn get_regen_current_limit_ma() -> u16 {
+    REGEN_LIMIT_MA.load(Ordering::Relaxed)
+}
+
+/// Get active phase map
+pub fn get_phase_map() -> PhaseMap {
+    PHASE_MAP.lock(|m| m.get())
+}
+
+/// Get active commutation step table
+pub fn get_scan_scheme() -> ScanScheme {
+    SCAN_SCHEME.lock(|s| s.get())
+}
+
+/// Get what times the running steps
+pub fn get_commutation_mode() -> CommutationMode {
+    COMMUTATION_MODE.lock(|m| m.get())
+}
+
+/// Whether `cmd` bypasses the control queue
+pub fn is_safety_command(cmd: &MotorCommand) -> bool {
+    matches!(cmd, MotorCommand::Stop | MotorCommand::Estop)
+}
+
+/// Post a host `Stop` or `Estop` ahead of everything in the control queue
+///
+/// The control task takes it before its next queued message, and running
+/// sequences see it through `poll_control`.
+pub fn request_safety_stop(cmd: &MotorCommand) {
+    let bit = if matches!(cmd, MotorCommand::Estop) {
+        SAFETY_ESTOP_BIT
+    } else {
+        SAFETY_STOP_BIT
+    };
+    post_safety_stop(bit | SAFETY_HOST_BIT);
+}
+
+/// Post a `Stop` raised by the device itself, as `request_safety_stop` does
+/// for the host but without publishing a verdict
+pub fn request_internal_stop() {
+    post_safety_stop(SAFETY_STOP_BIT);
+}
+
+fn post_safety_stop(bits: u8) {
+    SAFETY_STOP.fetch_or(bits, Ordering::Relaxed);
+    SAFETY_WAKE.signal(());
+}
+
+/// Take the pending safety stop, if any
+///
+/// One also requested by the host comes back as a `Command`, so its verdict
+/// reaches the waiting endpoint server; otherwise as an `Internal` one.
+pub fn take_safety_stop() -> Option<ControlMsg> {
+    let bits = SAFETY_STOP.swap(0, Ordering::Relaxed);
+    if bits == 0 {
+        return None;
+    }
+    SAFETY_WAKE.reset();
+    let cmd = if bits & SAFETY_ESTOP_BIT != 0 {
+        MotorCommand::Estop
+    } else {
+        MotorCommand::Stop
+    };
+    Some(if bits & SAFETY_HOST_BIT != 0 {
+        ControlMsg::Command(cmd, Trace::NONE)
+    } else {
+        ControlMsg::Internal(cmd)
+    })
+}
+
+/// Next message for a running sequence: a safety stop first, then the queue
+pub fn poll_control(control: &ControlReceiver) -> Option<ControlMsg> {
+    take_safety_stop().or_else(|| control.try_receive().ok())
 }
 
 /// Get current motor status
 pub fn get_motor_status() -> MotorStatus {
+    let (duty, step) = get_motor_output();
     MotorStatus {
         state: get_motor_state(),
-        duty: get_motor_duty(),
-        step: get_motor_step(),
+        duty,
+        step,
+        rejected: None,
     }
 }
 
+/// Snapshot of all runtime state for `FullStatusEndpoint`
+///
+/// Every writer runs in a task or interrupt on this core, so reading with
+/// interrupts masked sees all fields from the same instant.
+pub fn capture_full_status() -> FullStatus {
+    cortex_m::interrupt::free(|_| {
+        let (duty, step) = get_motor_output();
+        FullStatus {
+            uptime_ms: Instant::now().as_millis() as u32,
+            state: get_motor_state(),
+            rpm: get_rpm_estimate(),
+            duty,
+            step,
+            vbus_mv: telemetry::vbus_mv(),
+            temp_dc: telemetry::temp_dc(),
+            current_ma: telemetry::current_ma(),
+            armed: get_motor_armed(),
+            estop: ESTOP_LATCHED.load(Ordering::Relaxed),
+            limp: get_limp_active(),
+            adc_fault: adc_health::adc_fault(),
+            fault: get_fault(),
+            mode: get_commutation_mode(),
+            scan_scheme: get_scan_scheme(),
+            commutation_period_us: get_run_period_us(),
+        }
+    })
+}
+
 /// Motor control context
 pub struct MotorController<'d> {
     pwm: MotorPwm<'d>,
-    current_step: CommutationStep,
+    params: MotorParams,
+    startup: StartupConfig,
+    accel: AccelConfig,
+    dwell: DwellRamp,
+    sequencer: Sequencer,
+    scan_scheme: ScanScheme,
+    commutation_mode: CommutationMode,
+    low_speed: LowSpeedGuard,
+    protection: Protection,
+    protection_cfg: ProtectionConfig,
+    last_stop: Option<Instant>,
+    last_start: Option<Instant>,
     target_duty: u8,
-    commutation_period_ms: u32,
+    commutation_period_us: u32,
+    direction: Direction,
+    reverse_cfg: ReverseConfig,
+    reversal: Option<Reversal>,
+    stall: StallRecovery,
+    regen_cfg: RegenConfig,
+    /// Regen brake of this many ms for the control task to run (see `take_brake_request`)
+    brake_request: Option<u32>,
+    /// The bootstrap capacitors were charged, see `precharge_bootstrap_once`
+    bootstrap_charged: bool,
+    #[cfg(feature = "diagnostics")]
+    rl_cfg: RlConfig,
+    #[cfg(feature = "diagnostics")]
+    cogging_cfg: CoggingConfig,
+    adc_cfg: AdcHealthConfig,
+    sync_cfg: SyncGuardConfig,
+    freewheel_cfg: FreewheelConfig,
+    /// Freewheel test waiting for its spin time (see `take_freewheel_due`)
+    freewheel: Option<freewheel::Plan>,
 }
 
 impl<'d> MotorController<'d> {
     /// Create a new motor controller
     pub fn new(pwm: MotorPwm<'d>) -> Self {
         set_motor_state(MotorState::Stopped);
-        set_motor_duty(0);
-        set_motor_step(0);
+        set_motor_output(0, 0);
+        set_motor_armed(false);
+        COMMUTATION_PERIOD_MS.store(500, Ordering::Relaxed);
+        RUN_PERIOD_US.store(500_000, Ordering::Relaxed);
+        REGEN_LIMIT_MA.store(
+            RegenConfig::default().regen_current_limit_ma,
+            Ordering::Relaxed,
+        );
 
         Self {
             pwm,
-            current_step: CommutationStep::Step0,
+            params: MotorParams::default(),
+            startup: StartupConfig::default(),
+            accel: AccelConfig::default(),
+            dwell: DwellRamp::new(500_000),
+            sequencer: Sequencer::default(),
+            scan_scheme: ScanScheme::default(),
+            commutation_mode: CommutationMode::OpenLoop,
+            low_speed: LowSpeedGuard::default(),
+            protection: Protection::default(),
+            protection_cfg: ProtectionConfig::default(),
+            last_stop: None,
+            last_start: None,
             target_duty: 0,
-            commutation_period_ms: 500,  // Very slow for initial testing (500ms per step = ~2.8 RPM)
+            commutation_period_us: 500_000, // Very slow for initial testing (500ms per step = ~2.8 RPM)
+            direction: Direction::Forward,
+            reverse_cfg: ReverseConfig::default(),
+            reversal: None,
+            stall: StallRecovery::default(),
+            regen_cfg: RegenConfig::default(),
+            brake_request: None,
+            bootstrap_charged: false,
+            #[cfg(feature = "diagnostics")]
+            rl_cfg: RlConfig::default(),
+            #[cfg(feature = "diagnostics")]
+            cogging_cfg: CoggingConfig::default(),
+            adc_cfg: AdcHealthConfig::default(),
+            sync_cfg: SyncGuardConfig::default(),
+            freewheel_cfg: FreewheelConfig::default(),
+            freewheel: None,
         }
     }
 
     /// Initialize motor PWM hardware
-    pub fn init(
-        tim1: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::TIM1>>,
-        pa8: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::PA8>>,
-        pc13: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::PC13>>,
-        pa9: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::PA9>>,
-        pa12: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::PA12>>,
-        pa10: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::PA10>>,
-        pb15: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::PB15>>,
-    ) -> Self {
+    pub fn init(pins: PwmPins<'d>) -> Self {
         let config = MotorPwmConfig::default();
-        let pwm = MotorPwm::new(tim1, pa8, pc13, pa9, pa12, pa10, pb15, config);
+        let pwm = MotorPwm::new(pins, config);
+        profile::set_active(profile::from_parts(
+            "default",
+            &MotorParams::default(),
+            &config,
+        ));
         Self::new(pwm)
     }
 
+    /// Handle a message from the control queue
+    ///
+    /// `SelfTest` needs the hall sensors and runs in the control task instead.
+    pub fn handle_msg(&mut self, msg: ControlMsg) {
+        match msg {
+            ControlMsg::Command(cmd, trace) => self.handle_command(&cmd, trace),
+            ControlMsg::Internal(cmd) => self.handle_command(&cmd, Trace::NONE),
+            ControlMsg::ApplyProfile(p) => self.apply_profile(&p),
+            ControlMsg::SelfTest(_) => {}
+        }
+    }
+
+    /// Whether test/diagnostic motion is allowed
+    pub fn is_armed(&self) -> bool {
+        get_motor_armed()
+    }
+
+    /// Set which TIM1 output drives each logical phase
+    pub fn set_phase_map(&mut self, map: PhaseMap) {
+        self.pwm.set_phase_map(map);
+        PHASE_MAP.lock(|m| m.set(self.pwm.phase_map()));
+        log::info!("Phase map: {}", self.pwm.phase_map().channels);
+    }
+
+    /// Active phase map
+    pub fn phase_map(&self) -> PhaseMap {
+        self.pwm.phase_map()
+    }
+
+    /// Commutation step table in use
+    #[cfg(feature = "diagnostics")]
+    pub fn scan_scheme(&self) -> ScanScheme {
+        self.scan_scheme
+    }
+
+    /// What times the running steps
+    pub fn commutation_mode(&self) -> CommutationMode {
+        self.commutation_mode
+    }
+
+    /// Time the running steps by the run period or by the external trigger
+    ///
+    /// Takes effect with the next step, also while running; an edge seen
+    /// before the switch does not count.
+    pub fn set_commutation_mode(&mut self, mode: CommutationMode) {
+        ext_trigger::clear();
+        self.commutation_mode = mode;
+        COMMUTATION_MODE.lock(|m| m.set(mode));
+        log::info!("Commutation mode: {}", defmt::Debug2Format(&mode));
+    }
+
+    /// Stop the motor and switch the commutation step table
+    pub fn set_scan_scheme(&mut self, scheme: ScanScheme) {
+        if get_motor_state() == MotorState::Running {
+            self.stop();
+        }
+        let scheme = safe_mode::allowed_scheme(scheme);
+        self.scan_scheme = scheme;
+        SCAN_SCHEME.lock(|s| s.set(scheme));
+        log::info!("Scan scheme: {}", defmt::Debug2Format(&scheme));
+    }
+
+    /// Energize one commutation step at `duty`, outside the running sequencer
+    pub fn apply_step(&mut self, step: CommutationStep, duty: u8) {
+        self.pwm
+            .apply_phase_state(duty, &step.phase_state(self.scan_scheme));
+        blanking::mark_commutation();
+        set_motor_output(duty.min(safe_mode::DUTY_CEILING), step.as_u8());
+    }
+
+    /// Drive the phases directly, outside any commutation step (test patterns)
+    #[cfg(feature = "diagnostics")]
+    pub fn apply_phases_permille(&mut self, state: &PhaseState, duty_permille: u16) {
+        self.pwm.apply_phase_state_permille(duty_permille, state);
+        set_motor_output((duty_permille.min(1000) / 10) as u8, get_motor_step());
+    }
+
+    /// Hold each phase at its own duty in percent, outside the sequencer (see `phase_duty::run`)
+    #[cfg(feature = "diagnostics")]
+    pub fn apply_phase_duties(&mut self, a: u8, b: u8, c: u8) {
+        self.pwm.set_all_phase_duties(a, b, c);
+        set_motor_output(a.max(b).max(c).min(100), get_motor_step());
+    }
+
+    /// Last step energized, by the sequencer or `apply_step`
+    pub fn last_step(&self) -> CommutationStep {
+        CommutationStep::from_u8(get_motor_step())
+    }
+
+    /// Energize one commutation step at `duty_permille` (0.1% steps)
+    pub fn apply_step_permille(&mut self, step: CommutationStep, duty_permille: u16) {
+        self.pwm
+            .apply_phase_state_permille(duty_permille, &step.phase_state(self.scan_scheme));
+        blanking::mark_commutation();
+        set_motor_output((duty_permille.min(1000) / 10) as u8, step.as_u8());
+    }
+
+    /// Startup sequence tunables
+    pub fn startup_config(&self) -> &StartupConfig {
+        &self.startup
+    }
+
+    /// Regenerative braking tunables
+    pub fn regen_config(&self) -> &RegenConfig {
+        &self.regen_cfg
+    }
+
+    /// Resistance/inductance measurement tunables
+    #[cfg(feature = "diagnostics")]
+    pub fn rl_config(&self) -> &RlConfig {
+        &self.rl_cfg
+    }
+
+    /// Cogging scan tunables
+    #[cfg(feature = "diagnostics")]
+    pub fn cogging_config(&self) -> &CoggingConfig {
+        &self.cogging_cfg
+    }
+
+    /// ADC plausibility limits and the fixed fallbacks
+    pub fn adc_health_config(&self) -> &AdcHealthConfig {
+        &self.adc_cfg
+    }
+
+    /// Skipped and early step limits for the external trigger
+    pub fn sync_guard_config(&self) -> &SyncGuardConfig {
+        &self.sync_cfg
+    }
+
+    /// Freewheel coast-down tracking tunables
+    pub fn freewheel_config(&self) -> &FreewheelConfig {
+        &self.freewheel_cfg
+    }
+
+    /// Cut the drive of the running motor after `spin_ms`, then track the coast for up to `coast_ms`
+    pub fn plan_freewheel(&mut self, spin_ms: u16, coast_ms: u16, trace: Trace) {
+        let float_at = Instant::now() + Duration::from_millis(spin_ms as u64);
+        self.freewheel = Some(freewheel::Plan {
+            float_at,
+            coast_ms,
+            trace,
+        });
+        log::info!(
+            "Freewheel{}: spinning {}ms before the coast-down",
+            trace,
+            spin_ms
+        );
+    }
+
+    /// The freewheel test whose spin time is over, for the control task to run (see `freewheel::coast`)
+    pub fn take_freewheel_due(&mut self) -> Option<freewheel::Plan> {
+        match self.freewheel {
+            Some(plan)
+                if get_motor_state() == MotorState::Running && Instant::now() >= plan.float_at =>
+            {
+                self.freewheel.take()
+            }
+            _ => None,
+        }
+    }
+
+    /// Highest duty the PWM applies as asked, in 0.1% steps (see `MotorPwm::duty_ceiling_permille`)
+    #[cfg(feature = "diagnostics")]
+    pub fn duty_ceiling_permille(&self) -> u16 {
+        self.pwm.duty_ceiling_permille()
+    }
+
+    /// A controlled stop from running brakes with a regulated current instead of coasting
+    pub fn regen_enabled(&self) -> bool {
+        // The brake regulates on the shunt current; without it the motor coasts
+        self.regen_cfg.regen_current_limit_ma > 0 && adc_health::trusted(Signals::CURRENT)
+    }
+
+    /// Regen brake a reversal asked for, to be run by the control task (`regen::run`)
+    pub fn take_brake_request(&mut self) -> Option<u32> {
+        self.brake_request.take()
+    }
+
+    /// Dwell the running sequencer continues from; it accelerates from there to the run period
+    pub fn seed_dwell_ms(&mut self, ms: u32) {
+        self.dwell.reset(ms * 1000);
+    }
+
+    /// Short the windings through the low sides for `short_permille` of each PWM period
+    pub fn apply_brake_permille(&mut self, short_permille: u16) {
+        self.pwm.apply_brake_permille(short_permille);
+        set_motor_output(0, get_motor_step());
+    }
+
+    /// Turn every switch off and let the motor coast
+    pub fn coast(&mut self) {
+        self.pwm.outputs_disable();
+        set_motor_output(0, get_motor_step());
+    }
+
+    /// Stop without braking: with every switch already off, the stop neither discharges nor brakes
+    pub fn stop_coasting(&mut self) {
+        self.coast();
+        self.stop();
+    }
+
+    /// Charge the high-side bootstrap capacitors if nothing has since boot
+    ///
+    /// The control task calls this before any sequence that energizes the
+    /// bridge from standstill; only the first call switches anything.
+    pub fn precharge_bootstrap_once(&mut self) {
+        if self.bootstrap_charged {
+            return;
+        }
+        self.bootstrap_charged = true;
+        self.pwm.precharge_bootstrap();
+    }
+
+    /// Set all phases to 0% duty
+    pub fn all_phases_off(&mut self) {
+        self.pwm.emergency_stop();
+    }
+
+    /// Stop the motor and apply a complete parameter set
+    pub fn apply_profile(&mut self, p: &MotorProfile) {
+        self.stop();
+        let (params, pwm_config) = profile::to_parts(p);
+        self.params = params;
+        self.pwm.apply_config(&pwm_config);
+        profile::set_active(p.clone());
+        log::info!(
+            "Profile '{}' applied: pole_pairs={}, kv={}",
+            p.name.as_str(),
+            params.pole_pairs,
+            params.kv_rating
+        );
+    }
+
+    /// Motor physical parameters in use
+    pub fn params(&self) -> &MotorParams {
+        &self.params
+    }
+
+    /// Decide whether `cmd` may run now
+    ///
+    /// A start needs `get_min_off_ms()` in `Stopped` since the last stop, and repeated
+    /// starts within that time of each other are dropped; this keeps a
+    /// command storm from hammering the gate driver and supply. After an
+    /// `Estop`, nothing that moves the motor is admitted until `Arm`, and a
+    /// nudge, test pattern, R/L measurement, cogging scan, freewheel test or manual phase
+    /// duty always needs the motor armed; a freewheel test is also paced like a start.
+    pub fn admit(&self, cmd: &MotorCommand) -> Result<(), CommandRejection> {
+        #[cfg(not(feature = "diagnostics"))]
+        if matches!(
+            cmd,
+            MotorCommand::TestPattern { .. }
+                | MotorCommand::SetPhaseDuties { .. }
+                | MotorCommand::MeasureRL
+                | MotorCommand::CoggingScan { .. }
+                | MotorCommand::CaptureBemf { .. }
+        ) {
+            return Err(CommandRejection::NotBuilt);
+        }
+        let diagnostic = matches!(
+            cmd,
+            MotorCommand::Nudge { .. }
+                | MotorCommand::TestPattern { .. }
+                | MotorCommand::MeasureRL
+                | MotorCommand::CoggingScan { .. }
+                | MotorCommand::SetPhaseDuties { .. }
+                | MotorCommand::Freewheel { .. }
+        );
+        let moves = diagnostic
+            || matches!(
+                cmd,
+                MotorCommand::Start { .. }
+                    | MotorCommand::SetSpeed { .. }
+                    | MotorCommand::Reverse { .. }
+            );
+        if moves && ESTOP_LATCHED.load(Ordering::Relaxed) {
+            return Err(CommandRejection::EstopLatched);
+        }
+        if diagnostic && !self.is_armed() {
+            return Err(CommandRejection::Disarmed);
+        }
+        let needs = match cmd {
+            MotorCommand::MeasureRL => Signals::CURRENT.with(Signals::VBUS, true),
+            MotorCommand::CoggingScan { .. } => Signals::CURRENT,
+            _ => Signals::NONE,
+        };
+        if adc_health::untrusted().contains(needs) {
+            return Err(CommandRejection::AdcFault);
+        }
+        // A running motor's current would read as offset
+        if matches!(cmd, MotorCommand::CalibrateCurrent { .. })
+            && get_motor_state() == MotorState::Running
+        {
+            return Err(CommandRejection::MotorRunning);
+        }
+        if !matches!(
+            cmd,
+            MotorCommand::Start { .. } | MotorCommand::Freewheel { .. }
+        ) {
+            return Ok(());
+        }
+        let min_off = Duration::from_millis(get_min_off_ms() as u64);
+        match get_motor_state() {
+            MotorState::Running => match self.last_start {
+                Some(t) if t.elapsed() < min_off => Err(CommandRejection::StartDebounced),
+                _ => Ok(()),
+            },
+            _ => match self.last_stop {
+                Some(t) if t.elapsed() < min_off => Err(CommandRejection::MinOffTime {
+                    remaining_ms: (min_off - t.elapsed()).as_millis() as u16,
+                }),
+                _ => Ok(()),
+            },
+        }
+    }
+
     /// Handle motor command
-    pub fn handle_command(&mut self, cmd: &MotorCommand) {
+    ///
+    /// `trace` tags the log lines with the host's correlation id, if it sent one.
+    pub fn handle_command(&mut self, cmd: &MotorCommand, trace: Trace) {
         match cmd {
             MotorCommand::Stop => {
-                defmt::info!("Motor command: STOP");
+                log::info!("Motor command{}: STOP", trace);
                 self.stop();
             }
-            MotorCommand::Start { duty } => {
-                defmt::info!("Motor command: START duty={}", duty);
-                self.start(*duty);
+            MotorCommand::Start { duty, step } => {
+                log::info!("Motor command{}: START duty={} step={}", trace, duty, step);
+                self.start(*duty, step.map(CommutationStep::from_u8));
             }
             MotorCommand::SetSpeed { duty } => {
-                defmt::info!("Motor command: SET_SPEED duty={}", duty);
+                log::info!("Motor command{}: SET_SPEED duty={}", trace, duty);
                 self.set_speed(*duty);
             }
+            MotorCommand::Arm => {
+                log::info!("Motor command{}: ARM", trace);
+                ESTOP_LATCHED.store(false, Ordering::Relaxed);
+                set_motor_armed(true);
+            }
+            MotorCommand::Disarm => {
+                log::info!("Motor command{}: DISARM", trace);
+                self.stop();
+                set_motor_armed(false);
+            }
+            MotorCommand::Nudge { .. } => {
+                // Timed sequence; runs in the control task (see `nudge::run`)
+            }
+            MotorCommand::TestPattern { .. } => {
+                // Runs in the control task until the next command (see `test_pattern::run`)
+            }
+            MotorCommand::MeasureRL => {
+                // Timed sequence; runs in the control task (see `rl::run`)
+            }
+            MotorCommand::CoggingScan { .. } => {
+                // Needs the current sense and halls; runs in the control task (see `cogging::run`)
+            }
+            MotorCommand::SetPhaseDuties { .. } => {
+                // Runs in the control task until the next command (see `phase_duty::run`)
+            }
+            MotorCommand::CalibrateCurrent { .. } => {
+                // Needs the current sense; runs in the control task (see `current_cal::run`)
+            }
+            MotorCommand::Estop => {
+                log::warn!("Motor command{}: ESTOP", trace);
+                self.halt();
+                set_motor_armed(false);
+                ESTOP_LATCHED.store(true, Ordering::Relaxed);
+            }
+            MotorCommand::SetTelemetryMask { mask } => {
+                log::info!("Motor command{}: SET_TELEMETRY_MASK {=u16:#x}", trace, mask);
+                telemetry::set_mask(*mask);
+            }
+            MotorCommand::SetTelemetryDelta { key_interval } => {
+                log::info!(
+                    "Motor command{}: SET_TELEMETRY_DELTA key_interval={}",
+                    trace,
+                    key_interval
+                );
+                telemetry::set_key_interval(*key_interval);
+            }
+            MotorCommand::CaptureBemf { samples } => {
+                log::info!("Motor command{}: CAPTURE_BEMF samples={}", trace, samples);
+                #[cfg(feature = "diagnostics")]
+                bemf::arm(*samples);
+            }
+            MotorCommand::SetScanScheme { scheme } => {
+                log::info!("Motor command{}: SET_SCAN_SCHEME", trace);
+                self.set_scan_scheme(*scheme);
+            }
+            MotorCommand::SetLowSpeedCutoff {
+                min_rpm,
+                timeout_ms,
+            } => {
+                log::info!(
+                    "Motor command{}: SET_LOW_SPEED_CUTOFF min_rpm={} timeout={}ms",
+                    trace,
+                    min_rpm,
+                    timeout_ms
+                );
+                low_speed::configure(*min_rpm, *timeout_ms);
+                self.low_speed.reset();
+            }
+            MotorCommand::SetMaxPhaseVoltage { mv } => {
+                log::info!("Motor command{}: SET_MAX_PHASE_VOLTAGE {}mV", trace, mv);
+                MAX_PHASE_MV.store(*mv, Ordering::Relaxed);
+            }
+            MotorCommand::SetCommutationPeriodUs { us } => {
+                let applied = self.set_commutation_period_us(*us);
+                log::info!(
+                    "Motor command{}: SET_COMMUTATION_PERIOD {}us (applied {}us)",
+                    trace,
+                    us,
+                    applied
+                );
+            }
+            MotorCommand::Reverse { dwell_ms } => {
+                log::info!("Motor command{}: REVERSE dwell={}ms", trace, dwell_ms);
+                self.reverse(*dwell_ms);
+            }
+            MotorCommand::SetCommutationMode { mode } => {
+                log::info!("Motor command{}: SET_COMMUTATION_MODE", trace);
+                self.set_commutation_mode(*mode);
+            }
+            MotorCommand::Freewheel {
+                duty,
+                spin_ms,
+                coast_ms,
+            } => {
+                log::info!(
+                    "Motor command{}: FREEWHEEL duty={} spin={}ms coast={}ms",
+                    trace,
+                    duty,
+                    spin_ms,
+                    coast_ms
+                );
+                // From standstill the control task starts the motor first (see `freewheel`)
+                if get_motor_state() == MotorState::Running {
+                    self.set_speed(*duty);
+                    self.plan_freewheel(*spin_ms, *coast_ms, trace);
+                }
+            }
         }
     }
 
-    /// Start the motor with specified duty cycle
-    fn start(&mut self, duty: u8) {
-        let duty = duty.min(100);
+    /// Start the motor with specified duty cycle, from `step` or the configured start step
+    ///
+    /// Without a startup sequence nothing aligns the rotor, so a start that
+    /// names no step and has no fixed `start_step` continues from the step
+    /// last energized.
+    fn start(&mut self, duty: u8, step: Option<CommutationStep>) {
+        let step = step
+            .or(self.startup.start_step)
+            .unwrap_or_else(|| self.last_step());
+        self.start_at(duty, step);
+    }
+
+    /// Enter the running state with `step` as the next step to commutate
+    ///
+    /// Used after the startup sequence has aligned and ramped the rotor. The
+    /// reported duty and step change with the first commutation.
+    pub fn start_at(&mut self, duty: u8, step: CommutationStep) {
+        let duty = duty.min(safe_mode::DUTY_CEILING);
         self.target_duty = duty;
-        set_motor_duty(duty);
         set_motor_state(MotorState::Running);
 
-        // Reset to step 0
-        self.current_step = CommutationStep::Step0;
-        set_motor_step(0);
+        // The startup ramp always runs forward
+        self.reversal = None;
+        self.direction = Direction::Forward;
+        self.sequencer.set_direction(Direction::Forward);
+        self.sequencer.reset(step);
+        self.low_speed.reset();
+        self.stall.reset(duty);
+        self.protection.reset();
+        set_fault(None);
+        self.last_start = Some(Instant::now());
+        // An external trigger times steps from the first edge after the start
+        ext_trigger::clear();
 
-        defmt::info!("Motor started: duty={}%", duty);
+        log::info!("Motor started: duty={}%", duty);
     }
 
-    /// Stop the motor
+    /// Stop the motor (coasts with the outputs off unless the PWM config brakes)
     fn stop(&mut self) {
+        self.pwm.stop_outputs();
+        self.settle_stopped();
+    }
+
+    /// Stop on `fault`, leaving every phase off (see `MotorPwm::fault_outputs`)
+    fn fault_stop(&mut self, fault: Fault) {
+        set_fault(Some(fault));
+        self.halt();
+    }
+
+    /// Stop into a latched state (a fault or an estop), with the bridge verified off
+    fn halt(&mut self) {
+        if !self.pwm.fault_outputs() {
+            log::error!("Bridge still driven after forcing the outputs off");
+        }
+        self.settle_stopped();
+    }
+
+    /// Controller state after the bridge was turned off
+    fn settle_stopped(&mut self) {
         self.target_duty = 0;
-        self.pwm.emergency_stop();
+        self.reversal = None;
+        self.freewheel = None;
+        self.stall.reset(0);
+        self.brake_request = None;
+        self.protection.reset();
+        LIMP_ACTIVE.store(false, Ordering::Relaxed);
+        self.last_stop = Some(Instant::now());
         set_motor_state(MotorState::Stopped);
-        set_motor_duty(0);
-        defmt::info!("Motor stopped");
+        set_motor_output(0, get_motor_step());
+        log::info!("Motor stopped");
     }
 
-    /// Set motor speed (adjust duty while running)
+    /// Set motor speed (adjust duty while running); applied at the next commutation
     fn set_speed(&mut self, duty: u8) {
-        let duty = duty.min(100);
+        if self.reversal.is_some() {
+            log::warn!("Speed change ignored: reversing");
+            return;
+        }
+        let duty = duty.min(safe_mode::DUTY_CEILING);
+        self.target_duty = duty;
+        self.stall.set_duty(duty);
+        log::info!("Motor speed set: duty={}%", duty);
+    }
+
+    /// Begin reversing from the running duty (see `reverse`)
+    fn reverse(&mut self, dwell_ms: u16) {
+        if get_motor_state() != MotorState::Running {
+            log::warn!("Reverse ignored: motor not running");
+            return;
+        }
+        if self.reversal.is_some() {
+            log::warn!("Reverse ignored: already reversing");
+            return;
+        }
+        // A commanded reversal takes over from any stall attempt
+        self.stall.reset(self.stall.duty());
+        let now_ms = Instant::now().as_millis() as u32;
+        self.reversal = Some(Reversal::new(
+            self.target_duty,
+            dwell_ms,
+            &self.reverse_cfg,
+            now_ms,
+        ));
+    }
+
+    /// Move the reversal in progress on; `false` while the rotor is left to settle
+    fn advance_reversal(&mut self) -> bool {
+        let Some(rev) = self.reversal.as_mut() else {
+            return true;
+        };
+        let (duty, entered) = rev.advance(Instant::now().as_millis() as u32);
+        let (from, dwell_ms) = (rev.duty(), rev.dwell_ms());
         self.target_duty = duty;
-        set_motor_duty(duty);
-        defmt::info!("Motor speed set: duty={}%", duty);
+        match entered {
+            Some(Phase::Dwell) => {
+                if self.regen_enabled() {
+                    self.brake_request = Some(dwell_ms.min(self.regen_cfg.max_brake_ms as u32));
+                } else {
+                    self.pwm.stop_outputs();
+                }
+                set_motor_output(0, get_motor_step());
+                self.direction = self.direction.opposite();
+                self.sequencer.set_direction(self.direction);
+                log::info!("Reverse: at zero duty, settling");
+            }
+            Some(Phase::Accelerate) => {
+                // From standstill: pick up at the ramp start period, as after startup
+                let step = match self.direction {
+                    Direction::Forward => self.last_step().next(),
+                    Direction::Reverse => self.last_step().prev(),
+                };
+                self.sequencer.reset(step);
+                self.dwell
+                    .reset(self.startup.ramp_start_period_ms as u32 * 1000);
+                self.low_speed.reset();
+                log::info!("Reverse: driving {}", defmt::Debug2Format(&self.direction));
+            }
+            Some(Phase::Done) => {
+                self.reversal = None;
+                log::info!("Reverse done: duty={}%", from);
+            }
+            _ => {}
+        }
+        !matches!(
+            self.reversal.as_ref().map(Reversal::phase),
+            Some(Phase::Dwell)
+        )
     }
 
     /// Perform one commutation step
@@ -176,34 +1107,220 @@ impl<'d> MotorController<'d> {
             return;
         }
 
-        // Get phase states for current step
-        let (ph_a_en, ph_b_en, ph_c_en, _ph_a_high, _ph_b_high, _ph_c_high) =
-            self.current_step.get_phase_states();
+        self.check_break();
+        if get_motor_state() != MotorState::Running {
+            return;
+        }
+        // Passing through zero is the point of a reversal, not a stall
+        if self.reversal.is_none() {
+            self.check_low_speed();
+            self.check_stall_recovery();
+        }
+        self.check_protection();
+        if get_motor_state() != MotorState::Running || !self.advance_reversal() {
+            return;
+        }
+
+        // Limp mode clamps what is applied, not what was commanded
+        let duty = if get_limp_active() {
+            self.target_duty.min(self.protection_cfg.limp_duty)
+        } else {
+            self.target_duty
+        };
+
+        // The active strategy picks the phases; outputs change only through the driver
+        let table = six_step::table(self.scan_scheme);
+        if let Some(step) = commutator::tick(&mut self.sequencer, &mut self.pwm, table, duty) {
+            blanking::mark_commutation();
+            set_motor_output(duty, step.as_u8());
+            // Dwell of this step, one schedule step closer to the run period;
+            // with the external trigger, the time between its last two edges
+            let ramped = self
+                .dwell
+                .advance(self.commutation_period_us, self.accel.steps_per_s2);
+            let dwell_us = match self.commutation_mode {
+                CommutationMode::OpenLoop => ramped,
+                CommutationMode::External => ext_trigger::interval_us().unwrap_or(ramped),
+            };
+            STEP_DWELL_US.store(dwell_us, Ordering::Relaxed);
+            COMMUTATION_PERIOD_MS.store((dwell_us / 1000).max(1), Ordering::Relaxed);
+        }
+    }
+
+    /// Rescale the duty ceiling for the latest bus voltage reading
+    ///
+    /// An untrusted reading is replaced by `AdcHealthConfig::assumed_vbus_mv`.
+    pub fn limit_phase_voltage(&mut self, vbus_mv: u16) {
+        let vbus_mv = if adc_health::trusted(Signals::VBUS) {
+            vbus_mv
+        } else {
+            self.adc_cfg.assumed_vbus_mv
+        };
+        self.pwm
+            .set_voltage_limit(get_max_phase_voltage_mv(), vbus_mv);
+    }
 
-        // Apply commutation pattern with current duty cycle
-        self.pwm.apply_commutation(
+    /// Reduce duty or stop after too long below the low-speed cutoff
+    fn check_low_speed(&mut self) {
+        let verdict = self.low_speed.check(
+            get_rpm_estimate(),
             self.target_duty,
-            ph_a_en,
-            ph_b_en,
-            ph_c_en,
+            low_speed::min_rpm(),
+            low_speed::timeout_ms(),
+            Instant::now().as_millis(),
         );
+        match verdict {
+            Verdict::Ok => {}
+            Verdict::ReduceDuty(duty) => {
+                log::warn!(
+                    "Below {} rpm for {}ms: duty {}% -> {}%",
+                    low_speed::min_rpm(),
+                    low_speed::timeout_ms(),
+                    self.target_duty,
+                    duty
+                );
+                self.target_duty = duty;
+            }
+            Verdict::Stop => self.on_stall(),
+        }
+    }
 
-        // Update global state
-        set_motor_step(self.current_step.as_u8());
+    /// Reverse to free a stalled rotor while attempts remain, otherwise stop
+    fn on_stall(&mut self) {
+        let retries = self.reverse_cfg.stall_retries;
+        if self.stall.on_stall(self.direction, retries) {
+            log::warn!(
+                "Still below {} rpm at minimum duty: reversing to free it (attempt {}/{})",
+                low_speed::min_rpm(),
+                self.stall.attempts(),
+                retries
+            );
+            let now_ms = Instant::now().as_millis() as u32;
+            let dwell_ms = self.reverse_cfg.stall_dwell_ms;
+            self.reversal = Some(Reversal::from_standstill(
+                self.stall.duty(),
+                dwell_ms,
+                &self.reverse_cfg,
+                now_ms,
+            ));
+        } else if retries > 0 {
+            log::error!("Still stalled after {} reverse attempts: stopping", retries);
+            self.fault_stop(Fault::Stall);
+        } else {
+            log::warn!(
+                "Still below {} rpm at minimum duty: stopping",
+                low_speed::min_rpm()
+            );
+            self.stop();
+        }
+    }
 
-        // Advance to next step
-        self.current_step = self.current_step.next();
+    /// Turn back once a stall attempt has backed off; end it once the original direction is up to speed
+    fn check_stall_recovery(&mut self) {
+        if get_motor_state() != MotorState::Running
+            || self.reversal.is_some()
+            || !self.stall.active()
+        {
+            return;
+        }
+        let now_ms = Instant::now().as_millis() as u32;
+        if self
+            .stall
+            .backed_off(self.direction, now_ms, self.reverse_cfg.stall_backoff_ms)
+        {
+            log::info!("Stall attempt: backed off, reversing to retry");
+            let dwell_ms = self.reverse_cfg.stall_dwell_ms;
+            self.reversal = Some(Reversal::new(
+                self.target_duty,
+                dwell_ms,
+                &self.reverse_cfg,
+                now_ms,
+            ));
+        } else if get_rpm_estimate() >= low_speed::min_rpm() && self.stall.recovered(self.direction)
+        {
+            log::info!("Stall cleared: running at {} rpm", get_rpm_estimate());
+        }
     }
 
-    /// Get commutation period based on desired speed
+    /// Latch a gate driver fault if the break input forced the outputs off
+    ///
+    /// The hardware has stopped switching by the time this runs; what is left
+    /// is recording why and moving the controller to `Stopped`.
+    fn check_break(&mut self) {
+        if self.pwm.take_break() {
+            log::error!("Break input tripped: PWM outputs forced off, stopping");
+            self.fault_stop(Fault::GateDriver);
+        }
+    }
+
+    /// Stop after the external trigger kept missing its schedule (see `sync_guard`)
+    pub fn lose_sync(&mut self) {
+        log::error!(
+            "Commutation trigger lost: {} steps in a row without an edge, stopping",
+            self.sync_cfg.max_corrections as u16 + 1
+        );
+        self.fault_stop(Fault::LostSync);
+    }
+
+    /// Stop on a hard fault; enter or leave limp mode on soft ones
+    fn check_protection(&mut self) {
+        let readings = telemetry::readings();
+        let limp = match self.protection.check(&readings, &self.protection_cfg) {
+            protection::Verdict::Run => false,
+            protection::Verdict::Limp(fault) => {
+                if !get_limp_active() {
+                    log::warn!(
+                        "{}: limp mode, duty clamped to {}%",
+                        fault,
+                        self.protection_cfg.limp_duty
+                    );
+                }
+                set_fault(Some(fault));
+                true
+            }
+            protection::Verdict::Stop(fault) => {
+                log::error!(
+                    "{}: stopping (current={}mA temp={}dC vbus={}mV)",
+                    fault,
+                    readings.current_ma,
+                    readings.temp_dc,
+                    readings.vbus_mv
+                );
+                self.fault_stop(fault);
+                return;
+            }
+        };
+        if !limp && get_limp_active() {
+            log::info!("Fault cleared: full power restored");
+            set_fault(None);
+        }
+        LIMP_ACTIVE.store(limp, Ordering::Relaxed);
+    }
+
+    /// Time until the next commutation
+    ///
+    /// While running this is the scheduled dwell of the current step, which
+    /// shortens towards the run period as the motor accelerates (see `accel`);
+    /// otherwise it is the run period itself.
     pub fn get_commutation_period(&self) -> Duration {
-        // For now, use fixed period
-        // TODO: Calculate based on duty cycle for smoother speed control
-        Duration::from_millis(self.commutation_period_ms as u64)
+        if get_motor_state() == MotorState::Running {
+            Duration::from_micros(self.dwell.dwell_us() as u64)
+        } else {
+            Duration::from_micros(self.commutation_period_us as u64)
+        }
     }
 
-    /// Set commutation period (for speed tuning)
-    pub fn set_commutation_period_ms(&mut self, period_ms: u32) {
-        self.commutation_period_ms = period_ms;
+    /// Set the run commutation period; a running motor accelerates to it on the schedule
+    ///
+    /// The period is clamped to `MIN_COMMUTATION_PERIOD_US..=MAX_COMMUTATION_PERIOD_US`
+    /// (raised to `safe_mode::MIN_PERIOD_US` in safe mode); returns the applied value.
+    pub fn set_commutation_period_us(&mut self, period_us: u32) -> u32 {
+        let period_us = period_us.clamp(safe_mode::MIN_PERIOD_US, MAX_COMMUTATION_PERIOD_US);
+        self.commutation_period_us = period_us;
+        RUN_PERIOD_US.store(period_us, Ordering::Relaxed);
+        if get_motor_state() != MotorState::Running {
+            COMMUTATION_PERIOD_MS.store((period_us / 1000).max(1), Ordering::Relaxed);
+        }
+        period_us
     }
 }
@@ -0,0 +1,59 @@
+//! Duty-limited nudge: a bounded, one-shot move of a few commutation steps
+//!
+//! Used for checking rotor detents and phase alignment by hand. The sequence
+//! starts from the last energized step, holds each new step for a fixed dwell
+//! (ignoring the open-loop commutation timer), then turns all phases off.
+
+use embassy_time::{Duration, Timer};
+use oxifoc_protocol::MotorCommand;
+use oxifoc_protocol::constants::{MAX_NUDGE_DUTY, MAX_NUDGE_STEPS};
+
+use super::{ControlMsg, ControlReceiver, MotorController, Trace, poll_control};
+use crate::log;
+
+/// Hold per step; long enough for the rotor to settle into the detent
+const STEP_DWELL: Duration = Duration::from_millis(150);
+/// Abort poll interval within a dwell
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Run a nudge of `steps` (negative = reverse) at `duty`
+///
+/// Any control message arriving during the move aborts it; the message is
+/// returned so the caller can still act on it.
+pub async fn run(
+    motor: &mut MotorController<'_>,
+    steps: i8,
+    duty: u8,
+    trace: Trace,
+    control: &ControlReceiver,
+) -> Option<ControlMsg> {
+    if !motor.is_armed() {
+        log::warn!("Nudge{} rejected: not armed", trace);
+        return None;
+    }
+
+    let count = steps.unsigned_abs().min(MAX_NUDGE_STEPS);
+    let duty = duty.min(MAX_NUDGE_DUTY);
+    let dir = if steps < 0 { "reverse" } else { "forward" };
+    log::info!("Nudge{}: {} steps {} at duty={}%", trace, count, dir, duty);
+    motor.handle_command(&MotorCommand::Stop, trace);
+
+    let mut step = motor.last_step();
+    let polls_per_step = (STEP_DWELL.as_ticks() / POLL_INTERVAL.as_ticks()).max(1);
+
+    for _ in 0..count {
+        step = if steps < 0 { step.prev() } else { step.next() };
+        motor.apply_step(step, duty);
+        for _ in 0..polls_per_step {
+            Timer::after(POLL_INTERVAL).await;
+            if let Some(msg) = poll_control(control) {
+                motor.all_phases_off();
+                log::warn!("Nudge{} aborted by incoming command", trace);
+                return Some(msg);
+            }
+        }
+    }
+    motor.all_phases_off();
+    log::info!("Nudge{} done at step {}", trace, step.as_u8());
+    None
+}
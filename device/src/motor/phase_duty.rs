@@ -0,0 +1,63 @@
+//! Manual duty per phase (`MotorCommand::SetPhaseDuties`)
+//!
+//! The lowest-level drive there is: each phase switches at a duty of its
+//! own, complementary with dead time, and the commutation sequencer is not
+//! involved at all. Meant for checking the PWM stage and for trying out a
+//! modulation scheme from the host before it is written in firmware. Like a
+//! test pattern it needs the motor armed and holds until the next control
+//! message. A further `SetPhaseDuties` only moves the duties, without the
+//! outputs going off in between, so the host can step through a waveform;
+//! anything else turns the phases off and is handed back to the caller. The
+//! duties are bounded like every other: `max_duty_percent`, the VBUS-scaled
+//! ceiling and safe mode.
+
+use embassy_time::{Duration, Timer};
+use oxifoc_protocol::MotorCommand;
+
+use super::{ControlMsg, ControlReceiver, MotorController, Trace, poll_control};
+use crate::log;
+
+/// Abort poll interval
+const TICK: Duration = Duration::from_millis(1);
+
+/// Hold phases A, B, C at `duties` percent until a control message other than a new set of duties
+///
+/// The message is returned so the caller can still act on it; all phases
+/// are at 0% by then.
+pub async fn run(
+    motor: &mut MotorController<'_>,
+    duties: [u8; 3],
+    trace: Trace,
+    control: &ControlReceiver,
+) -> Option<ControlMsg> {
+    if !motor.is_armed() {
+        log::warn!("Manual phase duties{} rejected: not armed", trace);
+        return None;
+    }
+    motor.handle_command(&MotorCommand::Stop, trace);
+
+    let mut duties = duties;
+    let mut trace = trace;
+    loop {
+        let [a, b, c] = duties;
+        log::info!("Manual phase duties{}: A={}% B={}% C={}%", trace, a, b, c);
+        motor.apply_phase_duties(a, b, c);
+        let msg = loop {
+            Timer::after(TICK).await;
+            if let Some(msg) = poll_control(control) {
+                break msg;
+            }
+        };
+        match msg {
+            ControlMsg::Command(MotorCommand::SetPhaseDuties { a, b, c }, next) => {
+                duties = [a, b, c];
+                trace = next;
+            }
+            msg => {
+                motor.all_phases_off();
+                log::info!("Manual phase duties{} ended by incoming command", trace);
+                return Some(msg);
+            }
+        }
+    }
+}
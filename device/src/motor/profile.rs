@@ -0,0 +1,118 @@
+//! Named motor parameter presets persisted to flash
+//!
+//! A profile bundles the motor constants and PWM setup that otherwise have to be
+//! re-entered when switching test motors. Loading hands the profile to the motor
+//! control task, which stops the motor and applies the whole set at once.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use heapless::{String, Vec};
+use oxifoc_protocol::{MAX_PROFILES, MotorProfile, ProfileCommand, ProfileResponse};
+
+use super::pwm::MotorPwmConfig;
+use super::safe_mode;
+use super::{ControlMsg, ControlSender, MotorParams};
+use crate::log;
+use crate::storage::{self, RecordKey};
+
+type ProfileTable = Vec<MotorProfile, MAX_PROFILES>;
+
+/// Parameter set currently applied by the motor controller
+static ACTIVE: Mutex<CriticalSectionRawMutex, RefCell<Option<MotorProfile>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Build a profile from the controller's parameter structs
+pub fn from_parts(name: &str, params: &MotorParams, pwm: &MotorPwmConfig) -> MotorProfile {
+    let mut n: String<16> = String::new();
+    for c in name.chars() {
+        if n.push(c).is_err() {
+            break;
+        }
+    }
+    MotorProfile {
+        name: n,
+        pole_pairs: params.pole_pairs,
+        kv: params.kv_rating,
+        // The frequency actually produced, also when set through `duty_steps`
+        pwm_freq: pwm.timing().map_or(pwm.pwm_freq, |t| t.freq_hz),
+        dead_time_ns: pwm.dead_time_ns,
+        max_duty: pwm.max_duty_percent,
+    }
+}
+
+/// Split a profile into the controller's parameter structs
+pub fn to_parts(profile: &MotorProfile) -> (MotorParams, MotorPwmConfig) {
+    let params = MotorParams {
+        pole_pairs: profile.pole_pairs,
+        kv_rating: profile.kv,
+    };
+    let pwm = MotorPwmConfig {
+        pwm_freq: profile.pwm_freq,
+        dead_time_ns: profile.dead_time_ns,
+        max_duty_percent: profile.max_duty.min(safe_mode::DUTY_CEILING),
+        // Output polarity belongs to the board, not the motor
+        ..MotorPwmConfig::default()
+    };
+    (params, pwm)
+}
+
+/// Record the profile the controller is now running with
+pub fn set_active(profile: MotorProfile) {
+    ACTIVE.lock(|a| *a.borrow_mut() = Some(profile));
+}
+
+/// Profile the controller is currently running with
+pub fn active() -> MotorProfile {
+    ACTIVE.lock(|a| a.borrow().clone()).unwrap_or_else(|| {
+        from_parts(
+            "default",
+            &MotorParams::default(),
+            &MotorPwmConfig::default(),
+        )
+    })
+}
+
+async fn load_table() -> ProfileTable {
+    storage::load(RecordKey::Profiles).await.unwrap_or_default()
+}
+
+/// Execute a profile command from the host
+pub async fn handle(cmd: &ProfileCommand, control: &ControlSender) -> ProfileResponse {
+    match cmd {
+        ProfileCommand::List => ProfileResponse::List(load_table().await),
+        ProfileCommand::Save { name } => {
+            let mut table = load_table().await;
+            let mut profile = active();
+            profile.name = name.clone();
+            if let Some(slot) = table.iter_mut().find(|p| p.name == *name) {
+                *slot = profile;
+            } else if table.push(profile).is_err() {
+                log::warn!("Profile save '{}': table full", name.as_str());
+                return ProfileResponse::Full;
+            }
+            match storage::store(RecordKey::Profiles, &table).await {
+                Ok(()) => {
+                    log::info!("Profile saved: '{}'", name.as_str());
+                    ProfileResponse::Saved
+                }
+                Err(e) => {
+                    log::error!("Profile save '{}' failed: {}", name.as_str(), e);
+                    ProfileResponse::StorageError
+                }
+            }
+        }
+        ProfileCommand::Load { name } => {
+            let table = load_table().await;
+            let Some(profile) = table.into_iter().find(|p| p.name == *name) else {
+                log::warn!("Profile load '{}': not found", name.as_str());
+                return ProfileResponse::NotFound;
+            };
+            control
+                .send(ControlMsg::ApplyProfile(profile.clone()))
+                .await;
+            ProfileResponse::Loaded(profile)
+        }
+    }
+}
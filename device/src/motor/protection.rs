@@ -0,0 +1,284 @@
+//! Fault protection: hard faults stop the motor, soft faults may limp
+//!
+//! The control task checks its latest analog readings before every
+//! commutation. Overcurrent is a hard fault and always stops the motor. Board
+//! overtemperature and supply brownout are soft: depending on their
+//! `FaultAction` they either stop it too or put it in limp mode, where the
+//! applied duty is clamped to `limp_duty` while the commanded duty is kept.
+//! Full power returns by itself once the reading is back past a hysteresis
+//! band, so a reading hovering at the threshold does not toggle the mode.
+//!
+//! A reading the ADC health check no longer trusts is not checked against
+//! its limit; the motor limps instead for as long as any is untrusted, as
+//! the fixed fallback for limits that cannot be watched (see `adc_health`).
+
+use super::adc_health::Signals;
+
+/// What a soft fault does while it lasts
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum FaultAction {
+    /// Stop the motor, as for a hard fault
+    Stop,
+    /// Keep running with duty clamped to `ProtectionConfig::limp_duty`
+    Limp,
+}
+
+/// Conditions the protection logic watches
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Fault {
+    /// Winding current above `overcurrent_ma` (hard)
+    Overcurrent,
+    /// Board temperature above `overtemp_dc` (soft)
+    Overtemperature,
+    /// Bus voltage below `brownout_mv` (soft)
+    Brownout,
+    /// TIM1 break input tripped, outputs already forced off (hard, see `pwm::BreakConfig`)
+    GateDriver,
+    /// Still below the low-speed cutoff after every stall attempt (hard, see `reverse::StallRecovery`)
+    Stall,
+    /// ADC readings implausible, protection on fixed limits (soft, always limps, see `adc_health`)
+    Adc,
+    /// The external trigger missed too many steps in a row (hard, see `sync_guard`)
+    LostSync,
+}
+
+impl From<Fault> for oxifoc_protocol::FaultKind {
+    fn from(fault: Fault) -> Self {
+        match fault {
+            Fault::Overcurrent => Self::Overcurrent,
+            Fault::Overtemperature => Self::Overtemperature,
+            Fault::Brownout => Self::Brownout,
+            Fault::GateDriver => Self::GateDriver,
+            Fault::Stall => Self::Stall,
+            Fault::Adc => Self::Adc,
+            Fault::LostSync => Self::LostSync,
+        }
+    }
+}
+
+/// Protection tunables
+#[derive(Clone, Copy)]
+pub struct ProtectionConfig {
+    /// Winding current that stops the motor, in mA (0 disables)
+    pub overcurrent_ma: u16,
+    /// Board temperature that triggers `overtemp_action`, in 0.1 °C
+    pub overtemp_dc: i16,
+    pub overtemp_action: FaultAction,
+    /// Bus voltage below which `brownout_action` applies, in mV (0 disables)
+    pub brownout_mv: u16,
+    pub brownout_action: FaultAction,
+    /// Duty ceiling in limp mode, in percent
+    pub limp_duty: u8,
+}
+
+impl Default for ProtectionConfig {
+    fn default() -> Self {
+        Self {
+            overcurrent_ma: 8000,
+            overtemp_dc: 800, // 80 °C
+            overtemp_action: FaultAction::Limp,
+            brownout_mv: 9000, // empty 3S pack
+            // Draining an empty pack further damages it
+            brownout_action: FaultAction::Stop,
+            limp_duty: 5,
+        }
+    }
+}
+
+/// Cooling needed before an overtemperature clears
+const TEMP_HYSTERESIS_DC: i16 = 50;
+/// Recovery needed before a brownout clears
+const VBUS_HYSTERESIS_MV: u16 = 500;
+
+/// Analog readings checked against the limits
+#[derive(Clone, Copy)]
+pub struct Readings {
+    pub current_ma: u16,
+    pub temp_dc: i16,
+    /// 0 = not measured yet; brownout is not checked
+    pub vbus_mv: u16,
+    /// Readings the ADC health check does not trust
+    pub untrusted: Signals,
+}
+
+/// What the controller must do after a check
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Verdict {
+    Run,
+    /// Clamp duty to `limp_duty` because of this soft fault
+    Limp(Fault),
+    /// Stop the motor
+    Stop(Fault),
+}
+
+/// Soft fault state, kept between checks for the hysteresis
+#[derive(Default)]
+pub struct Protection {
+    overtemp: bool,
+    brownout: bool,
+}
+
+impl Protection {
+    /// Forget active soft faults (call when the motor stops)
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Check one set of readings taken while running
+    pub fn check(&mut self, r: &Readings, cfg: &ProtectionConfig) -> Verdict {
+        let trusted = |signal| !r.untrusted.contains(signal);
+        if cfg.overcurrent_ma != 0
+            && trusted(Signals::CURRENT)
+            && r.current_ma >= cfg.overcurrent_ma
+        {
+            return Verdict::Stop(Fault::Overcurrent);
+        }
+        self.overtemp = trusted(Signals::TEMP)
+            && if self.overtemp {
+                r.temp_dc > cfg.overtemp_dc.saturating_sub(TEMP_HYSTERESIS_DC)
+            } else {
+                r.temp_dc >= cfg.overtemp_dc
+            };
+        self.brownout = cfg.brownout_mv != 0
+            && trusted(Signals::VBUS)
+            && r.vbus_mv != 0
+            && if self.brownout {
+                r.vbus_mv < cfg.brownout_mv.saturating_add(VBUS_HYSTERESIS_MV)
+            } else {
+                r.vbus_mv < cfg.brownout_mv
+            };
+
+        let soft = [
+            (self.overtemp, Fault::Overtemperature, cfg.overtemp_action),
+            (self.brownout, Fault::Brownout, cfg.brownout_action),
+            (!r.untrusted.is_empty(), Fault::Adc, FaultAction::Limp),
+        ];
+        let mut verdict = Verdict::Run;
+        for (active, fault, action) in soft {
+            match (active, action) {
+                (false, _) => {}
+                (true, FaultAction::Stop) => return Verdict::Stop(fault),
+                (true, FaultAction::Limp) if verdict == Verdict::Run => {
+                    verdict = Verdict::Limp(fault)
+                }
+                (true, FaultAction::Limp) => {}
+            }
+        }
+        verdict
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NORMAL: Readings = Readings {
+        current_ma: 1000,
+        temp_dc: 300,
+        vbus_mv: 12_000,
+        untrusted: Signals::NONE,
+    };
+
+    #[test]
+    fn test_overcurrent_always_stops() {
+        let mut p = Protection::default();
+        let cfg = ProtectionConfig::default();
+        assert_eq!(p.check(&NORMAL, &cfg), Verdict::Run);
+        let hot_and_high = Readings {
+            current_ma: 8000,
+            temp_dc: 900,
+            ..NORMAL
+        };
+        assert_eq!(
+            p.check(&hot_and_high, &cfg),
+            Verdict::Stop(Fault::Overcurrent)
+        );
+    }
+
+    #[test]
+    fn test_overtemperature_limps_then_recovers_past_hysteresis() {
+        let mut p = Protection::default();
+        let cfg = ProtectionConfig::default();
+        let at = |temp_dc| Readings { temp_dc, ..NORMAL };
+        assert_eq!(p.check(&at(799), &cfg), Verdict::Run);
+        assert_eq!(
+            p.check(&at(800), &cfg),
+            Verdict::Limp(Fault::Overtemperature)
+        );
+        assert_eq!(
+            p.check(&at(760), &cfg),
+            Verdict::Limp(Fault::Overtemperature)
+        );
+        assert_eq!(p.check(&at(750), &cfg), Verdict::Run);
+        assert_eq!(p.check(&at(790), &cfg), Verdict::Run);
+    }
+
+    #[test]
+    fn test_soft_fault_action_is_per_fault() {
+        let mut p = Protection::default();
+        let cfg = ProtectionConfig {
+            brownout_action: FaultAction::Stop,
+            ..ProtectionConfig::default()
+        };
+        let hot = Readings {
+            temp_dc: 850,
+            ..NORMAL
+        };
+        assert_eq!(p.check(&hot, &cfg), Verdict::Limp(Fault::Overtemperature));
+        let hot_and_sagging = Readings {
+            vbus_mv: 8500,
+            ..hot
+        };
+        assert_eq!(
+            p.check(&hot_and_sagging, &cfg),
+            Verdict::Stop(Fault::Brownout)
+        );
+        // An unmeasured bus is not a brownout
+        p.reset();
+        assert_eq!(
+            p.check(
+                &Readings {
+                    vbus_mv: 0,
+                    ..NORMAL
+                },
+                &cfg
+            ),
+            Verdict::Run
+        );
+    }
+
+    #[test]
+    fn test_untrusted_readings_are_not_acted_on_and_limp_instead() {
+        let mut p = Protection::default();
+        let cfg = ProtectionConfig {
+            overtemp_action: FaultAction::Stop,
+            brownout_action: FaultAction::Limp,
+            ..ProtectionConfig::default()
+        };
+        // A railed shunt and an open NTC would stop the motor if believed
+        let railed = Readings {
+            current_ma: 60_000,
+            temp_dc: 1239,
+            ..NORMAL
+        };
+        let untrusted = Signals::CURRENT.with(Signals::TEMP, true);
+        assert_eq!(
+            p.check(
+                &Readings {
+                    untrusted,
+                    ..railed
+                },
+                &cfg
+            ),
+            Verdict::Limp(Fault::Adc)
+        );
+        // The signals still trusted are checked as usual
+        let sagging = Readings {
+            vbus_mv: 8500,
+            untrusted,
+            ..railed
+        };
+        assert_eq!(p.check(&sagging, &cfg), Verdict::Limp(Fault::Brownout));
+        assert_eq!(p.check(&railed, &cfg), Verdict::Stop(Fault::Overcurrent));
+    }
+}
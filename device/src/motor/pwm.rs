@@ -1,25 +1,193 @@
 //! TIM1 complementary PWM configuration for 3-phase motor control
 
+use cortex_m::peripheral::DWT;
+use embassy_stm32::Peri;
 use embassy_stm32::gpio::OutputType;
-use embassy_stm32::time::khz;
+use embassy_stm32::pac::gpio::vals::{Moder, Pupdr};
+use embassy_stm32::pac::timer::vals::{Bkinp, Bkp, FilterValue, Mms, Ossi, Ossr};
+use embassy_stm32::peripherals::{PA8, PA9, PA10, PA12, PB12, PB15, PC13, TIM1};
+use embassy_stm32::time::Hertz;
 use embassy_stm32::timer::Channel;
 use embassy_stm32::timer::complementary_pwm::{ComplementaryPwm, ComplementaryPwmPin};
+use embassy_stm32::timer::low_level::{CountingMode, OutputPolarity};
 use embassy_stm32::timer::simple_pwm::PwmPin;
-use embassy_stm32::timer::low_level::CountingMode;
+use oxifoc_protocol::PhaseMap;
+
+use super::blanking::CYCLES_PER_US;
+use super::commutator::PhaseDriver;
+use super::safe_mode;
+use super::six_step::{PhaseDrive, PhaseState};
+use crate::log;
+
+/// TIM1 kernel clock (APB2 timer clock = SYSCLK, 170 MHz, see `main`)
+const TIM_CLOCK_HZ: u32 = 170_000_000;
+
+/// Lowest accepted PWM frequency; below this the switching is audible and
+/// the current ripple per period grows
+pub const MIN_PWM_FREQ_HZ: u32 = 8_000;
+/// Highest accepted PWM frequency; the 2 µs default dead time would take a
+/// large share of shorter periods, and gate driver losses grow with it
+pub const MAX_PWM_FREQ_HZ: u32 = 60_000;
+/// Frequency used when a requested timing is rejected at init
+const FALLBACK_PWM_FREQ_HZ: u32 = 20_000;
+
+/// Timer period derived from a `MotorPwmConfig`
+///
+/// Center-aligned counting runs up to ARR and back, so one PWM period is
+/// `2 * (PSC + 1) * ARR` timer ticks and the duty has ARR steps. The prescaler
+/// stays at 1 throughout the accepted range: a larger one would only cost
+/// resolution at the same frequency.
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct PwmTiming {
+    /// Exact resulting PWM frequency, rounded down to whole Hz
+    pub freq_hz: u32,
+    /// Auto-reload value: the number of duty steps
+    pub arr: u16,
+}
+
+/// Why a `MotorPwmConfig` has no valid timing
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum PwmTimingError {
+    /// The frequency (requested, or implied by `duty_steps`) is outside
+    /// `MIN_PWM_FREQ_HZ..=MAX_PWM_FREQ_HZ`
+    FrequencyOutOfRange(u32),
+}
+
+impl PwmTiming {
+    /// Closest period to `freq_hz` at full resolution
+    pub fn for_frequency(freq_hz: u32) -> Result<Self, PwmTimingError> {
+        Self::check(freq_hz)?;
+        let arr = (TIM_CLOCK_HZ / (2 * freq_hz)) as u16;
+        Ok(Self {
+            freq_hz: TIM_CLOCK_HZ / (2 * arr as u32),
+            arr,
+        })
+    }
+
+    /// Period with exactly `steps` duty steps; the frequency follows
+    pub fn for_duty_steps(steps: u16) -> Result<Self, PwmTimingError> {
+        let freq_hz = TIM_CLOCK_HZ / (2 * steps.max(1) as u32);
+        Self::check(freq_hz)?;
+        Ok(Self {
+            freq_hz,
+            arr: steps,
+        })
+    }
+
+    fn check(freq_hz: u32) -> Result<(), PwmTimingError> {
+        if (MIN_PWM_FREQ_HZ..=MAX_PWM_FREQ_HZ).contains(&freq_hz) {
+            Ok(())
+        } else {
+            Err(PwmTimingError::FrequencyOutOfRange(freq_hz))
+        }
+    }
+}
+
+/// How `apply_phase_state` treats the phase a step leaves floating
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum FloatingPhase {
+    /// 0% duty: the low side keeps switching, clamping the phase to ground
+    ZeroDuty,
+    /// Both outputs of the channel disabled: high-Z, so its BEMF can be read
+    Tristate,
+}
+
+/// Signal that trips the TIM1 break input
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum BreakSource {
+    /// The BKIN pin (PB12, AF6), e.g. a gate driver fault or desaturation line
+    Pin,
+    /// The output of COMP1..COMP4, which must be set up separately
+    Comparator(u8),
+}
+
+/// Hardware break: the timer clears MOE by itself when `source` goes active
+///
+/// The outputs fall to their idle (off) levels within a few timer clocks,
+/// without waiting for software. Automatic output enable stays off, so they
+/// remain off until a new step is energized after the controller has seen
+/// the break flag (`MotorPwm::take_break`) and stopped.
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct BreakConfig {
+    pub source: BreakSource,
+    /// The break trips on a low level (open-drain fault lines); false: on a high one
+    pub active_low: bool,
+    /// Digital filter (BKF, 0-15): 0 trips on the first sample, higher values
+    /// need the level to hold for longer, up to 8 samples at fDTS/32
+    pub filter: u8,
+}
 
 /// PWM configuration for the motor
+///
+/// The polarities must match the gate driver inputs: with a wrong setting a
+/// 0% duty drives both switches of a leg on (shoot-through). They, the break
+/// input and `debug_halt_off` are applied only at init, never by `apply_config`.
+#[derive(Clone, Copy)]
 pub struct MotorPwmConfig {
-    pub pwm_freq: u32,  // Hz
-    pub dead_time_ns: u32,  // nanoseconds
-    pub max_duty_percent: u8,  // 0-100
+    pub pwm_freq: u32, // Hz
+    /// Request this many duty steps instead of `pwm_freq`; the frequency
+    /// becomes 85 MHz / steps (e.g. 4096 steps: 20.75 kHz, 8500: 10 kHz)
+    pub duty_steps: Option<u16>,
+    pub dead_time_ns: u32, // nanoseconds
+    /// On-time added to every non-zero phase duty to make up for what the
+    /// dead time takes, in nanoseconds (0 = off; about `dead_time_ns` is typical)
+    pub deadband_comp_ns: u32,
+    pub max_duty_percent: u8,               // 0-100
+    pub high_side_polarity: OutputPolarity, // CH1..CH3 (CCxP)
+    pub low_side_polarity: OutputPolarity,  // CH1N..CH3N (CCxNP)
+    /// After a fault stop, wait for the zero compares to load and check that
+    /// no phase is left driven, forcing the main output enable off if one is
+    /// (false = trust the stop, as for any other)
+    pub fault_off_check: bool,
+    /// On a controlled stop, hold all low sides on for this long before
+    /// floating the phases, so a fast-coasting motor bleeds its back-EMF into
+    /// the windings instead of the bridge, in microseconds (0 = float at once).
+    /// Only a controlled stop discharges, never a fault stop or an estop.
+    pub stop_discharge_us: u32,
+    /// Before the first step after boot, switch only the low sides, ramping
+    /// up to fully on over this long, to charge the high-side bootstrap
+    /// capacitors, in microseconds (0 = energize at once)
+    pub bootstrap_precharge_us: u32,
+    pub floating_phase: FloatingPhase,
+    /// Switch commutation steps at a PWM update event, so no period mixes
+    /// two steps' outputs (false = as soon as the step is applied)
+    pub commutation_sync: bool,
+    /// Force the outputs off in hardware on a fault signal (None = no break input)
+    pub break_input: Option<BreakConfig>,
+    /// Stop TIM1 and turn its outputs off while a debugger halts the core,
+    /// instead of leaving the last step energized (false = keep switching)
+    pub debug_halt_off: bool,
+}
+
+impl MotorPwmConfig {
+    /// Timer period for these settings, validated against the safe frequency range
+    pub fn timing(&self) -> Result<PwmTiming, PwmTimingError> {
+        match self.duty_steps {
+            Some(steps) => PwmTiming::for_duty_steps(steps),
+            None => PwmTiming::for_frequency(self.pwm_freq),
+        }
+    }
 }
 
 impl Default for MotorPwmConfig {
     fn default() -> Self {
         Self {
-            pwm_freq: 20_000,  // 20 kHz
-            dead_time_ns: 2000,  // 2 µs
-            max_duty_percent: 15,  // 15% for very safe initial testing
+            pwm_freq: 20_000,   // 20 kHz
+            duty_steps: None,   // 4250 steps at 20 kHz
+            dead_time_ns: 2000, // 2 µs
+            deadband_comp_ns: 0,
+            max_duty_percent: safe_mode::cap_duty(15), // 15% for very safe initial testing
+            // B-G431B-ESC1: L6387 HIN/LIN inputs are both active-high
+            high_side_polarity: OutputPolarity::ActiveHigh,
+            low_side_polarity: OutputPolarity::ActiveHigh,
+            fault_off_check: true,
+            stop_discharge_us: 0, // opt-in: the short brakes as well as bleeds
+            bootstrap_precharge_us: 2000, // a few time constants of the L6387 bootstrap diode and cap
+            floating_phase: FloatingPhase::Tristate,
+            commutation_sync: false,
+            // The L6387 drivers on the B-G431B-ESC1 have no fault output
+            break_input: None,
+            debug_halt_off: true,
         }
     }
 }
@@ -27,44 +195,59 @@ impl Default for MotorPwmConfig {
 /// Motor PWM controller
 pub struct MotorPwm<'d> {
     pwm: ComplementaryPwm<'d, embassy_stm32::peripherals::TIM1>,
+    timing: PwmTiming,
     max_duty: u16,
     duty_limit: u16,
+    /// Compare counts added to non-zero duties, see `deadband_counts`
+    deadband_comp: u16,
+    /// VBUS-scaled ceiling in 0.1% steps, see `set_voltage_limit`
+    voltage_limit_permille: u16,
+    phase_map: PhaseMap,
+    fault_off_check: bool,
+    stop_discharge_us: u32,
+    bootstrap_precharge_us: u32,
+    floating_phase: FloatingPhase,
+    commutation_sync: bool,
+    outputs_enabled: bool,
+}
+
+/// TIM1 and the pins `MotorPwm` takes over on the B-G431B-ESC1
+pub struct PwmPins<'d> {
+    pub tim1: Peri<'d, TIM1>,
+    pub a_high: Peri<'d, PA8>,
+    pub a_low: Peri<'d, PC13>,
+    pub b_high: Peri<'d, PA9>,
+    pub b_low: Peri<'d, PA12>,
+    pub c_high: Peri<'d, PA10>,
+    pub c_low: Peri<'d, PB15>,
+    /// TIM1_BKIN, used only if `MotorPwmConfig::break_input` selects it
+    pub bkin: Option<Peri<'d, PB12>>,
 }
 
 impl<'d> MotorPwm<'d> {
     /// Initialize TIM1 complementary PWM for the B-G431B-ESC1 board
-    pub fn new(
-        tim1: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::TIM1>>,
-        pa8: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::PA8>>,
-        pc13: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::PC13>>,
-        pa9: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::PA9>>,
-        pa12: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::PA12>>,
-        pa10: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::PA10>>,
-        pb15: impl Into<embassy_stm32::Peri<'d, embassy_stm32::peripherals::PB15>>,
-        config: MotorPwmConfig,
-    ) -> Self {
-        let tim1 = tim1.into();
-        let pa8 = pa8.into();
-        let pc13 = pc13.into();
-        let pa9 = pa9.into();
-        let pa12 = pa12.into();
-        let pa10 = pa10.into();
-        let pb15 = pb15.into();
-
+    pub fn new(pins: PwmPins<'d>, config: MotorPwmConfig) -> Self {
         // High-side pins
-        let ch1 = PwmPin::new(pa8, OutputType::PushPull);   // Phase A high
-        let ch2 = PwmPin::new(pa9, OutputType::PushPull);   // Phase B high
-        let ch3 = PwmPin::new(pa10, OutputType::PushPull);  // Phase C high
+        let ch1 = PwmPin::new(pins.a_high, OutputType::PushPull);
+        let ch2 = PwmPin::new(pins.b_high, OutputType::PushPull);
+        let ch3 = PwmPin::new(pins.c_high, OutputType::PushPull);
 
         // Low-side pins (complementary)
-        let ch1n = ComplementaryPwmPin::new(pc13, OutputType::PushPull);  // Phase A low
-        let ch2n = ComplementaryPwmPin::new(pa12, OutputType::PushPull);  // Phase B low
-        let ch3n = ComplementaryPwmPin::new(pb15, OutputType::PushPull);  // Phase C low
+        let ch1n = ComplementaryPwmPin::new(pins.a_low, OutputType::PushPull);
+        let ch2n = ComplementaryPwmPin::new(pins.b_low, OutputType::PushPull);
+        let ch3n = ComplementaryPwmPin::new(pins.c_low, OutputType::PushPull);
 
-        let pwm_freq = khz(config.pwm_freq / 1000);
+        let timing = config.timing().unwrap_or_else(|e| {
+            log::warn!(
+                "PWM timing rejected ({}); using {}Hz",
+                e,
+                FALLBACK_PWM_FREQ_HZ
+            );
+            defmt::unwrap!(PwmTiming::for_frequency(FALLBACK_PWM_FREQ_HZ))
+        });
 
         let mut pwm = ComplementaryPwm::new(
-            tim1,
+            pins.tim1,
             Some(ch1),
             Some(ch1n),
             Some(ch2),
@@ -73,29 +256,58 @@ impl<'d> MotorPwm<'d> {
             Some(ch3n),
             None,
             None,
-            pwm_freq,
+            Hertz(timing.freq_hz),
             CountingMode::CenterAlignedBothInterrupts,
         );
+        set_timing(&timing);
+        // TRGO on every update event: the top and the bottom of each period in
+        // center-aligned mode, which triggers dual-edge current sampling
+        embassy_stm32::pac::TIM1
+            .cr2()
+            .modify(|w| w.set_mms(Mms::UPDATE));
+        set_compare_preload();
 
         let max_duty = pwm.get_max_duty();
 
-        // Calculate dead time in timer ticks
-        // At 170 MHz, each tick is ~5.88 ns
-        // For 2 µs dead time, we need ~340 ticks
-        // But dead time register has specific encoding - use a fraction of max_duty
-        let dead_time_ticks = max_duty / 512;  // Conservative ~2µs at 20kHz
-        pwm.set_dead_time(dead_time_ticks);
+        // Dead time in timer ticks; the HAL picks the DTG encoding/clock division
+        pwm.set_dead_time(dead_time_ticks(config.dead_time_ns));
 
         // Calculate duty cycle limit based on max_duty_percent
-        let duty_limit = (max_duty as u32 * config.max_duty_percent as u32 / 100) as u16;
+        let duty_limit = duty_limit(max_duty, config.max_duty_percent);
 
-        defmt::info!(
-            "Motor PWM init: freq={}Hz, max_duty={}, limit={}%",
-            config.pwm_freq,
+        log::info!(
+            "Motor PWM init: freq={}Hz, max_duty={}, limit={}%, deadband comp={}ns, commutation sync={}",
+            timing.freq_hz,
             max_duty,
-            config.max_duty_percent
+            config.max_duty_percent,
+            config.deadband_comp_ns,
+            config.commutation_sync
         );
 
+        // Output polarity must be in place before any channel is enabled
+        set_output_polarity(config.high_side_polarity, config.low_side_polarity);
+
+        // Break before the outputs switch, so a fault present at boot holds them off
+        if let Some(brk) = config.break_input {
+            match brk.source {
+                BreakSource::Pin if pins.bkin.is_none() => {
+                    log::warn!("Break input on BKIN but no pin given; break disabled")
+                }
+                BreakSource::Comparator(n) if !(1..=4).contains(&n) => {
+                    log::warn!("Break input on COMP{} does not exist; break disabled", n)
+                }
+                source => {
+                    if source == BreakSource::Pin {
+                        bkin_pin_setup(brk.active_low);
+                    }
+                    set_break_input(&brk);
+                }
+            }
+        }
+
+        // Relies on the OSSI and idle levels `set_output_polarity` has just set
+        set_debug_halt_off(config.debug_halt_off);
+
         // Enable all three channels initially (will be controlled by 6-step logic)
         pwm.enable(Channel::Ch1);
         pwm.enable(Channel::Ch2);
@@ -103,18 +315,137 @@ impl<'d> MotorPwm<'d> {
 
         Self {
             pwm,
+            timing,
             max_duty,
             duty_limit,
+            deadband_comp: deadband_counts(config.deadband_comp_ns),
+            voltage_limit_permille: 1000,
+            phase_map: PhaseMap::IDENTITY,
+            fault_off_check: config.fault_off_check,
+            stop_discharge_us: config.stop_discharge_us,
+            bootstrap_precharge_us: config.bootstrap_precharge_us,
+            floating_phase: config.floating_phase,
+            commutation_sync: config.commutation_sync,
+            // `ComplementaryPwm::new` sets MOE
+            outputs_enabled: true,
+        }
+    }
+
+    /// Set which TIM1 output drives each logical phase
+    ///
+    /// All phases are set to 0% first so no output keeps a stale duty.
+    pub fn set_phase_map(&mut self, map: PhaseMap) {
+        if !map.is_valid() {
+            log::warn!("Ignoring invalid phase map {}", map.channels);
+            return;
         }
+        self.emergency_stop();
+        self.phase_map = map;
+    }
+
+    /// Active phase map
+    pub fn phase_map(&self) -> PhaseMap {
+        self.phase_map
+    }
+
+    /// TIM1 channel driving logical phase `phase` (0 = A, 1 = B, 2 = C)
+    fn channel(&self, phase: usize) -> Channel {
+        match self.phase_map.channels[phase] {
+            0 => Channel::Ch1,
+            1 => Channel::Ch2,
+            _ => Channel::Ch3,
+        }
+    }
+
+    /// Enable or disable both outputs (CCxE/CCxNE) of logical phase `phase`
+    ///
+    /// Written directly because `ComplementaryPwm::enable` also sets MOE. A
+    /// disabled channel holds both switches off (OSSR, see `set_output_polarity`).
+    fn set_phase_outputs(&mut self, phase: usize, enabled: bool) {
+        let ch = self.phase_map.channels[phase] as usize;
+        embassy_stm32::pac::TIM1.ccer().modify(|w| {
+            w.set_cce(ch, enabled);
+            w.set_ccne(ch, enabled);
+        });
+    }
+
+    /// Apply a new frequency / dead time / duty limit at runtime
+    ///
+    /// All phases are set to 0% first; the caller must have stopped the motor.
+    /// Output polarity is left as set at init. A timing outside the safe
+    /// frequency range is rejected and the current period kept.
+    pub fn apply_config(&mut self, config: &MotorPwmConfig) {
+        self.emergency_stop();
+        match config.timing() {
+            Ok(timing) => {
+                self.pwm.set_frequency(Hertz(timing.freq_hz));
+                set_timing(&timing);
+                self.timing = timing;
+            }
+            Err(e) => log::warn!(
+                "PWM timing rejected ({}); keeping {}Hz",
+                e,
+                self.timing.freq_hz
+            ),
+        }
+        self.max_duty = self.pwm.get_max_duty();
+        self.pwm.set_dead_time(dead_time_ticks(config.dead_time_ns));
+        self.duty_limit = duty_limit(self.max_duty, config.max_duty_percent);
+        self.deadband_comp = deadband_counts(config.deadband_comp_ns);
+        self.fault_off_check = config.fault_off_check;
+        self.stop_discharge_us = config.stop_discharge_us;
+        self.bootstrap_precharge_us = config.bootstrap_precharge_us;
+        self.floating_phase = config.floating_phase;
+        self.commutation_sync = config.commutation_sync;
+
+        log::info!(
+            "Motor PWM reconfigured: freq={}Hz, dead_time={}ns, max_duty={}, limit={}%",
+            self.timing.freq_hz,
+            config.dead_time_ns,
+            self.max_duty,
+            config.max_duty_percent
+        );
+    }
+
+    /// Cap the duty so the average phase voltage stays under `max_phase_mv`
+    ///
+    /// The ceiling is `max_phase_mv / vbus_mv`, applied on top of
+    /// `max_duty_percent`; call it whenever VBUS is sampled. `max_phase_mv` 0
+    /// or an unknown VBUS (0) leaves only the fixed limit.
+    pub fn set_voltage_limit(&mut self, max_phase_mv: u16, vbus_mv: u16) {
+        self.voltage_limit_permille = voltage_limit_permille(max_phase_mv, vbus_mv);
+    }
+
+    /// Highest duty `set_phase_duty_permille` passes on unclamped, in 0.1% steps
+    #[cfg(feature = "diagnostics")]
+    pub fn duty_ceiling_permille(&self) -> u16 {
+        let limit = (self.duty_limit as u32 * 1000 / self.max_duty.max(1) as u32) as u16;
+        limit.min(self.voltage_limit_permille).min(1000)
     }
 
     /// Set duty cycle for a specific phase (0-100%)
     ///
     /// Duty is clamped to the configured max_duty_percent
+    #[cfg(feature = "diagnostics")]
     pub fn set_phase_duty(&mut self, channel: Channel, duty_percent: u8) {
-        let duty_percent = duty_percent.min(100);
-        let duty = (self.max_duty as u32 * duty_percent as u32 / 100) as u16;
-        let duty = duty.min(self.duty_limit);
+        self.set_phase_duty_permille(channel, duty_percent.min(100) as u16 * 10);
+    }
+
+    /// Set duty cycle for a specific phase in 0.1% steps (0-1000)
+    ///
+    /// A non-zero duty gets the deadband compensation added. Duty is clamped
+    /// to the configured max_duty_percent and the VBUS-scaled ceiling after
+    /// that, so the compensation never takes it past either.
+    pub fn set_phase_duty_permille(&mut self, channel: Channel, duty_permille: u16) {
+        let ceiling_permille = self.voltage_limit_permille.min(1000);
+        let ceiling = (self.max_duty as u32 * ceiling_permille as u32 / 1000) as u16;
+        let duty = (self.max_duty as u32 * duty_permille.min(1000) as u32 / 1000) as u16;
+        let duty = if duty == 0 {
+            0
+        } else {
+            duty.saturating_add(self.deadband_comp)
+        };
+        let duty = duty.min(ceiling).min(self.duty_limit);
         self.pwm.set_duty(channel, duty);
     }
 
@@ -123,42 +454,236 @@ impl<'d> MotorPwm<'d> {
         self.pwm.set_duty(channel, 0);
     }
 
-    /// Apply 6-step commutation pattern
-    ///
-    /// - enable flags: true = phase active with PWM, false = disabled (floating)
-    /// - For active phases, duty_percent is applied
-    /// - For inactive phases, duty is set to 0
-    pub fn apply_commutation(
-        &mut self,
-        duty_percent: u8,
-        ph_a_en: bool,
-        ph_b_en: bool,
-        ph_c_en: bool,
-    ) {
-        if ph_a_en {
-            self.set_phase_duty(Channel::Ch1, duty_percent);
-        } else {
-            self.disable_phase(Channel::Ch1);
+    /// Apply one commutation step's phase drives
+    ///
+    /// - `Pwm` phases switch at duty_percent
+    /// - `Low` phases sit at 0% duty (low side on)
+    /// - `Float` phases get 0% duty and, with `FloatingPhase::Tristate`, both
+    ///   outputs disabled
+    pub fn apply_phase_state(&mut self, duty_percent: u8, state: &PhaseState) {
+        self.apply_phase_state_permille(duty_percent.min(100) as u16 * 10, state);
+    }
+
+    /// Apply one commutation step's phase drives with duty in 0.1% steps (0-1000)
+    ///
+    /// The compare values are preloaded, so they take effect at the next
+    /// update event whatever the setting. With `commutation_sync`, a step
+    /// that changes which outputs are enabled waits for that update event
+    /// (at most half a period: center-aligned, it comes at both the top and
+    /// the bottom) and only then writes the enables, so the new duties and
+    /// outputs start together. Without it the enables switch at once, up to
+    /// half a period ahead of the duties.
+    pub fn apply_phase_state_permille(&mut self, duty_permille: u16, state: &PhaseState) {
+        // Nothing to glitch while the outputs are off
+        let sync = self.commutation_sync && self.outputs_enabled;
+        self.outputs_enable();
+        let mut enables = [true; 3];
+        for (phase, drive) in state.0.into_iter().enumerate() {
+            let channel = self.channel(phase);
+            match drive {
+                PhaseDrive::Pwm => self.set_phase_duty_permille(channel, duty_permille),
+                PhaseDrive::Low => self.disable_phase(channel),
+                PhaseDrive::Float => {
+                    self.disable_phase(channel);
+                    enables[phase] = self.floating_phase == FloatingPhase::ZeroDuty;
+                }
+            }
+        }
+        let ccer = embassy_stm32::pac::TIM1.ccer().read();
+        let changed = (0..3).any(|phase| {
+            let ch = self.phase_map.channels[phase] as usize;
+            ccer.cce(ch) != enables[phase] || ccer.ccne(ch) != enables[phase]
+        });
+        if !changed {
+            return;
+        }
+        if sync {
+            wait_update_event(&self.timing);
+        }
+        for (phase, enabled) in enables.into_iter().enumerate() {
+            self.set_phase_outputs(phase, enabled);
         }
+    }
 
-        if ph_b_en {
-            self.set_phase_duty(Channel::Ch2, duty_percent);
-        } else {
-            self.disable_phase(Channel::Ch2);
+    /// Drive each logical phase at its own duty (0-100%), all outputs switching
+    ///
+    /// Every phase behaves like a `Pwm` phase of a commutation step, with the
+    /// same clamping (`set_phase_duty_permille`); a 0% phase has its low side on.
+    #[cfg(feature = "diagnostics")]
+    pub fn set_all_phase_duties(&mut self, a: u8, b: u8, c: u8) {
+        self.outputs_enable();
+        for (phase, duty) in [a, b, c].into_iter().enumerate() {
+            self.set_phase_duty(self.channel(phase), duty);
+            self.set_phase_outputs(phase, true);
         }
+    }
 
-        if ph_c_en {
-            self.set_phase_duty(Channel::Ch3, duty_percent);
-        } else {
-            self.disable_phase(Channel::Ch3);
+    /// Brake: high sides off, low sides shorting the windings for `short_permille` of each period
+    ///
+    /// With only CCxNE set, a channel's low side follows OCxREF itself rather
+    /// than its inverse, with no dead time (there is no high side to leave
+    /// for), so the compare is the short duty. The outputs are switched to
+    /// low-side-only before the compare changes, so no high side sees it.
+    /// The duty limits do not apply: a longer short never raises a phase above
+    /// ground.
+    pub fn apply_brake_permille(&mut self, short_permille: u16) {
+        self.outputs_enable();
+        let duty = (self.max_duty as u32 * short_permille.min(1000) as u32 / 1000) as u16;
+        for phase in 0..3 {
+            let ch = self.phase_map.channels[phase] as usize;
+            embassy_stm32::pac::TIM1.ccer().modify(|w| {
+                w.set_cce(ch, false);
+                w.set_ccne(ch, true);
+            });
+            self.pwm.set_duty(self.channel(phase), duty);
+        }
+    }
+
+    /// Charge the high-side bootstrap capacitors before the first commutation
+    ///
+    /// A leg's bootstrap capacitor only charges while its low side is on, and
+    /// at power-on all three are empty: a first step would switch its high
+    /// side with too little gate drive and misfire. The high sides are held
+    /// off and the low sides switched alone, as in `apply_brake_permille`, so
+    /// no dead time is involved until every low side is fully on. The on-time
+    /// ramps up over `bootstrap_precharge_us` so the capacitors fill gradually
+    /// instead of in one inrush from the driver supply. The low sides are then
+    /// switched off for a PWM period, so the zero compare has latched before
+    /// the high side outputs come back, and the bridge stops as `stop_outputs`
+    /// would. Blocks the caller, like the stop discharge.
+    pub fn precharge_bootstrap(&mut self) {
+        const RAMP_STEPS: u32 = 10;
+        let duration = embassy_time::Duration::from_micros(self.bootstrap_precharge_us as u64);
+        if duration.as_micros() == 0 {
+            return;
+        }
+        log::debug!(
+            "Pre-charging bootstrap capacitors for {}us",
+            duration.as_micros()
+        );
+        for i in 1..=RAMP_STEPS {
+            self.apply_brake_permille((1000 * i / RAMP_STEPS) as u16);
+            embassy_time::block_for(duration / RAMP_STEPS);
         }
+        self.apply_brake_permille(0);
+        let period_us = 1_000_000 / self.timing.freq_hz.max(1);
+        embassy_time::block_for(embassy_time::Duration::from_micros(period_us as u64));
+        self.stop_outputs();
     }
 
     /// Emergency stop - disable all phases immediately
+    ///
+    /// Tristated phases are re-enabled at 0% duty, so all three legs end up in
+    /// the same state whatever step was applied last.
     pub fn emergency_stop(&mut self) {
         self.disable_phase(Channel::Ch1);
         self.disable_phase(Channel::Ch2);
         self.disable_phase(Channel::Ch3);
+        for phase in 0..3 {
+            self.set_phase_outputs(phase, true);
+        }
+    }
+
+    /// Turn the bridge off for a controlled stop: main output enable off, so
+    /// the phases float (after the `stop_discharge_us` low-side short, if any)
+    pub fn stop_outputs(&mut self) {
+        self.discharge();
+        self.outputs_disable();
+    }
+
+    /// Turn the bridge off after a fault: main output enable off, no discharge
+    ///
+    /// A fault can strike mid-step, with one phase pair energized at the last
+    /// duty. With `fault_off_check`, the stop is then verified: the compares
+    /// are preloaded, so the zeros only act from the next update event, which
+    /// is waited for before reading the timer back (`drive_state`). If any
+    /// phase could still be driven, or the legs are not all alike, MOE is
+    /// cleared, which takes every switch off whatever the compares hold, and
+    /// read back once more. Returns whether the bridge was found off; blocks
+    /// for at most two PWM periods. It never discharges (`stop_discharge_us`):
+    /// a fault or an estop takes the switches off without shorting anything.
+    pub fn fault_outputs(&mut self) -> bool {
+        self.outputs_disable();
+        if !self.fault_off_check {
+            return true;
+        }
+        wait_update_event(&self.timing);
+        let state = read_drive_state();
+        if state.is_off() {
+            return true;
+        }
+        log::error!(
+            "Bridge not off after a fault stop (moe={} compares={} enables={=u8:b}); forcing outputs off",
+            state.moe,
+            state.compares,
+            state.enables
+        );
+        self.outputs_disable();
+        wait_update_event(&self.timing);
+        read_drive_state().is_off()
+    }
+
+    /// Short the windings through the low sides for `stop_discharge_us`
+    ///
+    /// Zero duty on every channel, tristated ones included, turns each high
+    /// side off and its low side on after the hardware dead time, so no leg
+    /// conducts through both switches. The zero compare only latches at the
+    /// next update event, so the wait covers one PWM period on top. Blocks the
+    /// caller: this runs once per stop and is kept short.
+    fn discharge(&mut self) {
+        if !self.outputs_enabled || self.stop_discharge_us == 0 {
+            return;
+        }
+        self.emergency_stop();
+        let period_us = 1_000_000 / self.timing.freq_hz.max(1);
+        embassy_time::block_for(embassy_time::Duration::from_micros(
+            (period_us + self.stop_discharge_us) as u64,
+        ));
+    }
+
+    /// Clear the main output enable so no output switches
+    ///
+    /// Duties are zeroed first. The outputs then sit at their idle levels,
+    /// which `set_output_polarity` chose to be "off" for the gate driver.
+    pub fn outputs_disable(&mut self) {
+        self.emergency_stop();
+        embassy_stm32::pac::TIM1.bdtr().modify(|w| w.set_moe(false));
+        if self.outputs_enabled {
+            log::debug!("Motor PWM outputs disabled");
+        }
+        self.outputs_enabled = false;
+    }
+
+    /// Restore switching after `outputs_disable`; a no-op if already enabled
+    ///
+    /// Only MOE changes, so dead time and the channel setup are kept, and all
+    /// duties are 0 when the outputs come back. Energizing a step calls this.
+    pub fn outputs_enable(&mut self) {
+        if self.outputs_enabled {
+            return;
+        }
+        self.emergency_stop();
+        embassy_stm32::pac::TIM1.bdtr().modify(|w| w.set_moe(true));
+        self.outputs_enabled = true;
+        log::debug!("Motor PWM outputs enabled");
+    }
+
+    /// Whether the break input has tripped since the last call; clears the flag
+    ///
+    /// The hardware has already cleared MOE, so the outputs are recorded as
+    /// disabled: the next energized step turns them back on (which the timer
+    /// refuses while the break input is still active), and a stop does not
+    /// try to discharge through a bridge that just reported a fault. Always
+    /// false without a `BreakConfig`.
+    pub fn take_break(&mut self) -> bool {
+        let tim = embassy_stm32::pac::TIM1;
+        if !tim.sr().read().bif(0) {
+            return false;
+        }
+        // rc_w0: writing the flags read back as 1 leaves them set
+        tim.sr().modify(|w| w.set_bif(0, false));
+        self.outputs_enabled = false;
+        true
     }
 
     /// Get maximum duty cycle value
@@ -166,3 +691,269 @@ impl<'d> MotorPwm<'d> {
         self.max_duty
     }
 }
+
+impl PhaseDriver for MotorPwm<'_> {
+    fn energize(&mut self, state: &PhaseState, duty_percent: u8) {
+        self.apply_phase_state(duty_percent, state);
+    }
+}
+
+/// TIM1 output stage as read back after a fault stop
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct DriveState {
+    /// Main output enable
+    moe: bool,
+    /// CCR1..CCR3
+    compares: [u16; 3],
+    /// CC1E, CC1NE, CC2E, CC2NE, CC3E, CC3NE from bit 0 up
+    enables: u8,
+}
+
+impl DriveState {
+    /// Whether no winding can see the bus: MOE off (every switch at its idle,
+    /// off level), or every compare at 0 with all three legs alike, so at
+    /// most the low sides conduct and together short the windings evenly
+    fn is_off(&self) -> bool {
+        let legs_alike = self.enables == 0 || self.enables == 0b11_1111;
+        !self.moe || (self.compares == [0; 3] && legs_alike)
+    }
+}
+
+fn read_drive_state() -> DriveState {
+    let tim = embassy_stm32::pac::TIM1;
+    let ccer = tim.ccer().read();
+    let enables = (0..3).fold(0u8, |bits, ch| {
+        bits | (ccer.cce(ch) as u8) << (2 * ch) | (ccer.ccne(ch) as u8) << (2 * ch + 1)
+    });
+    DriveState {
+        moe: tim.bdtr().read().moe(),
+        compares: [0, 1, 2].map(|ch| tim.ccr(ch).read().ccr()),
+        enables,
+    }
+}
+
+/// Program the TIM1 CCER polarity bits of CH1..CH3 and CH1N..CH3N
+///
+/// `ComplementaryPwm` only sets both outputs of a channel together, so the
+/// registers are written directly. The idle levels used while MOE is off
+/// (CR2 OISx/OISxN) are set to each output's inactive level, and OSSI keeps
+/// the outputs driven there rather than floating the gate driver inputs. OSSR
+/// does the same for a disabled channel while MOE is on (a tristated phase).
+fn set_output_polarity(high: OutputPolarity, low: OutputPolarity) {
+    let high_inverted = matches!(high, OutputPolarity::ActiveLow);
+    let low_inverted = matches!(low, OutputPolarity::ActiveLow);
+    let tim = embassy_stm32::pac::TIM1;
+    tim.ccer().modify(|w| {
+        for ch in 0..3 {
+            w.set_ccp(ch, high_inverted);
+            w.set_ccnp(ch, low_inverted);
+        }
+    });
+    tim.cr2().modify(|w| {
+        for ch in 0..3 {
+            w.set_ois(ch, high_inverted);
+            w.set_oisn(ch, low_inverted);
+        }
+    });
+    tim.bdtr().modify(|w| {
+        w.set_ossi(Ossi::IDLE_LEVEL);
+        w.set_ossr(Ossr::IDLE_LEVEL);
+    });
+    log::info!(
+        "Motor PWM polarity: high-side active-{}, low-side active-{}",
+        if high_inverted { "low" } else { "high" },
+        if low_inverted { "low" } else { "high" }
+    );
+}
+
+/// Whether TIM1 stops while the core is halted by a debugger (DBG_TIM1_STOP)
+///
+/// A stopped advanced timer has its outputs disabled as if MOE were clear:
+/// with OSSI set they are held at their idle (off) levels, so a probe that
+/// halts a running motor leaves no phase energized. They switch again on
+/// resume. Left running, the timer keeps applying the step that was active
+/// at the halt for as long as the core stays stopped.
+fn set_debug_halt_off(enabled: bool) {
+    embassy_stm32::pac::DBGMCU
+        .apb2fzr()
+        .modify(|w| w.set_tim1(enabled));
+    log::info!(
+        "Motor PWM on debug halt: {}",
+        if enabled {
+            "outputs off"
+        } else {
+            "keeps switching"
+        }
+    );
+}
+
+/// Route PB12 to TIM1_BKIN (AF6), pulled up for an active-low open-drain line
+///
+/// The HAL has no break pin type, so the GPIO is set up directly; the caller
+/// holds the `PB12` peripheral so nothing else claims the pin.
+fn bkin_pin_setup(active_low: bool) {
+    let gpio = embassy_stm32::pac::GPIOB;
+    gpio.pupdr().modify(|w| {
+        w.set_pupdr(
+            12,
+            if active_low {
+                Pupdr::PULL_UP
+            } else {
+                Pupdr::PULL_DOWN
+            },
+        )
+    });
+    gpio.afr(1).modify(|w| w.set_afr(12 - 8, 6));
+    gpio.moder().modify(|w| w.set_moder(12, Moder::ALTERNATE));
+}
+
+/// Enable the TIM1 break input from `brk.source`
+///
+/// The source enables and their polarity are in AF1; BDTR holds the
+/// resulting break's polarity, filter and enable. The break is armed last,
+/// after a stale flag is cleared, so configuring it does not trip it.
+fn set_break_input(brk: &BreakConfig) {
+    let tim = embassy_stm32::pac::TIM1;
+    tim.af1().modify(|w| {
+        w.set_bkine(brk.source == BreakSource::Pin);
+        w.set_bkinp(Bkinp::NOT_INVERTED);
+        for n in 0..4 {
+            w.set_bkcmpe(n, brk.source == BreakSource::Comparator(n as u8 + 1));
+            w.set_bkcmpp(n, Bkinp::NOT_INVERTED);
+        }
+    });
+    tim.bdtr().modify(|w| {
+        w.set_bkp(
+            0,
+            if brk.active_low {
+                Bkp::ACTIVE_LOW
+            } else {
+                Bkp::ACTIVE_HIGH
+            },
+        );
+        w.set_bkf(0, FilterValue::from_bits(brk.filter.min(15)));
+        w.set_aoe(false);
+    });
+    tim.sr().modify(|w| w.set_bif(0, false));
+    tim.bdtr().modify(|w| w.set_bke(0, true));
+    log::info!(
+        "Motor PWM break input: {}, active-{}, filter {}",
+        brk.source,
+        if brk.active_low { "low" } else { "high" },
+        brk.filter.min(15)
+    );
+}
+
+/// Buffer CCR1..CCR3 (OCxPE) and ARR (ARPE) so new values load at an update event
+///
+/// A compare written mid-period then never cuts the current pulse short or
+/// stretches it. `ComplementaryPwm` may already do so; set it here regardless,
+/// since `commutation_sync` depends on it.
+fn set_compare_preload() {
+    let tim = embassy_stm32::pac::TIM1;
+    for ch in 0..3 {
+        tim.ccmr_output(ch / 2).modify(|w| w.set_ocpe(ch % 2, true));
+    }
+    tim.cr1().modify(|w| w.set_arpe(true));
+}
+
+/// Spin until the next TIM1 update event, at most one period
+///
+/// Clear UIF only after the compare values are written, so the event waited
+/// for is one that loads them.
+fn wait_update_event(timing: &PwmTiming) {
+    let tim = embassy_stm32::pac::TIM1;
+    tim.sr().modify(|w| w.set_uif(false));
+    let timeout = 1_000_000 / timing.freq_hz.max(1) * CYCLES_PER_US;
+    let start = DWT::cycle_count();
+    while !tim.sr().read().uif() {
+        if DWT::cycle_count().wrapping_sub(start) > timeout {
+            log::warn!("No TIM1 update event within a period; commutating unsynchronized");
+            return;
+        }
+    }
+}
+
+/// Program PSC and ARR for `timing` exactly
+///
+/// The HAL picks its own prescaler/ARR split from the frequency alone, which
+/// can round the step count; writing both keeps a requested `duty_steps`
+/// exact. The update event loads them (and restarts the counter) at once.
+fn set_timing(timing: &PwmTiming) {
+    let tim = embassy_stm32::pac::TIM1;
+    tim.psc().write_value(0);
+    tim.arr().write(|w| w.set_arr(timing.arr));
+    tim.egr().write(|w| w.set_ug(true));
+}
+
+/// Convert a dead time in nanoseconds to TIM1 ticks (~5.88 ns per tick at 170 MHz)
+fn dead_time_ticks(dead_time_ns: u32) -> u16 {
+    let ticks = dead_time_ns as u64 * TIM_CLOCK_HZ as u64 / 1_000_000_000;
+    ticks.min(u16::MAX as u64) as u16
+}
+
+/// Compare counts of on-time lost to `comp_ns` of dead time
+///
+/// Center-aligned, one compare count is two timer ticks of on-time per
+/// period (~11.8 ns at 170 MHz). In 6-step the PWM phase always sources the
+/// winding current, so the dead time delays its high side on every period
+/// and the loss has a fixed sign: adding it back is enough, no current
+/// polarity is needed.
+fn deadband_counts(comp_ns: u32) -> u16 {
+    let counts = comp_ns as u64 * TIM_CLOCK_HZ as u64 / 2 / 1_000_000_000;
+    counts.min(u16::MAX as u64) as u16
+}
+
+/// Duty fraction (0.1% steps) that averages `max_phase_mv` out of `vbus_mv`
+fn voltage_limit_permille(max_phase_mv: u16, vbus_mv: u16) -> u16 {
+    if max_phase_mv == 0 || vbus_mv == 0 {
+        return 1000;
+    }
+    (max_phase_mv as u32 * 1000 / vbus_mv as u32).min(1000) as u16
+}
+
+/// Duty compare value corresponding to `max_duty_percent` of `max_duty`
+fn duty_limit(max_duty: u16, max_duty_percent: u8) -> u16 {
+    let percent = max_duty_percent.min(safe_mode::DUTY_CEILING);
+    (max_duty as u32 * percent as u32 / 100) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_a_lingering_duty_or_a_tristated_leg_is_not_off() {
+        let zero_duty = DriveState {
+            moe: true,
+            compares: [0; 3],
+            enables: 0b11_1111,
+        };
+        assert!(zero_duty.is_off());
+        // The last step's high side still switching
+        assert!(
+            !DriveState {
+                compares: [0, 425, 0],
+                ..zero_duty
+            }
+            .is_off()
+        );
+        // A phase left floating while the other two short: uneven braking
+        assert!(
+            !DriveState {
+                enables: 0b11_0011,
+                ..zero_duty
+            }
+            .is_off()
+        );
+        // MOE off holds every switch off whatever the compares say
+        assert!(
+            DriveState {
+                moe: false,
+                compares: [0, 425, 0],
+                enables: 0b11_0011
+            }
+            .is_off()
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Current-regulated regenerative braking
+//!
+//! Shorting all three windings through the low sides (the discharge before
+//! a stop floats the phases) stops a loaded motor hard, with a
+//! braking current set only by its back-EMF and winding resistance. Braking
+//! through PWM instead, the high sides held off and the low sides switching,
+//! makes the windings a boost converter: each short builds up current and
+//! each off-time pushes it through the high-side body diodes into the bus.
+//! The short duty sets how much. Here it is integrated, like the align
+//! current at startup, so the filtered shunt current sits at
+//! `regen_current_limit_ma`. The bus capacitor then takes a bounded charge
+//! instead of whatever the motor gives back.
+//!
+//! A controlled stop from running, and the dwell of a `Reverse`, brake this
+//! way when the limit is non-zero. An estop never does.
+
+use embassy_time::{Duration, Instant, Timer};
+
+use super::current::{CurrentIntegrator, CurrentSense};
+use super::{ControlMsg, ControlReceiver, MotorController, Trace, blanking, poll_control};
+use crate::log;
+
+/// Regenerative braking tunables
+#[derive(Clone, Copy)]
+pub struct RegenConfig {
+    /// Braking current to regulate to, in milliamps (0 = no regen braking)
+    pub regen_current_limit_ma: u16,
+    /// Longest a brake may run before the outputs are turned off anyway
+    pub max_brake_ms: u16,
+    /// With the short fully on, a current below this means the rotor is
+    /// close enough to a standstill to stop braking
+    pub done_below_ma: u16,
+}
+
+impl Default for RegenConfig {
+    fn default() -> Self {
+        Self {
+            regen_current_limit_ma: 0,
+            max_brake_ms: 2000,
+            done_below_ma: 200,
+        }
+    }
+}
+
+/// Regulation update interval
+const TICK: Duration = Duration::from_millis(1);
+/// Integral gain: short duty (0.1% steps) per mA of error per tick, as 1/`KI_DIV`
+const KI_DIV: i32 = 500;
+
+/// Integrator from braking current to short duty, up to fully on
+fn regulator() -> CurrentIntegrator {
+    CurrentIntegrator::new(KI_DIV, 1000)
+}
+
+/// Whether the short is fully on and the current has died down anyway
+fn settled(reg: &CurrentIntegrator, done_below_ma: u16) -> bool {
+    reg.duty_permille() == 1000 && reg.current_ma() < done_below_ma as u32
+}
+
+/// Brake for up to `max_ms`, regulating to `cfg.regen_current_limit_ma`
+///
+/// Returns early once the rotor has slowed down (`settled`). The
+/// bridge is left off, not shorted, so a brake cut short by `max_ms` does not
+/// end in the hard short it replaces; a control message ends the brake too
+/// and is returned so the caller can still act on it.
+pub async fn run(
+    motor: &mut MotorController<'_>,
+    sense: &mut CurrentSense<'_>,
+    cfg: &RegenConfig,
+    max_ms: u32,
+    trace: Trace,
+    control: &ControlReceiver,
+) -> Option<ControlMsg> {
+    log::info!(
+        "Regen brake{}: {}mA for up to {}ms",
+        trace,
+        cfg.regen_current_limit_ma,
+        max_ms
+    );
+    let mut reg = regulator();
+    let start = Instant::now();
+    let end = start + Duration::from_millis(max_ms as u64);
+    while Instant::now() < end {
+        motor.apply_brake_permille(reg.duty_permille());
+        Timer::after(TICK).await;
+        if let Some(msg) = poll_control(control) {
+            motor.coast();
+            log::warn!("Regen brake{} ended by incoming command", trace);
+            return Some(msg);
+        }
+        blanking::wait_clear().await;
+        reg.update(sense.magnitude(), cfg.regen_current_limit_ma);
+        if settled(&reg, cfg.done_below_ma) {
+            break;
+        }
+    }
+    motor.coast();
+    log::info!(
+        "Regen brake{} done after {}ms: {}mA at short={}/1000",
+        trace,
+        start.elapsed().as_millis(),
+        reg.current_ma(),
+        reg.duty_permille()
+    );
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_rises_until_current_limit_then_backs_off() {
+        let mut reg = regulator();
+        assert_eq!(reg.duty_permille(), 0);
+        // No current yet: the short keeps lengthening, up to fully on
+        for _ in 0..1000 {
+            reg.update(0, 2000);
+        }
+        assert_eq!(reg.duty_permille(), 1000);
+        assert!(settled(&reg, 200));
+
+        // Over the limit: it shortens again
+        for _ in 0..20 {
+            reg.update(4000, 2000);
+        }
+        assert!(reg.duty_permille() < 1000);
+        assert!(!settled(&reg, 200));
+    }
+}
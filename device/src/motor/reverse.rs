@@ -0,0 +1,284 @@
+//! Reverse on command: slow down, let the rotor settle, come back the other way
+//!
+//! A `Reverse` while running ramps the duty to zero, turns the outputs off
+//! for the commanded dwell (coasting, or braking if the PWM config brakes on
+//! stop or a regen current limit is set), flips the sequencer's direction and ramps back up to the duty it
+//! started from. The `Reversal` below only works out where in that sequence
+//! a given time falls; the controller advances it at every commutation and
+//! does the switching. Any other motion command, and every stop, cancels it.
+//!
+//! The same sequence can get a stalled motor free. With `stall_retries` set,
+//! the low-speed cutoff's final stop becomes an attempt instead: the rotor
+//! is already at a standstill, so the reversal starts at its dwell, drives
+//! the other way for `stall_backoff_ms`, then reverses back and tries again.
+//! `StallRecovery` counts the attempts; once they are used up the motor
+//! stops with a `Stall` fault.
+
+use oxifoc_protocol::Direction;
+use oxifoc_protocol::constants::MIN_REVERSE_DWELL_MS;
+
+/// Reverse tunables
+#[derive(Clone, Copy)]
+pub struct ReverseConfig {
+    /// Time to move the duty by one percent, either way (0 = jump)
+    pub ramp_ms_per_percent: u16,
+    /// Reverse-and-retry attempts after a low-speed stop before faulting (0 = just stop)
+    pub stall_retries: u8,
+    /// Time driven the other way during an attempt, in milliseconds
+    pub stall_backoff_ms: u16,
+    /// Coast before each change of direction during an attempt, in milliseconds
+    pub stall_dwell_ms: u16,
+}
+
+impl Default for ReverseConfig {
+    fn default() -> Self {
+        Self {
+            ramp_ms_per_percent: 20,
+            stall_retries: 0,
+            stall_backoff_ms: 500,
+            stall_dwell_ms: 300,
+        }
+    }
+}
+
+/// Part of the sequence a reversal is in
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Phase {
+    Decelerate,
+    Dwell,
+    Accelerate,
+    Done,
+}
+
+/// One reversal in progress
+///
+/// Phases advance one at a time and are timed from when the controller
+/// first sees them, so a slow commutation tick stretches the sequence but
+/// never skips the dwell.
+pub struct Reversal {
+    duty: u8,
+    dwell_ms: u32,
+    ms_per_percent: u32,
+    phase: Phase,
+    since_ms: u32,
+}
+
+impl Reversal {
+    /// Reverse from `duty` at `now_ms`, dwelling at least `MIN_REVERSE_DWELL_MS`
+    pub fn new(duty: u8, dwell_ms: u16, cfg: &ReverseConfig, now_ms: u32) -> Self {
+        Self {
+            duty,
+            dwell_ms: dwell_ms.max(MIN_REVERSE_DWELL_MS) as u32,
+            ms_per_percent: cfg.ramp_ms_per_percent as u32,
+            phase: Phase::Decelerate,
+            since_ms: now_ms,
+        }
+    }
+
+    /// Reverse a rotor that is already at a standstill, from the dwell on
+    ///
+    /// The deceleration counts as already over, so the first `advance` enters
+    /// the dwell.
+    pub fn from_standstill(duty: u8, dwell_ms: u16, cfg: &ReverseConfig, now_ms: u32) -> Self {
+        let mut rev = Self::new(duty, dwell_ms, cfg, now_ms);
+        rev.since_ms = now_ms.wrapping_sub(rev.length_ms(Phase::Decelerate));
+        rev
+    }
+
+    /// Duty the reversal started from, and returns to
+    pub fn duty(&self) -> u8 {
+        self.duty
+    }
+
+    /// Coast at standstill between the ramps, in milliseconds
+    pub fn dwell_ms(&self) -> u32 {
+        self.dwell_ms
+    }
+
+    /// Phase reached by the last `advance`
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    fn length_ms(&self, phase: Phase) -> u32 {
+        match phase {
+            Phase::Decelerate | Phase::Accelerate => self.duty as u32 * self.ms_per_percent,
+            Phase::Dwell => self.dwell_ms,
+            Phase::Done => 0,
+        }
+    }
+
+    /// Move to `now_ms`; returns the duty and the phase just entered, if any
+    pub fn advance(&mut self, now_ms: u32) -> (u8, Option<Phase>) {
+        let mut entered = None;
+        if self.phase != Phase::Done
+            && now_ms.wrapping_sub(self.since_ms) >= self.length_ms(self.phase)
+        {
+            self.phase = match self.phase {
+                Phase::Decelerate => Phase::Dwell,
+                Phase::Dwell => Phase::Accelerate,
+                Phase::Accelerate | Phase::Done => Phase::Done,
+            };
+            self.since_ms = now_ms;
+            entered = Some(self.phase);
+        }
+        let ramped = (now_ms.wrapping_sub(self.since_ms) / self.ms_per_percent.max(1))
+            .min(self.duty as u32) as u8;
+        let duty = match self.phase {
+            Phase::Decelerate => self.duty - ramped,
+            Phase::Dwell => 0,
+            Phase::Accelerate => ramped,
+            Phase::Done => self.duty,
+        };
+        (duty, entered)
+    }
+}
+
+/// Reverse-and-retry attempts after stalls since the last start
+#[derive(Default)]
+pub struct StallRecovery {
+    /// Direction being retried, while an attempt is under way
+    origin: Option<Direction>,
+    attempts: u8,
+    /// Duty to drive the attempts at, as last commanded
+    duty: u8,
+    backed_since_ms: Option<u32>,
+}
+
+impl StallRecovery {
+    /// Forget all attempts and record the commanded duty (call when the motor starts)
+    pub fn reset(&mut self, duty: u8) {
+        *self = Self {
+            duty,
+            ..Self::default()
+        };
+    }
+
+    /// Record a new commanded duty, which the next attempt drives at
+    pub fn set_duty(&mut self, duty: u8) {
+        self.duty = duty;
+    }
+
+    /// Duty to drive an attempt at
+    pub fn duty(&self) -> u8 {
+        self.duty
+    }
+
+    /// Attempts made so far
+    pub fn attempts(&self) -> u8 {
+        self.attempts
+    }
+
+    /// Whether an attempt is under way
+    pub fn active(&self) -> bool {
+        self.origin.is_some()
+    }
+
+    /// A stall while running in `direction`; true if another attempt may be made
+    ///
+    /// A stall while backing off counts too, and the attempt it starts heads
+    /// straight back to the original direction.
+    pub fn on_stall(&mut self, direction: Direction, retries: u8) -> bool {
+        if self.attempts >= retries {
+            return false;
+        }
+        self.attempts += 1;
+        self.origin.get_or_insert(direction);
+        self.backed_since_ms = None;
+        true
+    }
+
+    /// Check a motor running in `direction` with no reversal in progress; true
+    /// once the back-off is over and it is time to reverse back
+    pub fn backed_off(&mut self, direction: Direction, now_ms: u32, backoff_ms: u16) -> bool {
+        if self.origin.is_none_or(|o| o == direction) {
+            return false;
+        }
+        let since = *self.backed_since_ms.get_or_insert(now_ms);
+        if now_ms.wrapping_sub(since) < backoff_ms as u32 {
+            return false;
+        }
+        self.backed_since_ms = None;
+        true
+    }
+
+    /// Report the motor up to speed in `direction`; true if that ends an attempt
+    ///
+    /// Only the original direction counts. A later stall then gets the full
+    /// number of attempts again.
+    pub fn recovered(&mut self, direction: Direction) -> bool {
+        if self.origin != Some(direction) {
+            return false;
+        }
+        self.reset(self.duty);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ramps_down_dwells_and_ramps_back_up() {
+        let cfg = ReverseConfig {
+            ramp_ms_per_percent: 10,
+            ..ReverseConfig::default()
+        };
+        let mut rev = Reversal::new(20, 300, &cfg, 1000);
+        assert_eq!(rev.advance(1000), (20, None));
+        assert_eq!(rev.advance(1100), (10, None));
+        assert_eq!(rev.advance(1200), (0, Some(Phase::Dwell)));
+        assert_eq!(rev.advance(1499), (0, None));
+        assert_eq!(rev.advance(1500), (0, Some(Phase::Accelerate)));
+        assert_eq!(rev.advance(1550), (5, None));
+        assert_eq!(rev.advance(1700), (20, Some(Phase::Done)));
+        assert_eq!(rev.advance(1800), (20, None));
+    }
+
+    #[test]
+    fn test_late_ticks_never_skip_the_dwell() {
+        let cfg = ReverseConfig {
+            ramp_ms_per_percent: 0,
+            ..ReverseConfig::default()
+        };
+        let mut rev = Reversal::new(20, 0, &cfg, 0);
+        // Zero ramp: straight to the dwell, which is held for the minimum
+        assert_eq!(rev.advance(5000), (0, Some(Phase::Dwell)));
+        assert_eq!(
+            rev.advance(5000 + MIN_REVERSE_DWELL_MS as u32 - 1),
+            (0, None)
+        );
+        assert_eq!(rev.advance(9000), (0, Some(Phase::Accelerate)));
+        assert_eq!(rev.advance(9001), (20, Some(Phase::Done)));
+    }
+
+    #[test]
+    fn test_stall_attempts_back_off_return_and_run_out() {
+        let cfg = ReverseConfig {
+            ramp_ms_per_percent: 10,
+            ..ReverseConfig::default()
+        };
+        let mut rev = Reversal::from_standstill(20, 300, &cfg, 1000);
+        assert_eq!(rev.advance(1000), (0, Some(Phase::Dwell)));
+        assert_eq!(rev.advance(1300), (0, Some(Phase::Accelerate)));
+
+        let mut rec = StallRecovery::default();
+        rec.reset(20);
+        assert!(rec.on_stall(Direction::Forward, 2));
+        // Still going forward: the reversal has not flipped the direction yet
+        assert!(!rec.backed_off(Direction::Forward, 0, 500));
+        assert!(!rec.backed_off(Direction::Reverse, 1000, 500));
+        assert!(!rec.backed_off(Direction::Reverse, 1499, 500));
+        assert!(rec.backed_off(Direction::Reverse, 1500, 500));
+        // Forward again but stalled once more: the last attempt, then none left
+        assert!(rec.on_stall(Direction::Forward, 2));
+        assert!(!rec.on_stall(Direction::Forward, 2));
+        // Back up to speed the other way round is no recovery
+        assert!(!rec.recovered(Direction::Reverse));
+        assert!(rec.active());
+        assert!(rec.recovered(Direction::Forward));
+        assert!(!rec.active());
+        assert_eq!((rec.attempts(), rec.duty()), (0, 20));
+    }
+}
@@ -0,0 +1,317 @@
+//! Phase resistance and inductance measurement (`MotorCommand::MeasureRL`)
+//!
+//! One phase pair is driven, A switching and B's low side on, with C off. The
+//! current then flows through two windings in series, 2R and 2L:
+//! 1. Resistance: the duty rises in 0.1% steps until the filtered current
+//!    reaches `target_current_ma`, then holds while the current is averaged.
+//!    R = V / I, with V the average applied voltage (duty × VBUS).
+//! 2. Inductance: once the current has died away, the same duty is applied
+//!    as a step and the current sampled back to back. Driven by a voltage
+//!    step, a series RL rises as I(1 - e^(-t/τ)) with τ = L / R. The time
+//!    from 39.3% to 77.7% of the steady current is exactly one τ, which needs
+//!    no knowledge of when the step reached the bridge. The step is repeated
+//!    `rise_repeats` times and τ averaged.
+//!
+//! Both come out per phase by halving the pair. Dead time and the switch
+//! drops make the real voltage a little lower than duty × VBUS, so R reads
+//! slightly high on very low-resistance motors.
+//!
+//! Every part is bounded by `RlConfig`: a duty ceiling, an abort current and
+//! a timeout on each rise. The rises are sampled in a blocking loop, at most
+//! `rise_timeout_us` each. It needs the motor armed.
+
+use core::cell::Cell;
+
+use cortex_m::peripheral::DWT;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Timer};
+use oxifoc_protocol::{MotorCommand, MotorElectricalParams, PhaseDrive, RlFailure, RlMeasurement};
+
+use super::blanking::{self, CYCLES_PER_US};
+use super::current::{CurrentIntegrator, CurrentSense};
+use super::six_step::PhaseState;
+use super::{ControlMsg, ControlReceiver, MotorController, Trace, poll_control};
+use crate::log;
+
+/// Measurement tunables
+#[derive(Clone, Copy)]
+pub struct RlConfig {
+    /// Winding current to measure at, in milliamps
+    pub target_current_ma: u16,
+    /// Give up once the current passes this, in milliamps
+    pub abort_current_ma: u16,
+    /// Highest duty the search may reach, in 0.1% steps (the PWM limits apply too)
+    pub max_duty_permille: u16,
+    /// Hold at the found duty while averaging, and wait for the current to decay, in milliseconds
+    pub settle_ms: u16,
+    /// Longest wait for one current rise, in microseconds
+    pub rise_timeout_us: u32,
+    /// Current rises timed and averaged
+    pub rise_repeats: u8,
+}
+
+impl Default for RlConfig {
+    fn default() -> Self {
+        Self {
+            target_current_ma: 1500,
+            abort_current_ma: 4000,
+            max_duty_permille: 150,
+            settle_ms: 30,
+            rise_timeout_us: 10_000,
+            rise_repeats: 4,
+        }
+    }
+}
+
+/// A current through A and back out through B
+const PAIR: PhaseState = PhaseState([PhaseDrive::Pwm, PhaseDrive::Low, PhaseDrive::Float]);
+/// Duty search and averaging interval
+const TICK: Duration = Duration::from_millis(1);
+/// Rise timing levels, in 0.1% of the steady current: 1 - e^-0.5 and 1 - e^-1.5
+const RISE_LO_PERMILLE: u32 = 393;
+const RISE_HI_PERMILLE: u32 = 777;
+
+/// Latest measurement, for the endpoint server
+static LATEST: Mutex<CriticalSectionRawMutex, Cell<RlMeasurement>> =
+    Mutex::new(Cell::new(RlMeasurement::Idle));
+
+/// State of the latest measurement
+pub fn latest() -> RlMeasurement {
+    LATEST.lock(|l| l.get())
+}
+
+fn set_latest(m: RlMeasurement) {
+    LATEST.lock(|l| l.set(m));
+}
+
+/// Times one current rise from the sample stream
+///
+/// Each level's crossing is interpolated between the samples either side of
+/// it, so the result is finer than the sampling interval.
+pub struct RiseTimer {
+    lo_ma: u32,
+    hi_ma: u32,
+    prev: Option<(u32, u32)>,
+    t_lo: Option<u32>,
+}
+
+impl RiseTimer {
+    /// Time a rise towards `steady_ma`
+    pub fn new(steady_ma: u32) -> Self {
+        Self {
+            lo_ma: steady_ma * RISE_LO_PERMILLE / 1000,
+            hi_ma: steady_ma * RISE_HI_PERMILLE / 1000,
+            prev: None,
+            t_lo: None,
+        }
+    }
+
+    /// Take a sample of `i_ma` at `t_us`; returns τ in microseconds once both levels are crossed
+    pub fn sample(&mut self, t_us: u32, i_ma: u32) -> Option<u32> {
+        let crossed = |level: u32| match self.prev {
+            Some((t0, i0)) if i0 < level && i_ma > i0 => {
+                t0 + (t_us - t0) * (level - i0) / (i_ma - i0)
+            }
+            _ => t_us,
+        };
+        if self.t_lo.is_none() && i_ma >= self.lo_ma {
+            self.t_lo = Some(crossed(self.lo_ma));
+        }
+        let tau = match self.t_lo {
+            Some(t_lo) if i_ma >= self.hi_ma => Some(crossed(self.hi_ma).saturating_sub(t_lo)),
+            _ => None,
+        };
+        self.prev = Some((t_us, i_ma));
+        tau
+    }
+}
+
+/// Per-phase parameters from the pair voltage, the steady current and τ
+pub fn params(v_mv: u32, i_ma: u32, tau_us: u32) -> Option<MotorElectricalParams> {
+    if i_ma == 0 {
+        return None;
+    }
+    let r_pair_mohm = v_mv as u64 * 1000 / i_ma as u64;
+    let l_pair_uh = tau_us as u64 * r_pair_mohm / 1000;
+    let r_mohm = u16::try_from(r_pair_mohm / 2).ok().filter(|&r| r > 0)?;
+    let l_uh = u16::try_from(l_pair_uh / 2).ok()?;
+    Some(MotorElectricalParams { r_mohm, l_uh })
+}
+
+/// Measure, leaving the result for `latest`
+///
+/// Any control message ends the measurement and is returned so the caller
+/// can still act on it (e.g. a `Stop`). The outputs are off afterwards.
+pub async fn run(
+    motor: &mut MotorController<'_>,
+    sense: &mut CurrentSense<'_>,
+    cfg: &RlConfig,
+    trace: Trace,
+    control: &ControlReceiver,
+) -> Option<ControlMsg> {
+    if !motor.is_armed() {
+        log::warn!("R/L measurement{} rejected: not armed", trace);
+        set_latest(RlMeasurement::Failed(RlFailure::Disarmed));
+        return None;
+    }
+    // Before any await, so a host polling right after the command never sees a stale result
+    set_latest(RlMeasurement::Measuring);
+    motor.handle_command(&MotorCommand::Stop, trace);
+    log::info!("R/L measurement{}: {}mA", trace, cfg.target_current_ma);
+
+    let mut pending = None;
+    let result = measure(motor, sense, cfg, control, &mut pending).await;
+    motor.coast();
+    match result {
+        Ok(p) => {
+            log::info!(
+                "R/L measurement{}: R={}mOhm L={}uH per phase",
+                trace,
+                p.r_mohm,
+                p.l_uh
+            );
+            set_latest(RlMeasurement::Done(p));
+        }
+        Err(f) => {
+            log::warn!(
+                "R/L measurement{} failed: {}",
+                trace,
+                defmt::Debug2Format(&f)
+            );
+            set_latest(RlMeasurement::Failed(f));
+        }
+    }
+    pending
+}
+
+async fn measure(
+    motor: &mut MotorController<'_>,
+    sense: &mut CurrentSense<'_>,
+    cfg: &RlConfig,
+    control: &ControlReceiver,
+    pending: &mut Option<ControlMsg>,
+) -> Result<MotorElectricalParams, RlFailure> {
+    let ceiling = cfg.max_duty_permille.min(motor.duty_ceiling_permille());
+    let settle_ticks = cfg.settle_ms.max(2) as u32;
+
+    // Duty search
+    // The duty steps up by itself; the integrator only filters the current
+    let mut duty = 0;
+    let mut search = CurrentIntegrator::new(1, ceiling);
+    while search.current_ma() < cfg.target_current_ma as u32 {
+        if duty >= ceiling {
+            return Err(RlFailure::NoCurrent);
+        }
+        duty += 1;
+        motor.apply_phases_permille(&PAIR, duty);
+        search.filter(sample(motor, sense, cfg, control, pending).await?);
+    }
+
+    // Steady current, averaged over the second half of the hold
+    let mut sum = 0;
+    for n in 0..settle_ticks {
+        let i = sample(motor, sense, cfg, control, pending).await?;
+        if n >= settle_ticks / 2 {
+            sum += i;
+        }
+    }
+    let steady_ma = sum / (settle_ticks - settle_ticks / 2);
+    let v_mv = duty as u32 * sense.vbus_mv() / 1000;
+
+    // Current rises
+    let mut tau_sum = 0;
+    let repeats = cfg.rise_repeats.max(1) as u32;
+    for _ in 0..repeats {
+        motor.all_phases_off();
+        for _ in 0..settle_ticks {
+            sample(motor, sense, cfg, control, pending).await?;
+        }
+        motor.apply_phases_permille(&PAIR, duty);
+        tau_sum += time_rise(sense, steady_ma, cfg)?;
+    }
+    motor.all_phases_off();
+    params(v_mv, steady_ma, tau_sum / repeats).ok_or(RlFailure::OutOfRange)
+}
+
+/// Wait a tick and read the current, ending the measurement on a command or overcurrent
+async fn sample(
+    motor: &mut MotorController<'_>,
+    sense: &mut CurrentSense<'_>,
+    cfg: &RlConfig,
+    control: &ControlReceiver,
+    pending: &mut Option<ControlMsg>,
+) -> Result<u32, RlFailure> {
+    Timer::after(TICK).await;
+    if let Some(msg) = poll_control(control) {
+        motor.all_phases_off();
+        *pending = Some(msg);
+        return Err(RlFailure::Aborted);
+    }
+    blanking::wait_clear().await;
+    let i = sense.magnitude();
+    if i > cfg.abort_current_ma as u32 {
+        motor.all_phases_off();
+        return Err(RlFailure::OverCurrent);
+    }
+    Ok(i)
+}
+
+/// Sample back to back until `RiseTimer` has τ, in microseconds
+fn time_rise(
+    sense: &mut CurrentSense<'_>,
+    steady_ma: u32,
+    cfg: &RlConfig,
+) -> Result<u32, RlFailure> {
+    let mut timer = RiseTimer::new(steady_ma);
+    let start = DWT::cycle_count();
+    loop {
+        let i = sense.magnitude();
+        let t_us = DWT::cycle_count().wrapping_sub(start) / CYCLES_PER_US;
+        if i > cfg.abort_current_ma as u32 {
+            return Err(RlFailure::OverCurrent);
+        }
+        if let Some(tau) = timer.sample(t_us, i) {
+            return Ok(tau);
+        }
+        if t_us > cfg.rise_timeout_us {
+            return Err(RlFailure::OutOfRange);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rise_gives_tau_whatever_the_step_delay() {
+        // 100 mΩ and 200 µH per phase: τ = 400 µH / 0.2 Ω = 2000 µs
+        // sampled every 20 µs, the step arriving 37 µs late
+        let mut timer = RiseTimer::new(1500);
+        let mut remaining = 1500.0f64;
+        let mut found = None;
+        for t in (0..10_000u32).step_by(20) {
+            if t > 37 {
+                // e^(-20 / 2000)
+                remaining *= 0.990_049_833_7;
+            }
+            if let Some(tau) = timer.sample(t, (1500.0 - remaining) as u32) {
+                found = Some(tau);
+                break;
+            }
+        }
+        let found = found.unwrap();
+        assert!((1980..=2020).contains(&found), "tau {}", found);
+
+        // 300 mV across the pair at 1.5 A
+        assert_eq!(
+            params(300, 1500, found),
+            Some(MotorElectricalParams {
+                r_mohm: 100,
+                l_uh: (found / 10) as u16
+            })
+        );
+        assert_eq!(params(300, 0, 2000), None);
+    }
+}
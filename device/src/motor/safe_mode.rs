@@ -0,0 +1,72 @@
+//! Bring-up guardrail, built in with the `safe-mode` feature
+//!
+//! For the first power-on of a new board, where a wiring or firmware bug can
+//! destroy the bridge. The limits are compile-time constants applied where
+//! the controller and the PWM driver clamp their inputs, so no runtime
+//! command, profile load or config change can get past them:
+//! - duty never exceeds `DUTY_CEILING` (5%), down to the compare value
+//! - the open-loop step period never drops below `MIN_PERIOD_US` (the 500 ms
+//!   boot default, the slowest the firmware commutates)
+//! - only 120° conduction, with one phase floating, is allowed
+//! - alignment regulates a lower current
+//!
+//! Without the feature the constants are the normal protocol bounds.
+
+use oxifoc_protocol::ScanScheme;
+use oxifoc_protocol::constants::{MAX_DUTY_PERCENT, MIN_COMMUTATION_PERIOD_US};
+
+use crate::log;
+
+/// Whether the firmware was built with `safe-mode`
+pub const ENABLED: bool = cfg!(feature = "safe-mode");
+
+/// Absolute duty ceiling in percent
+pub const DUTY_CEILING: u8 = if ENABLED { 5 } else { MAX_DUTY_PERCENT };
+
+/// Shortest run step period, in microseconds
+pub const MIN_PERIOD_US: u32 = if ENABLED {
+    500_000
+} else {
+    MIN_COMMUTATION_PERIOD_US
+};
+
+/// Winding current regulated during alignment in safe mode, in milliamps
+pub const ALIGN_CURRENT_MA: u16 = 500;
+
+/// `duty` limited to `DUTY_CEILING`, usable in constants
+pub const fn cap_duty(duty: u8) -> u8 {
+    if duty < DUTY_CEILING {
+        duty
+    } else {
+        DUTY_CEILING
+    }
+}
+
+/// The scan scheme actually applied when `scheme` is requested
+///
+/// 180° conduction drives all three phases on every step, so it is the
+/// high-power mode safe mode refuses.
+pub fn allowed_scheme(scheme: ScanScheme) -> ScanScheme {
+    if ENABLED && scheme == ScanScheme::Conduction180 {
+        log::warn!("SAFE MODE: 180-degree conduction refused, keeping 120");
+        return ScanScheme::Conduction120;
+    }
+    scheme
+}
+
+/// Announce the limits at boot, so a safe-mode build is never mistaken for a normal one
+pub fn banner() {
+    if !ENABLED {
+        return;
+    }
+    log::warn!("==================================================");
+    log::warn!("SAFE MODE firmware: bring-up limits are compiled in");
+    log::warn!(
+        "  duty <= {}%, step period >= {}ms, 120-degree only, align {}mA",
+        DUTY_CEILING,
+        MIN_PERIOD_US / 1000,
+        ALIGN_CURRENT_MA
+    );
+    log::warn!("  rebuild without `--features safe-mode` for full power");
+    log::warn!("==================================================");
+}
@@ -0,0 +1,151 @@
+//! Direction self-test and phase-order auto-correction
+//!
+//! Drives a few electrical revolutions of forward open-loop commutation at a
+//! hard-capped duty while sampling the hall sensors. If the halls report the
+//! rotor turning backwards, outputs B and C are swapped in the phase map (and
+//! optionally persisted) so a miswired motor runs the right way without
+//! rewiring.
+//!
+//! Which way the hall sequence runs depends on how the sensors are wired, not
+//! only on the motor's phases, so "backwards" is judged against a hall order
+//! learned first: a test with `learn` set, run while the motor is known to turn
+//! forward, stores the direction the halls report as forward
+//! (`RecordKey::HallOrder`). Until then the test reports `Uncalibrated` and
+//! leaves the phase map alone. Without hall sensors fitted there are no
+//! transitions to count, and the test always ends `Inconclusive`.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use oxifoc_protocol::{Direction, PhaseMap, SelfTestReport, SelfTestRequest, SelfTestResult};
+
+use super::blanking;
+use super::hall::{DirectionTracker, HallSensors};
+use super::six_step::CommutationStep;
+use super::{ControlMsg, ControlReceiver, MotorController, Trace, poll_control};
+use crate::log;
+use crate::storage::{self, RecordKey};
+
+/// Absolute duty ceiling for the self-test, regardless of the request
+pub const MAX_TEST_DUTY: u8 = super::safe_mode::cap_duty(10);
+/// Forward steps driven (4 electrical revolutions)
+const TEST_STEPS: u8 = 24;
+/// Dwell per step; slow enough for the rotor to follow without feedback
+const STEP_DWELL: Duration = Duration::from_millis(60);
+/// Hall sampling interval within a dwell
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(5);
+/// Transitions needed before a direction is trusted
+const MIN_TRANSITIONS: u8 = 6;
+
+/// Report of the most recent self-test, for the endpoint server awaiting it
+pub static RESULT: Signal<CriticalSectionRawMutex, SelfTestReport> = Signal::new();
+
+fn report(
+    result: SelfTestResult,
+    observed: Option<Direction>,
+    transitions: u8,
+    phase_map: PhaseMap,
+) -> SelfTestReport {
+    SelfTestReport {
+        result,
+        observed,
+        transitions,
+        phase_map,
+    }
+}
+
+/// Run the self-test
+///
+/// Any control message arriving during the test aborts it; the message is
+/// returned so the caller can still act on it (e.g. a `Stop`).
+pub async fn run(
+    motor: &mut MotorController<'_>,
+    hall: &HallSensors<'_>,
+    req: &SelfTestRequest,
+    control: &ControlReceiver,
+) -> (SelfTestReport, Option<ControlMsg>) {
+    if !motor.is_armed() {
+        log::warn!("Self-test rejected: not armed");
+        return (
+            report(SelfTestResult::Disarmed, None, 0, motor.phase_map()),
+            None,
+        );
+    }
+
+    let duty = req.duty.min(MAX_TEST_DUTY);
+    log::info!("Self-test: direction check at duty={}%", duty);
+    motor.handle_command(&oxifoc_protocol::MotorCommand::Stop, Trace::NONE);
+
+    let mut tracker = DirectionTracker::default();
+    let mut step = CommutationStep::Step0;
+    let samples_per_step = (STEP_DWELL.as_ticks() / SAMPLE_INTERVAL.as_ticks()).max(1);
+
+    for _ in 0..TEST_STEPS {
+        motor.apply_step(step, duty);
+        for _ in 0..samples_per_step {
+            Timer::after(SAMPLE_INTERVAL).await;
+            blanking::wait_clear().await;
+            tracker.sample(hall.read());
+            if let Some(msg) = poll_control(control) {
+                motor.all_phases_off();
+                log::warn!("Self-test aborted by incoming command");
+                let r = report(
+                    SelfTestResult::Aborted,
+                    None,
+                    tracker.transitions(),
+                    motor.phase_map(),
+                );
+                return (r, Some(msg));
+            }
+        }
+        step = step.next();
+    }
+    motor.all_phases_off();
+
+    let transitions = tracker.transitions();
+    let observed = tracker.direction(MIN_TRANSITIONS);
+    let forward = storage::load::<Direction>(RecordKey::HallOrder).await;
+    let result = match (observed, forward) {
+        (None, _) => {
+            log::warn!("Self-test inconclusive: {} hall transitions", transitions);
+            SelfTestResult::Inconclusive
+        }
+        (Some(seen), _) if req.learn => {
+            if let Err(e) = storage::store(RecordKey::HallOrder, &seen).await {
+                log::error!("Self-test: failed to store the hall order: {}", e);
+            }
+            log::info!(
+                "Self-test: hall order learned, forward reads as {}",
+                defmt::Debug2Format(&seen)
+            );
+            SelfTestResult::Learned
+        }
+        (Some(_), None) => {
+            log::warn!("Self-test: no hall order learned; run it with learn first");
+            SelfTestResult::Uncalibrated
+        }
+        (Some(seen), Some(forward)) if seen == forward => {
+            log::info!("Self-test OK: rotation matches commanded direction");
+            SelfTestResult::Ok
+        }
+        (Some(_), Some(_)) => {
+            let map = motor.phase_map().swapped_bc();
+            motor.set_phase_map(map);
+            log::warn!(
+                "Self-test: reversed rotation, phase map now {}",
+                map.channels
+            );
+            if req.persist
+                && let Err(e) = storage::store(RecordKey::PhaseMap, &map).await
+            {
+                log::error!("Self-test: failed to persist phase map: {}", e);
+            }
+            SelfTestResult::Corrected
+        }
+    };
+
+    (
+        report(result, observed, transitions, motor.phase_map()),
+        None,
+    )
+}
@@ -1,20 +1,28 @@
 //! 6-step commutation logic for BLDC motor control
+//!
+//! Which phases a step energizes comes from the step table of the selected
+//! `ScanScheme`; `CommutationStep` itself is only the position in the sequence.
+
+pub use oxifoc_protocol::PhaseDrive;
+use oxifoc_protocol::ScanScheme;
+
+use self::PhaseDrive::{Float, Low, Pwm};
 
 /// 6-step commutation state
 ///
-/// Each step energizes 2 of the 3 phases:
+/// With `ScanScheme::Conduction120` each step energizes 2 of the 3 phases:
 /// - One phase driven high
 /// - One phase driven low
 /// - One phase floating (high-Z)
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum CommutationStep {
-    Step0 = 0,  // A+, B-, C floating
-    Step1 = 1,  // A+, C-, B floating
-    Step2 = 2,  // B+, C-, A floating
-    Step3 = 3,  // B+, A-, C floating
-    Step4 = 4,  // C+, A-, B floating
-    Step5 = 5,  // C+, B-, A floating
+    Step0 = 0, // A+, B-, C floating
+    Step1 = 1, // A+, C-, B floating
+    Step2 = 2, // B+, C-, A floating
+    Step3 = 3, // B+, A-, C floating
+    Step4 = 4, // C+, A-, B floating
+    Step5 = 5, // C+, B-, A floating
 }
 
 impl CommutationStep {
@@ -30,31 +38,82 @@ impl CommutationStep {
         }
     }
 
+    /// Step back to the previous commutation step
+    pub fn prev(self) -> Self {
+        match self {
+            Self::Step0 => Self::Step5,
+            Self::Step1 => Self::Step0,
+            Self::Step2 => Self::Step1,
+            Self::Step3 => Self::Step2,
+            Self::Step4 => Self::Step3,
+            Self::Step5 => Self::Step4,
+        }
+    }
+
+    /// Step from its number (taken modulo 6)
+    pub fn from_u8(n: u8) -> Self {
+        match n % 6 {
+            0 => Self::Step0,
+            1 => Self::Step1,
+            2 => Self::Step2,
+            3 => Self::Step3,
+            4 => Self::Step4,
+            _ => Self::Step5,
+        }
+    }
+
     /// Get the step number (0-5)
     pub fn as_u8(self) -> u8 {
         self as u8
     }
 
-    /// Get phase enable/disable pattern for this step
-    ///
-    /// Returns (ph_a_en, ph_b_en, ph_c_en, ph_a_high, ph_b_high, ph_c_high)
-    /// - enable flags: true = phase active, false = floating
-    /// - high flags: true = high-side on, false = low-side on
-    pub fn get_phase_states(self) -> (bool, bool, bool, bool, bool, bool) {
-        match self {
-            // A+, B-, C floating
-            Self::Step0 => (true, true, false, true, false, false),
-            // A+, C-, B floating
-            Self::Step1 => (true, false, true, true, false, false),
-            // B+, C-, A floating
-            Self::Step2 => (false, true, true, false, true, false),
-            // B+, A-, C floating
-            Self::Step3 => (true, true, false, false, true, false),
-            // C+, A-, B floating
-            Self::Step4 => (true, false, true, false, false, true),
-            // C+, B-, A floating
-            Self::Step5 => (false, true, true, false, false, true),
-        }
+    /// Phase drives of this step under `scheme`
+    pub fn phase_state(self, scheme: ScanScheme) -> PhaseState {
+        table(scheme)[self as usize]
+    }
+}
+
+/// Drives of phases A, B and C for one commutation step
+///
+/// `PhaseDrive` is shared with the protocol, so the tables can be reported
+/// to the host as they are (`CommutationTableEndpoint`); how `Float` is
+/// realized is up to `pwm::FloatingPhase`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PhaseState(pub [PhaseDrive; 3]);
+
+impl PhaseState {
+    /// Phase left floating (0 = A, 1 = B, 2 = C), if any
+    #[cfg(any(test, feature = "diagnostics"))]
+    pub fn floating_phase(&self) -> Option<usize> {
+        self.0.iter().position(|d| *d == PhaseDrive::Float)
+    }
+}
+
+/// Two phases conduct, the third floats for BEMF sensing
+const CONDUCTION_120: [PhaseState; 6] = [
+    PhaseState([Pwm, Low, Float]), // A+, B-
+    PhaseState([Pwm, Float, Low]), // A+, C-
+    PhaseState([Float, Pwm, Low]), // B+, C-
+    PhaseState([Low, Pwm, Float]), // B+, A-
+    PhaseState([Low, Float, Pwm]), // C+, A-
+    PhaseState([Float, Low, Pwm]), // C+, B-
+];
+
+/// All three phases conduct; more torque per amp, but no floating phase
+const CONDUCTION_180: [PhaseState; 6] = [
+    PhaseState([Pwm, Low, Low]), // A+, B-, C-
+    PhaseState([Pwm, Pwm, Low]), // A+, B+, C-
+    PhaseState([Low, Pwm, Low]), // B+, A-, C-
+    PhaseState([Low, Pwm, Pwm]), // B+, C+, A-
+    PhaseState([Low, Low, Pwm]), // C+, A-, B-
+    PhaseState([Pwm, Low, Pwm]), // C+, A+, B-
+];
+
+/// Step table of `scheme`, indexed by step number
+pub const fn table(scheme: ScanScheme) -> &'static [PhaseState; 6] {
+    match scheme {
+        ScanScheme::Conduction120 => &CONDUCTION_120,
+        ScanScheme::Conduction180 => &CONDUCTION_180,
     }
 }
 
@@ -70,4 +129,78 @@ mod tests {
             assert_eq!(step.as_u8(), i % 6);
         }
     }
+
+    #[test]
+    fn test_prev_inverts_next() {
+        for n in 0..6 {
+            let step = CommutationStep::from_u8(n);
+            assert_eq!(step.as_u8(), n);
+            assert_eq!(step.next().prev(), step);
+        }
+    }
+
+    const SCHEMES: [ScanScheme; 2] = [ScanScheme::Conduction120, ScanScheme::Conduction180];
+
+    /// Stator field of a step as (2·x, 2/√3·y), phases at 0°, 120°, 240°
+    fn field(state: &PhaseState) -> (i32, i32) {
+        const AXES: [(i32, i32); 3] = [(2, 0), (-1, 1), (-1, -1)];
+        state
+            .0
+            .iter()
+            .zip(AXES)
+            .fold((0, 0), |(x, y), (d, (ax, ay))| {
+                let v = match d {
+                    Pwm => 1,
+                    Low => -1,
+                    Float => 0,
+                };
+                (x + v * ax, y + v * ay)
+            })
+    }
+
+    #[test]
+    fn test_schemes_drive_both_rails() {
+        for scheme in SCHEMES {
+            for state in table(scheme) {
+                assert!(state.0.contains(&Pwm), "{:?}: {:?}", scheme, state);
+                assert!(state.0.contains(&Low), "{:?}: {:?}", scheme, state);
+            }
+        }
+    }
+
+    #[test]
+    fn test_scheme_floating_phases() {
+        let floating: [Option<usize>; 6] =
+            core::array::from_fn(|n| CONDUCTION_120[n].floating_phase());
+        assert_eq!(
+            floating,
+            [Some(2), Some(1), Some(0), Some(2), Some(1), Some(0)]
+        );
+        assert!(CONDUCTION_180.iter().all(|s| s.floating_phase().is_none()));
+    }
+
+    #[test]
+    fn test_scheme_field_advances_one_sector_per_step() {
+        for scheme in SCHEMES {
+            let table = table(scheme);
+            for n in 0..6 {
+                let (x1, y1) = field(&table[n]);
+                let (x2, y2) = field(&table[(n + 1) % 6]);
+                // Counter-clockwise and less than 90° apart; six such steps
+                // close the circle only if each is one 60° sector
+                assert!(
+                    x1 * y2 - x2 * y1 > 0,
+                    "{:?} step {} turns backwards",
+                    scheme,
+                    n
+                );
+                assert!(
+                    x1 * x2 + 3 * y1 * y2 > 0,
+                    "{:?} step {} skips a sector",
+                    scheme,
+                    n
+                );
+            }
+        }
+    }
 }
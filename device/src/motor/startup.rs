@@ -0,0 +1,238 @@
+//! Startup sequence: current-regulated align, open-loop ramp, then run
+//!
+//! Aligning at a fixed duty gives a current (and torque) that scales with VBUS
+//! and winding resistance, and the uncontrolled inrush is what makes the
+//! audible chirp at start. Instead the align phase regulates duty so the
+//! measured winding current rises smoothly to `align_current_ma`. The ramp
+//! then steps open-loop from `ramp_start_period_ms` towards the run period
+//! before the regular sequencer takes over, and the sequencer's acceleration
+//! schedule (`super::accel`) covers the rest of the way. With `ramp_steps` at
+//! 0 the schedule does the whole ramp.
+//!
+//! One align step pulls the rotor to its position from anywhere but the
+//! opposite one, where the torque is zero and the rotor may stay put or go
+//! either way; it then starts up to 30° from where the ramp assumes, which
+//! can kick it backwards. `AlignMode::Double` first holds the step before
+//! for `pre_align_time_ms`, so the final align always pulls through 60°
+//! from a known position. The current regulation carries on across both.
+//! Without a trusted current reading the align holds the fixed
+//! `AdcHealthConfig::align_duty` instead (see `adc_health`).
+
+use embassy_time::{Duration, Instant, Timer};
+
+use super::adc_health::{self, Signals};
+use super::blanking;
+use super::current::{CurrentIntegrator, CurrentSense};
+use super::safe_mode;
+use super::six_step::CommutationStep;
+use super::{ControlMsg, ControlReceiver, MotorController, poll_control};
+use crate::log;
+
+/// How the rotor is positioned before the ramp
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum AlignMode {
+    /// Hold the start step only
+    Single,
+    /// Hold the step before it first, then the start step
+    Double,
+}
+
+/// Startup tunables
+#[derive(Clone, Copy)]
+pub struct StartupConfig {
+    pub align_mode: AlignMode,
+    /// Time spent holding the step before the start step with `AlignMode::Double`
+    pub pre_align_time_ms: u16,
+    /// Winding current regulated during alignment, in milliamps
+    pub align_current_ma: u16,
+    /// Duty ceiling while aligning, in percent (caps current if sensing is off)
+    pub align_max_duty: u8,
+    /// Time spent holding the align position
+    pub align_time_ms: u16,
+    /// First open-loop step period; no ramp if not above the run period
+    pub ramp_start_period_ms: u16,
+    /// Steps taken to ramp from `ramp_start_period_ms` to the run period
+    pub ramp_steps: u16,
+    /// Step to align at when the start command names none; `None` re-uses
+    /// the step last energized, where a parked rotor is most likely held
+    pub start_step: Option<CommutationStep>,
+}
+
+impl Default for StartupConfig {
+    fn default() -> Self {
+        Self {
+            align_mode: AlignMode::Single,
+            pre_align_time_ms: 150,
+            align_current_ma: if safe_mode::ENABLED {
+                safe_mode::ALIGN_CURRENT_MA
+            } else {
+                1500
+            },
+            align_max_duty: safe_mode::cap_duty(10),
+            align_time_ms: 300,
+            ramp_start_period_ms: 200,
+            ramp_steps: 12,
+            start_step: Some(CommutationStep::Step0),
+        }
+    }
+}
+
+/// Startup stage, for logging
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum StartupStage {
+    PreAlign,
+    Align,
+    Ramp,
+    Run,
+}
+
+/// How the startup sequence ended
+pub enum StartupOutcome {
+    /// Ramp finished; continue commutating from this step
+    Running(CommutationStep),
+    /// A control message arrived; all phases are off and it must be handled
+    Aborted(ControlMsg),
+}
+
+/// Current regulation update interval during alignment
+const ALIGN_TICK: Duration = Duration::from_millis(1);
+/// Integral gain: duty (0.1% steps) per mA of error per tick, as 1/`KI_DIV`
+const KI_DIV: i32 = 1000;
+
+/// Run align and ramp for a start at `duty`
+///
+/// The rotor is aligned at `step`, or the configured `start_step`, so the
+/// sequencer carries on from a position the rotor is known to hold.
+pub async fn run(
+    motor: &mut MotorController<'_>,
+    sense: &mut CurrentSense<'_>,
+    duty: u8,
+    step: Option<CommutationStep>,
+    control: &ControlReceiver,
+) -> StartupOutcome {
+    let cfg = *motor.startup_config();
+    let step = step.or(cfg.start_step).unwrap_or_else(|| motor.last_step());
+
+    // Kept across align steps
+    let mut align = CurrentIntegrator::new(KI_DIV, cfg.align_max_duty.min(100) as u16 * 10);
+    if cfg.align_mode == AlignMode::Double {
+        let pre = step.prev();
+        log::info!(
+            "Startup {}: step {} for {}ms",
+            StartupStage::PreAlign,
+            pre.as_u8(),
+            cfg.pre_align_time_ms
+        );
+        if let Err(msg) = align_hold(
+            &mut align,
+            motor,
+            sense,
+            pre,
+            cfg.pre_align_time_ms,
+            &cfg,
+            control,
+        )
+        .await
+        {
+            return StartupOutcome::Aborted(msg);
+        }
+    }
+
+    // Align: integrate current error into duty, hold for align_time_ms
+    log::info!(
+        "Startup {}: step {} at {}mA for {}ms",
+        StartupStage::Align,
+        step.as_u8(),
+        cfg.align_current_ma,
+        cfg.align_time_ms
+    );
+    if let Err(msg) = align_hold(
+        &mut align,
+        motor,
+        sense,
+        step,
+        cfg.align_time_ms,
+        &cfg,
+        control,
+    )
+    .await
+    {
+        return StartupOutcome::Aborted(msg);
+    }
+    log::info!(
+        "Startup align done: {}mA at duty={}/1000",
+        align.current_ma(),
+        align.duty_permille()
+    );
+
+    // Ramp: shorten the step period linearly towards the run period
+    let run_ms = motor.get_commutation_period().as_millis() as u32;
+    let start_ms = cfg.ramp_start_period_ms as u32;
+    let mut step = step;
+    // Dwell the sequencer continues from once running
+    let mut hand_over_ms = start_ms.max(run_ms);
+    if start_ms > run_ms && cfg.ramp_steps > 0 {
+        log::info!(
+            "Startup {}: {}ms -> {}ms over {} steps",
+            StartupStage::Ramp,
+            start_ms,
+            run_ms,
+            cfg.ramp_steps
+        );
+        let n = cfg.ramp_steps as u32;
+        for i in 0..n {
+            step = step.next();
+            motor.apply_step(step, duty);
+            let period = start_ms - (start_ms - run_ms) * i / n;
+            hand_over_ms = period;
+            Timer::after(Duration::from_millis(period as u64)).await;
+            if let Some(msg) = poll_control(control) {
+                motor.all_phases_off();
+                log::warn!("Startup aborted by incoming command");
+                return StartupOutcome::Aborted(msg);
+            }
+        }
+    }
+
+    motor.seed_dwell_ms(hand_over_ms);
+    log::info!("Startup {}: duty={}%", StartupStage::Run, duty);
+    StartupOutcome::Running(step.next())
+}
+
+/// Hold `step` for `time_ms`, regulating to `align_current_ma` if the current is trusted
+async fn align_hold(
+    align: &mut CurrentIntegrator,
+    motor: &mut MotorController<'_>,
+    sense: &mut CurrentSense<'_>,
+    step: CommutationStep,
+    time_ms: u16,
+    cfg: &StartupConfig,
+    control: &ControlReceiver,
+) -> Result<(), ControlMsg> {
+    let end = Instant::now() + Duration::from_millis(time_ms as u64);
+    // Without a trusted current there is nothing to regulate on
+    let fixed = (!adc_health::trusted(Signals::CURRENT))
+        .then(|| motor.adc_health_config().align_duty.min(cfg.align_max_duty) as u16 * 10);
+    if let Some(permille) = fixed {
+        log::warn!(
+            "Startup align: current untrusted, holding duty={}/1000",
+            permille
+        );
+    }
+    while Instant::now() < end {
+        let permille = fixed.unwrap_or(align.duty_permille());
+        motor.apply_step_permille(step, permille);
+        Timer::after(ALIGN_TICK).await;
+        if let Some(msg) = poll_control(control) {
+            motor.all_phases_off();
+            log::warn!("Startup aborted by incoming command");
+            return Err(msg);
+        }
+        if fixed.is_some() {
+            continue;
+        }
+        blanking::wait_clear().await;
+        align.update(sense.magnitude(), cfg.align_current_ma);
+    }
+    Ok(())
+}
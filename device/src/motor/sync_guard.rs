@@ -0,0 +1,156 @@
+//! Skipped and early step detection for the external trigger
+//!
+//! In `CommutationMode::External` every step waits for an edge on the trigger
+//! input, typically a zero-crossing comparator. A crossing that is missed
+//! skips a step, and a glitch that passes for one steps too early; either way
+//! the field falls behind or ahead of the rotor, and the next ones cascade into
+//! lost sync. The guard keeps a running estimate of the step interval from
+//! edges that arrive on schedule, and the control task checks each edge
+//! against it:
+//! - an edge less than `early_percent` of the estimate after the last step
+//!   is ignored; the step keeps waiting for the next edge
+//! - a step with no edge by `late_percent` of the estimate is taken without
+//!   one, bridging the missed crossing
+//!
+//! Each bridged step is a correction. An on-schedule edge clears them; after
+//! `max_corrections` in a row the motor stops with `Fault::LostSync` instead
+//! of stepping blind. Until there is an estimate (the first edge after a start
+//! or a mode switch), and with the guard off, a step waits for its edge up to
+//! the trigger timeout as before.
+
+/// Sync guard tunables
+#[derive(Clone, Copy)]
+pub struct SyncGuardConfig {
+    pub enabled: bool,
+    /// Edges sooner than this share of the expected interval are ignored, in percent
+    pub early_percent: u8,
+    /// A step takes this share of the expected interval at most before it goes without an edge, in percent
+    pub late_percent: u16,
+    /// Bridged steps in a row before sync counts as lost
+    pub max_corrections: u8,
+}
+
+impl Default for SyncGuardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            early_percent: 50,
+            late_percent: 150, // halfway to where the crossing after a missed one lands
+            max_corrections: 3,
+        }
+    }
+}
+
+/// What the control task does about the latest edge or deadline
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Timing {
+    /// Step now
+    OnTime,
+    /// Ignore the edge, keep waiting
+    Early,
+    /// Step now without an edge
+    Late,
+    /// Stop: too many steps went without an edge
+    Lost,
+}
+
+/// Step interval estimate and the corrections made since the last good edge
+#[derive(Default)]
+pub struct SyncGuard {
+    last_step_us: Option<u64>,
+    expected_us: Option<u32>,
+    corrections: u8,
+}
+
+impl SyncGuard {
+    /// Forget the estimate (the trigger is not being followed)
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Expected step interval in µs, once one was measured
+    pub fn expected_us(&self) -> Option<u32> {
+        self.expected_us
+    }
+
+    /// Time by which the next step goes without an edge, in µs; `None` without an estimate
+    pub fn late_deadline_us(&self, cfg: &SyncGuardConfig) -> Option<u64> {
+        if !cfg.enabled {
+            return None;
+        }
+        let (last, expected) = (self.last_step_us?, self.expected_us?);
+        Some(last + expected as u64 * cfg.late_percent as u64 / 100)
+    }
+
+    /// Judge an edge seen at `now_us`
+    pub fn on_edge(&mut self, now_us: u64, cfg: &SyncGuardConfig) -> Timing {
+        let interval = self
+            .last_step_us
+            .map(|last| now_us.saturating_sub(last).min(u32::MAX as u64) as u32);
+        if let (Some(interval), Some(expected)) = (interval, self.expected_us)
+            && cfg.enabled
+            && (interval as u64) * 100 < expected as u64 * cfg.early_percent as u64
+        {
+            return Timing::Early;
+        }
+        // On schedule: the estimate follows it, a quarter of the way
+        self.expected_us = match (self.expected_us, interval) {
+            (Some(expected), Some(interval)) => Some(expected - expected / 4 + interval / 4),
+            (None, interval) => interval,
+            (expected, None) => expected,
+        };
+        self.corrections = 0;
+        self.last_step_us = Some(now_us);
+        Timing::OnTime
+    }
+
+    /// The late deadline passed at `now_us` without an edge
+    pub fn on_late(&mut self, now_us: u64, cfg: &SyncGuardConfig) -> Timing {
+        self.corrections = self.corrections.saturating_add(1);
+        if self.corrections > cfg.max_corrections {
+            self.reset();
+            return Timing::Lost;
+        }
+        self.last_step_us = Some(now_us);
+        Timing::Late
+    }
+
+    /// Corrections since the last on-schedule edge
+    pub fn corrections(&self) -> u8 {
+        self.corrections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_a_missed_edge_is_bridged_and_too_many_lose_sync() {
+        let cfg = SyncGuardConfig::default();
+        let mut guard = SyncGuard::default();
+        assert_eq!(guard.late_deadline_us(&cfg), None);
+        assert_eq!(guard.on_edge(10_000, &cfg), Timing::OnTime);
+        assert_eq!(guard.on_edge(11_000, &cfg), Timing::OnTime);
+        assert_eq!(guard.expected_us(), Some(1_000));
+
+        // A glitch halfway through a step does not step
+        assert_eq!(guard.on_edge(11_400, &cfg), Timing::Early);
+        assert_eq!(guard.on_edge(12_200, &cfg), Timing::OnTime);
+        assert_eq!(guard.expected_us(), Some(1_050));
+
+        // The next crossing is missed: step at the deadline, count it
+        assert_eq!(guard.late_deadline_us(&cfg), Some(12_200 + 1_575));
+        assert_eq!(guard.on_late(13_775, &cfg), Timing::Late);
+        assert_eq!(guard.on_edge(14_900, &cfg), Timing::OnTime);
+        assert_eq!(guard.corrections(), 0);
+
+        for _ in 0..3 {
+            let deadline = guard.late_deadline_us(&cfg).unwrap();
+            assert_eq!(guard.on_late(deadline, &cfg), Timing::Late);
+        }
+        let deadline = guard.late_deadline_us(&cfg).unwrap();
+        assert_eq!(guard.on_late(deadline, &cfg), Timing::Lost);
+        assert_eq!(guard.late_deadline_us(&cfg), None);
+    }
+}
@@ -0,0 +1,118 @@
+//! Telemetry sampling for the `TelemetryEndpoint` stream
+//!
+//! The control task owns the ADC, so it records the analog readings once per
+//! loop; the telemetry task combines them with the motor state atomics and
+//! sends only the fields selected by `MotorCommand::SetTelemetryMask`, delta
+//! coded with `MotorCommand::SetTelemetryDelta`. Each recording also widens
+//! the `TelemetryExtremes` served to the host.
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicI16, AtomicU8, AtomicU16, Ordering};
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use oxifoc_protocol::{Telemetry, TelemetryExtremes, telemetry_field};
+
+use super::adc_health;
+use super::current::CurrentSense;
+use super::protection::Readings;
+use super::{get_fault, get_limp_active, get_motor_output, get_motor_state, get_rpm_estimate};
+
+static MASK: AtomicU16 = AtomicU16::new(telemetry_field::ALL);
+static KEY_INTERVAL: AtomicU8 = AtomicU8::new(0);
+static VBUS_MV: AtomicU16 = AtomicU16::new(0);
+static TEMP_DC: AtomicI16 = AtomicI16::new(0);
+static CURRENT_MA: AtomicU16 = AtomicU16::new(0);
+static EXTREMES: Mutex<CriticalSectionRawMutex, Cell<TelemetryExtremes>> =
+    Mutex::new(Cell::new(TelemetryExtremes::reset(0)));
+
+/// Select the streamed fields; unknown bits are ignored
+pub fn set_mask(mask: u16) {
+    MASK.store(mask & telemetry_field::ALL, Ordering::Relaxed);
+}
+
+/// Fields currently streamed
+pub fn mask() -> u16 {
+    MASK.load(Ordering::Relaxed)
+}
+
+/// Samples per key frame of the delta coding; 0 = full samples only
+pub fn set_key_interval(key_interval: u8) {
+    KEY_INTERVAL.store(key_interval, Ordering::Relaxed);
+}
+
+pub fn key_interval() -> u8 {
+    KEY_INTERVAL.load(Ordering::Relaxed)
+}
+
+/// Take the analog readings; call from the task that owns `sense`
+pub fn record_analog(sense: &mut CurrentSense<'_>) {
+    let vbus_mv = sense.vbus_mv().min(u16::MAX as u32) as u16;
+    let temp_dc = sense.temperature_dc() as i16;
+    let current_ma = sense.magnitude().min(u16::MAX as u32) as u16;
+    VBUS_MV.store(vbus_mv, Ordering::Relaxed);
+    TEMP_DC.store(temp_dc, Ordering::Relaxed);
+    CURRENT_MA.store(current_ma, Ordering::Relaxed);
+    let rpm = get_rpm_estimate();
+    EXTREMES.lock(|e| {
+        let mut ext = e.get();
+        ext.record(rpm, vbus_mv, temp_dc, current_ma);
+        e.set(ext);
+    });
+}
+
+/// Extremes of every reading since the last `reset_extremes`
+#[cfg(feature = "diagnostics")]
+pub fn extremes() -> TelemetryExtremes {
+    EXTREMES.lock(|e| e.get())
+}
+
+/// Start the extremes over from now; returns the ones that ended
+#[cfg(feature = "diagnostics")]
+pub fn reset_extremes() -> TelemetryExtremes {
+    let fresh = TelemetryExtremes::reset(embassy_time::Instant::now().as_millis() as u32);
+    EXTREMES.lock(|e| e.replace(fresh))
+}
+
+/// Bus voltage from the last `record_analog`, 0 before the first
+pub fn vbus_mv() -> u16 {
+    VBUS_MV.load(Ordering::Relaxed)
+}
+
+/// Board temperature from the last `record_analog`, in 0.1 °C
+pub fn temp_dc() -> i16 {
+    TEMP_DC.load(Ordering::Relaxed)
+}
+
+/// Winding current from the last `record_analog`, in mA
+pub fn current_ma() -> u16 {
+    CURRENT_MA.load(Ordering::Relaxed)
+}
+
+/// Readings from the last `record_analog`, with the signals the ADC health check distrusts
+pub fn readings() -> Readings {
+    Readings {
+        current_ma: current_ma(),
+        temp_dc: temp_dc(),
+        vbus_mv: vbus_mv(),
+        untrusted: adc_health::untrusted(),
+    }
+}
+
+/// Latest values of every telemetry field
+pub fn sample() -> Telemetry {
+    let (duty, step) = get_motor_output();
+    Telemetry {
+        rpm: Some(get_rpm_estimate()),
+        duty: Some(duty),
+        step: Some(step),
+        state: Some(get_motor_state()),
+        vbus_mv: Some(VBUS_MV.load(Ordering::Relaxed)),
+        temp_dc: Some(TEMP_DC.load(Ordering::Relaxed)),
+        current_ma: Some(CURRENT_MA.load(Ordering::Relaxed)),
+        limp: Some(get_limp_active()),
+        fault: Some(get_fault()),
+        fan: crate::fan::duty(),
+        adc_fault: Some(adc_health::adc_fault()),
+    }
+}
@@ -0,0 +1,113 @@
+//! Bench test patterns: deterministic PWM for checking the bridge on a scope
+//!
+//! Meant for bring-up with no motor attached: each pattern exercises the
+//! gate drivers and PWM timing in a fixed, repeatable way. Like a nudge it
+//! needs the motor armed and runs in the control task, bypassing the
+//! commutation sequencer; unlike a nudge it keeps going until any control
+//! message arrives. Duty is capped at `MAX_TEST_PATTERN_DUTY` on top of the
+//! PWM's own limits.
+
+use embassy_time::{Duration, Instant, Timer};
+use oxifoc_protocol::constants::{MAX_TEST_PATTERN_DUTY, MIN_TEST_PATTERN_PERIOD_MS};
+use oxifoc_protocol::{MotorCommand, PhaseDrive, TestPattern};
+
+use super::six_step::PhaseState;
+use super::{ControlMsg, ControlReceiver, MotorController, Trace, poll_control};
+use crate::log;
+
+/// Output update and abort poll interval
+const TICK: Duration = Duration::from_millis(MIN_TEST_PATTERN_PERIOD_MS as u64);
+
+/// Phase drives and duty (0.1% steps) of `pattern` at `t_ms` after it started
+pub fn output_at(pattern: TestPattern, t_ms: u32) -> (PhaseState, u16) {
+    let peak = pattern.duty().min(MAX_TEST_PATTERN_DUTY) as u32 * 10;
+    let period = pattern
+        .period_ms()
+        .unwrap_or(0)
+        .max(MIN_TEST_PATTERN_PERIOD_MS) as u32;
+    match pattern {
+        TestPattern::WalkPhase { .. } => {
+            let mut drives = [PhaseDrive::Float; 3];
+            drives[(t_ms / period % 3) as usize] = PhaseDrive::Pwm;
+            (PhaseState(drives), peak as u16)
+        }
+        TestPattern::AllPhases { .. } => (PhaseState([PhaseDrive::Pwm; 3]), peak as u16),
+        TestPattern::RampDuty { .. } => {
+            // Triangle: up over the first half of the period, down over the second
+            let half = period / 2;
+            let pos = t_ms % period;
+            let rise = if pos < half { pos } else { period - pos };
+            (
+                PhaseState([PhaseDrive::Pwm; 3]),
+                (peak * rise / half) as u16,
+            )
+        }
+    }
+}
+
+/// Drive `pattern` until a control message arrives
+///
+/// The message is returned so the caller can still act on it; all phases
+/// are at 0% by then.
+pub async fn run(
+    motor: &mut MotorController<'_>,
+    pattern: TestPattern,
+    trace: Trace,
+    control: &ControlReceiver,
+) -> Option<ControlMsg> {
+    if !motor.is_armed() {
+        log::warn!("Test pattern{} rejected: not armed", trace);
+        return None;
+    }
+
+    log::info!("Test pattern{}: {}", trace, defmt::Debug2Format(&pattern));
+    motor.handle_command(&MotorCommand::Stop, trace);
+
+    let start = Instant::now();
+    loop {
+        let (state, duty_permille) = output_at(pattern, start.elapsed().as_millis() as u32);
+        motor.apply_phases_permille(&state, duty_permille);
+        Timer::after(TICK).await;
+        if let Some(msg) = poll_control(control) {
+            motor.all_phases_off();
+            log::info!("Test pattern{} ended by incoming command", trace);
+            return Some(msg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_walk_moves_one_phase_per_dwell_and_ramp_is_a_triangle() {
+        let walk = TestPattern::WalkPhase {
+            duty: 5,
+            dwell_ms: 100,
+        };
+        let pwm_phase = |t| {
+            output_at(walk, t)
+                .0
+                .0
+                .iter()
+                .position(|d| *d == PhaseDrive::Pwm)
+        };
+        assert_eq!(
+            [0, 99, 100, 250, 300].map(pwm_phase),
+            [Some(0), Some(0), Some(1), Some(2), Some(0)]
+        );
+        assert_eq!(output_at(walk, 0).1, 50);
+
+        let ramp = TestPattern::RampDuty {
+            duty: 10,
+            period_ms: 200,
+        };
+        let duty = |t| output_at(ramp, t).1;
+        assert_eq!([0, 50, 100, 150, 200].map(duty), [0, 50, 100, 50, 0]);
+
+        // Out-of-range commands are clamped, not rejected, on this end
+        let hot = TestPattern::AllPhases { duty: 100 };
+        assert_eq!(output_at(hot, 0).1, MAX_TEST_PATTERN_DUTY as u16 * 10);
+    }
+}
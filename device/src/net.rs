@@ -0,0 +1,84 @@
+//! Ergot network stack construction and node addressing
+//!
+//! The device is the DirectEdge target: the host is the controller and owns
+//! the network; the device is `1.2` and talks to the host at `1.1`.
+//!
+//! The stack, the RX worker and the local/peer node ids live here so `main`
+//! does not hardcode a profile.
+
+use ergot::{
+    Address, exports::bbq2::traits::coordination::cas::AtomicCoord,
+    toolkits::embedded_io_async_v0_6 as kit,
+};
+use mutex::raw_impls::cs::CriticalSectionRawMutex;
+use oxifoc_protocol::constants::{CONTROLLER_NODE_ID, DEFAULT_NETWORK_ID, TARGET_NODE_ID};
+
+use crate::rtt_io;
+
+pub use oxifoc_protocol::constants::MAX_PACKET_SIZE;
+
+pub const OUT_QUEUE_SIZE: usize = 2048;
+
+/// Network id shared by both ends of the RTT link
+///
+/// Override at build time with `OXIFOC_NETWORK_ID=<n>` so several rigs on one
+/// machine use distinct networks; must match `[addressing] network_id` on the host.
+pub const NETWORK_ID: u16 = match option_env!("OXIFOC_NETWORK_ID") {
+    Some(s) => parse_u16(s),
+    None => DEFAULT_NETWORK_ID,
+};
+
+/// Const decimal parser for build-time configuration
+const fn parse_u16(s: &str) -> u16 {
+    let bytes = s.as_bytes();
+    assert!(!bytes.is_empty(), "OXIFOC_NETWORK_ID is empty");
+    let mut value: u32 = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let d = bytes[i];
+        assert!(
+            d.is_ascii_digit(),
+            "OXIFOC_NETWORK_ID must be a decimal number"
+        );
+        value = value * 10 + (d - b'0') as u32;
+        assert!(value <= u16::MAX as u32, "OXIFOC_NETWORK_ID out of range");
+        i += 1;
+    }
+    value as u16
+}
+// Type aliases for our application
+pub type Queue = kit::Queue<OUT_QUEUE_SIZE, AtomicCoord>;
+pub type Stack = kit::Stack<&'static Queue, CriticalSectionRawMutex>;
+pub type RxWorker = kit::RxWorker<&'static Queue, CriticalSectionRawMutex, rtt_io::RttReader>;
+
+/// Statically store our outgoing packet buffer
+pub static OUTQ: Queue = kit::Queue::new();
+
+/// Statically store our netstack
+pub static STACK: Stack = kit::new_target_stack(OUTQ.stream_producer(), MAX_PACKET_SIZE as u16);
+
+/// Create the RX worker for the RTT link
+///
+/// The worker sets the interface Inactive, then Active after the first frame.
+pub fn new_rx_worker(rx: rtt_io::RttReader) -> RxWorker {
+    RxWorker::new_target(&STACK, rx, ())
+}
+
+/// Node id of this device on the link
+pub const fn local_node_id() -> u8 {
+    TARGET_NODE_ID
+}
+
+/// Node id of the peer at the other end of the RTT link
+pub const fn peer_node_id() -> u8 {
+    CONTROLLER_NODE_ID
+}
+
+/// Address of the peer (normally the host) for device-originated requests
+pub const fn peer_addr() -> Address {
+    Address {
+        network_id: NETWORK_ID,
+        node_id: peer_node_id(),
+        port_id: 0,
+    }
+}
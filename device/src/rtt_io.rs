@@ -33,8 +33,10 @@ impl Read for RttReader {
         // Non-blocking read from RTT down channel
         // If no data available, yield to prevent busy-waiting
         loop {
+            crate::task_stats::RX.tick();
             let n = self.down.read(buf);
             if n > 0 {
+                crate::link_failsafe::note_rx();
                 return Ok(n);
             }
             // Yield to allow other tasks to run
@@ -43,14 +45,63 @@ impl Read for RttReader {
     }
 }
 
+/// Size of the optional write batching buffer (one max-size ergot frame plus COBS overhead)
+pub const BATCH_SIZE: usize = 520;
+
+/// COBS frame delimiter used by the ergot stream framing
+const FRAME_DELIMITER: u8 = 0x00;
+
 /// RTT channel wrapper for writing (UpChannel - device to host)
+///
+/// In batching mode small writes accumulate in an internal buffer and are pushed to
+/// the channel on `flush`, when a frame delimiter completes a frame, or when the
+/// buffer fills. Each channel write only ever
+/// carries whole COBS frames, so with `NoBlockSkip` a full channel drops complete
+/// frames rather than cutting one in half.
 pub struct RttWriter {
     channel: &'static mut UpChannel,
+    batch: Option<heapless::Vec<u8, BATCH_SIZE>>,
 }
 
 impl RttWriter {
     pub fn new(channel: &'static mut UpChannel) -> Self {
-        Self { channel }
+        Self {
+            channel,
+            batch: None,
+        }
+    }
+
+    /// Create a writer that batches writes until `flush` or the buffer fills
+    pub fn new_batched(channel: &'static mut UpChannel) -> Self {
+        Self {
+            channel,
+            batch: Some(heapless::Vec::new()),
+        }
+    }
+
+    /// Write `data` to the channel in one call
+    fn emit(channel: &mut UpChannel, data: &[u8]) {
+        if !data.is_empty() {
+            channel.write(data);
+        }
+    }
+
+    /// Emit all complete frames in the batch buffer, keeping any trailing partial frame
+    ///
+    /// If the buffer holds no delimiter at all (a single frame larger than the buffer)
+    /// everything is emitted, since it can never be completed in place.
+    fn emit_complete_frames(&mut self) {
+        let Some(batch) = self.batch.as_mut() else {
+            return;
+        };
+        let split = match batch.iter().rposition(|&b| b == FRAME_DELIMITER) {
+            Some(idx) => idx + 1,
+            None => batch.len(),
+        };
+        Self::emit(self.channel, &batch[..split]);
+        let rest = batch.len() - split;
+        batch.copy_within(split.., 0);
+        batch.truncate(rest);
     }
 }
 
@@ -60,13 +111,33 @@ impl ErrorType for RttWriter {
 
 impl Write for RttWriter {
     async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
-        // RTT write is blocking, but typically very fast
-        let written = self.channel.write(buf);
-        Ok(written)
+        crate::task_stats::TX.tick();
+        let Some(batch) = self.batch.as_mut() else {
+            // RTT write is blocking, but typically very fast
+            let written = self.channel.write(buf);
+            return Ok(written);
+        };
+
+        if batch.len() + buf.len() > BATCH_SIZE {
+            self.emit_complete_frames();
+        }
+
+        let batch = self.batch.as_mut().unwrap();
+        let take = buf.len().min(BATCH_SIZE - batch.len());
+        // Cannot fail: `take` is bounded by the remaining capacity
+        let _ = batch.extend_from_slice(&buf[..take]);
+        if batch.is_full() || batch.last() == Some(&FRAME_DELIMITER) {
+            self.emit_complete_frames();
+        }
+        Ok(take)
     }
 
     async fn flush(&mut self) -> Result<(), Self::Error> {
-        // RTT doesn't need explicit flushing
+        // Unbatched writes go straight to the channel; nothing to push
+        if let Some(batch) = self.batch.as_mut() {
+            Self::emit(self.channel, batch);
+            batch.clear();
+        }
         Ok(())
     }
 }
@@ -85,6 +156,14 @@ impl RttIo {
         }
     }
 
+    /// Enable write batching on the up channel (see [`RttWriter::new_batched`])
+    pub fn with_batching(self) -> Self {
+        Self {
+            reader: self.reader,
+            writer: RttWriter::new_batched(self.writer.channel),
+        }
+    }
+
     pub fn split(self) -> (RttReader, RttWriter) {
         (self.reader, self.writer)
     }
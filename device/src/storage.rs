@@ -0,0 +1,135 @@
+//! Persistent parameter storage in the last pages of internal flash
+//!
+//! Each record type owns one 2 KiB page counted down from the end of flash, so
+//! updating one record never disturbs another. A record is a small header
+//! (magic, length, checksum) followed by the postcard-encoded value.
+//!
+//! The firmware image must stay below `FLASH_SIZE - RESERVED_PAGES * PAGE_SIZE`;
+//! `ld/memory.x` links it there. Diagnostics and bench builds do not fit and use
+//! all of flash instead (see `build.rs`), so they keep no records: nothing
+//! loads, and storing fails with `Disabled`. Records left by another build
+//! are overwritten by the image and fail the magic or checksum afterwards.
+
+use embassy_stm32::flash::{Blocking, Flash};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::log;
+
+/// STM32G431CB: 128 KiB flash, 2 KiB pages
+const FLASH_SIZE: u32 = 128 * 1024;
+const PAGE_SIZE: u32 = 2048;
+/// Pages reserved at the end of flash for records
+pub const RESERVED_PAGES: u32 = 8;
+/// Whether this build leaves the reserved pages to the records
+const ENABLED: bool = cfg!(not(any(feature = "diagnostics", feature = "bench")));
+
+const RECORD_MAGIC: u32 = 0x4346_584F; // "OXFC"
+const HEADER_LEN: usize = 8; // magic (4) + payload length (2) + checksum (2)
+const MAX_RECORD_LEN: usize = 512;
+/// G4 flash is programmed in 64-bit double words
+const WRITE_ALIGN: usize = 8;
+
+/// Record types, each stored in its own flash page
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub enum RecordKey {
+    Profiles = 0,
+    PhaseMap = 1,
+    CurrentCalibration = 2,
+    HallOrder = 3,
+}
+
+impl RecordKey {
+    fn offset(self) -> u32 {
+        FLASH_SIZE - PAGE_SIZE * (self as u32 + 1)
+    }
+}
+
+#[derive(Clone, Copy, Debug, defmt::Format)]
+pub enum StorageError {
+    NotInitialized,
+    Disabled,
+    Flash,
+    Encode,
+}
+
+struct Storage {
+    flash: Flash<'static, Blocking>,
+}
+
+static STORAGE: Mutex<CriticalSectionRawMutex, Option<Storage>> = Mutex::new(None);
+
+/// Hand the flash peripheral to the storage module
+pub async fn init(flash: Flash<'static, Blocking>) {
+    if !ENABLED {
+        log::warn!("Storage: disabled, this build uses the reserved pages for code");
+        return;
+    }
+    *STORAGE.lock().await = Some(Storage { flash });
+    log::info!(
+        "Storage ready: {} pages reserved at 0x{:x}",
+        RESERVED_PAGES,
+        FLASH_SIZE - RESERVED_PAGES * PAGE_SIZE
+    );
+}
+
+fn checksum(data: &[u8]) -> u16 {
+    data.iter()
+        .fold(0u16, |acc, &b| acc.rotate_left(1) ^ b as u16)
+}
+
+/// Load a record, returning `None` if it is missing, corrupt, or fails to decode
+pub async fn load<T: DeserializeOwned>(key: RecordKey) -> Option<T> {
+    let mut guard = STORAGE.lock().await;
+    let storage = guard.as_mut()?;
+
+    let mut buf = [0u8; HEADER_LEN + MAX_RECORD_LEN];
+    storage.flash.blocking_read(key.offset(), &mut buf).ok()?;
+
+    let magic = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    let len = u16::from_le_bytes([buf[4], buf[5]]) as usize;
+    let sum = u16::from_le_bytes([buf[6], buf[7]]);
+    if magic != RECORD_MAGIC || len > MAX_RECORD_LEN {
+        return None;
+    }
+    let payload = &buf[HEADER_LEN..HEADER_LEN + len];
+    if checksum(payload) != sum {
+        log::warn!("Storage: checksum mismatch for record {}", key);
+        return None;
+    }
+    postcard::from_bytes(payload).ok()
+}
+
+/// Encode and store a record, replacing any previous value
+pub async fn store<T: Serialize>(key: RecordKey, value: &T) -> Result<(), StorageError> {
+    if !ENABLED {
+        return Err(StorageError::Disabled);
+    }
+    let mut guard = STORAGE.lock().await;
+    let storage = guard.as_mut().ok_or(StorageError::NotInitialized)?;
+
+    let mut buf = [0xFFu8; HEADER_LEN + MAX_RECORD_LEN];
+    let len = postcard::to_slice(value, &mut buf[HEADER_LEN..])
+        .map_err(|_| StorageError::Encode)?
+        .len();
+    let sum = checksum(&buf[HEADER_LEN..HEADER_LEN + len]);
+    buf[0..4].copy_from_slice(&RECORD_MAGIC.to_le_bytes());
+    buf[4..6].copy_from_slice(&(len as u16).to_le_bytes());
+    buf[6..8].copy_from_slice(&sum.to_le_bytes());
+
+    let total = (HEADER_LEN + len).div_ceil(WRITE_ALIGN) * WRITE_ALIGN;
+    let offset = key.offset();
+    storage
+        .flash
+        .blocking_erase(offset, offset + PAGE_SIZE)
+        .map_err(|_| StorageError::Flash)?;
+    storage
+        .flash
+        .blocking_write(offset, &buf[..total])
+        .map_err(|_| StorageError::Flash)?;
+
+    log::info!("Storage: wrote record {} ({} bytes)", key, len);
+    Ok(())
+}
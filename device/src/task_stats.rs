@@ -0,0 +1,128 @@
+//! Scheduling statistics of the busiest task loops (`TaskStatsEndpoint`)
+//!
+//! Each instrumented loop ticks its `TaskClock` once per iteration with the
+//! embassy clock. A clock counts the ticks and keeps the longest gap between
+//! two, so a loop that is starved by its neighbours, or held up by its own
+//! work, shows a gap well past its period. Every query starts the counts and
+//! gaps over, so the host sees each interval on its own.
+//!
+//! All tasks run on the one thread-mode executor, so no tick interleaves with
+//! a query and relaxed atomics are enough.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use oxifoc_protocol::{TaskStats, TaskTiming};
+
+/// Iterations and longest gap of one task loop since the last query
+pub struct TaskClock {
+    iterations: AtomicU32,
+    /// Time of the last tick in µs since boot (wrapping), once `ticked`
+    last_us: AtomicU32,
+    max_interval_us: AtomicU32,
+    ticked: AtomicBool,
+}
+
+impl TaskClock {
+    pub const fn new() -> Self {
+        Self {
+            iterations: AtomicU32::new(0),
+            last_us: AtomicU32::new(0),
+            max_interval_us: AtomicU32::new(0),
+            ticked: AtomicBool::new(false),
+        }
+    }
+
+    /// Record one iteration of the loop, now
+    pub fn tick(&self) {
+        self.tick_at(embassy_time::Instant::now().as_micros() as u32);
+    }
+
+    fn tick_at(&self, now_us: u32) {
+        if self.ticked.swap(true, Ordering::Relaxed) {
+            let gap = now_us.wrapping_sub(self.last_us.load(Ordering::Relaxed));
+            self.max_interval_us.fetch_max(gap, Ordering::Relaxed);
+        }
+        self.last_us.store(now_us, Ordering::Relaxed);
+        self.iterations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The figures so far, starting them over; the next gap still counts from the last tick
+    fn take(&self) -> TaskTiming {
+        TaskTiming {
+            iterations: self.iterations.swap(0, Ordering::Relaxed),
+            max_interval_us: self.max_interval_us.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+/// `motor_control_task`, once per commutation step
+pub static CONTROL: TaskClock = TaskClock::new();
+/// `telemetry_task`, once per period
+pub static TELEMETRY: TaskClock = TaskClock::new();
+/// RX worker, once per poll of the RTT down channel
+pub static RX: TaskClock = TaskClock::new();
+/// TX worker, once per write to the RTT up channel
+pub static TX: TaskClock = TaskClock::new();
+
+/// Uptime at the last query, in ms
+static SINCE_MS: AtomicU32 = AtomicU32::new(0);
+
+/// The figures of every task since the last query, starting them over
+pub fn take() -> TaskStats {
+    let now_ms = embassy_time::Instant::now().as_millis() as u32;
+    TaskStats {
+        since_ms: SINCE_MS.swap(now_ms, Ordering::Relaxed),
+        uptime_ms: now_ms,
+        control: CONTROL.take(),
+        telemetry: TELEMETRY.take(),
+        rx: RX.take(),
+        tx: TX.take(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_longest_gap_spans_queries_and_the_clock_wrap() {
+        let clock = TaskClock::new();
+        clock.tick_at(1_000);
+        assert_eq!(
+            clock.take(),
+            TaskTiming {
+                iterations: 1,
+                max_interval_us: 0
+            }
+        );
+        // The first gap after a query still counts from the tick before it
+        clock.tick_at(3_000);
+        clock.tick_at(3_500);
+        assert_eq!(
+            clock.take(),
+            TaskTiming {
+                iterations: 2,
+                max_interval_us: 2_000
+            }
+        );
+        assert_eq!(
+            clock.take(),
+            TaskTiming {
+                iterations: 0,
+                max_interval_us: 0
+            }
+        );
+
+        // The µs counter wraps after about 71 minutes
+        let clock = TaskClock::new();
+        clock.tick_at(u32::MAX - 99);
+        clock.tick_at(400);
+        assert_eq!(
+            clock.take(),
+            TaskTiming {
+                iterations: 2,
+                max_interval_us: 500
+            }
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! RTT throughput benchmark results (`oxifoc-host bench-throughput`)
+//!
+//! The device floods `event/bench` with numbered frames for a fixed time
+//! (see `OxifocDevice::bench_throughput`). `BenchTally` counts what arrives
+//! and finds the drops as gaps in the sequence; together with the device's
+//! `BenchReport` that gives the link's practical ceiling for the probe, poll
+//! interval and channel sizes in use.
+
+use std::time::Duration;
+
+use oxifoc_protocol::BenchFrame;
+
+/// Frames received during one benchmark
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BenchTally {
+    /// Frames received
+    pub frames: u32,
+    /// Payload bytes received
+    pub bytes: u64,
+    /// Runs of missing sequence numbers
+    pub gaps: u32,
+    /// Sequence numbers skipped over by those runs
+    pub missing: u32,
+    /// Frames older than one already seen
+    pub out_of_order: u32,
+    /// Frames whose payload did not hold its pattern
+    pub corrupt: u32,
+    /// `host_time` of the first frame
+    pub first_at: Option<Duration>,
+    /// `host_time` of the latest frame
+    pub last_at: Option<Duration>,
+    next: u32,
+}
+
+impl BenchTally {
+    /// Count `frame`, received at `at`
+    pub fn push(&mut self, frame: &BenchFrame, at: Duration) {
+        self.frames += 1;
+        self.bytes += frame.data.len() as u64;
+        if !frame.is_intact() {
+            self.corrupt += 1;
+        }
+        self.first_at.get_or_insert(at);
+        self.last_at = Some(at);
+        if frame.seq < self.next {
+            self.out_of_order += 1;
+            return;
+        }
+        if frame.seq > self.next {
+            self.gaps += 1;
+            self.missing += frame.seq - self.next;
+        }
+        self.next = frame.seq + 1;
+    }
+
+    /// Frames the device sent that never arrived
+    pub fn lost(&self, sent: u32) -> u32 {
+        sent.saturating_sub(self.frames)
+    }
+
+    /// Share of `sent` that was lost, in percent
+    pub fn loss_percent(&self, sent: u32) -> f64 {
+        match sent {
+            0 => 0.0,
+            n => self.lost(n) as f64 * 100.0 / n as f64,
+        }
+    }
+
+    /// Payload bytes per second over `elapsed`
+    pub fn bytes_per_sec(&self, elapsed: Duration) -> f64 {
+        match elapsed.as_secs_f64() {
+            s if s > 0.0 => self.bytes as f64 / s,
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaps_and_late_frames_are_told_apart() {
+        let mut tally = BenchTally::default();
+        let ms = Duration::from_millis;
+        for (seq, t) in [(0, 10), (1, 11), (4, 12), (5, 13), (2, 14), (9, 15)] {
+            tally.push(&BenchFrame::new(seq, 8), ms(t));
+        }
+        let mut bad = BenchFrame::new(10, 8);
+        bad.data[0] ^= 0xff;
+        tally.push(&bad, ms(16));
+
+        assert_eq!((tally.frames, tally.bytes), (7, 56));
+        // 2..=3 and 6..=8 were skipped; 2 came late
+        assert_eq!((tally.gaps, tally.missing, tally.out_of_order), (2, 5, 1));
+        assert_eq!(tally.corrupt, 1);
+        assert_eq!(
+            (tally.first_at, tally.last_at),
+            (Some(ms(10)), Some(ms(16)))
+        );
+        assert_eq!(tally.lost(12), 5);
+        assert!((tally.loss_percent(20) - 65.0).abs() < 1e-9);
+        assert_eq!(tally.bytes_per_sec(ms(500)), 112.0);
+    }
+}
@@ -0,0 +1,83 @@
+//! Firmware identity check: does the flashed build match the ELF?
+//!
+//! The defmt table and this host's idea of the protocol both come from the
+//! ELF. Flash a newer firmware and keep decoding with the old ELF, and log
+//! lines come out garbled or subtly wrong. The device reports a hash of its
+//! build inputs in `DeviceInfo`, and the same bytes sit in the ELF under
+//! `OXIFOC_BUILD_HASH` (see `device/build.rs`), so the two can be compared
+//! once the handshake is done.
+
+use anyhow::{Context, Result};
+use object::{Object, ObjectSection, ObjectSymbol};
+
+/// Symbol holding the build hash in the device ELF
+pub(crate) const SYMBOL: &str = "OXIFOC_BUILD_HASH";
+
+/// Build hash stored in `elf`; `None` for an ELF built without one
+pub(crate) fn from_elf(elf: &[u8]) -> Result<Option<String>> {
+    let file = object::File::parse(elf).context("Parsing the device ELF")?;
+    let Some(sym) = file.symbols().find(|s| s.name() == Ok(SYMBOL)) else {
+        return Ok(None);
+    };
+    let bytes = sym
+        .section_index()
+        .and_then(|i| file.section_by_index(i).ok())
+        .and_then(|section| section.data_range(sym.address(), sym.size()).ok().flatten())
+        .with_context(|| format!("{} has no data in the ELF", SYMBOL))?;
+    parse(bytes).map(Some)
+}
+
+/// Read the build hash from the ELF at `path`, warning (once) about anything that stops the check
+pub(crate) fn read(path: &str) -> Option<String> {
+    let hash = std::fs::read(path)
+        .with_context(|| format!("Failed to read ELF at {}", path))
+        .and_then(|elf| from_elf(&elf));
+    match hash {
+        Ok(Some(hash)) => Some(hash),
+        Ok(None) => {
+            tracing::info!("{} has no {}; firmware build check skipped", path, SYMBOL);
+            None
+        }
+        Err(e) => {
+            tracing::warn!("{:#}; firmware build check skipped", e);
+            None
+        }
+    }
+}
+
+fn parse(bytes: &[u8]) -> Result<String> {
+    match std::str::from_utf8(bytes) {
+        Ok(s) if s.len() == 16 && s.bytes().all(|b| b.is_ascii_hexdigit()) => Ok(s.to_string()),
+        _ => anyhow::bail!("{} in the ELF is not a build hash: {:02x?}", SYMBOL, bytes),
+    }
+}
+
+/// Warn when the device runs a different build than `elf` (the ELF's hash)
+///
+/// Returns whether they match.
+pub(crate) fn check(elf: &str, device: &str) -> bool {
+    if elf == device {
+        tracing::debug!("Firmware build {} matches the ELF", device);
+        return true;
+    }
+    tracing::warn!(
+        "Device runs firmware build {} but the ELF is build {}: defmt lines may decode wrongly and \
+         protocol assumptions may not hold. Point `elf` at the flashed firmware, or reflash it",
+        device,
+        elf
+    );
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_only_sixteen_hex_digits() {
+        assert_eq!(parse(b"0011aabbccddeeff").unwrap(), "0011aabbccddeeff");
+        assert!(parse(b"0011aabbccddeef").is_err());
+        assert!(parse(b"0011aabbccddeefg").is_err());
+        assert!(parse(&[0xff; 16]).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+//! RTT channel selection
+//!
+//! The firmware names its channels ("defmt" and "ergot" up, "ergot-down"
+//! down) and the host looks them up by name. Firmware that leaves them
+//! unnamed gets the indices of the stock layout instead, which is a guess: on
+//! a different layout it cross-wires the channels, and the COBS decoder
+//! quietly turns defmt bytes into garbage frames. So a selection that uses
+//! one up channel for both streams is always refused, every guess is warned
+//! about, and `strict_channels` refuses guesses altogether.
+
+/// Stock layout of `rtt_init!` in the firmware's `main`
+const DEFMT_UP_GUESS: usize = 0;
+const ERGOT_UP_GUESS: usize = 1;
+const ERGOT_DOWN_GUESS: usize = 0;
+
+/// One resolved channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolved {
+    pub index: usize,
+    /// No channel had the expected name; `index` is the stock layout's
+    pub guessed: bool,
+}
+
+/// Channels the pump reads and writes; `None` ones are not used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    pub ergot_up: Option<Resolved>,
+    pub defmt_up: Option<Resolved>,
+    pub ergot_down: Option<Resolved>,
+}
+
+impl Selection {
+    /// Pick the channels from the names the target reports
+    ///
+    /// A guess is only made for an index that exists.
+    pub fn resolve(up: &[Option<&str>], down: &[Option<&str>], ergot: bool, defmt: bool) -> Self {
+        Self {
+            ergot_up: ergot.then(|| find(up, "ergot", ERGOT_UP_GUESS)).flatten(),
+            defmt_up: defmt.then(|| find(up, "defmt", DEFMT_UP_GUESS)).flatten(),
+            ergot_down: ergot
+                .then(|| find(down, "ergot-down", ERGOT_DOWN_GUESS))
+                .flatten(),
+        }
+    }
+
+    /// Refuse an unusable selection, else return a warning for every guess
+    ///
+    /// With `strict`, a guess is refused too.
+    pub fn check(&self, strict: bool) -> Result<Vec<String>, String> {
+        if let (Some(ergot), Some(defmt)) = (self.ergot_up, self.defmt_up)
+            && ergot.index == defmt.index
+        {
+            return Err(format!(
+                "ergot and defmt would both read RTT up channel {}",
+                ergot.index
+            ));
+        }
+        let guesses: Vec<String> = [
+            (self.ergot_up, "up", "ergot"),
+            (self.defmt_up, "up", "defmt"),
+            (self.ergot_down, "down", "ergot-down"),
+        ]
+        .into_iter()
+        .filter_map(|(ch, dir, name)| match ch {
+            Some(r) if r.guessed => Some(format!(
+                "no RTT channel named '{}', guessing {}{}",
+                name, dir, r.index
+            )),
+            _ => None,
+        })
+        .collect();
+        if strict && !guesses.is_empty() {
+            return Err(format!("{} (strict channel check)", guesses.join("; ")));
+        }
+        Ok(guesses)
+    }
+}
+
+fn find(names: &[Option<&str>], name: &str, guess: usize) -> Option<Resolved> {
+    match names.iter().position(|n| *n == Some(name)) {
+        Some(index) => Some(Resolved {
+            index,
+            guessed: false,
+        }),
+        None if guess < names.len() => Some(Resolved {
+            index: guess,
+            guessed: true,
+        }),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_channels_win_and_guesses_are_checked() {
+        let named = Selection::resolve(
+            &[Some("ergot"), Some("defmt")],
+            &[Some("ergot-down")],
+            true,
+            true,
+        );
+        assert_eq!(
+            named.ergot_up,
+            Some(Resolved {
+                index: 0,
+                guessed: false
+            })
+        );
+        assert_eq!(
+            named.defmt_up,
+            Some(Resolved {
+                index: 1,
+                guessed: false
+            })
+        );
+        assert_eq!(named.check(true), Ok(vec![]));
+
+        let unnamed = Selection::resolve(&[None, None], &[None], true, true);
+        assert_eq!(unnamed.check(false).unwrap().len(), 3);
+        assert!(unnamed.check(true).is_err());
+
+        // The guess for ergot lands on the channel already named defmt
+        let crossed = Selection::resolve(
+            &[Some("rtt"), Some("defmt")],
+            &[Some("ergot-down")],
+            true,
+            true,
+        );
+        assert!(crossed.check(false).unwrap_err().contains("up channel 1"));
+
+        // Only what is streamed is resolved
+        let defmt_only = Selection::resolve(&[None], &[], false, true);
+        assert_eq!(defmt_only.ergot_up, None);
+        assert_eq!(defmt_only.ergot_down, None);
+    }
+}
@@ -0,0 +1,1020 @@
+//! Minimal command-line parsing for the host
+//!
+//! With no subcommand the host streams defmt/ergot until interrupted (monitor mode).
+//! Subcommands run once against the connected device and exit. Options (`--name`,
+//! `--name value` or `--name=value`) may appear anywhere on the line.
+
+use oxifoc_host::HandshakeGiveUp;
+use oxifoc_host::invariant::Invariant;
+use oxifoc_host::replay::RawChannel;
+use oxifoc_protocol::BENCH_MAX_PAYLOAD;
+use oxifoc_protocol::constants::{MAX_BENCH_DURATION_MS, MAX_DUTY_PERCENT};
+use oxifoc_protocol::{CommutationMode, LogLevel, MotorCommand, ScanScheme, TestPattern};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Stream logs and events (default)
+    Monitor,
+    /// `profile save|load|list`
+    Profile(ProfileAction),
+    /// `start [duty] [--step <n>]`: align, ramp and run open-loop
+    Start { duty: u8, step: Option<u8> },
+    /// `speed <duty>`: change the duty while running
+    Speed { duty: u8 },
+    /// Stop the motor
+    Stop,
+    /// Stop and latch: motion refused until the next `arm`
+    Estop,
+    /// Allow test/diagnostic motion on the device
+    Arm,
+    /// Stop and block test/diagnostic motion
+    Disarm,
+    /// `self-test [duty] [--persist] [--learn]`: direction check with phase-order correction
+    SelfTest {
+        duty: u8,
+        persist: bool,
+        learn: bool,
+    },
+    /// Measure per-phase resistance and inductance from standstill
+    MeasureRL,
+    /// `calibrate-current [num/den] [--persist]`: measure the shunt offsets, optionally set the gain
+    CalibrateCurrent {
+        gain: Option<(u16, u16)>,
+        persist: bool,
+    },
+    /// `nudge <steps> [duty]`: move a few commutation steps once, then stop
+    Nudge { steps: i8, duty: u8 },
+    /// `reverse [dwell_ms]`: slow to a stop, coast, then run the other way
+    Reverse { dwell_ms: u16 },
+    /// `pattern walk|all|ramp <duty> [ms]`: bench PWM pattern until the next command
+    Pattern(TestPattern),
+    /// `phase-duties <a> <b> <c>`: hold each phase at its own duty until the next command
+    PhaseDuties { a: u8, b: u8, c: u8 },
+    /// `identify [secs]`: blink the device LED so the board can be found
+    Identify { secs: u16 },
+    /// `log-level <level>`: set the device's runtime defmt verbosity
+    LogLevel(LogLevel),
+    /// `telemetry <fields>`: select the streamed telemetry fields (mask bits)
+    Telemetry { mask: u16 },
+    /// `telemetry-delta <n>`: key frame every n samples, deltas in between (0 disables)
+    TelemetryDelta { key_interval: u8 },
+    /// `min-rpm <rpm> [timeout_ms]`: low-speed cutoff (0 disables)
+    MinRpm { min_rpm: u16, timeout_ms: u16 },
+    /// `max-phase-voltage <mV>`: VBUS-scaled duty ceiling (0 disables)
+    MaxPhaseVoltage { mv: u16 },
+    /// Print a coherent snapshot of all device state
+    Status,
+    /// `extremes [reset]`: print the min/max readings since the last reset, then optionally start over
+    Extremes { reset: bool },
+    /// `task-stats [ms]`: print how often each device task loop ran over [ms], and its longest gap
+    TaskStats { window_ms: u64 },
+    /// `comm get|set --us <n>`: open-loop commutation period
+    Comm(CommAction),
+    /// `scheme <120|180>`: stop the motor and select the commutation step table
+    Scheme(ScanScheme),
+    /// `mode <open-loop|external>`: time the running steps by the run period or the trigger input
+    Mode(CommutationMode),
+    /// `bemf <path> [samples]`: capture the floating phase voltage to a CSV file
+    Bemf { path: String, samples: u16 },
+    /// `cogging <path> [current_ma] [settle_ms]`: hold each step of one revolution, write the currents to a CSV file
+    Cogging {
+        path: String,
+        current_ma: u16,
+        settle_ms: u16,
+    },
+    /// `freewheel <path> [duty] [spin_ms] [coast_ms]`: spin up, cut the drive, write the coast-down to a CSV file
+    Freewheel {
+        path: String,
+        duty: u8,
+        spin_ms: u16,
+        coast_ms: u16,
+    },
+    /// `step-test <from> <to> [hold_ms] [--interactive]`: step the running motor's duty, print the rpm response figures
+    StepTest {
+        from: u8,
+        to: u8,
+        hold_ms: u16,
+        interactive: bool,
+    },
+    /// `bench-throughput [ms] [bytes]`: flood the link from the device and report what arrived
+    BenchThroughput { duration_ms: u16, payload: u16 },
+    /// Print the schema of every endpoint (no device needed)
+    Schema,
+    /// `decode --raw <file> --channel ergot|defmt [--elf <path>]`: replay a raw RTT capture (no device needed)
+    Decode {
+        raw: String,
+        channel: RawChannel,
+        elf: Option<String>,
+    },
+    /// `config show|table|dump|diff`: print, snapshot or check the device's configuration
+    Config(ConfigAction),
+    /// `run-script <file>`: run the commands listed in a file, in order
+    RunScript { path: String },
+    /// `send <endpoint> [json]`: send a JSON request to any endpoint, print the JSON reply
+    Send {
+        endpoint: &'static str,
+        json: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigAction {
+    Show,
+    /// Print the active commutation step table
+    Table,
+    /// Print the full configuration as TOML, and nothing else, on stdout
+    Dump,
+    /// Compare the configuration with a `Dump` saved at this path
+    Diff {
+        baseline: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommAction {
+    /// Print the run period and the current step dwell
+    Get,
+    /// Set the run period in microseconds
+    Set { us: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileAction {
+    Save(String),
+    Load(String),
+    List,
+}
+
+/// Output sink selection
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputOpts {
+    /// Append text output to this file
+    pub log_file: Option<String>,
+    /// Write motor status samples as CSV to this file
+    pub csv: Option<String>,
+    /// Write the device command log as CSV to this file
+    pub command_csv: Option<String>,
+    /// Write command, state and log timings as a Chrome trace to this file
+    pub trace: Option<String>,
+    /// Print per-kind event counts on exit
+    pub metrics: bool,
+    /// Disable stdout text output
+    pub no_stdout: bool,
+    /// Describe the run in a header at the top of the log file and CSVs
+    pub label: Option<String>,
+    /// Collapse consecutive identical defmt lines into one with a repeat count
+    pub dedup: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cli {
+    pub command: Command,
+    pub output: OutputOpts,
+    /// Abort if defmt decoding cannot be set up (default: warn and continue)
+    pub require_defmt: bool,
+    /// Keep the old link when the device resets instead of rebuilding it
+    pub no_reconnect: bool,
+    /// Send motor commands even if the device's protocol version differs
+    pub force: bool,
+    /// Poll until a matching probe appears instead of failing
+    pub wait_for_probe: bool,
+    /// Reset the device through the probe when it goes silent
+    pub reset_on_hang: bool,
+    /// Device info requests before giving up
+    pub handshake_attempts: Option<u32>,
+    /// Time allowed for each device info request, in ms
+    pub handshake_timeout_ms: Option<u64>,
+    /// What to do once the handshake attempts run out
+    pub handshake_give_up: Option<HandshakeGiveUp>,
+    /// Refuse RTT channels found by index guessing
+    pub strict_channels: bool,
+    /// Keep only RTT I/O on the pump thread, paced by a spin/sleep timer
+    pub rtt_thread: bool,
+    /// Pin the RTT pump thread to this CPU core
+    pub rtt_core: Option<usize>,
+    /// Send at most this many motor commands per second
+    pub max_command_rate: Option<u32>,
+    /// Invariants checked against every status and telemetry sample
+    pub asserts: Vec<Invariant>,
+    /// End the run with an error on the first failed invariant
+    pub assert_exit: bool,
+    /// Copy the raw ergot up-channel bytes to this file
+    pub raw_capture_ergot: Option<String>,
+    /// Copy the raw defmt up-channel bytes to this file
+    pub raw_capture_defmt: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CliError(pub String);
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n\n{}", self.0, USAGE)
+    }
+}
+
+impl std::error::Error for CliError {}
+
+pub const USAGE: &str = "\
+Usage: oxifoc-host [OPTIONS] [COMMAND]
+
+Commands:
+  (none)                 stream defmt logs and ergot events
+  profile list           list motor profiles stored on the device
+  profile save <name>    store the active motor parameters as <name>
+  profile load <name>    stop the motor and apply profile <name>
+  start [duty] [--step <n>]
+                         start the motor at duty% (default 5), aligning
+                         at commutation step n (0-5, default: device's)
+  speed <duty>           change the duty of the running motor to duty%
+  stop                   stop the motor
+  estop                  stop and disarm; motion refused until the next arm
+  arm                    allow test/diagnostic motion
+  disarm                 stop the motor and block test/diagnostic motion
+  self-test [duty] [--persist] [--learn]
+                         spin briefly, check direction via halls, fix phase order;
+                         --learn (motor known to turn forward) stores the hall
+                         order first
+  measure-rl             from standstill, measure phase resistance and
+                         inductance (needs arming)
+  calibrate-current [num/den] [--persist]
+                         while stopped, measure the zero-current shunt
+                         offsets and scale currents by num/den (default:
+                         keep the gain); --persist stores them in flash
+  nudge <steps> [duty]   move a few commutation steps (negative = reverse), then stop
+  reverse [dwell_ms]     while running: ramp down to 0, coast [dwell_ms]
+                         (default 500, at least 200), then ramp back up to
+                         the same duty the other way round
+  pattern <walk|all|ramp> <duty> [ms]
+                         drive a PWM test pattern (duty up to 10%) for a scope
+                         until the next command: walk one phase every [ms]
+                         (default 500), all phases at once, or ramp all
+                         phases up and down every [ms] (default 2000)
+  phase-duties <a> <b> <c>
+                         hold phases A, B and C at a%, b% and c% (within
+                         max_duty), bypassing commutation, until the next
+                         command (needs arming)
+  identify [secs]        blink the device LED rapidly for [secs] (default 10);
+                         0 returns to the status pattern
+  log-level <level>      have the device send only defmt lines at <level>
+                         (trace, debug, info, warn, error) or above, until
+                         it resets
+  telemetry <fields>     stream only <fields>: comma-separated rpm, duty, step,
+                         state, vbus, temp, current, limp, fault, fan, adc,
+                         or all / none
+  telemetry-delta <n>    send a full sample every <n> samples and only the
+                         changed fields in between; 0 sends full samples only
+  min-rpm <rpm> [ms]     halve duty, then stop, after [ms] (default 2000) below
+                         <rpm> while running; 0 disables
+  max-phase-voltage <mV> cap duty at <mV> / VBUS on top of max_duty; 0 disables
+  status                 print motor, supply, fault and arming state, all
+                         captured by the device at the same instant
+  extremes [reset]       print the lowest and highest rpm, VBUS, temperature and
+                         current the device has read since boot or the last
+                         reset; `reset` prints them and starts over
+  task-stats [ms]        print how often the device's control, telemetry, RX
+                         and TX loops ran over [ms] (default 1000) and the
+                         longest gap between two runs of each
+  comm get               print the open-loop commutation period and step dwell
+  comm set --us <n>      run at <n> us per commutation step (200-1000000),
+                         bypassing the duty-to-speed mapping
+  scheme <120|180>       stop the motor and select 120° or 180° conduction
+  mode <open-loop|external>
+                         advance the running motor a step per run period, or
+                         per edge on the trigger input (PB3); without edges
+                         for 500 ms it stops
+  bemf <path> [samples]  capture floating-phase voltage while running (default
+                         512 samples) and write it to <path> as CSV
+  cogging <path> [mA] [ms]
+                         from standstill and armed, hold each commutation step
+                         of one revolution at [mA] (default 1000) for [ms]
+                         (default 100) and write the duty each position took
+                         to <path> as CSV
+  freewheel <path> [duty] [spin-ms] [coast-ms]
+                         run at [duty]% (default 20) for [spin-ms] (default
+                         2000), then turn every switch off and track the
+                         coasting rotor by its BEMF for up to [coast-ms]
+                         (default 5000, at most 20000); writes the speed to
+                         <path> as CSV and prints the decay time constant
+  step-test <from> <to> [ms] [--interactive]
+                         hold the running motor at <from>% duty for [ms]
+                         (default 2000), step to <to>% and record [ms] more;
+                         prints the rise time, overshoot and settling time of
+                         the telemetry rpm. --interactive then prompts: Enter
+                         repeats the step, `<from> <to>` changes it, q quits
+  bench-throughput [ms] [bytes]
+                         while armed and stopped, have the device send
+                         [bytes]-byte frames (default 64, at most 256) as fast
+                         as it can for [ms] (default 2000, at most 2500) and
+                         report the throughput and frames lost
+  config show            print the device's full configuration
+  config table           print which phases each commutation step energizes
+  config dump            print the full configuration as TOML (for a baseline)
+  config diff <baseline> report every field that differs from a saved dump;
+                         exits with 10 if any does
+  run-script <file>      run the commands in <file>, one per line in the
+                         syntax above, with `wait <ms>` between them and
+                         `#` comments; a failed line stops the motor and
+                         the script
+  schema                 print endpoint schemas, keys and encoded sizes (offline)
+  decode --raw <file> --channel <ergot|defmt> [--elf <path>]
+                         print the events in a raw RTT capture (offline),
+                         defmt decoded with <path> instead of the config's ELF
+  send <endpoint> [json] send [json] (default null) as the request of
+                         <endpoint>, a path like req/full_status or its last
+                         part, and print the reply as JSON; not validated
+                         or paced like the commands above
+
+Options:
+  --log-file <path>      also append text output to <path>
+  --csv <path>           write motor status samples to <path> as CSV
+  --command-csv <path>   write the device command log to <path> as CSV
+  --trace <path>         write motor commands, the states they lead to and
+                         device log lines to <path> as a Chrome trace (JSON,
+                         opens in chrome://tracing or ui.perfetto.dev)
+  --metrics              print per-kind event counts on exit
+  --no-stdout            disable text output on stdout
+  --dedup                print a run of identical device log lines once, then
+                         `<line> [×N]` for its N repeats when a different line
+                         arrives (or every dedup_flush_ms, default 5000)
+  --label <text>         start the log file and CSVs with a header naming the
+                         run: <text>, the start time, and the device's hw, sw,
+                         uid, build hash and profile
+  --require-defmt        fail if the device ELF for defmt decoding is unusable
+  --no-reconnect         do not rebuild the link when the device resets
+  --force                send motor commands despite a protocol version mismatch
+  --wait-for-probe       poll with backoff until the (configured) probe appears
+                         instead of failing
+  --reset-on-hang        reset the device through the probe after hang_timeout_ms
+                         (default 10000) without ergot traffic, then reattach
+  --handshake-attempts <n>
+                         device info requests before giving up (default 10)
+  --handshake-timeout <ms>
+                         time allowed for each of them (default 800)
+  --handshake-give-up <continue|exit|retry>
+                         once they run out: carry on without device info
+                         (default), fail with exit code 8, or keep trying
+  --strict-channels      fail (exit code 5) instead of guessing RTT channel
+                         indices when the firmware's channels are unnamed
+  --rtt-thread           poll RTT on a thread of its own with a spin/sleep timer
+                         (every 1 ms unless rtt_poll_us says otherwise), for
+                         even telemetry timing
+  --rtt-core <n>         pin that thread to CPU core <n> (implies --rtt-thread)
+  --max-command-rate <n> send at most <n> motor commands per second, holding
+                         back faster ones in order (stop and estop never wait;
+                         0 = no limit)
+  --assert <exprs>       check every status/telemetry sample against the
+                         comma-separated expressions, e.g. 'rpm < 10000,
+                         temp_c < 80, fault == None' (fields rpm, duty, step,
+                         vbus_v, temp_c, current_ma, fan, limp, adc_fault,
+                         state, fault;
+                         && || and parentheses combine), logging each failure
+  --assert-exit          stop at the first failed assertion, exit code 11
+  --raw-capture-ergot <path>
+                         copy the raw ergot channel bytes to <path>
+  --raw-capture-defmt <path>
+                         copy the raw defmt channel bytes to <path>, even
+                         without the device ELF";
+
+/// Options that take a value
+const VALUE_OPTS: &[&str] = &[
+    "log-file",
+    "label",
+    "csv",
+    "command-csv",
+    "trace",
+    "raw",
+    "channel",
+    "elf",
+    "raw-capture-ergot",
+    "raw-capture-defmt",
+    "us",
+    "step",
+    "handshake-attempts",
+    "handshake-timeout",
+    "handshake-give-up",
+    "rtt-core",
+    "max-command-rate",
+    "assert",
+];
+/// Options of single commands, the only ones a script line may carry
+const COMMAND_OPTS: &[&str] = &["step", "persist", "learn", "us"];
+/// Boolean switches
+const FLAG_OPTS: &[&str] = &[
+    "persist",
+    "learn",
+    "metrics",
+    "no-stdout",
+    "dedup",
+    "require-defmt",
+    "no-reconnect",
+    "force",
+    "wait-for-probe",
+    "reset-on-hang",
+    "strict-channels",
+    "rtt-thread",
+    "assert-exit",
+    "interactive",
+];
+
+fn err(msg: impl Into<String>) -> CliError {
+    CliError(msg.into())
+}
+
+/// Positional words plus parsed `--options`
+struct Args {
+    words: Vec<String>,
+    opts: HashMap<String, String>,
+}
+
+impl Args {
+    fn split(args: Vec<String>) -> Result<Self, CliError> {
+        let mut words = Vec::new();
+        let mut opts = HashMap::new();
+        let mut it = args.into_iter();
+        while let Some(arg) = it.next() {
+            let Some(opt) = arg.strip_prefix("--") else {
+                words.push(arg);
+                continue;
+            };
+            let (name, inline) = match opt.split_once('=') {
+                Some((n, v)) => (n.to_owned(), Some(v.to_owned())),
+                None => (opt.to_owned(), None),
+            };
+            if name == "help" {
+                return Err(err(""));
+            } else if VALUE_OPTS.contains(&name.as_str()) {
+                let value = match inline {
+                    Some(v) => v,
+                    None => it
+                        .next()
+                        .ok_or_else(|| err(format!("--{} requires a value", name)))?,
+                };
+                opts.insert(name, value);
+            } else if FLAG_OPTS.contains(&name.as_str()) {
+                if inline.is_some() {
+                    return Err(err(format!("--{} does not take a value", name)));
+                }
+                opts.insert(name, String::new());
+            } else {
+                return Err(err(format!("unknown option '--{}'", name)));
+            }
+        }
+        Ok(Self { words, opts })
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.opts.contains_key(name)
+    }
+
+    fn value(&self, name: &str) -> Option<String> {
+        self.opts.get(name).cloned()
+    }
+}
+
+impl Cli {
+    /// Parse from the process arguments
+    pub fn from_env() -> Result<Self, CliError> {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    /// Parse from an argument list (without the program name)
+    pub fn parse<I, S>(args: I) -> Result<Self, CliError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let args = Args::split(args.into_iter().map(Into::into).collect())?;
+        let command = parse_command(&args)?;
+
+        let output = OutputOpts {
+            log_file: args.value("log-file"),
+            csv: args.value("csv"),
+            command_csv: args.value("command-csv"),
+            trace: args.value("trace"),
+            metrics: args.flag("metrics"),
+            no_stdout: args.flag("no-stdout"),
+            label: args.value("label"),
+            dedup: args.flag("dedup"),
+        };
+        Ok(Self {
+            command,
+            output,
+            require_defmt: args.flag("require-defmt"),
+            no_reconnect: args.flag("no-reconnect"),
+            force: args.flag("force"),
+            wait_for_probe: args.flag("wait-for-probe"),
+            reset_on_hang: args.flag("reset-on-hang"),
+            handshake_attempts: match args.value("handshake-attempts") {
+                Some(n) => Some(parse_num(&n, "handshake attempts")?),
+                None => None,
+            },
+            handshake_timeout_ms: match args.value("handshake-timeout") {
+                Some(n) => Some(parse_num(&n, "handshake timeout")?),
+                None => None,
+            },
+            handshake_give_up: match args.value("handshake-give-up") {
+                Some(s) => Some(
+                    s.parse()
+                        .map_err(|e| err(format!("--handshake-give-up: {}", e)))?,
+                ),
+                None => None,
+            },
+            strict_channels: args.flag("strict-channels"),
+            rtt_thread: args.flag("rtt-thread"),
+            rtt_core: match args.value("rtt-core") {
+                Some(n) => Some(parse_num(&n, "RTT core")?),
+                None => None,
+            },
+            max_command_rate: match args.value("max-command-rate") {
+                Some(n) => Some(parse_num(&n, "command rate")?),
+                None => None,
+            },
+            asserts: match args.value("assert") {
+                Some(list) => Invariant::parse_list(&list).map_err(err)?,
+                None => Vec::new(),
+            },
+            assert_exit: args.flag("assert-exit"),
+            raw_capture_ergot: args.value("raw-capture-ergot"),
+            raw_capture_defmt: args.value("raw-capture-defmt"),
+        })
+    }
+}
+
+impl Command {
+    /// Parse one command from its words, as written on a script line
+    ///
+    /// Only the options of single commands are accepted; the rest apply to a
+    /// whole run and belong on its command line.
+    pub fn parse<I, S>(args: I) -> Result<Self, CliError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let args = Args::split(args.into_iter().map(Into::into).collect())?;
+        if let Some(opt) = args
+            .opts
+            .keys()
+            .find(|o| !COMMAND_OPTS.contains(&o.as_str()))
+        {
+            return Err(err(format!(
+                "--{} applies to a whole run, not to one command",
+                opt
+            )));
+        }
+        parse_command(&args)
+    }
+}
+
+fn parse_command(args: &Args) -> Result<Command, CliError> {
+    let mut words = args.words.iter().map(String::as_str);
+
+    let command = match words.next() {
+        None | Some("monitor") => Command::Monitor,
+        Some("profile") => Command::Profile(parse_profile(&mut words)?),
+        Some("start") => Command::Start {
+            duty: match words.next() {
+                Some(n) => parse_duty(n, "start duty")?,
+                None => 5,
+            },
+            step: match args.value("step") {
+                Some(n) => Some(parse_num(&n, "start step")?),
+                None => None,
+            },
+        },
+        Some("speed") => Command::Speed {
+            duty: parse_duty(
+                words.next().ok_or_else(|| err("speed requires a duty"))?,
+                "speed duty",
+            )?,
+        },
+        Some("stop") => Command::Stop,
+        Some("estop") => Command::Estop,
+        Some("arm") => Command::Arm,
+        Some("disarm") => Command::Disarm,
+        Some("self-test") => Command::SelfTest {
+            duty: match words.next() {
+                Some(n) => parse_duty(n, "self-test duty")?,
+                None => 5,
+            },
+            persist: args.flag("persist"),
+            learn: args.flag("learn"),
+        },
+        Some("measure-rl") => Command::MeasureRL,
+        Some("calibrate-current") => Command::CalibrateCurrent {
+            gain: match words.next() {
+                Some(g) => {
+                    let (num, den) = g
+                        .split_once('/')
+                        .ok_or_else(|| err(format!("invalid gain '{}' (expected num/den)", g)))?;
+                    Some((
+                        parse_num(num, "gain numerator")?,
+                        parse_num(den, "gain denominator")?,
+                    ))
+                }
+                None => None,
+            },
+            persist: args.flag("persist"),
+        },
+        Some("nudge") => Command::Nudge {
+            steps: parse_num(
+                words
+                    .next()
+                    .ok_or_else(|| err("nudge requires a step count"))?,
+                "nudge steps",
+            )?,
+            duty: match words.next() {
+                Some(n) => parse_duty(n, "nudge duty")?,
+                None => 5,
+            },
+        },
+        Some("reverse") => Command::Reverse {
+            dwell_ms: match words.next() {
+                Some(n) => parse_num(n, "reverse dwell")?,
+                None => 500,
+            },
+        },
+        Some("pattern") => Command::Pattern(parse_pattern(&mut words)?),
+        Some("phase-duties") => {
+            let mut duty = |phase: &str| {
+                let what = format!("phase {} duty", phase);
+                let n = words
+                    .next()
+                    .ok_or_else(|| err(format!("phase-duties requires a {}", what)))?;
+                parse_duty(n, &what)
+            };
+            Command::PhaseDuties {
+                a: duty("A")?,
+                b: duty("B")?,
+                c: duty("C")?,
+            }
+        }
+        Some("identify") => Command::Identify {
+            secs: match words.next() {
+                Some(n) => parse_num(n, "identify duration")?,
+                None => 10,
+            },
+        },
+        Some("log-level") => Command::LogLevel(
+            match words
+                .next()
+                .ok_or_else(|| err("log-level requires a level"))?
+            {
+                "trace" => LogLevel::Trace,
+                "debug" => LogLevel::Debug,
+                "info" => LogLevel::Info,
+                "warn" => LogLevel::Warn,
+                "error" => LogLevel::Error,
+                other => return Err(err(format!("unknown log level '{}'", other))),
+            },
+        ),
+        Some("telemetry") => Command::Telemetry {
+            mask: parse_telemetry_fields(
+                words
+                    .next()
+                    .ok_or_else(|| err("telemetry requires a field list"))?,
+            )?,
+        },
+        Some("telemetry-delta") => Command::TelemetryDelta {
+            key_interval: parse_num(
+                words
+                    .next()
+                    .ok_or_else(|| err("telemetry-delta requires a key interval"))?,
+                "telemetry-delta key interval",
+            )?,
+        },
+        Some("min-rpm") => Command::MinRpm {
+            min_rpm: parse_num(
+                words
+                    .next()
+                    .ok_or_else(|| err("min-rpm requires a speed"))?,
+                "min-rpm speed",
+            )?,
+            timeout_ms: match words.next() {
+                Some(n) => parse_num(n, "min-rpm timeout")?,
+                None => 2000,
+            },
+        },
+        Some("max-phase-voltage") => Command::MaxPhaseVoltage {
+            mv: parse_num(
+                words
+                    .next()
+                    .ok_or_else(|| err("max-phase-voltage requires a voltage in mV"))?,
+                "max-phase-voltage",
+            )?,
+        },
+        Some("status") => Command::Status,
+        Some("extremes") => Command::Extremes {
+            reset: match words.next() {
+                None => false,
+                Some("reset") => true,
+                Some(other) => return Err(err(format!("unknown extremes action '{}'", other))),
+            },
+        },
+        Some("task-stats") => Command::TaskStats {
+            window_ms: match words.next() {
+                Some(n) => parse_num(n, "task-stats window")?,
+                None => 1000,
+            },
+        },
+        Some("comm") => match words.next() {
+            None | Some("get") => Command::Comm(CommAction::Get),
+            Some("set") => Command::Comm(CommAction::Set {
+                us: parse_num(
+                    &args
+                        .value("us")
+                        .ok_or_else(|| err("comm set requires --us <period>"))?,
+                    "commutation period",
+                )?,
+            }),
+            Some(other) => return Err(err(format!("unknown comm action '{}'", other))),
+        },
+        Some("scheme") => Command::Scheme(
+            match words
+                .next()
+                .ok_or_else(|| err("scheme requires 120 or 180"))?
+            {
+                "120" => ScanScheme::Conduction120,
+                "180" => ScanScheme::Conduction180,
+                other => return Err(err(format!("unknown scan scheme '{}'", other))),
+            },
+        ),
+        Some("mode") => Command::Mode(
+            match words
+                .next()
+                .ok_or_else(|| err("mode requires open-loop or external"))?
+            {
+                "open-loop" => CommutationMode::OpenLoop,
+                "external" => CommutationMode::External,
+                other => return Err(err(format!("unknown commutation mode '{}'", other))),
+            },
+        ),
+        Some("bemf") => Command::Bemf {
+            path: words
+                .next()
+                .ok_or_else(|| err("bemf requires an output path"))?
+                .to_owned(),
+            samples: match words.next() {
+                Some(n) => parse_num(n, "bemf sample count")?,
+                None => oxifoc_protocol::BEMF_MAX_SAMPLES,
+            },
+        },
+        Some("cogging") => Command::Cogging {
+            path: words
+                .next()
+                .ok_or_else(|| err("cogging requires an output path"))?
+                .to_owned(),
+            current_ma: match words.next() {
+                Some(n) => parse_num(n, "cogging current")?,
+                None => 1000,
+            },
+            settle_ms: match words.next() {
+                Some(n) => parse_num(n, "cogging settle time")?,
+                None => 100,
+            },
+        },
+        Some("step-test") => Command::StepTest {
+            from: parse_duty(
+                words
+                    .next()
+                    .ok_or_else(|| err("step-test requires a starting duty"))?,
+                "step-test duty",
+            )?,
+            to: parse_duty(
+                words
+                    .next()
+                    .ok_or_else(|| err("step-test requires a target duty"))?,
+                "step-test duty",
+            )?,
+            hold_ms: match words.next() {
+                Some(n) => parse_num(n, "step-test hold time")?,
+                None => 2000,
+            },
+            interactive: args.flag("interactive"),
+        },
+        Some("freewheel") => Command::Freewheel {
+            path: words
+                .next()
+                .ok_or_else(|| err("freewheel requires an output path"))?
+                .to_owned(),
+            duty: match words.next() {
+                Some(n) => parse_num(n, "freewheel duty")?,
+                None => 20,
+            },
+            spin_ms: match words.next() {
+                Some(n) => parse_num(n, "freewheel spin time")?,
+                None => 2000,
+            },
+            coast_ms: match words.next() {
+                Some(n) => parse_num(n, "freewheel coast time")?,
+                None => 5000,
+            },
+        },
+        Some("config") => match words.next() {
+            None | Some("show") => Command::Config(ConfigAction::Show),
+            Some("table") => Command::Config(ConfigAction::Table),
+            Some("dump") => Command::Config(ConfigAction::Dump),
+            Some("diff") => Command::Config(ConfigAction::Diff {
+                baseline: words
+                    .next()
+                    .ok_or_else(|| err("config diff requires a baseline file"))?
+                    .to_owned(),
+            }),
+            Some(other) => return Err(err(format!("unknown config action '{}'", other))),
+        },
+        Some("schema") => Command::Schema,
+        Some("bench-throughput") => {
+            let duration_ms = match words.next() {
+                Some(n) => parse_num(n, "benchmark duration")?,
+                None => 2000,
+            };
+            let payload = match words.next() {
+                Some(n) => parse_num(n, "benchmark frame size")?,
+                None => 64,
+            };
+            if duration_ms == 0 || duration_ms > MAX_BENCH_DURATION_MS {
+                return Err(err(format!(
+                    "benchmark duration must be 1-{} ms",
+                    MAX_BENCH_DURATION_MS
+                )));
+            }
+            if payload as usize > BENCH_MAX_PAYLOAD {
+                return Err(err(format!(
+                    "benchmark frame size must be at most {} bytes",
+                    BENCH_MAX_PAYLOAD
+                )));
+            }
+            Command::BenchThroughput {
+                duration_ms,
+                payload,
+            }
+        }
+        Some("send") => {
+            let name = words
+                .next()
+                .ok_or_else(|| err("send requires an endpoint"))?;
+            let entry = oxifoc_host::send::find(name).ok_or_else(|| {
+                let paths: Vec<_> = oxifoc_host::send::ENDPOINTS
+                    .iter()
+                    .map(|e| e.path)
+                    .collect();
+                err(format!(
+                    "unknown endpoint '{}' (one of {})",
+                    name,
+                    paths.join(", ")
+                ))
+            })?;
+            // A script splits the JSON at its spaces; put it back together
+            let json = words.by_ref().collect::<Vec<_>>().join(" ");
+            let json = if json.is_empty() {
+                "null".to_owned()
+            } else {
+                json
+            };
+            if let Err(e) = serde_json::from_str::<serde_json::Value>(&json) {
+                return Err(err(format!("send: invalid JSON ({})", e)));
+            }
+            Command::Send {
+                endpoint: entry.path,
+                json,
+            }
+        }
+        Some("run-script") => Command::RunScript {
+            path: words
+                .next()
+                .ok_or_else(|| err("run-script requires a file"))?
+                .to_owned(),
+        },
+        Some("decode") => Command::Decode {
+            raw: args
+                .value("raw")
+                .ok_or_else(|| err("decode requires --raw <file>"))?,
+            channel: args
+                .value("channel")
+                .ok_or_else(|| err("decode requires --channel ergot|defmt"))?
+                .parse()
+                .map_err(err)?,
+            elf: args.value("elf"),
+        },
+        Some("-h") | Some("help") => return Err(err("")),
+        Some(other) => return Err(err(format!("unknown command '{}'", other))),
+    };
+    if let Some(extra) = words.next() {
+        return Err(err(format!("unexpected argument '{}'", extra)));
+    }
+    // Refuse what the device would clamp, by the protocol's own bounds
+    let checked = match &command {
+        Command::Start {
+            duty,
+            step: Some(step),
+        } => MotorCommand::start_at(*duty, *step).map(drop),
+        Command::Nudge { steps, duty } => MotorCommand::nudge(*steps, *duty).map(drop),
+        Command::Reverse { dwell_ms } => MotorCommand::reverse(*dwell_ms).map(drop),
+        Command::CalibrateCurrent { gain, persist } => {
+            MotorCommand::calibrate_current(*gain, *persist).map(drop)
+        }
+        Command::Pattern(pattern) => MotorCommand::test_pattern(*pattern).map(drop),
+        Command::Cogging {
+            current_ma,
+            settle_ms,
+            ..
+        } => MotorCommand::cogging_scan(*current_ma, *settle_ms).map(drop),
+        Command::Freewheel {
+            duty,
+            spin_ms,
+            coast_ms,
+            ..
+        } => MotorCommand::freewheel(*duty, *spin_ms, *coast_ms).map(drop),
+        Command::Comm(CommAction::Set { us }) => {
+            MotorCommand::set_commutation_period_us(*us).map(drop)
+        }
+        _ => Ok(()),
+    };
+    checked.map_err(|e| err(e.to_string()))?;
+    Ok(command)
+}
+
+fn parse_duty(s: &str, what: &str) -> Result<u8, CliError> {
+    match parse_num(s, what)? {
+        duty if duty > MAX_DUTY_PERCENT => Err(err(format!(
+            "{} {} is above {}%",
+            what, duty, MAX_DUTY_PERCENT
+        ))),
+        duty => Ok(duty),
+    }
+}
+
+fn parse_num<T: std::str::FromStr>(s: &str, what: &str) -> Result<T, CliError> {
+    s.parse()
+        .map_err(|_| err(format!("invalid {} '{}'", what, s)))
+}
+
+fn parse_profile<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<ProfileAction, CliError> {
+    let action = words
+        .next()
+        .ok_or_else(|| err("profile requires an action (list|save|load)"))?;
+    let mut name = |what: &str| {
+        words
+            .next()
+            .map(str::to_owned)
+            .ok_or_else(|| err(format!("profile {} requires a name", what)))
+    };
+    match action {
+        "list" => Ok(ProfileAction::List),
+        "save" => Ok(ProfileAction::Save(name("save")?)),
+        "load" => Ok(ProfileAction::Load(name("load")?)),
+        other => Err(err(format!("unknown profile action '{}'", other))),
+    }
+}
+
+fn parse_pattern<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<TestPattern, CliError> {
+    let kind = words
+        .next()
+        .ok_or_else(|| err("pattern requires a kind (walk|all|ramp)"))?;
+    let duty = parse_duty(
+        words.next().ok_or_else(|| err("pattern requires a duty"))?,
+        "pattern duty",
+    )?;
+    let mut period = |default: u16| match words.next() {
+        Some(n) => parse_num(n, "pattern period"),
+        None => Ok(default),
+    };
+    match kind {
+        "walk" => Ok(TestPattern::WalkPhase {
+            duty,
+            dwell_ms: period(500)?,
+        }),
+        "all" => Ok(TestPattern::AllPhases { duty }),
+        "ramp" => Ok(TestPattern::RampDuty {
+            duty,
+            period_ms: period(2000)?,
+        }),
+        other => Err(err(format!("unknown pattern '{}'", other))),
+    }
+}
+
+/// Parse a comma-separated telemetry field list into `telemetry_field` bits
+fn parse_telemetry_fields(list: &str) -> Result<u16, CliError> {
+    use oxifoc_protocol::telemetry_field::*;
+    list.split(',').try_fold(0, |mask, name| {
+        let bit = match name.trim() {
+            "all" => ALL,
+            "none" => 0,
+            "rpm" => RPM,
+            "duty" => DUTY,
+            "step" => STEP,
+            "state" => STATE,
+            "vbus" => VBUS,
+            "temp" => TEMP,
+            "current" => CURRENT,
+            "limp" => LIMP,
+            "fault" => FAULT,
+            "fan" => FAN,
+            "adc" => ADC_FAULT,
+            other => return Err(err(format!("unknown telemetry field '{}'", other))),
+        };
+        Ok(mask | bit)
+    })
+}
@@ -1,43 +1,143 @@
+use anyhow::Context;
+use ergot::Address;
+use oxifoc_protocol::constants::{CONTROLLER_NODE_ID, DEFAULT_NETWORK_ID, TARGET_NODE_ID};
 use serde::Deserialize;
-use std::{env, fs, path::PathBuf};
+use std::{env, fs, path::PathBuf, time::Duration};
+
+use crate::error::ErrorKind;
+
+/// Ergot addressing of the RTT link (`[addressing]` table)
+///
+/// The defaults match a device built without `OXIFOC_NETWORK_ID`. Give each rig a
+/// distinct `network_id` (and build its firmware to match) to run several at once.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct Addressing {
+    pub network_id: u16,
+    pub host_node: u8,    // DirectEdge controller
+    pub device_node: u8,  // DirectEdge target
+    pub default_port: u8, // port for requests to the device (0 = resolve by name)
+}
+
+impl Default for Addressing {
+    fn default() -> Self {
+        Self {
+            network_id: DEFAULT_NETWORK_ID,
+            host_node: CONTROLLER_NODE_ID,
+            device_node: TARGET_NODE_ID,
+            default_port: 0,
+        }
+    }
+}
+
+impl Addressing {
+    /// Address for requests to the device
+    pub fn device_addr(&self) -> Address {
+        Address {
+            network_id: self.network_id,
+            node_id: self.device_node,
+            port_id: self.default_port,
+        }
+    }
+}
+
+/// What the host does when the device info handshake runs out of attempts
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum HandshakeGiveUp {
+    /// Bring the link up without device info (the default)
+    #[default]
+    Continue,
+    /// Fail the connection with `ErrorKind::NoHandshake`
+    Exit,
+    /// Keep asking until the device answers
+    Retry,
+}
+
+impl std::str::FromStr for HandshakeGiveUp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "continue" => Ok(Self::Continue),
+            "exit" => Ok(Self::Exit),
+            "retry" => Ok(Self::Retry),
+            _ => Err(format!("expected continue, exit or retry, got '{}'", s)),
+        }
+    }
+}
+
+/// Retry policy of the device info handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandshakePolicy {
+    /// Requests sent before `give_up` applies (at least 1)
+    pub attempts: u32,
+    /// Time allowed for each request
+    pub timeout: Duration,
+    pub give_up: HandshakeGiveUp,
+}
 
 #[derive(Debug, Default, Deserialize, Clone)]
 pub struct HostConfig {
-    pub probe: Option<String>,      // e.g. "0483:374b:<serial>" or "0483:374b"
-    pub chip: Option<String>,       // e.g. "STM32G431CBTx"
-    pub elf: Option<String>,        // path to device ELF with .defmt
-    pub stream_defmt: Option<bool>, // default: true
-    pub stream_ergot: Option<bool>, // default: true
+    pub probe: Option<String>,       // e.g. "0483:374b:<serial>" or "0483:374b"
+    pub chip: Option<String>,        // e.g. "STM32G431CBTx"
+    pub elf: Option<String>,         // path to device ELF with .defmt
+    pub stream_defmt: Option<bool>,  // default: true
+    pub stream_ergot: Option<bool>,  // default: true
+    pub stdout: Option<bool>,        // text output on stdout, default: true
+    pub log_file: Option<String>,    // append text output to this file
+    pub csv: Option<String>,         // motor status samples as CSV
+    pub command_csv: Option<String>, // device command log as CSV
+    pub trace: Option<String>,       // command and state timings as a Chrome trace
+    pub metrics: Option<bool>,       // per-kind event counts on exit, default: false
+    pub dedup: Option<bool>,         // collapse repeated defmt lines, default: false
+    pub dedup_flush_ms: Option<u64>, // longest a repeat count is held back, default: 5000
+    pub reconnect_on_reset: Option<bool>, // rebuild the link after a device reset, default: true
+    pub reset_timeout_ms: Option<u64>, // keepalive silence treated as a reset, default: 3000
+    pub wait_for_probe: Option<bool>, // poll until the probe appears instead of failing, default: false
+    pub reset_on_hang: Option<bool>, // reset the core through the probe when the device goes silent, default: false
+    pub hang_timeout_ms: Option<u64>, // ergot silence treated as a hang, default: 10000
+    pub hang_reset_cooldown_ms: Option<u64>, // minimum time between two hang resets, default: 30000
+    pub handshake_attempts: Option<u32>, // device info requests before giving up, default: 10
+    pub handshake_timeout_ms: Option<u64>, // time allowed for each of them, default: 800
+    pub handshake_give_up: Option<HandshakeGiveUp>, // "continue", "exit" or "retry", default: "continue"
+    pub strict_channels: Option<bool>, // refuse RTT channels not found by name, default: false
+    pub verify_build: Option<bool>, // warn when the flashed build is not the ELF's, default: true
+    pub rtt_thread: Option<bool>, // RTT I/O alone on the pump thread, hybrid-timer paced, default: false
+    pub rtt_poll_us: Option<u64>, // time between RTT I/O rounds, default: 10000 (1000 with rtt_thread)
+    pub rtt_spin_us: Option<u64>, // busy-wait at the end of each round with rtt_thread, default: 200
+    pub rtt_core: Option<usize>,  // pin the RTT thread to this CPU core (implies rtt_thread)
+    pub max_command_rate: Option<u32>, // motor commands sent per second at most, default: 0 (unlimited)
+    #[serde(default)]
+    pub addressing: Addressing,
 }
 
 impl HostConfig {
-    pub fn load_default() -> Option<Self> {
-        // Priority: OXIFOC_HOST_CONFIG env var, then ./oxifoc-host.toml if exists
+    /// Config from `OXIFOC_HOST_CONFIG`, else `./oxifoc-host.toml`; `None` if neither is set or present
+    ///
+    /// A file that exists but cannot be read or parsed is an
+    /// `ErrorKind::ConfigInvalid` error rather than a silent fallback to defaults.
+    pub fn load_default() -> anyhow::Result<Option<Self>> {
         if let Ok(p) = env::var("OXIFOC_HOST_CONFIG") {
-            return Self::from_path(PathBuf::from(p));
+            return Self::from_path(PathBuf::from(p)).map(Some);
         }
-        let cwd = env::current_dir().ok()?;
+        let Ok(cwd) = env::current_dir() else {
+            return Ok(None);
+        };
         let p = cwd.join("oxifoc-host.toml");
         if p.exists() {
-            return Self::from_path(p);
-        }
-        None
-    }
-
-    fn from_path(path: PathBuf) -> Option<Self> {
-        match fs::read_to_string(&path) {
-            Ok(s) => match toml::from_str::<HostConfig>(&s) {
-                Ok(cfg) => Some(cfg),
-                Err(e) => {
-                    eprintln!("Failed to parse config (TOML) {}: {}", path.display(), e);
-                    None
-                }
-            },
-            Err(e) => {
-                eprintln!("Failed to read config {}: {}", path.display(), e);
-                None
-            }
+            return Self::from_path(p).map(Some);
         }
+        Ok(None)
+    }
+
+    fn from_path(path: PathBuf) -> anyhow::Result<Self> {
+        let s = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config {}", path.display()))
+            .context(ErrorKind::ConfigInvalid)?;
+        toml::from_str::<HostConfig>(&s)
+            .with_context(|| format!("Failed to parse config (TOML) {}", path.display()))
+            .context(ErrorKind::ConfigInvalid)
     }
 
     pub fn stream_defmt(&self) -> bool {
@@ -46,4 +146,54 @@ impl HostConfig {
     pub fn stream_ergot(&self) -> bool {
         self.stream_ergot.unwrap_or(true)
     }
+    pub fn reconnect_on_reset(&self) -> bool {
+        self.reconnect_on_reset.unwrap_or(true)
+    }
+    pub fn wait_for_probe(&self) -> bool {
+        self.wait_for_probe.unwrap_or(false)
+    }
+    pub fn reset_timeout(&self) -> Duration {
+        Duration::from_millis(self.reset_timeout_ms.unwrap_or(3000))
+    }
+    pub fn strict_channels(&self) -> bool {
+        self.strict_channels.unwrap_or(false)
+    }
+    pub fn verify_build(&self) -> bool {
+        self.verify_build.unwrap_or(true)
+    }
+    pub fn rtt_thread(&self) -> bool {
+        self.rtt_thread.unwrap_or(false) || self.rtt_core.is_some()
+    }
+    /// Interval between RTT I/O rounds; a dedicated thread can afford a shorter one
+    pub fn rtt_poll_interval(&self, dedicated: bool) -> Duration {
+        Duration::from_micros(
+            self.rtt_poll_us
+                .unwrap_or(if dedicated { 1000 } else { 10_000 }),
+        )
+    }
+    pub fn rtt_spin(&self) -> Duration {
+        Duration::from_micros(self.rtt_spin_us.unwrap_or(200))
+    }
+    pub fn rtt_core(&self) -> Option<usize> {
+        self.rtt_core
+    }
+    pub fn max_command_rate(&self) -> u32 {
+        self.max_command_rate.unwrap_or(0)
+    }
+    pub fn reset_on_hang(&self) -> bool {
+        self.reset_on_hang.unwrap_or(false)
+    }
+    pub fn hang_timeout(&self) -> Duration {
+        Duration::from_millis(self.hang_timeout_ms.unwrap_or(10_000))
+    }
+    pub fn hang_reset_cooldown(&self) -> Duration {
+        Duration::from_millis(self.hang_reset_cooldown_ms.unwrap_or(30_000))
+    }
+    pub fn handshake_policy(&self) -> HandshakePolicy {
+        HandshakePolicy {
+            attempts: self.handshake_attempts.unwrap_or(10).max(1),
+            timeout: Duration::from_millis(self.handshake_timeout_ms.unwrap_or(800)),
+            give_up: self.handshake_give_up.unwrap_or_default(),
+        }
+    }
 }
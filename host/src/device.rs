@@ -0,0 +1,1245 @@
+//! Async handle to an oxifoc device
+//!
+//! `OxifocDevice::connect` opens the debug probe, starts the RTT pump on its
+//! own thread and waits for the first handshake. Its methods send requests
+//! over whatever link is current, so they keep working after the pump rebuilt
+//! the link following a device reset.
+//!
+//! ```no_run
+//! # async fn demo() -> anyhow::Result<()> {
+//! use oxifoc_host::{ConnectOptions, HostConfig, OxifocDevice, sink::Dispatcher};
+//!
+//! let (bus, _) = Dispatcher::default().spawn();
+//! let device = OxifocDevice::connect(&HostConfig::default(), ConnectOptions::default(), bus).await?;
+//! let mut telemetry = device.subscribe_telemetry();
+//! device.motor_start(5).await?;
+//! while let Ok(t) = telemetry.recv().await {
+//!     println!("rpm={:?} current={:?}", t.rpm, t.current_ma);
+//! }
+//! device.close().await
+//! # }
+//! ```
+
+use anyhow::{Context, Result};
+use defmt_decoder::Table;
+use ergot::traits::Endpoint;
+use oxifoc_protocol::{
+    BemfChunk, BemfSample, BenchEndpoint, BenchReport, BenchRequest, BenchTopic,
+    CalibrationFailure, CalibrationRun, CoggingSample, CommandRejection, CommutationMode,
+    CommutationPeriod, CommutationPeriodEndpoint, CommutationTable, CommutationTableEndpoint,
+    CurrentCalibration, CurrentCalibrationEndpoint, DeviceConfig, DeviceInfo,
+    ElectricalParamsEndpoint, ExtremesEndpoint, FullStatus, FullStatusEndpoint, IdentifyEndpoint,
+    InfoEndpoint, LogLevel, LogLevelEndpoint, MotorCommand, MotorElectricalParams, MotorEndpoint,
+    MotorRequest, MotorState, MotorStatus, ProfileCommand, ProfileEndpoint, ProfileResponse,
+    ResetExtremesEndpoint, RlFailure, RlMeasurement, ScanScheme, SelfTestEndpoint, SelfTestReport,
+    SelfTestRequest, TaskStats, TaskStatsEndpoint, Telemetry, TelemetryExtremes,
+};
+use probe_rs::Permissions;
+use probe_rs::probe::DebugProbeInfo;
+use probe_rs::probe::list::Lister;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::pin::pin;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+
+use crate::bench::BenchTally;
+use crate::build_id;
+use crate::config::{HandshakeGiveUp, HostConfig};
+use crate::error::ErrorKind;
+use crate::events::DeviceEvents;
+use crate::freewheel::DecayRecorder;
+use crate::link::{self, Link, PumpError, PumpIo, PumpOpts, REQUEST_TIMEOUT, Status};
+use crate::poll::{self, PollConfig};
+use crate::sink::{EventBus, HostEvent, host_time};
+use crate::step::StepCapture;
+use crate::throttle::CommandThrottle;
+
+/// Telemetry samples buffered per subscriber before it lags
+const TELEMETRY_BACKLOG: usize = 256;
+/// First and longest pause between probe scans with `wait_for_probe`
+const PROBE_POLL_MIN: Duration = Duration::from_millis(250);
+const PROBE_POLL_MAX: Duration = Duration::from_secs(5);
+/// Events buffered per `DeviceEvents` receiver before it lags
+const EVENT_BACKLOG: usize = 1024;
+/// BEMF chunks buffered per capture; a full capture is 16 chunks
+const BEMF_BACKLOG: usize = 32;
+/// Time allowed for a capture to fill and upload
+const BEMF_TIMEOUT: Duration = Duration::from_secs(5);
+/// Slack on top of the hold time while waiting for a cogging position; the
+/// first one is held several times longer while the rotor aligns
+const COGGING_SLACK: Duration = Duration::from_secs(2);
+/// Slack on top of the spin and coast times of a freewheel test, for the
+/// startup from standstill and the telemetry in flight
+const FREEWHEEL_SLACK: Duration = Duration::from_secs(5);
+/// Benchmark frames the host buffers before the receiver drops them
+const BENCH_BACKLOG: usize = 256;
+/// Quiet time after the benchmark report that ends collecting its frames
+const BENCH_DRAIN: Duration = Duration::from_millis(200);
+/// Interval between polls of a running R/L measurement
+const RL_POLL: Duration = Duration::from_millis(100);
+/// Longest an R/L measurement may take, polls included
+const RL_TIMEOUT: Duration = Duration::from_secs(5);
+/// Interval between polls of a running current calibration
+const CALIBRATION_POLL: Duration = Duration::from_millis(50);
+/// Longest a current calibration may take, flash write and polls included
+const CALIBRATION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How to connect
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    /// Abort if defmt decoding cannot be set up (default: warn and continue)
+    pub require_defmt: bool,
+    /// Keep the old link when the device resets instead of rebuilding it
+    pub no_reconnect: bool,
+    /// Send motor commands even if the device's protocol version differs
+    pub force: bool,
+    /// Poll until a matching probe appears instead of failing when there is none
+    pub wait_for_probe: bool,
+    /// Reset the core through the probe when the device stops sending
+    pub reset_on_hang: bool,
+    /// Device info requests before giving up (default: the config's)
+    pub handshake_attempts: Option<u32>,
+    /// Time allowed for each device info request (default: the config's)
+    pub handshake_timeout: Option<Duration>,
+    /// What to do once the attempts run out (default: the config's)
+    pub handshake_give_up: Option<HandshakeGiveUp>,
+    /// Refuse RTT channels that had to be guessed by index instead of found by name
+    pub strict_channels: bool,
+    /// Keep only RTT I/O on the pump thread, paced by a spin/sleep timer (default: the config's)
+    pub rtt_thread: bool,
+    /// Pin the pump thread to this CPU core; implies `rtt_thread` (default: the config's)
+    pub rtt_core: Option<usize>,
+    /// Copy the raw bytes read from the ergot up channel to this file
+    pub raw_capture_ergot: Option<String>,
+    /// Copy the raw bytes read from the defmt up channel to this file
+    pub raw_capture_defmt: Option<String>,
+    /// Send at most this many motor commands per second, 0 for no limit (default: the config's)
+    pub max_command_rate: Option<u32>,
+}
+
+/// A connected device
+pub struct OxifocDevice {
+    status: watch::Receiver<Status>,
+    telemetry: broadcast::Sender<Telemetry>,
+    bemf: broadcast::Sender<BemfChunk>,
+    events: broadcast::Sender<HostEvent>,
+    /// For the host's own events, such as `HostEvent::CommandReply`
+    bus: EventBus,
+    shutdown: watch::Sender<bool>,
+    halt_requests: mpsc::UnboundedSender<oneshot::Sender<Option<String>>>,
+    pump: Option<std::thread::JoinHandle<()>>,
+    force: bool,
+    /// Correlation id of the next motor command, see `MotorRequest::trace`
+    next_trace: AtomicU16,
+    /// Send slots for motor commands, if their rate is limited
+    throttle: Option<Mutex<CommandThrottle>>,
+}
+
+impl OxifocDevice {
+    /// Attach to the probe selected by `cfg` and wait for the device handshake
+    ///
+    /// Device events (logs, buttons, telemetry, ...) are published on `bus`.
+    pub async fn connect(cfg: &HostConfig, opts: ConnectOptions, bus: EventBus) -> Result<Self> {
+        let defmt_table = load_defmt(cfg, opts.require_defmt)?;
+        let raw = link::RawCapture::create(
+            opts.raw_capture_ergot.as_deref(),
+            opts.raw_capture_defmt.as_deref(),
+        )?;
+        let probe = find_probe(cfg, cfg.wait_for_probe() || opts.wait_for_probe).await?;
+        let session = open_session(cfg, &probe)?;
+
+        let (status_tx, status) = watch::channel(Status::Connecting);
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let (halt_tx, halt_rx) = mpsc::unbounded_channel();
+        let (telemetry, _) = broadcast::channel(TELEMETRY_BACKLOG);
+        let (bemf, _) = broadcast::channel(BEMF_BACKLOG);
+        let (events, _) = broadcast::channel(EVENT_BACKLOG);
+        let bus = bus.with_tap(events.clone());
+        let rtt_core = opts.rtt_core.or(cfg.rtt_core());
+        let dedicated = cfg.rtt_thread() || opts.rtt_thread || rtt_core.is_some();
+        let poll = PollConfig {
+            dedicated,
+            interval: cfg.rtt_poll_interval(dedicated),
+            spin: cfg.rtt_spin(),
+            core: rtt_core,
+        };
+        let pump_opts = PumpOpts {
+            cfg: cfg.clone(),
+            defmt_table,
+            reconnect: cfg.reconnect_on_reset() && !opts.no_reconnect,
+            reset_on_hang: cfg.reset_on_hang() || opts.reset_on_hang,
+            handshake: {
+                let mut policy = cfg.handshake_policy();
+                if let Some(n) = opts.handshake_attempts {
+                    policy.attempts = n.max(1);
+                }
+                policy.timeout = opts.handshake_timeout.unwrap_or(policy.timeout);
+                policy.give_up = opts.handshake_give_up.unwrap_or(policy.give_up);
+                policy
+            },
+            strict_channels: cfg.strict_channels() || opts.strict_channels,
+            poll,
+            tasks: dedicated.then(tokio::runtime::Handle::current),
+            elf_build: if cfg.verify_build() {
+                build_id::read(&elf_path(cfg))
+            } else {
+                None
+            },
+        };
+        let io = PumpIo {
+            bus: bus.clone(),
+            telemetry: telemetry.clone(),
+            bemf: bemf.clone(),
+            status: status_tx.clone(),
+            shutdown: shutdown_rx,
+            halt_requests: halt_rx,
+            raw,
+        };
+
+        // Probe I/O blocks, so the pump gets its own thread and runtime
+        let pump = std::thread::Builder::new()
+            .name("oxifoc-rtt".into())
+            .spawn(move || {
+                if let Some(core) = poll.core
+                    && !poll::pin_current_thread(core)
+                {
+                    tracing::warn!("Could not pin the RTT pump thread to core {}", core);
+                }
+                let result = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .context("Failed to start the RTT pump runtime")
+                    .and_then(|rt| rt.block_on(link::pump(session, pump_opts, io)));
+                let _ = status_tx.send(Status::Closed(result.map_err(|e| PumpError::new(&e))));
+            })
+            .context("Failed to spawn the RTT pump thread")?;
+
+        let mut device = Self {
+            status,
+            telemetry,
+            bemf,
+            events,
+            bus,
+            shutdown,
+            halt_requests: halt_tx,
+            pump: Some(pump),
+            force: opts.force,
+            next_trace: AtomicU16::new(1),
+            throttle: CommandThrottle::new(opts.max_command_rate.unwrap_or(cfg.max_command_rate()))
+                .map(Mutex::new),
+        };
+        device.wait_up().await?;
+        Ok(device)
+    }
+
+    /// Wait until the current link has completed its handshake
+    async fn wait_up(&mut self) -> Result<()> {
+        let status = self
+            .status
+            .wait_for(|s| matches!(s, Status::Up(_) | Status::Closed(_)))
+            .await
+            .map_err(|_| anyhow::anyhow!("RTT pump stopped"))?;
+        match &*status {
+            Status::Closed(Err(e)) => Err(e.to_error()),
+            Status::Closed(Ok(())) => Err(anyhow::anyhow!("Link closed")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Link for a request; fails while the link is being rebuilt
+    fn link(&self) -> Result<Link> {
+        match &*self.status.borrow() {
+            Status::Up(session) => Ok(session.link.clone()),
+            Status::Connecting | Status::Reset => anyhow::bail!("Device link is reconnecting"),
+            Status::Closed(_) => anyhow::bail!("Device link is closed"),
+        }
+    }
+
+    /// Link for a request that can move the motor; refused on protocol mismatch
+    fn motion_link(&self) -> Result<Link> {
+        if let Status::Up(session) = &*self.status.borrow()
+            && let Err(e) = &session.protocol
+        {
+            if !self.force {
+                anyhow::bail!(
+                    "{}; refusing to send motor commands (override with --force)",
+                    e
+                );
+            }
+            tracing::warn!("{}; sending anyway (--force)", e);
+        }
+        self.link()
+    }
+
+    /// Replace a request error by the halted-core report, if the device panicked
+    async fn explain(&self, err: anyhow::Error) -> anyhow::Error {
+        match self.halt_report().await {
+            Some(report) => anyhow::anyhow!(report),
+            None => err,
+        }
+    }
+
+    /// Describe why the core is halted, or `None` while it runs
+    pub async fn halt_report(&self) -> Option<String> {
+        let (tx, rx) = oneshot::channel();
+        self.halt_requests.send(tx).ok()?;
+        // Answered between pump rounds; give up if the pump is busy re-attaching
+        tokio::time::timeout(Duration::from_secs(1), rx)
+            .await
+            .ok()?
+            .ok()
+            .flatten()
+    }
+
+    /// Device info from the most recent handshake
+    pub fn handshake_info(&self) -> Option<DeviceInfo> {
+        match &*self.status.borrow() {
+            Status::Up(session) => session.info.clone(),
+            _ => None,
+        }
+    }
+
+    /// Query hardware and firmware identification
+    pub async fn info(&self) -> Result<DeviceInfo> {
+        let link = self.link()?;
+        let fut =
+            link.stack
+                .endpoints()
+                .request::<InfoEndpoint>(link.device, &(), Some("device_info"));
+        match tokio::time::timeout(REQUEST_TIMEOUT, fut).await {
+            Ok(Ok(info)) => Ok(info),
+            Ok(Err(e)) => Err(self
+                .explain(anyhow::anyhow!("Info request failed: {:?}", e))
+                .await),
+            Err(_) => Err(self
+                .explain(anyhow::anyhow!("Info request timed out"))
+                .await),
+        }
+    }
+
+    /// Blink the device LED in its identify pattern for `secs` seconds (0 ends it)
+    pub async fn identify(&self, secs: u16) -> Result<()> {
+        let link = self.link()?;
+        let fut = link.stack.endpoints().request::<IdentifyEndpoint>(
+            link.device,
+            &secs,
+            Some("identify"),
+        );
+        match tokio::time::timeout(REQUEST_TIMEOUT, fut).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(self
+                .explain(anyhow::anyhow!("Identify request failed: {:?}", e))
+                .await),
+            Err(_) => Err(self
+                .explain(anyhow::anyhow!("Identify request timed out"))
+                .await),
+        }
+    }
+
+    /// Set the device's runtime log level; frames below it are not sent until the next reset
+    pub async fn set_log_level(&self, level: LogLevel) -> Result<()> {
+        let link = self.link()?;
+        let fut = link.stack.endpoints().request::<LogLevelEndpoint>(
+            link.device,
+            &level,
+            Some("log_level"),
+        );
+        match tokio::time::timeout(REQUEST_TIMEOUT, fut).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(self
+                .explain(anyhow::anyhow!("Log level request failed: {:?}", e))
+                .await),
+            Err(_) => Err(self
+                .explain(anyhow::anyhow!("Log level request timed out"))
+                .await),
+        }
+    }
+
+    /// Query the device's full configuration
+    pub async fn config(&self) -> Result<DeviceConfig> {
+        let link = self.link()?;
+        match link::fetch_config(&link).await {
+            Ok(c) => Ok(c),
+            Err(e) => Err(self.explain(e).await),
+        }
+    }
+
+    /// Query the step table the device is commutating with
+    pub async fn commutation_table(&self) -> Result<CommutationTable> {
+        let link = self.link()?;
+        let fut = link.stack.endpoints().request::<CommutationTableEndpoint>(
+            link.device,
+            &(),
+            Some("comm_table"),
+        );
+        match tokio::time::timeout(REQUEST_TIMEOUT, fut).await {
+            Ok(Ok(table)) => Ok(table),
+            Ok(Err(e)) => Err(self
+                .explain(anyhow::anyhow!("Commutation table request failed: {:?}", e))
+                .await),
+            Err(_) => Err(self
+                .explain(anyhow::anyhow!("Commutation table request timed out"))
+                .await),
+        }
+    }
+
+    /// Query the open-loop run period and the current step dwell
+    pub async fn commutation_period(&self) -> Result<CommutationPeriod> {
+        let link = self.link()?;
+        let fut = link.stack.endpoints().request::<CommutationPeriodEndpoint>(
+            link.device,
+            &(),
+            Some("comm_period"),
+        );
+        match tokio::time::timeout(REQUEST_TIMEOUT, fut).await {
+            Ok(Ok(period)) => Ok(period),
+            Ok(Err(e)) => Err(self
+                .explain(anyhow::anyhow!(
+                    "Commutation period request failed: {:?}",
+                    e
+                ))
+                .await),
+            Err(_) => Err(self
+                .explain(anyhow::anyhow!("Commutation period request timed out"))
+                .await),
+        }
+    }
+
+    /// Query all runtime state at once, captured by the device at one instant
+    pub async fn full_status(&self) -> Result<FullStatus> {
+        let link = self.link()?;
+        let fut = link.stack.endpoints().request::<FullStatusEndpoint>(
+            link.device,
+            &(),
+            Some("full_status"),
+        );
+        match tokio::time::timeout(REQUEST_TIMEOUT, fut).await {
+            Ok(Ok(status)) => Ok(status),
+            Ok(Err(e)) => Err(self
+                .explain(anyhow::anyhow!("Full status request failed: {:?}", e))
+                .await),
+            Err(_) => Err(self
+                .explain(anyhow::anyhow!("Full status request timed out"))
+                .await),
+        }
+    }
+
+    /// Have the device flood the link for `req.duration_ms` and count what arrives
+    ///
+    /// Needs the device armed and the motor stopped. Frames still in flight
+    /// when the device reports are collected until `BENCH_DRAIN` passes
+    /// without one.
+    pub async fn bench_throughput(&self, req: &BenchRequest) -> Result<(BenchReport, BenchTally)> {
+        let link = self.link()?;
+        // Subscribe first so the first frames are not missed
+        let receiver = link
+            .stack
+            .topics()
+            .bounded_receiver::<BenchTopic, BENCH_BACKLOG>(None);
+        let receiver = pin!(receiver);
+        let mut frames = receiver.subscribe();
+        let mut tally = BenchTally::default();
+
+        let request =
+            link.stack
+                .endpoints()
+                .request::<BenchEndpoint>(link.device, req, Some("bench"));
+        let mut request = pin!(request);
+        let wait = Duration::from_millis(req.duration_ms as u64) + REQUEST_TIMEOUT;
+        let flood = async {
+            loop {
+                tokio::select! {
+                    report = &mut request => break report,
+                    msg = frames.recv() => tally.push(&msg.t, host_time()),
+                }
+            }
+        };
+        let report = match tokio::time::timeout(wait, flood).await {
+            Ok(Ok(report)) => report,
+            Ok(Err(e)) => {
+                return Err(self
+                    .explain(anyhow::anyhow!("Benchmark request failed: {:?}", e))
+                    .await);
+            }
+            Err(_) => {
+                return Err(self
+                    .explain(anyhow::anyhow!("Benchmark did not finish"))
+                    .await);
+            }
+        };
+        while let Ok(msg) = tokio::time::timeout(BENCH_DRAIN, frames.recv()).await {
+            tally.push(&msg.t, host_time());
+        }
+        Ok((report, tally))
+    }
+
+    /// Query the lowest and highest readings since the last reset
+    pub async fn extremes(&self) -> Result<TelemetryExtremes> {
+        let link = self.link()?;
+        let fut =
+            link.stack
+                .endpoints()
+                .request::<ExtremesEndpoint>(link.device, &(), Some("extremes"));
+        match tokio::time::timeout(REQUEST_TIMEOUT, fut).await {
+            Ok(Ok(ext)) => Ok(ext),
+            Ok(Err(e)) => Err(self
+                .explain(anyhow::anyhow!("Extremes request failed: {:?}", e))
+                .await),
+            Err(_) => Err(self
+                .explain(anyhow::anyhow!("Extremes request timed out"))
+                .await),
+        }
+    }
+
+    /// Query the device task scheduling since the last query; the device starts it over
+    pub async fn task_stats(&self) -> Result<TaskStats> {
+        let link = self.link()?;
+        let fut = link.stack.endpoints().request::<TaskStatsEndpoint>(
+            link.device,
+            &(),
+            Some("task_stats"),
+        );
+        match tokio::time::timeout(REQUEST_TIMEOUT, fut).await {
+            Ok(Ok(stats)) => Ok(stats),
+            Ok(Err(e)) => Err(self
+                .explain(anyhow::anyhow!("Task stats request failed: {:?}", e))
+                .await),
+            Err(_) => Err(self
+                .explain(anyhow::anyhow!("Task stats request timed out"))
+                .await),
+        }
+    }
+
+    /// Start the extremes over; returns the ones the reset ended
+    pub async fn reset_extremes(&self) -> Result<TelemetryExtremes> {
+        let link = self.link()?;
+        let fut = link.stack.endpoints().request::<ResetExtremesEndpoint>(
+            link.device,
+            &(),
+            Some("reset_extremes"),
+        );
+        match tokio::time::timeout(REQUEST_TIMEOUT, fut).await {
+            Ok(Ok(ext)) => Ok(ext),
+            Ok(Err(e)) => Err(self
+                .explain(anyhow::anyhow!("Extremes reset failed: {:?}", e))
+                .await),
+            Err(_) => Err(self
+                .explain(anyhow::anyhow!("Extremes reset timed out"))
+                .await),
+        }
+    }
+
+    /// Send `req` to endpoint `E` and return the device's reply
+    ///
+    /// For endpoints without a method of their own (see `send`). The request
+    /// goes out as given, not validated or paced; `motion` applies the
+    /// protocol mismatch guard of a request that can move the motor.
+    pub async fn request<E>(&self, req: &E::Request, motion: bool) -> Result<E::Response>
+    where
+        E: Endpoint,
+        E::Request: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+        E::Response: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    {
+        let link = if motion {
+            self.motion_link()?
+        } else {
+            self.link()?
+        };
+        // Every device server is named after the last segment of its path
+        let name = E::PATH.rsplit('/').next();
+        let fut = link.stack.endpoints().request::<E>(link.device, req, name);
+        match tokio::time::timeout(REQUEST_TIMEOUT, fut).await {
+            Ok(Ok(resp)) => Ok(resp),
+            Ok(Err(e)) => Err(self
+                .explain(anyhow::anyhow!("{} request failed: {:?}", E::PATH, e))
+                .await),
+            Err(_) => Err(self
+                .explain(anyhow::anyhow!("{} request timed out", E::PATH))
+                .await),
+        }
+    }
+
+    /// Send a motor command and return the status the device replied with
+    ///
+    /// A command outside the protocol's bounds (`MotorCommand::validate`) is
+    /// an error without being sent; one the device refused is reported as an
+    /// error with its reason. Each command gets the next correlation id, logged
+    /// here as `[id=N]` and by the device on the lines it logs for it.
+    pub async fn motor(&self, cmd: &MotorCommand) -> Result<MotorStatus> {
+        cmd.validate()?;
+        // A stop is never held back behind the commands it is meant to override
+        if let Some(throttle) = &self.throttle
+            && !matches!(cmd, MotorCommand::Stop | MotorCommand::Estop)
+        {
+            let wait = throttle.lock().unwrap().reserve(Instant::now());
+            if !wait.is_zero() {
+                tracing::info!(
+                    "Throttling motor commands: {} waits {}ms (--max-command-rate)",
+                    MotorCommand::name_of(cmd.id()),
+                    wait.as_millis()
+                );
+                tokio::time::sleep(wait).await;
+            }
+        }
+        let link = self.motion_link()?;
+        let trace = self.next_trace.fetch_add(1, Ordering::Relaxed);
+        tracing::info!(
+            "Motor command [id={}]: {}",
+            trace,
+            MotorCommand::name_of(cmd.id())
+        );
+        let req = MotorRequest {
+            cmd: cmd.clone(),
+            trace: Some(trace),
+        };
+        let fut = link
+            .stack
+            .endpoints()
+            .request::<MotorEndpoint>(link.device, &req, Some("motor"));
+        let sent_at = host_time();
+        let reply = tokio::time::timeout(REQUEST_TIMEOUT, fut).await;
+        self.bus.publish(HostEvent::CommandReply {
+            trace,
+            cmd: cmd.id(),
+            sent_at,
+            latency: host_time().saturating_sub(sent_at),
+            status: reply.as_ref().ok().and_then(|r| r.as_ref().ok()).cloned(),
+        });
+        let status = match reply {
+            Ok(Ok(status)) => status,
+            Ok(Err(e)) => {
+                return Err(self
+                    .explain(anyhow::anyhow!("Motor command failed: {:?}", e))
+                    .await);
+            }
+            Err(_) => {
+                return Err(self
+                    .explain(anyhow::anyhow!("Motor command timed out"))
+                    .await);
+            }
+        };
+        match status.rejected {
+            None => Ok(status),
+            Some(CommandRejection::MinOffTime { remaining_ms }) => anyhow::bail!(
+                "Device rejected {:?}: motor stopped too recently, retry in {}ms",
+                cmd,
+                remaining_ms
+            ),
+            Some(CommandRejection::StartDebounced) => {
+                anyhow::bail!("Device rejected {:?}: repeated start while running", cmd)
+            }
+            Some(CommandRejection::EstopLatched) => {
+                anyhow::bail!(
+                    "Device rejected {:?}: emergency stop latched, arm to clear",
+                    cmd
+                )
+            }
+            Some(CommandRejection::Disarmed) => {
+                anyhow::bail!("Device rejected {:?}: not armed, run `arm` first", cmd)
+            }
+            Some(CommandRejection::AdcFault) => {
+                anyhow::bail!(
+                    "Device rejected {:?}: ADC readings untrusted (see `status`)",
+                    cmd
+                )
+            }
+            Some(CommandRejection::MotorRunning) => {
+                anyhow::bail!("Device rejected {:?}: motor running, run `stop` first", cmd)
+            }
+            Some(CommandRejection::NotBuilt) => anyhow::bail!(
+                "Device rejected {:?}: firmware built without the `diagnostics` feature",
+                cmd
+            ),
+        }
+    }
+
+    /// Halve duty, then stop, while the motor runs below `min_rpm` (0 disables)
+    pub async fn set_low_speed_cutoff(&self, min_rpm: u16, timeout_ms: u16) -> Result<MotorStatus> {
+        self.motor(&MotorCommand::SetLowSpeedCutoff {
+            min_rpm,
+            timeout_ms,
+        })
+        .await
+    }
+
+    /// Cap duty so the average phase voltage stays under `mv` at any VBUS (0 disables)
+    pub async fn set_max_phase_voltage(&self, mv: u16) -> Result<MotorStatus> {
+        self.motor(&MotorCommand::SetMaxPhaseVoltage { mv }).await
+    }
+
+    /// Set the open-loop run period directly, bypassing the duty/speed mapping
+    ///
+    /// A period outside `MIN/MAX_COMMUTATION_PERIOD_US` is refused. The device
+    /// may still lengthen it (safe mode); read the applied value back with
+    /// `commutation_period`.
+    pub async fn set_commutation_period_us(&self, us: u32) -> Result<MotorStatus> {
+        self.motor(&MotorCommand::set_commutation_period_us(us)?)
+            .await
+    }
+
+    /// Time the running steps by the run period or by edges on the device's trigger input
+    pub async fn set_commutation_mode(&self, mode: CommutationMode) -> Result<MotorStatus> {
+        self.motor(&MotorCommand::SetCommutationMode { mode }).await
+    }
+
+    /// Stop the motor and switch the device's commutation step table
+    pub async fn set_scan_scheme(&self, scheme: ScanScheme) -> Result<MotorStatus> {
+        self.motor(&MotorCommand::SetScanScheme { scheme }).await
+    }
+
+    /// Record `samples` floating-phase voltage readings from the running motor
+    ///
+    /// The device fills its buffer during commutation, then uploads it; this
+    /// resolves once the whole capture has arrived.
+    pub async fn capture_bemf(&self, samples: u16) -> Result<Vec<BemfSample>> {
+        let cmd = MotorCommand::capture_bemf(samples)?;
+        // Subscribe first so no chunk can be missed
+        let mut chunks = self.bemf.subscribe();
+        let status = self.motor(&cmd).await?;
+        if status.state != MotorState::Running {
+            anyhow::bail!("Motor is not running; start it before capturing BEMF");
+        }
+        let collect = async {
+            let mut out: Vec<BemfSample> = Vec::new();
+            loop {
+                let chunk = chunks.recv().await.context("BEMF upload interrupted")?;
+                if chunk.offset as usize != out.len() {
+                    anyhow::bail!(
+                        "BEMF chunk at {} arrived after {} samples",
+                        chunk.offset,
+                        out.len()
+                    );
+                }
+                out.extend_from_slice(&chunk.samples);
+                if out.len() >= chunk.total as usize {
+                    return Ok(out);
+                }
+            }
+        };
+        match tokio::time::timeout(BEMF_TIMEOUT, collect).await {
+            Ok(result) => result,
+            Err(_) => Err(self
+                .explain(anyhow::anyhow!("BEMF capture timed out"))
+                .await),
+        }
+    }
+
+    /// Walk the rotor through one revolution, holding `current_ma` for `settle_ms` per step
+    ///
+    /// The device streams one sample per position as it goes; this resolves
+    /// with all of them once the scan has finished.
+    pub async fn cogging_scan(
+        &self,
+        current_ma: u16,
+        settle_ms: u16,
+    ) -> Result<Vec<CoggingSample>> {
+        let cmd = MotorCommand::cogging_scan(current_ma, settle_ms)?;
+        // Subscribe first so no sample can be missed
+        let mut events = self.events.subscribe();
+        self.motor(&cmd).await?;
+        let wait = Duration::from_millis(settle_ms as u64 * 8) + COGGING_SLACK;
+        let mut out: Vec<CoggingSample> = Vec::new();
+        loop {
+            let event = match tokio::time::timeout(wait, events.recv()).await {
+                Ok(Ok(event)) => event,
+                Ok(Err(broadcast::error::RecvError::Lagged(_))) => continue,
+                Ok(Err(broadcast::error::RecvError::Closed)) => {
+                    anyhow::bail!("Cogging scan interrupted")
+                }
+                Err(_) => {
+                    let e =
+                        anyhow::anyhow!("Cogging scan ended early after {} positions", out.len());
+                    return Err(self.explain(e).await);
+                }
+            };
+            let HostEvent::Cogging(sample) = event else {
+                continue;
+            };
+            if sample.index as usize != out.len() {
+                anyhow::bail!(
+                    "Cogging position {} arrived after {} positions",
+                    sample.index,
+                    out.len()
+                );
+            }
+            out.push(sample);
+            if out.len() >= sample.total as usize {
+                return Ok(out);
+            }
+        }
+    }
+
+    /// Spin up at `duty` percent for `spin_ms`, then cut the drive and record the coast-down
+    ///
+    /// The speed comes from telemetry, so its mask has to hold the rpm and
+    /// state fields. Ends at the first sample reading 0 rpm; the device gives
+    /// up tracking after `coast_ms`.
+    pub async fn freewheel(&self, duty: u8, spin_ms: u16, coast_ms: u16) -> Result<DecayRecorder> {
+        let cmd = MotorCommand::freewheel(duty, spin_ms, coast_ms)?;
+        // Subscribe first so the drive cut cannot be missed
+        let mut events = self.events.subscribe();
+        self.motor(&cmd).await?;
+        let deadline = Instant::now()
+            + Duration::from_millis(spin_ms as u64 + coast_ms as u64)
+            + FREEWHEEL_SLACK;
+        let mut recorder = DecayRecorder::default();
+        loop {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            let event = match tokio::time::timeout(wait, events.recv()).await {
+                Ok(Ok(event)) => event,
+                Ok(Err(broadcast::error::RecvError::Lagged(_))) => continue,
+                Ok(Err(broadcast::error::RecvError::Closed)) => {
+                    anyhow::bail!("Freewheel test interrupted")
+                }
+                Err(_) if recorder.started() => return Ok(recorder),
+                Err(_) => {
+                    return Err(self
+                        .explain(anyhow::anyhow!("Freewheel test: the drive was never cut"))
+                        .await);
+                }
+            };
+            let HostEvent::Telemetry(t) = event else {
+                continue;
+            };
+            if recorder.push(host_time(), &t) {
+                return Ok(recorder);
+            }
+        }
+    }
+
+    /// Hold the running motor at `from_duty` for `hold_ms`, then step to `to_duty` and record `hold_ms` more
+    ///
+    /// The speed comes from telemetry, so its mask has to hold the rpm field.
+    /// The motor is left running at `to_duty`.
+    pub async fn step_response(
+        &self,
+        from_duty: u8,
+        to_duty: u8,
+        hold_ms: u16,
+    ) -> Result<StepCapture> {
+        let step = MotorCommand::set_speed(to_duty)?;
+        let hold = Duration::from_millis(hold_ms as u64);
+        let mut telemetry = self.subscribe_telemetry();
+        let status = self.set_speed(from_duty).await?;
+        if status.state != MotorState::Running {
+            anyhow::bail!("Motor is not running; start it before a step test");
+        }
+        let before = rpm_for(&mut telemetry, hold).await?;
+        if before.is_empty() {
+            anyhow::bail!("No rpm in telemetry; enable it with `telemetry`");
+        }
+        let sent_at = host_time();
+        self.motor(&step).await?;
+        let after = rpm_for(&mut telemetry, hold).await?;
+        Ok(StepCapture {
+            before: before.into_iter().map(|(_, rpm)| rpm).collect(),
+            after: after
+                .into_iter()
+                .map(|(at, rpm)| (at.saturating_sub(sent_at), rpm))
+                .collect(),
+        })
+    }
+
+    /// Align, ramp and run open-loop at `duty` percent
+    pub async fn motor_start(&self, duty: u8) -> Result<MotorStatus> {
+        self.motor(&MotorCommand::start(duty)?).await
+    }
+
+    /// As `motor_start`, aligning at commutation step `step` (0-5) instead of the device's default
+    pub async fn motor_start_at(&self, duty: u8, step: u8) -> Result<MotorStatus> {
+        self.motor(&MotorCommand::start_at(duty, step)?).await
+    }
+
+    /// Stop the motor (ahead of any queued command on the device)
+    pub async fn motor_stop(&self) -> Result<MotorStatus> {
+        self.motor(&MotorCommand::Stop).await
+    }
+
+    /// Change duty while running
+    pub async fn set_speed(&self, duty: u8) -> Result<MotorStatus> {
+        self.motor(&MotorCommand::set_speed(duty)?).await
+    }
+
+    /// Stop, disarm and refuse motion until the next `Arm`
+    pub async fn estop(&self) -> Result<MotorStatus> {
+        self.motor(&MotorCommand::Estop).await
+    }
+
+    /// Select the streamed `telemetry_field` bits
+    pub async fn set_telemetry_mask(&self, mask: u16) -> Result<MotorStatus> {
+        self.motor(&MotorCommand::set_telemetry_mask(mask)?).await
+    }
+
+    /// Stream a key frame every `key_interval` samples and deltas from it in between; 0 = full samples only
+    ///
+    /// The link decodes either kind, so subscribers see full samples regardless.
+    pub async fn set_telemetry_delta(&self, key_interval: u8) -> Result<MotorStatus> {
+        self.motor(&MotorCommand::SetTelemetryDelta { key_interval })
+            .await
+    }
+
+    /// Receive every telemetry sample from now on
+    pub fn subscribe_telemetry(&self) -> broadcast::Receiver<Telemetry> {
+        self.telemetry.subscribe()
+    }
+
+    /// Typed stream of button, telemetry, fault, log and disconnect events
+    ///
+    /// Starts with the events published after the call; each receiver gets
+    /// every event.
+    pub fn events(&self) -> DeviceEvents {
+        DeviceEvents::new(self.events.subscribe(), self.status.clone())
+    }
+
+    /// Measure per-phase resistance and inductance
+    ///
+    /// The device drives one phase pair for well under a second, without
+    /// turning the rotor; this polls for the result until it is in.
+    pub async fn measure_rl(&self) -> Result<MotorElectricalParams> {
+        self.motor(&MotorCommand::MeasureRL).await?;
+        let link = self.link()?;
+        let poll = async {
+            loop {
+                tokio::time::sleep(RL_POLL).await;
+                let fut = link.stack.endpoints().request::<ElectricalParamsEndpoint>(
+                    link.device,
+                    &(),
+                    Some("electrical_params"),
+                );
+                match tokio::time::timeout(REQUEST_TIMEOUT, fut).await {
+                    Ok(Ok(RlMeasurement::Measuring)) => {}
+                    Ok(Ok(m)) => return Ok(m),
+                    Ok(Err(e)) => {
+                        return Err(anyhow::anyhow!("R/L measurement query failed: {:?}", e));
+                    }
+                    Err(_) => return Err(anyhow::anyhow!("R/L measurement query timed out")),
+                }
+            }
+        };
+        let m = match tokio::time::timeout(RL_TIMEOUT, poll).await {
+            Ok(Ok(m)) => m,
+            Ok(Err(e)) => return Err(self.explain(e).await),
+            Err(_) => {
+                return Err(self
+                    .explain(anyhow::anyhow!("R/L measurement did not finish"))
+                    .await);
+            }
+        };
+        match m {
+            RlMeasurement::Done(params) => Ok(params),
+            RlMeasurement::Failed(RlFailure::Disarmed) => {
+                anyhow::bail!("Device is not armed; run `arm` first")
+            }
+            RlMeasurement::Failed(RlFailure::Aborted) => {
+                anyhow::bail!("R/L measurement aborted on the device")
+            }
+            RlMeasurement::Failed(RlFailure::NoCurrent) => {
+                anyhow::bail!(
+                    "R/L measurement: no current at the duty ceiling; check the motor wiring"
+                )
+            }
+            RlMeasurement::Failed(RlFailure::OverCurrent) => {
+                anyhow::bail!(
+                    "R/L measurement: current past the abort level; resistance too low to measure"
+                )
+            }
+            RlMeasurement::Failed(RlFailure::OutOfRange) => {
+                anyhow::bail!("R/L measurement: current rise not timed, or a value out of range")
+            }
+            RlMeasurement::Idle | RlMeasurement::Measuring => {
+                anyhow::bail!("Device reported no R/L measurement")
+            }
+        }
+    }
+
+    /// Measure the shunt offsets, setting the gain to `num / den` if given and storing if `persist`
+    ///
+    /// The device measures after acknowledging the command; this polls for
+    /// the result until it is in.
+    pub async fn calibrate_current(
+        &self,
+        gain: Option<(u16, u16)>,
+        persist: bool,
+    ) -> Result<CurrentCalibration> {
+        self.motor(&MotorCommand::calibrate_current(gain, persist)?)
+            .await?;
+        let link = self.link()?;
+        let poll = async {
+            loop {
+                tokio::time::sleep(CALIBRATION_POLL).await;
+                let fut = link
+                    .stack
+                    .endpoints()
+                    .request::<CurrentCalibrationEndpoint>(
+                        link.device,
+                        &(),
+                        Some("current_calibration"),
+                    );
+                match tokio::time::timeout(REQUEST_TIMEOUT, fut).await {
+                    Ok(Ok(CalibrationRun::Measuring)) => {}
+                    Ok(Ok(run)) => return Ok(run),
+                    Ok(Err(e)) => {
+                        return Err(anyhow::anyhow!("Current calibration query failed: {:?}", e));
+                    }
+                    Err(_) => return Err(anyhow::anyhow!("Current calibration query timed out")),
+                }
+            }
+        };
+        let run = match tokio::time::timeout(CALIBRATION_TIMEOUT, poll).await {
+            Ok(Ok(run)) => run,
+            Ok(Err(e)) => return Err(self.explain(e).await),
+            Err(_) => {
+                return Err(self
+                    .explain(anyhow::anyhow!("Current calibration did not finish"))
+                    .await);
+            }
+        };
+        match run {
+            CalibrationRun::Done(cal) => Ok(cal),
+            CalibrationRun::Failed(CalibrationFailure::BadOffsets) => {
+                anyhow::bail!(
+                    "Current calibration: offsets out of range; current sensing stays untrusted"
+                )
+            }
+            CalibrationRun::Failed(CalibrationFailure::PersistFailed) => {
+                anyhow::bail!("Current calibration applied, but could not be saved to flash")
+            }
+            CalibrationRun::Idle | CalibrationRun::Measuring => {
+                anyhow::bail!("Device reported no current calibration")
+            }
+        }
+    }
+
+    /// Run the direction self-test; the device spins for a couple of seconds
+    pub async fn self_test(&self, req: &SelfTestRequest) -> Result<SelfTestReport> {
+        let link = self.motion_link()?;
+        let fut =
+            link.stack
+                .endpoints()
+                .request::<SelfTestEndpoint>(link.device, req, Some("self_test"));
+        match tokio::time::timeout(Duration::from_secs(12), fut).await {
+            Ok(Ok(report)) => Ok(report),
+            Ok(Err(e)) => Err(self
+                .explain(anyhow::anyhow!("Self-test request failed: {:?}", e))
+                .await),
+            Err(_) => Err(self.explain(anyhow::anyhow!("Self-test timed out")).await),
+        }
+    }
+
+    /// Save, load or list motor profiles; `Load` counts as a motor command
+    pub async fn profile(&self, cmd: &ProfileCommand) -> Result<ProfileResponse> {
+        let link = match cmd {
+            ProfileCommand::Load { .. } => self.motion_link()?,
+            _ => self.link()?,
+        };
+        let fut =
+            link.stack
+                .endpoints()
+                .request::<ProfileEndpoint>(link.device, cmd, Some("profile"));
+        match tokio::time::timeout(REQUEST_TIMEOUT, fut).await {
+            Ok(Ok(resp)) => Ok(resp),
+            Ok(Err(e)) => Err(self
+                .explain(anyhow::anyhow!("Profile request failed: {:?}", e))
+                .await),
+            Err(_) => Err(self
+                .explain(anyhow::anyhow!("Profile request timed out"))
+                .await),
+        }
+    }
+
+    /// Resolves when the current link is lost: a device reset, or the pump stopping
+    pub async fn link_lost(&self) -> Result<()> {
+        let mut status = self.status.clone();
+        let status = status
+            .wait_for(|s| matches!(s, Status::Reset | Status::Closed(_)))
+            .await
+            .map_err(|_| anyhow::anyhow!("RTT pump stopped"))?;
+        match &*status {
+            Status::Closed(Err(e)) => Err(e.to_error()),
+            Status::Reset => Err(anyhow::anyhow!("Device reset")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Resolves when the pump stops, with its error if it failed
+    pub async fn closed(&self) -> Result<()> {
+        let mut status = self.status.clone();
+        let status = status
+            .wait_for(|s| matches!(s, Status::Closed(_)))
+            .await
+            .map_err(|_| anyhow::anyhow!("RTT pump stopped"))?;
+        match &*status {
+            Status::Closed(Err(e)) => Err(e.to_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Stop the pump and release the probe
+    pub async fn close(mut self) -> Result<()> {
+        let _ = self.shutdown.send(true);
+        if let Some(pump) = self.pump.take() {
+            tokio::task::spawn_blocking(move || pump.join())
+                .await
+                .context("RTT pump join failed")?
+                .map_err(|_| anyhow::anyhow!("RTT pump thread panicked"))?;
+        }
+        self.closed().await
+    }
+}
+
+impl Drop for OxifocDevice {
+    fn drop(&mut self) {
+        // The thread notices on its next I/O round and exits
+        let _ = self.shutdown.send(true);
+    }
+}
+
+/// Telemetry rpm received over the next `hold`, as (`host_time`, rpm)
+async fn rpm_for(
+    telemetry: &mut broadcast::Receiver<Telemetry>,
+    hold: Duration,
+) -> Result<Vec<(Duration, u16)>> {
+    let end = Instant::now() + hold;
+    let mut out = Vec::new();
+    loop {
+        let wait = end.saturating_duration_since(Instant::now());
+        match tokio::time::timeout(wait, telemetry.recv()).await {
+            Ok(Ok(t)) => out.extend(t.rpm.map(|rpm| (host_time(), rpm))),
+            Ok(Err(broadcast::error::RecvError::Lagged(_))) => {}
+            Ok(Err(broadcast::error::RecvError::Closed)) => {
+                anyhow::bail!("Telemetry stopped during the step test")
+            }
+            Err(_) => return Ok(out),
+        }
+    }
+}
+
+/// Whether `p` matches a `VID:PID[:SERIAL]` selector (hex ids; missing parts match anything)
+fn probe_matches(sel: &str, p: &DebugProbeInfo) -> bool {
+    let mut parts = sel.split(':');
+    let vid = parts.next();
+    let pid = parts.next();
+    let serial = parts.next();
+    let ok_vid = vid
+        .and_then(|v| u16::from_str_radix(v, 16).ok())
+        .map(|v| p.vendor_id == v)
+        .unwrap_or(true);
+    let ok_pid = pid
+        .and_then(|v| u16::from_str_radix(v, 16).ok())
+        .map(|v| p.product_id == v)
+        .unwrap_or(true);
+    let ok_ser = serial
+        .map(|s| p.serial_number.as_deref() == Some(s))
+        .unwrap_or(true);
+    ok_vid && ok_pid && ok_ser
+}
+
+/// The configured probe, or the first found
+///
+/// With `wait`, scan again with backoff until one appears instead of failing.
+async fn find_probe(cfg: &HostConfig, wait: bool) -> Result<DebugProbeInfo> {
+    tracing::info!(
+        "Oxifoc Host - RTT (chip={:?}, probe={:?})",
+        cfg.chip,
+        cfg.probe
+    );
+    let lister = Lister::new();
+    let mut pause = PROBE_POLL_MIN;
+    loop {
+        let probes = lister.list_all();
+        if !probes.is_empty() {
+            tracing::info!("Found {} probe(s)", probes.len());
+        }
+        let found = match &cfg.probe {
+            Some(sel) => probes.into_iter().find(|p| probe_matches(sel, p)),
+            None => probes.into_iter().next(),
+        };
+        match (found, &cfg.probe) {
+            (Some(probe), _) => return Ok(probe),
+            (None, Some(sel)) if !wait => {
+                return Err(anyhow::anyhow!("Configured probe {} is not connected", sel))
+                    .context(ErrorKind::NoProbe);
+            }
+            (None, None) if !wait => {
+                tracing::error!("No debug probes found! Make sure ST-Link is connected.");
+                return Err(ErrorKind::NoProbe.into());
+            }
+            (None, Some(sel)) => {
+                tracing::info!(
+                    "Waiting for probe {}; next scan in {}ms",
+                    sel,
+                    pause.as_millis()
+                )
+            }
+            (None, None) => tracing::info!(
+                "Waiting for a debug probe; next scan in {}ms",
+                pause.as_millis()
+            ),
+        }
+        tokio::time::sleep(pause).await;
+        pause = (pause * 2).min(PROBE_POLL_MAX);
+    }
+}
+
+/// Open `info` and attach to the configured (or auto-detected) target
+fn open_session(cfg: &HostConfig, info: &DebugProbeInfo) -> Result<probe_rs::Session> {
+    tracing::info!("Connecting to STM32G431 via ST-Link...");
+    let probe = info
+        .open()
+        .context("Failed to open probe")
+        .context(ErrorKind::AttachFailed)?;
+
+    // Attach to the target (auto-detect by default, or explicit chip)
+    let ts = match &cfg.chip {
+        Some(name) => probe_rs::config::TargetSelector::from(name.as_str()),
+        None => probe_rs::config::TargetSelector::Auto,
+    };
+    let session = probe
+        .attach(ts, Permissions::default())
+        .context(ErrorKind::AttachFailed)?;
+
+    tracing::info!("Successfully attached to STM32G431");
+    Ok(session)
+}
+
+/// Load the defmt table from the configured (or default) device ELF
+fn load_defmt(cfg: &HostConfig, require: bool) -> Result<Option<Table>> {
+    if !cfg.stream_defmt() {
+        return Ok(None);
+    }
+    match load_defmt_table(&elf_path(cfg)) {
+        Ok(table) => Ok(Some(table)),
+        Err(e) if require => Err(e),
+        Err(e) => {
+            tracing::warn!("{:#}", e);
+            tracing::warn!(
+                "defmt decoding disabled; continuing with ergot only (use --require-defmt to make this fatal)"
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Configured device ELF, or the device crate's release build
+pub(crate) fn elf_path(cfg: &HostConfig) -> String {
+    let default_elf = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../device/target/thumbv7em-none-eabihf/release/oxifoc")
+        .to_string_lossy()
+        .into_owned();
+    cfg.elf.clone().unwrap_or(default_elf)
+}
+
+/// Read the device ELF and parse its defmt table
+pub(crate) fn load_defmt_table(elf_path: &str) -> Result<Table> {
+    let elf_bytes = std::fs::read(elf_path)
+        .with_context(|| format!("Failed to read ELF at {}", elf_path))
+        .context(ErrorKind::ElfMissing)?;
+    Table::parse(&elf_bytes)
+        .with_context(|| format!("Parsing defmt table from {} failed", elf_path))?
+        .ok_or_else(|| {
+            anyhow::anyhow!("No .defmt section in {}; build device with defmt", elf_path)
+        })
+}
@@ -0,0 +1,98 @@
+//! Failure categories with their own process exit codes
+//!
+//! Errors stay `anyhow::Error` throughout; the places that can tell what went
+//! wrong attach an `ErrorKind` as context. `ErrorKind::of` finds it again
+//! under any context added later, so the binary can exit with a code scripts
+//! can react to. Anything uncategorized exits with 1, a command-line usage
+//! error with 2.
+
+use std::fmt;
+
+/// What kind of failure stopped the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// No (matching) debug probe is connected
+    NoProbe,
+    /// The probe could not be opened or the target not attached
+    AttachFailed,
+    /// No RTT control block was found on the target, or its channels are unusable
+    RttAttachFailed,
+    /// The config file could not be read or parsed
+    ConfigInvalid,
+    /// The device ELF needed for defmt decoding could not be read
+    ElfMissing,
+    /// The device never answered the info handshake (`handshake_give_up = "exit"`)
+    NoHandshake,
+    /// Probe I/O failed on an established link (probe unplugged, target powered off)
+    ProbeLost,
+    /// `config diff` found the device configuration differs from the baseline
+    ConfigDrift,
+    /// A sample failed an `--assert` invariant (`--assert-exit`)
+    InvariantViolated,
+}
+
+impl ErrorKind {
+    /// Exit code for a generic failure
+    pub const GENERIC_EXIT_CODE: i32 = 1;
+    /// Exit code for invalid command-line arguments
+    pub const USAGE_EXIT_CODE: i32 = 2;
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::NoProbe => 3,
+            Self::AttachFailed => 4,
+            Self::RttAttachFailed => 5,
+            Self::ConfigInvalid => 6,
+            Self::ElfMissing => 7,
+            Self::NoHandshake => 8,
+            Self::ProbeLost => 9,
+            Self::ConfigDrift => 10,
+            Self::InvariantViolated => 11,
+        }
+    }
+
+    /// The category attached anywhere in `err`'s context chain
+    pub fn of(err: &anyhow::Error) -> Option<Self> {
+        err.downcast_ref::<Self>().copied()
+    }
+
+    /// Exit code for `err`: its category's, or the generic one
+    pub fn exit_code_of(err: &anyhow::Error) -> i32 {
+        Self::of(err).map_or(Self::GENERIC_EXIT_CODE, Self::exit_code)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NoProbe => "No debug probe found",
+            Self::AttachFailed => "Failed to attach to target",
+            Self::RttAttachFailed => "Failed to attach RTT",
+            Self::ConfigInvalid => "Invalid host configuration",
+            Self::ElfMissing => "Device ELF not found",
+            Self::NoHandshake => "Device did not answer the handshake",
+            Self::ProbeLost => "Lost the connection to the probe",
+            Self::ConfigDrift => "Device config differs from the baseline",
+            Self::InvariantViolated => "A sample failed an assertion",
+        })
+    }
+}
+
+impl std::error::Error for ErrorKind {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn kind_survives_later_context() {
+        let err = Err::<(), _>(anyhow::anyhow!("timeout"))
+            .context(ErrorKind::RttAttachFailed)
+            .context("Connecting")
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&err), Some(ErrorKind::RttAttachFailed));
+        assert_eq!(ErrorKind::exit_code_of(&err), 5);
+        assert_eq!(ErrorKind::exit_code_of(&anyhow::anyhow!("other")), 1);
+    }
+}
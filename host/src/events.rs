@@ -0,0 +1,171 @@
+//! Typed device events for programs using the host as a library
+//!
+//! `OxifocDevice::events` returns a `DeviceEvents` receiver fed from the same
+//! `EventBus` publications the sinks get, so a caller can loop over
+//! `events.next().await` instead of writing a `Sink` or parsing log text.
+//! A fault is reported once when the telemetry stream first carries it, and
+//! `Disconnected` whenever an established link goes away (device reset or
+//! pump stopped).
+
+use std::collections::VecDeque;
+
+use oxifoc_protocol::{ButtonEvent, FaultKind, Telemetry};
+use tokio::sync::{broadcast, watch};
+
+use crate::link::Status;
+use crate::sink::HostEvent;
+
+/// Something the device did or reported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceEvent {
+    Button(ButtonEvent),
+    /// Decoded telemetry sample (only the fields in the device's mask)
+    Telemetry(Telemetry),
+    /// Protection fault newly seen in telemetry (needs the `fault` field streamed)
+    Fault(FaultKind),
+    /// Decoded defmt log line
+    Defmt(String),
+    /// The link went down; events resume if it is rebuilt after a reset
+    Disconnected,
+}
+
+/// Receiver of `DeviceEvent`s, see `OxifocDevice::events`
+pub struct DeviceEvents {
+    events: broadcast::Receiver<HostEvent>,
+    status: watch::Receiver<Status>,
+    translator: Translator,
+    pending: VecDeque<DeviceEvent>,
+    up: bool,
+    closed: bool,
+}
+
+impl DeviceEvents {
+    pub(crate) fn new(
+        events: broadcast::Receiver<HostEvent>,
+        status: watch::Receiver<Status>,
+    ) -> Self {
+        let up = matches!(&*status.borrow(), Status::Up(_));
+        Self {
+            events,
+            status,
+            translator: Translator::default(),
+            pending: VecDeque::new(),
+            up,
+            closed: false,
+        }
+    }
+
+    /// Wait for the next event; `None` once the device is closed
+    ///
+    /// Events published while the receiver falls more than the backlog behind
+    /// are skipped with a warning.
+    pub async fn next(&mut self) -> Option<DeviceEvent> {
+        loop {
+            if let Some(ev) = self.pending.pop_front() {
+                return Some(ev);
+            }
+            if self.closed {
+                return None;
+            }
+            tokio::select! {
+                ev = self.events.recv() => match ev {
+                    Ok(ev) => self.translator.push(ev, &mut self.pending),
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::warn!("Device event receiver lagged; {} events dropped", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => self.close(),
+                },
+                changed = self.status.changed() => match changed {
+                    Ok(()) => {
+                        let status = self.status.borrow_and_update().clone();
+                        match status {
+                            Status::Up(_) => self.up = true,
+                            Status::Reset => self.link_down(),
+                            Status::Closed(_) => self.close(),
+                            Status::Connecting => {}
+                        }
+                    }
+                    Err(_) => self.close(),
+                },
+            }
+        }
+    }
+
+    fn link_down(&mut self) {
+        if self.up {
+            self.up = false;
+            self.pending.push_back(DeviceEvent::Disconnected);
+        }
+    }
+
+    fn close(&mut self) {
+        self.link_down();
+        self.closed = true;
+    }
+}
+
+/// Turns bus events into device events, tracking the last fault seen
+#[derive(Default)]
+struct Translator {
+    fault: Option<FaultKind>,
+}
+
+impl Translator {
+    fn push(&mut self, event: HostEvent, out: &mut VecDeque<DeviceEvent>) {
+        match event {
+            HostEvent::Button(ev) => out.push_back(DeviceEvent::Button(ev)),
+            HostEvent::Defmt(line) => out.push_back(DeviceEvent::Defmt(line)),
+            HostEvent::Telemetry(t) => {
+                let fault = t.fault;
+                out.push_back(DeviceEvent::Telemetry(t));
+                // `None`: not streamed, which says nothing about faults
+                if let Some(fault) = fault {
+                    if let Some(kind) = fault
+                        && self.fault != Some(kind)
+                    {
+                        out.push_back(DeviceEvent::Fault(kind));
+                    }
+                    self.fault = fault;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn telemetry(fault: Option<Option<FaultKind>>) -> HostEvent {
+        HostEvent::Telemetry(Telemetry {
+            fault,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn fault_is_reported_once_per_occurrence() {
+        let mut t = Translator::default();
+        let mut out = VecDeque::new();
+        let faulted = Some(Some(FaultKind::Overcurrent));
+        for ev in [
+            telemetry(Some(None)),
+            telemetry(faulted),
+            telemetry(faulted),
+            telemetry(None),
+        ] {
+            t.push(ev, &mut out);
+        }
+        // Cleared by a new start, then tripped again
+        t.push(telemetry(Some(None)), &mut out);
+        t.push(telemetry(faulted), &mut out);
+        t.push(HostEvent::Defmt("hello".into()), &mut out);
+        let faults: Vec<_> = out
+            .iter()
+            .filter(|e| matches!(e, DeviceEvent::Fault(_)))
+            .collect();
+        assert_eq!(faults, [&DeviceEvent::Fault(FaultKind::Overcurrent); 2]);
+        assert_eq!(out.back(), Some(&DeviceEvent::Defmt("hello".into())));
+    }
+}
@@ -0,0 +1,121 @@
+//! Freewheel coast-down curve (`oxifoc-host freewheel`)
+//!
+//! The device spins the motor up, cuts the drive with every switch off and
+//! reports the BEMF-measured speed of the coasting rotor in telemetry (see
+//! `OxifocDevice::freewheel`). `DecayRecorder` picks the coast-down out of
+//! the telemetry stream: it starts at the first sample after the motor went
+//! from running to stopped and ends at the first one reading 0 rpm.
+//!
+//! With friction and windage roughly proportional to speed, the speed falls
+//! off exponentially, `rpm(t) = rpm0 · e^(-t/τ)`. `time_constant` fits τ to
+//! the curve by least squares on ln(rpm); together with the rotor's inertia
+//! it gives the viscous damping, and a change between runs points at a
+//! bearing going bad.
+
+use std::time::Duration;
+
+use oxifoc_protocol::{MotorState, Telemetry};
+
+/// Speed samples of one coast-down
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DecayRecorder {
+    /// Running was seen, so the next Stopped is the drive being cut
+    was_running: bool,
+    /// `host_time` of the drive cut
+    started_at: Option<Duration>,
+    /// (time since the drive cut, rpm)
+    samples: Vec<(Duration, u16)>,
+    done: bool,
+}
+
+impl DecayRecorder {
+    /// Feed one telemetry sample received at `at`; returns whether the coast-down is over
+    pub fn push(&mut self, at: Duration, t: &Telemetry) -> bool {
+        if self.done {
+            return true;
+        }
+        let Some(rpm) = t.rpm else { return false };
+        match self.started_at {
+            Some(t0) => self.samples.push((at.saturating_sub(t0), rpm)),
+            None if t.state == Some(MotorState::Running) => {
+                self.was_running = true;
+                return false;
+            }
+            None if self.was_running && t.state == Some(MotorState::Stopped) => {
+                self.started_at = Some(at);
+                self.samples.push((Duration::ZERO, rpm));
+            }
+            None => return false,
+        }
+        self.done = rpm == 0;
+        self.done
+    }
+
+    /// Whether the coast-down has started
+    pub fn started(&self) -> bool {
+        self.started_at.is_some()
+    }
+
+    /// Samples so far, as (time since the drive cut, rpm)
+    pub fn samples(&self) -> &[(Duration, u16)] {
+        &self.samples
+    }
+
+    /// Speed's exponential time constant; `None` from fewer than three moving samples
+    pub fn time_constant(&self) -> Option<Duration> {
+        let points: Vec<(f64, f64)> = self
+            .samples
+            .iter()
+            .filter(|(_, rpm)| *rpm > 0)
+            .map(|(t, rpm)| (t.as_secs_f64(), (*rpm as f64).ln()))
+            .collect();
+        if points.len() < 3 {
+            return None;
+        }
+        let n = points.len() as f64;
+        let mean_t = points.iter().map(|p| p.0).sum::<f64>() / n;
+        let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+        let cov: f64 = points
+            .iter()
+            .map(|(t, y)| (t - mean_t) * (y - mean_y))
+            .sum();
+        let var: f64 = points.iter().map(|(t, _)| (t - mean_t).powi(2)).sum();
+        let slope = cov / var;
+        (slope < 0.0).then(|| Duration::from_secs_f64(-1.0 / slope))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_coast_down_starts_at_the_drive_cut_and_fits_its_decay() {
+        let ms = Duration::from_millis;
+        let sample = |state, rpm| Telemetry {
+            state: Some(state),
+            rpm: Some(rpm),
+            ..Default::default()
+        };
+        let mut rec = DecayRecorder::default();
+        // Stopped before the spin-up is not the drive cut
+        assert!(!rec.push(ms(0), &sample(MotorState::Stopped, 0)));
+        assert!(!rec.push(ms(100), &sample(MotorState::Stopped, 0)));
+        assert!(!rec.push(ms(200), &sample(MotorState::Running, 3000)));
+        assert!(!rec.started());
+
+        // 3000 rpm decaying with τ = 0.5 s
+        for i in 0..20u64 {
+            let rpm = (3000.0 * (-(i as f64) * 0.05 / 0.5).exp()).round() as u16;
+            assert!(!rec.push(ms(1000 + i * 50), &sample(MotorState::Stopped, rpm)));
+        }
+        assert!(rec.push(ms(2000), &sample(MotorState::Stopped, 0)));
+        assert!(rec.push(ms(3000), &sample(MotorState::Running, 3000)));
+
+        assert_eq!(rec.samples().len(), 21);
+        assert_eq!(rec.samples()[0], (Duration::ZERO, 3000));
+        assert_eq!(rec.samples()[20], (ms(1000), 0));
+        let tau = rec.time_constant().unwrap().as_secs_f64();
+        assert!((tau - 0.5).abs() < 0.01, "tau {}", tau);
+    }
+}
@@ -0,0 +1,459 @@
+//! Invariants checked against every status and telemetry sample (`--assert`)
+//!
+//! For unattended soak runs: `monitor --assert "rpm < 10000, temp_c < 80,
+//! fault == None"` checks each listed expression against every sample and
+//! logs an error when one stops holding, and again when it holds once more.
+//! An expression compares one telemetry field with a constant; comparisons
+//! combine with `&&`, `||` and parentheses:
+//! - numbers: `rpm`, `duty`, `step`, `vbus_v`, `temp_c`, `current_ma`, `fan`
+//!   (duty in percent), with `< <= > >= == !=`
+//! - `limp` and `adc_fault` against `true` / `false`, `state` against a `MotorState` and
+//!   `fault` against a `FaultKind` or `None`, with `==` and `!=`
+//!
+//! A sample without one of the fields an expression reads (outside the
+//! telemetry mask, or a motor status, which carries only state, duty and
+//! step) leaves it unchecked. `Violations` counts the failed checks, so the
+//! binary can end the run on the first one (`--assert-exit`).
+
+use crate::sink::{HostEvent, Sink};
+use oxifoc_protocol::{FaultKind, MotorState, Telemetry};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::watch;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Rpm,
+    Duty,
+    Step,
+    VbusV,
+    TempC,
+    CurrentMa,
+    Fan,
+    Limp,
+    AdcFault,
+    State,
+    Fault,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "rpm" => Self::Rpm,
+            "duty" => Self::Duty,
+            "step" => Self::Step,
+            "vbus_v" => Self::VbusV,
+            "temp_c" => Self::TempC,
+            "current_ma" => Self::CurrentMa,
+            "fan" => Self::Fan,
+            "limp" => Self::Limp,
+            "adc_fault" => Self::AdcFault,
+            "state" => Self::State,
+            "fault" => Self::Fault,
+            _ => return None,
+        })
+    }
+
+    /// Constants a non-numeric field compares with, lowercased
+    fn names(self) -> Option<Vec<String>> {
+        let names = match self {
+            Self::Limp | Self::AdcFault => vec!["true".to_owned(), "false".to_owned()],
+            Self::State => [MotorState::Stopped, MotorState::Running, MotorState::Error]
+                .iter()
+                .map(|s| format!("{:?}", s).to_lowercase())
+                .collect(),
+            Self::Fault => std::iter::once("none".to_owned())
+                .chain(
+                    (1..=u8::MAX)
+                        .map_while(FaultKind::from_code)
+                        .map(|f| format!("{:?}", f).to_lowercase()),
+                )
+                .collect(),
+            _ => return None,
+        };
+        Some(names)
+    }
+
+    fn value(self, t: &Telemetry) -> Option<Value> {
+        let num = |v: Option<f64>| v.map(Value::Num);
+        match self {
+            Self::Rpm => num(t.rpm.map(f64::from)),
+            Self::Duty => num(t.duty.map(f64::from)),
+            Self::Step => num(t.step.map(f64::from)),
+            Self::VbusV => num(t.vbus_mv.map(|v| v as f64 / 1000.0)),
+            Self::TempC => num(t.temp_dc.map(|v| v as f64 / 10.0)),
+            Self::CurrentMa => num(t.current_ma.map(f64::from)),
+            Self::Fan => num(t.fan.map(f64::from)),
+            Self::Limp => t.limp.map(|v| Value::Name(v.to_string())),
+            Self::AdcFault => t.adc_fault.map(|v| Value::Name(v.to_string())),
+            Self::State => t
+                .state
+                .as_ref()
+                .map(|s| Value::Name(format!("{:?}", s).to_lowercase())),
+            Self::Fault => t.fault.map(|f| {
+                Value::Name(f.map_or("none".to_owned(), |f| format!("{:?}", f).to_lowercase()))
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Num(f64),
+    Name(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Op {
+    fn holds(self, ord: std::cmp::Ordering) -> bool {
+        use std::cmp::Ordering::*;
+        match self {
+            Self::Lt => ord == Less,
+            Self::Le => ord != Greater,
+            Self::Gt => ord == Greater,
+            Self::Ge => ord != Less,
+            Self::Eq => ord == Equal,
+            Self::Ne => ord != Equal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Compare(Field, Op, Value),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// `None` when the sample lacks a field the result depends on
+    fn eval(&self, t: &Telemetry) -> Option<bool> {
+        match self {
+            Self::Compare(field, op, rhs) => match (field.value(t)?, rhs) {
+                (Value::Num(a), Value::Num(b)) => a.partial_cmp(b).map(|ord| op.holds(ord)),
+                (Value::Name(a), Value::Name(b)) => Some(op.holds(a.cmp(b))),
+                _ => None,
+            },
+            // A known false side decides either way the other side goes
+            Self::And(a, b) => match (a.eval(t), b.eval(t)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Self::Or(a, b) => match (a.eval(t), b.eval(t)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+        }
+    }
+}
+
+fn op_is_equality(op: Op) -> bool {
+    matches!(op, Op::Eq | Op::Ne)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Num(f64),
+    Op(Op),
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut word = String::new();
+            while let Some(&c) = chars
+                .peek()
+                .filter(|c| c.is_ascii_alphanumeric() || **c == '_')
+            {
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        } else if c.is_ascii_digit() || c == '-' || c == '.' {
+            let mut num = String::new();
+            while let Some(&c) = chars
+                .peek()
+                .filter(|c| c.is_ascii_digit() || **c == '.' || **c == '-')
+            {
+                num.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Num(
+                num.parse()
+                    .map_err(|_| format!("invalid number '{}'", num))?,
+            ));
+        } else {
+            chars.next();
+            tokens.push(match c {
+                '(' => Token::Open,
+                ')' => Token::Close,
+                '<' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Le),
+                '<' => Token::Op(Op::Lt),
+                '>' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Ge),
+                '>' => Token::Op(Op::Gt),
+                '=' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Eq),
+                '!' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Ne),
+                '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+                '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+                other => return Err(format!("unexpected '{}'", other)),
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent: `or := and ('||' and)*`, `and := atom ('&&' atom)*`
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+}
+
+impl Parser {
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.atom()?;
+        while self.tokens.next_if_eq(&Token::And).is_some() {
+            expr = Expr::And(Box::new(expr), Box::new(self.atom()?));
+        }
+        Ok(expr)
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        let name = match self.tokens.next() {
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                return match self.tokens.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("missing ')'".to_owned()),
+                };
+            }
+            Some(Token::Word(name)) => name,
+            _ => return Err("expected a field name".to_owned()),
+        };
+        let field = Field::parse(&name).ok_or_else(|| format!("unknown field '{}'", name))?;
+        let Some(Token::Op(op)) = self.tokens.next() else {
+            return Err(format!("expected a comparison after '{}'", name));
+        };
+        let value = match (self.tokens.next(), field.names()) {
+            (Some(Token::Num(n)), None) => Value::Num(n),
+            (Some(Token::Word(w)), Some(names)) if op_is_equality(op) => {
+                let w = w.to_lowercase();
+                if !names.contains(&w) {
+                    return Err(format!(
+                        "'{}' can only be one of: {}",
+                        name,
+                        names.join(", ")
+                    ));
+                }
+                Value::Name(w)
+            }
+            (_, None) => return Err(format!("'{}' compares with a number", name)),
+            (_, Some(names)) => {
+                return Err(format!(
+                    "'{}' only takes == or != with one of: {}",
+                    name,
+                    names.join(", ")
+                ));
+            }
+        };
+        Ok(Expr::Compare(field, op, value))
+    }
+}
+
+/// One parsed `--assert` expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invariant {
+    text: String,
+    expr: Expr,
+}
+
+impl Invariant {
+    /// Parse a comma-separated list, each entry its own invariant
+    pub fn parse_list(list: &str) -> Result<Vec<Self>, String> {
+        list.split(',').map(str::parse).collect()
+    }
+
+    /// Whether `sample` satisfies it; `None` if the sample lacks a field it reads
+    pub fn check(&self, sample: &Telemetry) -> Option<bool> {
+        self.expr.eval(sample)
+    }
+}
+
+impl FromStr for Invariant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let text = s.trim().to_owned();
+        let mut parser = Parser {
+            tokens: tokenize(&text)?.into_iter().peekable(),
+        };
+        let expr = parser
+            .or()
+            .map_err(|e| format!("assertion '{}': {}", text, e))?;
+        if parser.tokens.next().is_some() {
+            return Err(format!("assertion '{}': unexpected text at the end", text));
+        }
+        Ok(Self { text, expr })
+    }
+}
+
+impl fmt::Display for Invariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Count of failed checks so far, shared with an `AssertSink`
+#[derive(Clone)]
+pub struct Violations(watch::Receiver<u64>);
+
+impl Violations {
+    pub fn count(&self) -> u64 {
+        *self.0.borrow()
+    }
+
+    /// Resolve once any check has failed (never, if the sink is gone first)
+    pub async fn first(&mut self) {
+        if self.0.wait_for(|&n| n > 0).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Checks every sample against the invariants and logs each change
+pub struct AssertSink {
+    invariants: Vec<Invariant>,
+    /// Consecutive failing samples of each invariant
+    failing: Vec<u64>,
+    total: watch::Sender<u64>,
+}
+
+impl AssertSink {
+    pub fn new(invariants: Vec<Invariant>) -> (Self, Violations) {
+        let (total, rx) = watch::channel(0);
+        let failing = vec![0; invariants.len()];
+        (
+            Self {
+                invariants,
+                failing,
+                total,
+            },
+            Violations(rx),
+        )
+    }
+
+    fn check(&mut self, sample: &Telemetry) {
+        for (inv, failing) in self.invariants.iter().zip(&mut self.failing) {
+            match inv.check(sample) {
+                Some(false) => {
+                    if *failing == 0 {
+                        tracing::error!(
+                            "ASSERTION FAILED: {} ({})",
+                            inv,
+                            HostEvent::Telemetry(sample.clone()).to_text()
+                        );
+                    }
+                    *failing += 1;
+                    self.total.send_modify(|n| *n += 1);
+                }
+                Some(true) if *failing > 0 => {
+                    tracing::warn!(
+                        "Assertion holds again after {} failing sample(s): {}",
+                        failing,
+                        inv
+                    );
+                    *failing = 0;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Sink for AssertSink {
+    fn handle(&mut self, _at: Duration, event: &HostEvent) {
+        match event {
+            HostEvent::Telemetry(t) => self.check(t),
+            HostEvent::MotorStatus(s) => self.check(&Telemetry {
+                state: Some(s.state.clone()),
+                duty: Some(s.duty),
+                step: Some(s.step),
+                ..Default::default()
+            }),
+            _ => {}
+        }
+    }
+
+    fn flush(&mut self) {
+        let total = *self.total.borrow();
+        if total > 0 {
+            tracing::error!("{} sample(s) failed an assertion", total);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expressions_parse_and_check_only_sampled_fields() {
+        let invs = Invariant::parse_list(
+            "rpm < 10000, temp_c < 80 && fault == None, state != error || duty <= 5",
+        )
+        .unwrap();
+        let hot = Telemetry {
+            rpm: Some(12_000),
+            temp_dc: Some(812),
+            fault: Some(None),
+            ..Default::default()
+        };
+        assert_eq!(invs[0].check(&hot), Some(false));
+        assert_eq!(invs[1].check(&hot), Some(false));
+        // Neither state nor duty sampled
+        assert_eq!(invs[2].check(&hot), None);
+
+        let ok = Telemetry {
+            rpm: Some(900),
+            temp_dc: Some(455),
+            fault: Some(None),
+            state: Some(MotorState::Error),
+            duty: Some(3),
+            ..Default::default()
+        };
+        assert!(invs.iter().all(|i| i.check(&ok) == Some(true)));
+
+        assert!("rpm << 3".parse::<Invariant>().is_err());
+        assert!("speed < 3".parse::<Invariant>().is_err());
+        assert!("fault == Melted".parse::<Invariant>().is_err());
+        assert!("state < running".parse::<Invariant>().is_err());
+        assert!("(rpm > 1".parse::<Invariant>().is_err());
+    }
+}
@@ -0,0 +1,34 @@
+//! Host-side access to an oxifoc device over a debug probe (probe-rs RTT)
+//!
+//! `OxifocDevice` is the programmatic entry point; the `oxifoc-host` binary is
+//! a command-line front end over it. Device events are delivered to the sinks
+//! of a `sink::Dispatcher`, telemetry additionally through
+//! `OxifocDevice::subscribe_telemetry`, and the common ones as typed values
+//! through `OxifocDevice::events`.
+
+pub mod bench;
+mod build_id;
+mod channels;
+pub mod config;
+pub mod device;
+pub mod error;
+pub mod events;
+pub mod freewheel;
+pub mod invariant;
+mod link;
+mod poll;
+pub mod replay;
+pub mod reset;
+pub mod schema;
+pub mod send;
+pub mod sim;
+pub mod sink;
+pub mod snapshot;
+pub mod step;
+mod throttle;
+pub mod trace;
+
+pub use config::{HandshakeGiveUp, HostConfig};
+pub use device::{ConnectOptions, OxifocDevice};
+pub use error::ErrorKind;
+pub use events::{DeviceEvent, DeviceEvents};
@@ -0,0 +1,891 @@
+//! RTT transport: probe-rs channels, the ergot stack and the I/O pump
+//!
+//! The pump owns the probe session on its own thread (probe I/O is blocking).
+//! Each link lifetime attaches RTT, builds a fresh ergot stack, serves the
+//! device-originated endpoints and shuttles frames until the session ends or
+//! the device resets. `OxifocDevice` only sees the current `Session` through a
+//! watch channel. With a dedicated pump (`poll::PollConfig::dedicated`) the
+//! servers and the handshake run on the caller's runtime instead, talking to
+//! the pump through the stack's queues.
+
+use anyhow::{Context, Result};
+use cobs_acc::{CobsAccumulator, FeedResult};
+use core::pin::pin;
+use defmt_decoder::{DecodeError, StreamDecoder, Table};
+use ergot::Address;
+use ergot::interface_manager::profiles::direct_edge::DirectEdge;
+use ergot::interface_manager::profiles::direct_edge::process_frame as ergot_edge_process_frame;
+use ergot::interface_manager::utils::cobs_stream::Sink as ErgotSink;
+use ergot::interface_manager::utils::std::StdQueue as ErgotStdQueue;
+use ergot::interface_manager::utils::std::new_std_queue;
+use ergot::interface_manager::{Interface, InterfaceState};
+use ergot::net_stack::ArcNetStack;
+use mutex::raw_impls::cs::CriticalSectionRawMutex;
+use oxifoc_protocol::constants::MAX_PACKET_SIZE;
+use oxifoc_protocol::telemetry_delta::DeltaError;
+use oxifoc_protocol::{
+    BemfChunk, BemfEndpoint, ButtonEndpoint, ButtonEvent, CoggingEndpoint, CoggingSample,
+    CommandLog, CommandLogEndpoint, ConfigEndpoint, DeviceInfo, InfoEndpoint, KeepAlive,
+    KeepAliveEndpoint, PROTOCOL_VERSION, ProtocolVersionEndpoint, Telemetry, TelemetryDecoder,
+    TelemetryEndpoint, TelemetryFrame,
+};
+use probe_rs::CoreStatus;
+use probe_rs::rtt::{Rtt, ScanRegion};
+use std::fs::File;
+use std::io::Write;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tracing::{error, info};
+
+use crate::build_id;
+use crate::channels::Selection;
+use crate::config::{Addressing, HandshakeGiveUp, HandshakePolicy, HostConfig};
+use crate::error::ErrorKind;
+use crate::poll::{Pacer, PollConfig};
+use crate::reset::{HangWatchdog, KeepaliveGaps, ResetDetector};
+use crate::sink::{EventBus, HostEvent};
+
+pub(crate) type EdgeStack = ArcNetStack<CriticalSectionRawMutex, DirectEdge<RttInterface>>;
+
+pub(crate) struct RttInterface;
+impl Interface for RttInterface {
+    type Sink = ErgotSink<ErgotStdQueue>;
+}
+
+/// Host stack plus the device's address on it
+#[derive(Clone)]
+pub(crate) struct Link {
+    pub stack: EdgeStack,
+    pub device: Address,
+}
+
+/// Timeout for one request/response exchange
+pub(crate) const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A link that completed its handshake
+#[derive(Clone)]
+pub(crate) struct Session {
+    pub link: Link,
+    pub info: Option<DeviceInfo>,
+    /// `Err` with the reason when the device's protocol version differs or is unknown
+    pub protocol: Result<(), String>,
+}
+
+/// Link state published by the pump
+#[derive(Clone)]
+pub(crate) enum Status {
+    /// Attaching RTT and running the handshake
+    Connecting,
+    Up(Session),
+    /// Device reset detected; the link is being rebuilt
+    Reset,
+    /// Pump stopped; `Err` if it failed
+    Closed(Result<(), PumpError>),
+}
+
+/// Why the pump stopped, carried from its thread to every `OxifocDevice` caller
+#[derive(Clone)]
+pub(crate) struct PumpError {
+    message: String,
+    kind: Option<ErrorKind>,
+}
+
+impl PumpError {
+    pub fn new(err: &anyhow::Error) -> Self {
+        Self {
+            message: format!("{:#}", err),
+            kind: ErrorKind::of(err),
+        }
+    }
+
+    /// The failure as an error again, still categorized
+    pub fn to_error(&self) -> anyhow::Error {
+        match self.kind {
+            Some(kind) => anyhow::Error::new(kind).context(self.message.clone()),
+            None => anyhow::anyhow!("{}", self.message),
+        }
+    }
+}
+
+/// Pump settings
+pub(crate) struct PumpOpts {
+    pub cfg: HostConfig,
+    pub defmt_table: Option<Table>,
+    /// Rebuild the link when a device reset is detected
+    pub reconnect: bool,
+    /// Reset the core through the probe when the device goes silent
+    pub reset_on_hang: bool,
+    pub handshake: HandshakePolicy,
+    /// Refuse RTT channels found by index guessing instead of by name
+    pub strict_channels: bool,
+    pub poll: PollConfig,
+    /// Runtime of the endpoint servers and the handshake; `None`: the pump's own
+    pub tasks: Option<tokio::runtime::Handle>,
+    /// Build hash from the device ELF, compared with the one the device reports
+    pub elf_build: Option<String>,
+}
+
+/// Channels between the pump and `OxifocDevice`
+pub(crate) struct PumpIo {
+    pub bus: EventBus,
+    pub telemetry: broadcast::Sender<Telemetry>,
+    pub bemf: broadcast::Sender<BemfChunk>,
+    pub status: watch::Sender<Status>,
+    pub shutdown: watch::Receiver<bool>,
+    /// Requests for a halted-core report, answered between I/O rounds
+    pub halt_requests: mpsc::UnboundedReceiver<oneshot::Sender<Option<String>>>,
+    /// Raw copies of the up channels, kept across link rebuilds
+    pub raw: RawCapture,
+}
+
+/// Why a link iteration ended
+enum LinkEnd {
+    /// Shutdown requested
+    Closed,
+    /// Device reset detected (for this reason); RTT and the ergot stack must be rebuilt
+    DeviceReset(String),
+}
+
+/// Pump state that outlives each link, so a rebuild does not reset it
+///
+/// Everything else in `run_link` belongs to one connection. Output sinks sit
+/// behind the `EventBus` and are not touched by a rebuild at all.
+struct Durable {
+    /// Kept so the reset cooldown spans rebuilds
+    watchdog: Option<HangWatchdog>,
+    keepalive_gaps: KeepaliveGaps,
+}
+
+/// Run link lifetimes until shutdown, a fatal error, or a halted core
+pub(crate) async fn pump(
+    mut session: probe_rs::Session,
+    opts: PumpOpts,
+    mut io: PumpIo,
+) -> Result<()> {
+    let mut core = session.core(0)?;
+    let rt = opts
+        .tasks
+        .clone()
+        .unwrap_or_else(tokio::runtime::Handle::current);
+    let mut durable = Durable {
+        watchdog: opts.reset_on_hang.then(|| {
+            HangWatchdog::new(
+                opts.cfg.hang_timeout(),
+                opts.cfg.hang_reset_cooldown(),
+                std::time::Instant::now(),
+            )
+        }),
+        keepalive_gaps: KeepaliveGaps::default(),
+    };
+    let result = loop {
+        match run_link(&mut core, &rt, &opts, &mut io, &mut durable).await {
+            Ok(LinkEnd::Closed) => break Ok(()),
+            Ok(LinkEnd::DeviceReset(reason)) => {
+                let _ = io.status.send(Status::Reset);
+                io.bus.publish(HostEvent::Discontinuity(reason));
+                info!("Re-attaching RTT and rebuilding the ergot link after device reset");
+            }
+            Err(e) => break Err(e),
+        }
+    };
+    if durable.keepalive_gaps.missed() > 0 {
+        info!(
+            "{} keepalives missed over the session",
+            durable.keepalive_gaps.missed()
+        );
+    }
+    result
+}
+
+/// Describe a halted core; panic-probe ends a panic in a breakpoint
+///
+/// Returns `None` while the core is running.
+fn panic_report(core: &mut probe_rs::Core<'_>, last_panic: Option<&str>) -> Option<String> {
+    let reason = match core.status() {
+        Ok(CoreStatus::Halted(reason)) => reason,
+        _ => return None,
+    };
+    let pc_reg = core.program_counter().id();
+    let pc: Option<u32> = core.read_core_reg(pc_reg).ok();
+    let pc = pc.map_or_else(|| "pc unknown".to_owned(), |pc| format!("pc=0x{:08x}", pc));
+    Some(match last_panic {
+        Some(msg) => format!("Device {} (core halted: {:?}, {})", msg, reason, pc),
+        None => format!(
+            "Device halted ({:?}, {}); no panic message captured (is defmt decoding enabled?)",
+            reason, pc
+        ),
+    })
+}
+
+/// Attach RTT, retrying while a freshly reset device sets up its control block
+async fn attach_rtt(core: &mut probe_rs::Core<'_>) -> Result<Rtt> {
+    let mut attempt = 1;
+    loop {
+        match Rtt::attach_region(core, &ScanRegion::Ram) {
+            Ok(rtt) => return Ok(rtt),
+            Err(e) if attempt < 10 => {
+                tracing::debug!("RTT attach attempt {} failed: {}", attempt, e);
+                attempt += 1;
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            Err(e) => return Err(e).context(ErrorKind::RttAttachFailed),
+        }
+    }
+}
+
+/// Build an ergot DirectEdge stack in controller mode at the configured address
+///
+/// Controller, not router: the host is directly connected to one device.
+/// Frames the stack sends are queued on the returned queue.
+pub(crate) fn new_stack(addressing: Addressing) -> (EdgeStack, ErgotStdQueue) {
+    let queue = new_std_queue(4096);
+    let stack: EdgeStack = ArcNetStack::new_with_profile(DirectEdge::new_controller(
+        ErgotSink::new_from_handle(queue.clone(), MAX_PACKET_SIZE as u16),
+        InterfaceState::Active {
+            net_id: addressing.network_id,
+            node_id: addressing.host_node,
+        },
+    ));
+    (stack, queue)
+}
+
+/// Serve the device-originated event endpoints on `stack`
+///
+/// Button, telemetry, command log and cogging scan events are published on `bus`; telemetry
+/// also goes to `telemetry`, BEMF chunks only to `bemf` and keepalives only to
+/// `keepalive`. The tasks run on `rt` until aborted.
+pub(crate) fn spawn_event_servers(
+    rt: &tokio::runtime::Handle,
+    stack: &EdgeStack,
+    bus: &EventBus,
+    telemetry: broadcast::Sender<Telemetry>,
+    bemf: broadcast::Sender<BemfChunk>,
+    keepalive: mpsc::UnboundedSender<KeepAlive>,
+) -> Vec<tokio::task::JoinHandle<()>> {
+    let mut tasks = Vec::new();
+
+    // Spawn server for device-originated button events
+    tasks.push(rt.spawn({
+        let stack = stack.clone();
+        let bus = bus.clone();
+        async move {
+            let server = stack
+                .endpoints()
+                .bounded_server::<ButtonEndpoint, 8>(Some("button"));
+            let server = pin!(server);
+            let mut h = server.attach();
+            loop {
+                let _ = h
+                    .serve(|event: &ButtonEvent| {
+                        let ev = event.clone();
+                        let bus = bus.clone();
+                        async move { bus.publish(HostEvent::Button(ev)) }
+                    })
+                    .await;
+            }
+        }
+    }));
+
+    // Spawn server for the device's telemetry stream
+    tasks.push(rt.spawn({
+        let stack = stack.clone();
+        let bus = bus.clone();
+        async move {
+            let server = stack
+                .endpoints()
+                .bounded_server::<TelemetryEndpoint, 8>(Some("telemetry"));
+            let server = pin!(server);
+            let mut h = server.attach();
+            // Delta-coded frames need the key frame before them
+            let mut decoder = TelemetryDecoder::default();
+            loop {
+                let _ = h
+                    .serve(|frame: &TelemetryFrame| {
+                        match decoder.decode(frame) {
+                            Ok(t) => {
+                                // No subscribers is fine
+                                let _ = telemetry.send(t.clone());
+                                bus.publish(HostEvent::Telemetry(t));
+                            }
+                            Err(DeltaError::MissingKey) => {
+                                tracing::debug!(
+                                    "Telemetry delta without its key frame; waiting for the next"
+                                )
+                            }
+                            Err(DeltaError::Malformed) => tracing::warn!(
+                                "Malformed telemetry frame: mask={:#06x} len={}",
+                                frame.mask,
+                                frame.data.len()
+                            ),
+                        }
+                        async {}
+                    })
+                    .await;
+            }
+        }
+    }));
+
+    // Spawn server for BEMF capture uploads
+    tasks.push(rt.spawn({
+        let stack = stack.clone();
+        async move {
+            let server = stack
+                .endpoints()
+                .bounded_server::<BemfEndpoint, 8>(Some("bemf"));
+            let server = pin!(server);
+            let mut h = server.attach();
+            loop {
+                let _ = h
+                    .serve(|chunk: &BemfChunk| {
+                        // Nobody waiting means nobody asked for this capture
+                        let _ = bemf.send(chunk.clone());
+                        async {}
+                    })
+                    .await;
+            }
+        }
+    }));
+
+    // Spawn server for cogging scan positions
+    tasks.push(rt.spawn({
+        let stack = stack.clone();
+        let bus = bus.clone();
+        async move {
+            let server = stack
+                .endpoints()
+                .bounded_server::<CoggingEndpoint, 8>(Some("cogging"));
+            let server = pin!(server);
+            let mut h = server.attach();
+            loop {
+                let _ = h
+                    .serve(|sample: &CoggingSample| {
+                        let sample = *sample;
+                        let bus = bus.clone();
+                        async move { bus.publish(HostEvent::Cogging(sample)) }
+                    })
+                    .await;
+            }
+        }
+    }));
+
+    // Spawn server for the device's command log
+    tasks.push(rt.spawn({
+        let stack = stack.clone();
+        let bus = bus.clone();
+        async move {
+            let server = stack
+                .endpoints()
+                .bounded_server::<CommandLogEndpoint, 8>(Some("command_log"));
+            let server = pin!(server);
+            let mut h = server.attach();
+            loop {
+                let _ = h
+                    .serve(|log: &CommandLog| {
+                        let log = log.clone();
+                        let bus = bus.clone();
+                        async move { bus.publish(HostEvent::CommandLog(log)) }
+                    })
+                    .await;
+            }
+        }
+    }));
+
+    // Keepalives go to the caller, which owns reset detection
+    tasks.push(rt.spawn({
+        let stack = stack.clone();
+        async move {
+            let server = stack
+                .endpoints()
+                .bounded_server::<KeepAliveEndpoint, 4>(Some("keepalive"));
+            let server = pin!(server);
+            let mut h = server.attach();
+            loop {
+                let _ = h
+                    .serve(|ka: &KeepAlive| {
+                        let _ = keepalive.send(ka.clone());
+                        async {}
+                    })
+                    .await;
+            }
+        }
+    }));
+
+    tasks
+}
+
+/// One RTT attach + ergot stack lifetime: serve events, run the handshake, pump I/O
+async fn run_link(
+    core: &mut probe_rs::Core<'_>,
+    rt: &tokio::runtime::Handle,
+    opts: &PumpOpts,
+    io: &mut PumpIo,
+    durable: &mut Durable,
+) -> Result<LinkEnd> {
+    let cfg = &opts.cfg;
+    let bus = &io.bus;
+    let _ = io.status.send(Status::Connecting);
+
+    // Set up RTT - scan entire RAM
+    let mut rtt = attach_rtt(core).await?;
+    // The new link gets a full timeout to show signs of life
+    if let Some(w) = durable.watchdog.as_mut() {
+        w.activity(std::time::Instant::now());
+    }
+    // A restarted device numbers its keepalives from 0 again
+    durable.keepalive_gaps.rebase();
+
+    info!("RTT attached successfully");
+    info!("Available RTT up channels:");
+    for (idx, channel) in rtt.up_channels().iter().enumerate() {
+        info!("  up{}: {}", idx, channel.name().unwrap_or("unnamed"));
+    }
+    info!("Available RTT down channels:");
+    for (idx, channel) in rtt.down_channels().iter().enumerate() {
+        info!("  down{}: {}", idx, channel.name().unwrap_or("unnamed"));
+    }
+
+    // Find well-known channels by name, falling back to the stock layout
+    let channels = {
+        let up: Vec<_> = rtt
+            .up_channels()
+            .iter()
+            .map(|ch| ch.name().map(str::to_owned))
+            .collect();
+        let down: Vec<_> = rtt
+            .down_channels()
+            .iter()
+            .map(|ch| ch.name().map(str::to_owned))
+            .collect();
+        let up: Vec<_> = up.iter().map(Option::as_deref).collect();
+        let down: Vec<_> = down.iter().map(Option::as_deref).collect();
+        Selection::resolve(&up, &down, cfg.stream_ergot(), cfg.stream_defmt())
+    };
+    let guesses = channels
+        .check(opts.strict_channels)
+        .map_err(|e| anyhow::anyhow!("RTT channel layout not usable: {}", e))
+        .context(ErrorKind::RttAttachFailed)?;
+    for guess in &guesses {
+        tracing::warn!(
+            "{}; if the firmware layout differs, its streams will be cross-wired",
+            guess
+        );
+    }
+    let ergot_up_idx = channels.ergot_up.map(|c| c.index);
+    let defmt_up_idx = channels.defmt_up.map(|c| c.index);
+    info!(
+        "Using channels: ergot={:?}, defmt={:?}",
+        ergot_up_idx, defmt_up_idx
+    );
+
+    let addressing = cfg.addressing;
+    info!(
+        "Addressing: network={} host={} device={}",
+        addressing.network_id, addressing.host_node, addressing.device_node
+    );
+    let (stack, queue) = new_stack(addressing);
+    let link = Link {
+        stack: stack.clone(),
+        device: addressing.device_addr(),
+    };
+
+    // Keepalives go to the I/O loop, which owns reset detection
+    let (ka_tx, mut ka_rx) = mpsc::unbounded_channel::<KeepAlive>();
+    // Tasks bound to this stack; aborted when the link ends
+    let mut tasks = spawn_event_servers(
+        rt,
+        &stack,
+        bus,
+        io.telemetry.clone(),
+        io.bemf.clone(),
+        ka_tx,
+    );
+
+    // Handshake (runs concurrently with the I/O pump below), then publish the session.
+    // A policy that gives up by exiting hands its error to the I/O loop.
+    let (handshake_err_tx, mut handshake_err_rx) = oneshot::channel::<anyhow::Error>();
+    tasks.push(rt.spawn({
+        let link = link.clone();
+        let bus = bus.clone();
+        let status = io.status.clone();
+        let policy = opts.handshake;
+        let elf_build = opts.elf_build.clone();
+        async move {
+            let info = match handshake(&link, &policy).await {
+                Ok(info) => info,
+                Err(e) => {
+                    let _ = handshake_err_tx.send(e);
+                    return;
+                }
+            };
+            if let Some(info) = &info {
+                bus.publish(HostEvent::DeviceInfo(info.clone()));
+                if let Some(elf) = &elf_build {
+                    build_id::check(elf, &info.build);
+                }
+                // One-shot snapshot of the device configuration at connect
+                match fetch_config(&link).await {
+                    Ok(c) => bus.publish(HostEvent::DeviceConfig(c)),
+                    Err(e) => tracing::warn!("{:#}", e),
+                }
+            }
+            let protocol = check_protocol(&link).await.map_err(|e| format!("{:#}", e));
+            if let Err(e) = &protocol {
+                tracing::warn!("{}", e);
+            }
+            let _ = status.send(Status::Up(Session {
+                link,
+                info,
+                protocol,
+            }));
+        }
+    }));
+
+    let mut defmt_stream: Option<Box<dyn StreamDecoder + '_>> =
+        opts.defmt_table.as_ref().map(|t| t.new_stream_decoder());
+    let mut detector = ResetDetector::new(cfg.reset_timeout());
+    // Most recent panic message seen in the defmt stream (panic-probe prints it before halting)
+    let mut last_panic: Option<String> = None;
+
+    // Main loop - read from channels (drives RTT <-> ergot)
+    let mut buf = vec![0u8; 4096];
+    let mut defbuf = vec![0u8; 2048];
+    let mut ergot_frames = ErgotFrames::new();
+    // As controller we own the network id
+    let mut net_id = Some(addressing.network_id);
+    // Downlink writer uses the queue's consumer to send frames to device via RTT down channel
+    let down_idx = channels.ergot_down.map(|c| c.index);
+    let tx_consumer = queue.stream_consumer();
+    // Nothing else runs on a dedicated pump's runtime, so it never waits on the queue
+    let mut pacer = opts
+        .poll
+        .dedicated
+        .then(|| Pacer::new(&opts.poll, std::time::Instant::now()));
+    let drain_wait = if pacer.is_some() {
+        Duration::ZERO
+    } else {
+        Duration::from_millis(1)
+    };
+    let end = loop {
+        if let Ok(e) = handshake_err_rx.try_recv() {
+            break Err(e);
+        }
+        // A failed request may be the device having panicked; answer with the core state
+        while let Ok(reply) = io.halt_requests.try_recv() {
+            let _ = reply.send(panic_report(core, last_panic.as_deref()));
+        }
+        // Keepalives: publish, and watch for a restarted sequence or silence
+        let now = std::time::Instant::now();
+        let mut reset = None;
+        while let Ok(ka) = ka_rx.try_recv() {
+            reset = reset.or(detector.observe(ka.seq, now));
+            let skipped = durable.keepalive_gaps.observe(ka.seq);
+            if skipped > 0 {
+                tracing::debug!("{} keepalives missed before seq {}", skipped, ka.seq);
+            }
+            bus.publish(HostEvent::KeepAlive(ka));
+        }
+        if let Some(signal) = reset.or_else(|| detector.poll(now)) {
+            // A halted core is not coming back; report why instead of reconnecting
+            if let Some(report) = panic_report(core, last_panic.as_deref()) {
+                break Err(anyhow::anyhow!(report));
+            }
+            if opts.reconnect {
+                tracing::warn!("Device reset detected: {}", signal);
+                break Ok(LinkEnd::DeviceReset(format!("device reset ({})", signal)));
+            }
+            tracing::warn!("Device reset suspected ({}); reconnect disabled", signal);
+            detector = ResetDetector::new(cfg.reset_timeout());
+        }
+        // A wedged device sends nothing at all; reset it through the probe
+        if let Some(silence) = durable.watchdog.as_mut().and_then(|w| w.poll(now)) {
+            if let Some(report) = panic_report(core, last_panic.as_deref()) {
+                break Err(anyhow::anyhow!(report));
+            }
+            tracing::warn!(
+                "No traffic from the device for {:.1}s; resetting it through the probe",
+                silence.as_secs_f32()
+            );
+            match core.reset() {
+                Ok(()) => {
+                    break Ok(LinkEnd::DeviceReset(format!(
+                        "device reset through the probe after {:.1}s of silence",
+                        silence.as_secs_f32()
+                    )));
+                }
+                Err(e) => tracing::error!("Probe reset failed: {}", e),
+            }
+        }
+        // Read ERGOT channel (COBS-framed)
+        if let Some(up_idx) = ergot_up_idx
+            && let Some(channel) = rtt.up_channels().get_mut(up_idx)
+        {
+            let count = match channel.read(core, &mut buf) {
+                Ok(count) => count,
+                Err(e) => break Err(anyhow::Error::from(e).context(ErrorKind::ProbeLost)),
+            };
+            if count > 0 {
+                if let Some(w) = durable.watchdog.as_mut() {
+                    w.activity(now);
+                }
+                io.raw.record_ergot(&buf[..count]);
+                ergot_frames.feed(&mut buf[..count], |frame| {
+                    // Process frame using DirectEdge (controller mode)
+                    ergot_edge_process_frame(&mut net_id, frame, &stack, ());
+                });
+            }
+        }
+        // Read DEFMT channel: capture it raw, then decode it if the ELF's table loaded.
+        // A capture needs no ELF, so it can be taken on a machine without one.
+        if let Some(up_idx) = defmt_up_idx
+            && (defmt_stream.is_some() || io.raw.defmt.is_some())
+            && let Some(channel) = rtt.up_channels().get_mut(up_idx)
+        {
+            let count = match channel.read(core, &mut defbuf) {
+                Ok(count) => count,
+                Err(e) => break Err(anyhow::Error::from(e).context(ErrorKind::ProbeLost)),
+            };
+            if count > 0 {
+                io.raw.record_defmt(&defbuf[..count]);
+                if let Some(stream) = defmt_stream.as_mut()
+                    && let Some(panic) = decode_defmt(stream.as_mut(), &defbuf[..count], bus)
+                {
+                    last_panic = Some(panic);
+                }
+            }
+        }
+        // Flush any pending outbound ergot frames from queue to RTT down channel
+        if let Some(di) = down_idx
+            && let Some(channel) = rtt.down_channels().get_mut(di)
+        {
+            // Drain as many frames as available without blocking too long
+            for _ in 0..8 {
+                match tokio::time::timeout(drain_wait, tx_consumer.wait_read()).await {
+                    Ok(frame) => {
+                        let len = frame.len();
+                        if len == 0 {
+                            break;
+                        }
+                        let data = &frame[..len];
+                        let _ = channel.write(core, data);
+                        frame.release(len);
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+        if let Some(pacer) = pacer.as_mut() {
+            if *io.shutdown.borrow() {
+                break Ok(LinkEnd::Closed);
+            }
+            pacer.wait();
+            continue;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(opts.poll.interval) => {}
+            _ = io.shutdown.wait_for(|stop| *stop) => break Ok(LinkEnd::Closed),
+        }
+    };
+
+    if let Some(pacer) = &pacer {
+        info!(
+            "RTT poll: rounds started up to {}us late",
+            pacer.worst_late().as_micros()
+        );
+    }
+    for task in tasks {
+        task.abort();
+    }
+    end
+}
+
+/// Splits the raw ergot channel byte stream into COBS-decoded frames
+///
+/// Frames may span reads; the partial tail is kept until the next `feed`.
+pub(crate) struct ErgotFrames {
+    acc: CobsAccumulator<Box<[u8]>>,
+}
+
+impl ErgotFrames {
+    pub fn new() -> Self {
+        Self {
+            acc: CobsAccumulator::new_boxslice(1024 * 4),
+        }
+    }
+
+    /// Feed one read's worth of channel bytes, calling `frame` for each complete frame
+    pub fn feed(&mut self, mut window: &mut [u8], mut frame: impl FnMut(&[u8])) {
+        while !window.is_empty() {
+            window = match self.acc.feed_raw(window) {
+                FeedResult::Consumed => break,
+                FeedResult::OverFull(new_w) => new_w,
+                FeedResult::DecodeError(new_w) => new_w,
+                FeedResult::Success { data, remaining }
+                | FeedResult::SuccessInput { data, remaining } => {
+                    frame(data);
+                    remaining
+                }
+            };
+        }
+    }
+}
+
+/// Feed defmt channel bytes to `stream` and publish every complete log line
+///
+/// Returns the last panic message among the decoded lines, if any.
+pub(crate) fn decode_defmt(
+    stream: &mut (dyn StreamDecoder + '_),
+    bytes: &[u8],
+    bus: &EventBus,
+) -> Option<String> {
+    let mut panic = None;
+    stream.received(bytes);
+    loop {
+        match stream.decode() {
+            Ok(frame) => {
+                let line = frame.display(true).to_string();
+                if let Some(idx) = line.find("panicked at") {
+                    panic = Some(line[idx..].to_owned());
+                }
+                bus.publish(HostEvent::Defmt(line));
+            }
+            Err(DecodeError::UnexpectedEof) => break,
+            Err(DecodeError::Malformed) => {
+                error!("Malformed defmt frame");
+                break;
+            }
+        }
+    }
+    panic
+}
+
+/// Files receiving the raw bytes of the RTT up channels, for offline `decode`
+#[derive(Default)]
+pub(crate) struct RawCapture {
+    pub ergot: Option<File>,
+    pub defmt: Option<File>,
+}
+
+impl RawCapture {
+    /// Create (truncating) the capture files that are given
+    pub fn create(ergot: Option<&str>, defmt: Option<&str>) -> Result<Self> {
+        let open = |path: Option<&str>| -> Result<Option<File>> {
+            path.map(|p| {
+                File::create(p).with_context(|| format!("Failed to create raw capture {}", p))
+            })
+            .transpose()
+        };
+        Ok(Self {
+            ergot: open(ergot)?,
+            defmt: open(defmt)?,
+        })
+    }
+
+    pub fn record_ergot(&mut self, bytes: &[u8]) {
+        record(&mut self.ergot, bytes, "ergot");
+    }
+
+    pub fn record_defmt(&mut self, bytes: &[u8]) {
+        record(&mut self.defmt, bytes, "defmt");
+    }
+}
+
+/// Append `bytes` to a capture file; a write error ends that capture
+fn record(file: &mut Option<File>, bytes: &[u8], channel: &str) {
+    let Some(f) = file.as_mut() else {
+        return;
+    };
+    if let Err(e) = f.write_all(bytes) {
+        tracing::warn!("Raw {} capture stopped: {}", channel, e);
+        *file = None;
+    }
+}
+
+/// Retry querying device info until it succeeds or `policy` gives up
+///
+/// `Ok(None)` means the link should come up without it, `Err` that the
+/// connection must fail (`HandshakeGiveUp::Exit`).
+async fn handshake(link: &Link, policy: &HandshakePolicy) -> Result<Option<DeviceInfo>> {
+    let mut backoff = Duration::from_millis(100);
+    let mut attempt = 1u32;
+    loop {
+        let fut =
+            link.stack
+                .endpoints()
+                .request::<InfoEndpoint>(link.device, &(), Some("device_info"));
+        match tokio::time::timeout(policy.timeout, fut).await {
+            Ok(Ok(info)) => return Ok(Some(info)),
+            // Past the attempt budget a retrying handshake stops repeating itself
+            Ok(Err(e)) if attempt <= policy.attempts => {
+                tracing::warn!("DeviceInfo attempt {} failed: {:?}", attempt, e);
+            }
+            Err(_) if attempt <= policy.attempts => {
+                tracing::warn!("DeviceInfo attempt {} timed out", attempt);
+            }
+            _ => tracing::debug!("DeviceInfo attempt {} failed", attempt),
+        }
+        if attempt == policy.attempts {
+            match policy.give_up {
+                HandshakeGiveUp::Continue => {
+                    tracing::warn!(
+                        "Device info not received after {} attempts; continuing without it",
+                        attempt
+                    );
+                    return Ok(None);
+                }
+                HandshakeGiveUp::Exit => {
+                    return Err(anyhow::anyhow!(
+                        "Device info not received after {} attempts",
+                        attempt
+                    ))
+                    .context(ErrorKind::NoHandshake);
+                }
+                HandshakeGiveUp::Retry => {
+                    tracing::warn!(
+                        "Device info not received after {} attempts; still trying",
+                        attempt
+                    );
+                }
+            }
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(Duration::from_secs(2));
+        attempt = attempt.saturating_add(1);
+    }
+}
+
+/// Query the device's full configuration
+pub(crate) async fn fetch_config(link: &Link) -> Result<oxifoc_protocol::DeviceConfig> {
+    let fut = link
+        .stack
+        .endpoints()
+        .request::<ConfigEndpoint>(link.device, &(), Some("config"));
+    tokio::time::timeout(REQUEST_TIMEOUT, fut)
+        .await
+        .context("Config request timed out")?
+        .map_err(|e| anyhow::anyhow!("Config request failed: {:?}", e))
+}
+
+/// Compare the device's protocol version with the one this host was built with
+///
+/// Differing message layouts could make a command decode as something else on
+/// the device, so an unknown version counts as a mismatch.
+async fn check_protocol(link: &Link) -> Result<()> {
+    let fut = link.stack.endpoints().request::<ProtocolVersionEndpoint>(
+        link.device,
+        &(),
+        Some("protocol_version"),
+    );
+    let device = tokio::time::timeout(REQUEST_TIMEOUT, fut)
+        .await
+        .map_err(|_| anyhow::anyhow!("Device did not report its protocol version (timed out)"))?
+        .map_err(|e| anyhow::anyhow!("Device did not report its protocol version: {:?}", e))?;
+    if device != PROTOCOL_VERSION {
+        anyhow::bail!(
+            "Protocol version mismatch: host {} vs device {}; rebuild host and firmware from the same tree",
+            PROTOCOL_VERSION,
+            device
+        );
+    }
+    info!("Protocol version {} matches device", device);
+    Ok(())
+}
@@ -1,33 +1,42 @@
 use anyhow::{Context, Result};
-use probe_rs::Permissions;
-use probe_rs::probe::list::Lister;
-use probe_rs::rtt::{Rtt, ScanRegion};
-use std::time::Duration;
-use tracing::{error, info};
-// ergot stack and helpers
-use cobs_acc::{CobsAccumulator, FeedResult};
-use core::pin::pin;
-use defmt_decoder::{DecodeError, StreamDecoder, Table};
-use ergot::interface_manager::profiles::direct_edge::process_frame as ergot_edge_process_frame;
-use ergot::interface_manager::utils::cobs_stream::Sink as ErgotSink;
-use ergot::interface_manager::utils::std::StdQueue as ErgotStdQueue;
-use ergot::interface_manager::utils::std::new_std_queue;
-use ergot::interface_manager::{Interface, InterfaceState};
-use ergot::net_stack::ArcNetStack;
-use mutex::raw_impls::cs::CriticalSectionRawMutex;
-use oxifoc_protocol::{ButtonEndpoint, ButtonEvent, MotorCommand, MotorEndpoint, MotorStatus};
-use std::fs;
+use oxifoc_host::invariant::{AssertSink, Violations};
+use oxifoc_host::sink::{
+    self, CommandCsvSink, CsvSink, Dispatcher, EventBus, HostEvent, LogFileSink, MetricsSink,
+    SessionHeader, StdoutSink,
+};
+use oxifoc_host::trace::TraceSink;
+use oxifoc_host::{ConnectOptions, ErrorKind, HostConfig, OxifocDevice, schema, snapshot};
+use oxifoc_protocol::{
+    BenchReport, BenchRequest, MotorCommand, PhaseDrive, ProfileCommand, ProfileResponse,
+    SelfTestRequest, SelfTestResult,
+};
+use tracing::info;
 
-mod config;
-use config::HostConfig;
+mod cli;
+use cli::{Cli, CommAction, Command, ConfigAction, ProfileAction};
+
+mod script;
+use script::Step;
+
+/// Stamps tracing output on the same clock as the event sinks
+struct HostTimer;
+
+impl tracing_subscriber::fmt::time::FormatTime for HostTimer {
+    fn format_time(&self, w: &mut tracing_subscriber::fmt::format::Writer<'_>) -> std::fmt::Result {
+        write!(w, "{}", sink::format_stamp(sink::host_time()))
+    }
+}
 
 fn init_tracing() {
     // Default INFO; allow override via RUST_LOG
     let filter = tracing_subscriber::EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
     // Do not install a log tracer here to avoid SetLoggerError; rely on tracing only.
+    // stderr, so stdout carries only output such as `config dump`
     let _ = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
         .with_env_filter(filter)
+        .with_timer(HostTimer)
         .with_target(true)
         .with_level(true)
         .compact()
@@ -35,348 +44,874 @@ fn init_tracing() {
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
+    let cli = match Cli::from_env() {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(ErrorKind::USAGE_EXIT_CODE);
+        }
+    };
+    // Start the shared clock before anything is logged or published
+    sink::host_time();
     init_tracing();
 
-    // Load config file
-    let cfg = HostConfig::load_default().unwrap_or_default();
-    let probe_sel = cfg.probe.clone();
-    let chip = cfg.chip.clone();
-    let elf_from_cfg = cfg.elf.clone();
-
-    info!("Oxifoc Host - RTT (chip={:?}, probe={:?})", chip, probe_sel);
-    info!("Connecting to STM32G431 via ST-Link...");
-
-    // Get list of available probes
-    let lister = Lister::new();
-    let probes = lister.list_all();
-
-    if probes.is_empty() {
-        error!("No debug probes found! Make sure ST-Link is connected.");
-        return Err(anyhow::anyhow!("No probes found"));
+    // Scripts tell failures apart by the exit code (see `ErrorKind`)
+    if let Err(e) = run(cli).await {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(ErrorKind::exit_code_of(&e));
     }
+}
 
-    info!("Found {} probe(s)", probes.len());
+/// Everything after argument parsing; sinks are flushed before it returns
+async fn run(cli: Cli) -> Result<()> {
+    // Offline commands
+    if cli.command == Command::Schema {
+        print!("{}", schema::report());
+        return Ok(());
+    }
 
-    // Open specific probe if configured, otherwise first
-    let probe = if let Some(sel) = probe_sel {
-        let mut parts = sel.split(':');
-        let vid = parts.next();
-        let pid = parts.next();
-        let serial = parts.next();
-        let chosen = probes
-            .iter()
-            .find(|p| {
-                let ok_vid = vid
-                    .and_then(|v| u16::from_str_radix(v, 16).ok())
-                    .map(|v| p.vendor_id == v)
-                    .unwrap_or(true);
-                let ok_pid = pid
-                    .and_then(|v| u16::from_str_radix(v, 16).ok())
-                    .map(|v| p.product_id == v)
-                    .unwrap_or(true);
-                let ok_ser = serial
-                    .map(|s| p.serial_number.as_deref() == Some(s))
-                    .unwrap_or(true);
-                ok_vid && ok_pid && ok_ser
-            })
-            .ok_or_else(|| anyhow::anyhow!("Configured probe not found: {}", sel))?;
-        chosen.open().context("Failed to open selected probe")?
-    } else {
-        probes[0].open().context("Failed to open probe")?
-    };
+    // Load config file
+    let cfg = HostConfig::load_default()?.unwrap_or_default();
 
-    // Attach to the target (auto-detect by default, or explicit --chip)
-    let ts = match chip {
-        Some(name) => probe_rs::config::TargetSelector::from(name),
-        None => probe_rs::config::TargetSelector::Auto,
+    // Output pipeline: CLI options override the config file
+    let mut violations = None;
+    let bus = {
+        let out = &cli.output;
+        let mut d = Dispatcher::default();
+        let header = out
+            .label
+            .as_deref()
+            .map(|l| SessionHeader::new(l, std::time::SystemTime::now()));
+        let dumping = cli.command == Command::Config(ConfigAction::Dump);
+        if cfg.stdout.unwrap_or(true) && !out.no_stdout && !dumping {
+            d.add(StdoutSink);
+        }
+        if let Some(path) = out.log_file.as_ref().or(cfg.log_file.as_ref()) {
+            let sink = LogFileSink::create(path, header.clone());
+            d.add(sink.with_context(|| format!("Failed to open log file {}", path))?);
+        }
+        if let Some(path) = out.csv.as_ref().or(cfg.csv.as_ref()) {
+            let sink = CsvSink::create(path, header.clone());
+            d.add(sink.with_context(|| format!("Failed to create CSV {}", path))?);
+        }
+        if let Some(path) = out.command_csv.as_ref().or(cfg.command_csv.as_ref()) {
+            let sink = CommandCsvSink::create(path, header);
+            d.add(sink.with_context(|| format!("Failed to create CSV {}", path))?);
+        }
+        if let Some(path) = out.trace.as_ref().or(cfg.trace.as_ref()) {
+            let sink = TraceSink::create(path);
+            d.add(sink.with_context(|| format!("Failed to create trace {}", path))?);
+        }
+        if out.metrics || cfg.metrics.unwrap_or(false) {
+            d.add(MetricsSink::default());
+        }
+        if d.is_empty() {
+            tracing::warn!("All output sinks disabled; only tracing logs will be shown");
+        }
+        if out.dedup || cfg.dedup.unwrap_or(false) {
+            d.set_dedup(
+                cfg.dedup_flush_ms
+                    .map_or(sink::DEDUP_FLUSH, std::time::Duration::from_millis),
+            );
+        }
+        if !cli.asserts.is_empty() {
+            let (sink, v) = AssertSink::new(cli.asserts.clone());
+            d.add(sink);
+            violations = Some(v);
+        }
+        d.spawn().0
     };
-    let mut session = probe
-        .attach(ts, Permissions::default())
-        .context("Failed to attach to target")?;
-
-    info!("Successfully attached to STM32G431");
-
-    // Get the core
-    let mut core = session.core(0)?;
-
-    // Set up RTT - scan entire RAM
-    let mut rtt =
-        Rtt::attach_region(&mut core, &ScanRegion::Ram).context("Failed to attach RTT")?;
 
-    info!("RTT attached successfully");
-    info!("Available RTT up channels:");
-    for (idx, channel) in rtt.up_channels().iter().enumerate() {
-        info!("  up{}: {}", idx, channel.name().unwrap_or("unnamed"));
-    }
-    info!("Available RTT down channels:");
-    for (idx, channel) in rtt.down_channels().iter().enumerate() {
-        info!("  down{}: {}", idx, channel.name().unwrap_or("unnamed"));
+    // Offline replay goes through the same sinks as a live link
+    if let Command::Decode { raw, channel, elf } = &cli.command {
+        let cfg = HostConfig {
+            elf: elf.clone().or(cfg.elf.clone()),
+            ..cfg
+        };
+        let result = oxifoc_host::replay::decode_file(&cfg, *channel, raw, &bus).await;
+        bus.flush().await;
+        return result;
     }
 
-    // Find well-known channels by name
-    let mut find_by_name = |name: &str| -> Option<usize> {
-        rtt.up_channels().iter().enumerate().find_map(|(i, ch)| {
-            if ch.name().map(|n| n == name).unwrap_or(false) {
-                Some(i)
-            } else {
-                None
-            }
-        })
+    // Parse the whole script before touching the device
+    let script = match &cli.command {
+        Command::RunScript { path } => Some(script::load(path)?),
+        _ => None,
     };
-    let ergot_up_idx = if cfg.stream_ergot() {
-        find_by_name("ergot").or(Some(1))
-    } else {
-        None
+
+    let opts = ConnectOptions {
+        require_defmt: cli.require_defmt,
+        no_reconnect: cli.no_reconnect,
+        force: cli.force,
+        wait_for_probe: cli.wait_for_probe,
+        reset_on_hang: cli.reset_on_hang,
+        handshake_attempts: cli.handshake_attempts,
+        handshake_timeout: cli
+            .handshake_timeout_ms
+            .map(std::time::Duration::from_millis),
+        handshake_give_up: cli.handshake_give_up,
+        strict_channels: cli.strict_channels,
+        rtt_thread: cli.rtt_thread,
+        rtt_core: cli.rtt_core,
+        raw_capture_ergot: cli.raw_capture_ergot.clone(),
+        raw_capture_defmt: cli.raw_capture_defmt.clone(),
+        max_command_rate: cli.max_command_rate,
+    };
+    let reconnect = cfg.reconnect_on_reset() && !opts.no_reconnect;
+    let mut device = OxifocDevice::connect(&cfg, opts.clone(), bus.clone()).await?;
+
+    let interrupted = async {
+        let _ = tokio::signal::ctrl_c().await;
+        info!("Interrupted, shutting down");
     };
-    let defmt_up_idx = if cfg.stream_defmt() {
-        find_by_name("defmt").or(Some(0))
+    tokio::pin!(interrupted);
+    let result = if cli.command == Command::Monitor {
+        // The device rebuilds its link on resets; run until Ctrl-C or a fatal error
+        loop {
+            let r = tokio::select! {
+                _ = &mut interrupted => break Ok(()),
+                _ = first_violation(&mut violations, cli.assert_exit) => break Err(violated()),
+                r = device.closed() => r,
+            };
+            let lost = match r {
+                Err(e) if reconnect && ErrorKind::of(&e) == Some(ErrorKind::ProbeLost) => e,
+                r => break r,
+            };
+            // Same bus, so the sinks and CSVs carry on with a marker in between
+            tracing::warn!("{:#}; waiting for the probe to come back", lost);
+            bus.publish(HostEvent::Discontinuity(format!(
+                "probe connection lost: {:#}",
+                lost
+            )));
+            let _ = device.close().await;
+            let opts = ConnectOptions {
+                wait_for_probe: true,
+                ..opts.clone()
+            };
+            device = tokio::select! {
+                _ = &mut interrupted => return finish(&bus, Ok(())).await,
+                r = OxifocDevice::connect(&cfg, opts, bus.clone()) => match r {
+                    Ok(device) => device,
+                    Err(e) => return finish(&bus, Err(e)).await,
+                },
+            };
+        }
     } else {
-        None
+        let command = async {
+            match &script {
+                Some(lines) => run_script(&device, &bus, lines).await,
+                None => run_command(&device, &bus, &cli.command).await,
+            }
+        };
+        tokio::select! {
+            _ = interrupted => match &script {
+                // A script cut short would leave the motor wherever it had got to
+                Some(_) => device.motor_stop().await.map(drop),
+                None => Ok(()),
+            },
+            r = command => r,
+            r = device.link_lost() => Err(r
+                .err()
+                .unwrap_or_else(|| anyhow::anyhow!("Link closed"))
+                .context("Link lost while running the command")),
+        }
     };
-    info!(
-        "Using channels: ergot={:?}, defmt={:?}",
-        ergot_up_idx, defmt_up_idx
-    );
-
-    // Build an ergot DirectEdge stack in controller mode (not router - we're directly connected to one device)
-    use ergot::interface_manager::profiles::direct_edge::DirectEdge;
-    struct RttInterface;
-    impl Interface for RttInterface {
-        type Sink = ErgotSink<ErgotStdQueue>;
+    let closed = device.close().await;
+    let result = finish(&bus, result.and(closed)).await;
+    // Every event is through the sinks now, so the count is final
+    match &violations {
+        Some(v) if cli.assert_exit && result.is_ok() && v.count() > 0 => Err(violated()),
+        _ => result,
     }
-    type EdgeProfile = DirectEdge<RttInterface>;
-    type EdgeStack = ArcNetStack<CriticalSectionRawMutex, EdgeProfile>;
-    const ERGOT_MTU: u16 = 1024;
-    let queue = new_std_queue(4096);
+}
 
-    // Create stack with DirectEdge in controller mode (network 1, node 1)
-    let stack: EdgeStack = ArcNetStack::new_with_profile(DirectEdge::new_controller(
-        ErgotSink::new_from_handle(queue.clone(), ERGOT_MTU),
-        InterfaceState::Active {
-            net_id: 1,
-            node_id: 1,
-        },
-    ));
+/// Resolve on the first failed `--assert`, if the run should end on it
+async fn first_violation(violations: &mut Option<Violations>, exit: bool) {
+    match violations {
+        Some(v) if exit => v.first().await,
+        _ => std::future::pending().await,
+    }
+}
 
-    // Spawn server for device-originated button events
-    tokio::spawn({
-        let stack = stack.clone();
-        async move {
-            let server = stack
-                .endpoints()
-                .bounded_server::<ButtonEndpoint, 8>(Some("button"));
-            let server = pin!(server);
-            let mut h = server.attach();
-            loop {
-                let _ = h
-                    .serve(|event: &ButtonEvent| {
-                        let ev = event.clone();
-                        async move {
-                            match ev {
-                                ButtonEvent::SingleClick => tracing::info!("Button: SINGLE"),
-                                ButtonEvent::DoubleClick => tracing::info!("Button: DOUBLE"),
-                                ButtonEvent::Hold => tracing::info!("Button: HOLD"),
-                            }
-                        }
-                    })
-                    .await;
-            }
-        }
-    });
+fn violated() -> anyhow::Error {
+    anyhow::Error::new(ErrorKind::InvariantViolated)
+}
 
-    // Example: Send motor commands (commented out by default)
-    // Uncomment to test motor control
-    /*
-    tokio::spawn({
-        use ergot::Address;
-        let stack = stack.clone();
-        async move {
-            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+/// Let file/CSV/metrics sinks write out everything received
+async fn finish(bus: &EventBus, result: Result<()>) -> Result<()> {
+    bus.flush().await;
+    result
+}
 
-            let device_addr = Address {
-                network_id: 1,
-                node_id: 2,
-                port_id: 0,
+/// Execute a one-shot CLI command against the device
+async fn run_command(device: &OxifocDevice, bus: &EventBus, command: &Command) -> Result<()> {
+    match command {
+        Command::Monitor => Ok(()),
+        Command::Profile(action) => run_profile(device, action).await,
+        Command::Start { duty, step } => {
+            let status = match step {
+                Some(step) => device.motor_start_at(*duty, *step).await?,
+                None => device.motor_start(*duty).await?,
             };
-
-            // Start motor at 10% duty (very conservative for first test)
-            tracing::info!("Sending motor START command (10% duty)...");
-            match stack.endpoints().request::<MotorEndpoint>(
-                device_addr,
-                &MotorCommand::Start { duty: 10 },
-                Some("motor"),
-            ).await {
-                Ok(status) => tracing::info!("Motor status: state={:?}, duty={}%, step={}",
-                    status.state, status.duty, status.step),
-                Err(e) => tracing::error!("Motor command failed: {:?}", e),
+            bus.publish(HostEvent::MotorStatus(status));
+            info!("Start at {}% accepted", duty);
+            Ok(())
+        }
+        Command::Speed { duty } => {
+            let status = device.set_speed(*duty).await?;
+            bus.publish(HostEvent::MotorStatus(status));
+            info!("Speed {}% accepted", duty);
+            Ok(())
+        }
+        Command::Stop => {
+            let status = device.motor_stop().await?;
+            bus.publish(HostEvent::MotorStatus(status));
+            info!("Stopped");
+            Ok(())
+        }
+        Command::Estop => {
+            let status = device.estop().await?;
+            bus.publish(HostEvent::MotorStatus(status));
+            info!("Emergency stop latched; arm to clear");
+            Ok(())
+        }
+        Command::Arm => {
+            let status = device.motor(&MotorCommand::Arm).await?;
+            bus.publish(HostEvent::MotorStatus(status));
+            info!("Armed");
+            Ok(())
+        }
+        Command::Disarm => {
+            let status = device.motor(&MotorCommand::Disarm).await?;
+            bus.publish(HostEvent::MotorStatus(status));
+            info!("Disarmed");
+            Ok(())
+        }
+        Command::Nudge { steps, duty } => {
+            let cmd = MotorCommand::nudge(*steps, *duty)?;
+            let status = device.motor(&cmd).await?;
+            bus.publish(HostEvent::MotorStatus(status));
+            info!("Nudge of {} steps queued", steps);
+            Ok(())
+        }
+        Command::MeasureRL => {
+            info!("Measuring phase resistance and inductance");
+            let p = device.measure_rl().await?;
+            info!(
+                "Phase resistance {} mOhm, inductance {} uH",
+                p.r_mohm, p.l_uh
+            );
+            Ok(())
+        }
+        Command::CalibrateCurrent { gain, persist } => {
+            let cal = device.calibrate_current(*gain, *persist).await?;
+            info!(
+                "Current offsets A={} B={} C={} counts, gain {}/{}{}",
+                cal.offset[0],
+                cal.offset[1],
+                cal.offset[2],
+                cal.gain_num,
+                cal.gain_den,
+                if *persist {
+                    ", saved"
+                } else {
+                    " (not persisted)"
+                }
+            );
+            Ok(())
+        }
+        Command::Reverse { dwell_ms } => {
+            let status = device.motor(&MotorCommand::reverse(*dwell_ms)?).await?;
+            bus.publish(HostEvent::MotorStatus(status));
+            info!("Reverse queued ({}ms dwell)", dwell_ms);
+            Ok(())
+        }
+        Command::Pattern(pattern) => {
+            let status = device.motor(&MotorCommand::test_pattern(*pattern)?).await?;
+            bus.publish(HostEvent::MotorStatus(status));
+            info!("Test pattern {:?} running; send stop to end it", pattern);
+            Ok(())
+        }
+        Command::PhaseDuties { a, b, c } => {
+            let status = device
+                .motor(&MotorCommand::set_phase_duties(*a, *b, *c)?)
+                .await?;
+            bus.publish(HostEvent::MotorStatus(status));
+            info!(
+                "Phase duties A={}% B={}% C={}% held; send stop to end them",
+                a, b, c
+            );
+            Ok(())
+        }
+        Command::Identify { secs } => {
+            device.identify(*secs).await?;
+            match secs {
+                0 => info!("Identify blinking stopped"),
+                secs => info!("Identifying for {}s", secs),
             }
-
-            tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-
-            // Stop motor
-            tracing::info!("Sending motor STOP command...");
-            match stack.endpoints().request::<MotorEndpoint>(
-                device_addr,
-                &MotorCommand::Stop,
-                Some("motor"),
-            ).await {
-                Ok(status) => tracing::info!("Motor status: state={:?}, duty={}%, step={}",
-                    status.state, status.duty, status.step),
-                Err(e) => tracing::error!("Motor command failed: {:?}", e),
+            Ok(())
+        }
+        Command::LogLevel(level) => {
+            device.set_log_level(*level).await?;
+            info!("Device log level set to {:?}", level);
+            Ok(())
+        }
+        Command::Telemetry { mask } => {
+            let status = device.set_telemetry_mask(*mask).await?;
+            bus.publish(HostEvent::MotorStatus(status));
+            info!("Telemetry mask set to {:#06x}", mask);
+            Ok(())
+        }
+        Command::TelemetryDelta { key_interval } => {
+            let status = device.set_telemetry_delta(*key_interval).await?;
+            bus.publish(HostEvent::MotorStatus(status));
+            match key_interval {
+                0 => info!("Telemetry delta coding disabled"),
+                n => info!("Telemetry delta coded with a key frame every {} samples", n),
+            }
+            Ok(())
+        }
+        Command::MinRpm {
+            min_rpm,
+            timeout_ms,
+        } => {
+            let status = device.set_low_speed_cutoff(*min_rpm, *timeout_ms).await?;
+            bus.publish(HostEvent::MotorStatus(status));
+            match min_rpm {
+                0 => info!("Low-speed cutoff disabled"),
+                rpm => info!("Low-speed cutoff: {} rpm for {}ms", rpm, timeout_ms),
+            }
+            Ok(())
+        }
+        Command::MaxPhaseVoltage { mv } => {
+            let status = device.set_max_phase_voltage(*mv).await?;
+            bus.publish(HostEvent::MotorStatus(status));
+            match mv {
+                0 => info!("Phase voltage limit disabled"),
+                mv => info!("Phase voltage limited to {}mV", mv),
             }
+            Ok(())
         }
-    });
-    */
-    // Handshake task: retry querying device info until it succeeds (runs concurrently with I/O pump below)
-    tokio::spawn({
-        use ergot::Address;
-        let stack = stack.clone();
-        async move {
-            let device_addr = Address {
-                network_id: 1,
-                node_id: 2,
-                port_id: 0,
+        Command::Status => {
+            let s = device.full_status().await?;
+            println!(
+                "Status at {}ms: {:?} rpm={} duty={}% step={} period={}us {:?} {:?}",
+                s.uptime_ms,
+                s.state,
+                s.rpm,
+                s.duty,
+                s.step,
+                s.commutation_period_us,
+                s.mode,
+                s.scan_scheme
+            );
+            println!(
+                "  vbus={:.2}V temp={:.1}C current={}mA",
+                s.vbus_mv as f32 / 1000.0,
+                s.temp_dc as f32 / 10.0,
+                s.current_ma
+            );
+            println!(
+                "  armed={} estop={} limp={} adc_fault={} fault={}",
+                s.armed,
+                s.estop,
+                s.limp,
+                s.adc_fault,
+                s.fault.map_or("none".to_owned(), |f| format!("{:?}", f))
+            );
+            Ok(())
+        }
+        Command::Extremes { reset } => {
+            let ext = if *reset {
+                device.reset_extremes().await?
+            } else {
+                device.extremes().await?
             };
-            let mut backoff = Duration::from_millis(100);
-            for attempt in 1..=10u32 {
-                let fut = stack.endpoints().request::<oxifoc_protocol::InfoEndpoint>(
-                    device_addr,
-                    &(),
-                    Some("device_info"),
+            if ext.samples == 0 {
+                println!("Extremes since {}ms: no readings yet", ext.since_ms);
+            } else {
+                println!(
+                    "Extremes since {}ms ({} readings):",
+                    ext.since_ms, ext.samples
+                );
+                println!("  rpm={}..{}", ext.rpm_min, ext.rpm_max);
+                println!(
+                    "  vbus={:.2}..{:.2}V temp={:.1}..{:.1}C current={}..{}mA",
+                    ext.vbus_min_mv as f32 / 1000.0,
+                    ext.vbus_max_mv as f32 / 1000.0,
+                    ext.temp_min_dc as f32 / 10.0,
+                    ext.temp_max_dc as f32 / 10.0,
+                    ext.current_min_ma,
+                    ext.current_max_ma
+                );
+            }
+            if *reset {
+                info!("Extremes reset");
+            }
+            Ok(())
+        }
+        Command::TaskStats { window_ms } => {
+            // The first query only starts the figures over
+            device.task_stats().await?;
+            tokio::time::sleep(std::time::Duration::from_millis(*window_ms)).await;
+            let stats = device.task_stats().await?;
+            println!(
+                "Task scheduling over {}ms (uptime {}ms):",
+                stats.uptime_ms.wrapping_sub(stats.since_ms),
+                stats.uptime_ms
+            );
+            let tasks = [
+                ("control", stats.control),
+                ("telemetry", stats.telemetry),
+                ("rx", stats.rx),
+                ("tx", stats.tx),
+            ];
+            for (name, t) in tasks {
+                println!(
+                    "  {:<9} {:>8} iterations, longest gap {}us",
+                    name, t.iterations, t.max_interval_us
                 );
-                match tokio::time::timeout(Duration::from_millis(800), fut).await {
-                    Ok(Ok(info)) => {
-                        let hw = info.hw.as_str();
-                        let sw = info.sw.as_str();
-                        tracing::info!("Device connected: hw='{}' sw='{}'", hw, sw);
-                        return;
-                    }
-                    Ok(Err(e)) => {
-                        tracing::warn!("DeviceInfo attempt {} failed: {:?}", attempt, e);
-                    }
-                    Err(_) => {
-                        tracing::warn!("DeviceInfo attempt {} timed out", attempt);
-                    }
-                }
-                tokio::time::sleep(backoff).await;
-                backoff = (backoff * 2).min(Duration::from_secs(2));
             }
-            tracing::warn!("Device info not received after retries; continuing without it");
+            Ok(())
         }
-    });
+        Command::Comm(CommAction::Get) => {
+            let period = device.commutation_period().await?;
+            match period.dwell_us {
+                0 => info!("Commutation period: {}us per step", period.period_us),
+                dwell => info!(
+                    "Commutation period: {}us per step (running, current step {}us)",
+                    period.period_us, dwell
+                ),
+            }
+            Ok(())
+        }
+        Command::Comm(CommAction::Set { us }) => {
+            let status = device.set_commutation_period_us(*us).await?;
+            bus.publish(HostEvent::MotorStatus(status));
+            let applied = device.commutation_period().await?.period_us;
+            if applied == *us {
+                info!("Commutation period set to {}us per step", applied);
+            } else {
+                tracing::warn!(
+                    "Commutation period {}us out of range; device applied {}us",
+                    us,
+                    applied
+                );
+            }
+            Ok(())
+        }
+        Command::Scheme(scheme) => {
+            let status = device.set_scan_scheme(*scheme).await?;
+            bus.publish(HostEvent::MotorStatus(status));
+            info!("Scan scheme set to {:?}", scheme);
+            Ok(())
+        }
+        Command::Mode(mode) => {
+            let status = device.set_commutation_mode(*mode).await?;
+            bus.publish(HostEvent::MotorStatus(status));
+            info!("Commutation mode set to {:?}", mode);
+            Ok(())
+        }
+        Command::SelfTest {
+            duty,
+            persist,
+            learn,
+        } => {
+            let req = SelfTestRequest {
+                duty: *duty,
+                persist: *persist,
+                learn: *learn,
+            };
+            run_self_test(device, &req).await
+        }
+        Command::Bemf { path, samples } => run_bemf(device, path, *samples).await,
+        Command::Cogging {
+            path,
+            current_ma,
+            settle_ms,
+        } => run_cogging(device, path, *current_ma, *settle_ms).await,
+        Command::StepTest {
+            from,
+            to,
+            hold_ms,
+            interactive,
+        } => run_step_test(device, *from, *to, *hold_ms, *interactive).await,
+        Command::Freewheel {
+            path,
+            duty,
+            spin_ms,
+            coast_ms,
+        } => run_freewheel(device, path, *duty, *spin_ms, *coast_ms).await,
+        Command::BenchThroughput {
+            duration_ms,
+            payload,
+        } => run_bench(device, *duration_ms, *payload).await,
+        // Already published at connect; nothing more to do
+        Command::Config(ConfigAction::Show) => Ok(()),
+        Command::Config(ConfigAction::Table) => run_comm_table(device).await,
+        Command::Config(ConfigAction::Dump) => {
+            print!("{}", snapshot::to_toml(&device.config().await?)?);
+            Ok(())
+        }
+        Command::Config(ConfigAction::Diff { baseline }) => run_config_diff(device, baseline).await,
+        Command::Send { endpoint, json } => {
+            let Some(entry) = oxifoc_host::send::find(endpoint) else {
+                anyhow::bail!("unknown endpoint {endpoint}");
+            };
+            let reply = entry.send(device, serde_json::from_str(json)?).await?;
+            println!("{}", serde_json::to_string_pretty(&reply)?);
+            Ok(())
+        }
+        // Handled in `run`; a script cannot list them
+        Command::Schema | Command::Decode { .. } | Command::RunScript { .. } => Ok(()),
+    }
+}
 
-    // Prepare defmt decoder (ELF path)
-    let default_elf = {
-        let p = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
-            .join("../device/target/thumbv7em-none-eabihf/release/oxifoc");
-        p.to_string_lossy().into_owned()
-    };
-    let defmt_table: Option<Table> = if defmt_up_idx.is_some() {
-        let elf_path = elf_from_cfg.unwrap_or(default_elf);
-        let elf_bytes =
-            fs::read(&elf_path).with_context(|| format!("Failed to read ELF at {}", elf_path))?;
-        Some(
-            Table::parse(&elf_bytes)
-                .context("Parsing defmt table from ELF failed")?
-                .ok_or_else(|| {
-                    anyhow::anyhow!("No .defmt section in ELF; build device with defmt")
-                })?,
-        )
-    } else {
-        None
-    };
-    let mut defmt_stream: Option<Box<dyn StreamDecoder + '_>> =
-        defmt_table.as_ref().map(|t| t.new_stream_decoder());
+/// Run a script's steps in order; a failed step stops the motor and ends the script
+async fn run_script(device: &OxifocDevice, bus: &EventBus, lines: &[script::Line]) -> Result<()> {
+    for line in lines {
+        info!("Script line {}: {}", line.number, line.text);
+        let result = match &line.step {
+            Step::Wait(d) => {
+                tokio::time::sleep(*d).await;
+                Ok(())
+            }
+            Step::Run(command) => run_command(device, bus, command).await,
+        };
+        if let Err(e) = result {
+            if let Err(stop) = device.motor_stop().await {
+                tracing::warn!("Stopping the motor after the failed line: {:#}", stop);
+            }
+            return Err(e.context(format!(
+                "Script line {} ({}) failed",
+                line.number, line.text
+            )));
+        }
+    }
+    info!("Script done: {} lines", lines.len());
+    Ok(())
+}
 
-    // Main loop - read from channels (drives RTT <-> ergot)
-    let mut buf = vec![0u8; 4096];
-    let mut defbuf = vec![0u8; 2048];
-    // Accumulator for COBS-framed ergot data across RTT reads
-    let mut cobs_acc = CobsAccumulator::new_boxslice(1024 * 4);
-    // Controller always has net_id=1
-    let mut net_id = Some(1u16);
-    // Downlink writer uses the queue's consumer to send frames to device via RTT down channel
-    let down_idx = {
-        let mut find_down = |name: &str| -> Option<usize> {
-            rtt.down_channels().iter().enumerate().find_map(|(i, ch)| {
-                if ch.name().map(|n| n == name).unwrap_or(false) {
-                    Some(i)
-                } else {
-                    None
-                }
+async fn run_config_diff(device: &OxifocDevice, path: &str) -> Result<()> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read baseline {}", path))?;
+    let baseline = snapshot::parse(&text).with_context(|| format!("Invalid baseline {}", path))?;
+    let current = snapshot::parse(&snapshot::to_toml(&device.config().await?)?)?;
+    let drift = snapshot::diff(&baseline, &current);
+    for d in &drift {
+        println!("{}", d);
+    }
+    if drift.is_empty() {
+        info!("Device config matches {}", path);
+        return Ok(());
+    }
+    Err(anyhow::anyhow!(
+        "{} field(s) differ from {}",
+        drift.len(),
+        path
+    ))
+    .context(ErrorKind::ConfigDrift)
+}
+
+async fn run_comm_table(device: &OxifocDevice) -> Result<()> {
+    let table = device.commutation_table().await?;
+    let channels = table.phase_map.channels;
+    println!("Commutation table: {:?}", table.scheme);
+    println!(
+        "step  A (CH{})    B (CH{})    C (CH{})",
+        channels[0] + 1,
+        channels[1] + 1,
+        channels[2] + 1
+    );
+    for (n, drives) in table.steps.iter().enumerate() {
+        let cells: Vec<String> = drives
+            .iter()
+            .map(|d| {
+                let cell = match d {
+                    PhaseDrive::Pwm => "PWM",
+                    PhaseDrive::Low => "low",
+                    PhaseDrive::Float => "float",
+                };
+                format!("{:<11}", cell)
             })
-        };
-        find_down("ergot-down").or(Some(0))
+            .collect();
+        println!("{:<5} {}", n, cells.concat().trim_end());
+    }
+    Ok(())
+}
+
+async fn run_bemf(device: &OxifocDevice, path: &str, samples: u16) -> Result<()> {
+    info!("Capturing {} BEMF samples", samples);
+    let capture = device.capture_bemf(samples).await?;
+    let mut csv = String::from("t_us,step,phase,mv\n");
+    for s in &capture {
+        let phase = ["A", "B", "C"]
+            .get(s.phase as usize)
+            .copied()
+            .unwrap_or("?");
+        csv.push_str(&format!("{},{},{},{}\n", s.t_us, s.step, phase, s.mv));
+    }
+    std::fs::write(path, csv).with_context(|| format!("Failed to write {}", path))?;
+    info!("Wrote {} samples to {}", capture.len(), path);
+    Ok(())
+}
+
+async fn run_cogging(
+    device: &OxifocDevice,
+    path: &str,
+    current_ma: u16,
+    settle_ms: u16,
+) -> Result<()> {
+    info!(
+        "Cogging scan at {}mA, {}ms per position",
+        current_ma, settle_ms
+    );
+    let scan = device.cogging_scan(current_ma, settle_ms).await?;
+    let mut csv = String::from("index,angle_deg,step,hall,duty_permille,current_ma\n");
+    for s in &scan {
+        let angle = s.index as f32 * 360.0 / s.total.max(1) as f32;
+        csv.push_str(&format!(
+            "{},{:.2},{},{},{},{}\n",
+            s.index, angle, s.step, s.hall, s.duty_permille, s.current_ma
+        ));
+    }
+    std::fs::write(path, csv).with_context(|| format!("Failed to write {}", path))?;
+    if let (Some(lo), Some(hi)) = (
+        scan.iter().map(|s| s.duty_permille).min(),
+        scan.iter().map(|s| s.duty_permille).max(),
+    ) {
+        info!(
+            "Hold duty {:.1}%..{:.1}% over {} positions",
+            lo as f32 / 10.0,
+            hi as f32 / 10.0,
+            scan.len()
+        );
+    }
+    info!("Wrote {} positions to {}", scan.len(), path);
+    Ok(())
+}
+
+async fn run_step_test(
+    device: &OxifocDevice,
+    from: u8,
+    to: u8,
+    hold_ms: u16,
+    interactive: bool,
+) -> Result<()> {
+    use tokio::io::AsyncBufReadExt;
+
+    let ms = |d: Option<std::time::Duration>| {
+        d.map_or("-".to_owned(), |d| format!("{}ms", d.as_millis()))
     };
-    let tx_consumer = queue.stream_consumer();
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let (mut from, mut to) = (from, to);
     loop {
-        // Read ERGOT channel (COBS-framed)
-        if let Some(up_idx) = ergot_up_idx
-            && let Some(channel) = rtt.up_channels().get_mut(up_idx)
-        {
-            let count = channel.read(&mut core, &mut buf)?;
-            if count > 0 {
-                let mut window = &mut buf[..count];
-                while !window.is_empty() {
-                    window = match cobs_acc.feed_raw(window) {
-                        FeedResult::Consumed => break,
-                        FeedResult::OverFull(new_w) => new_w,
-                        FeedResult::DecodeError(new_w) => new_w,
-                        FeedResult::Success { data, remaining }
-                        | FeedResult::SuccessInput { data, remaining } => {
-                            // Process frame using DirectEdge (controller mode)
-                            ergot_edge_process_frame(&mut net_id, data, &stack, ());
-                            remaining
-                        }
-                    };
-                }
-            }
+        info!(
+            "Step test: {}% for {}ms, then {}% for {}ms",
+            from, hold_ms, to, hold_ms
+        );
+        let capture = device.step_response(from, to, hold_ms).await?;
+        match capture.metrics() {
+            Some(m) => info!(
+                "{:.0} -> {:.0} rpm: rise {}, overshoot {:.1}%, settling {} (±{}%)",
+                m.initial_rpm,
+                m.final_rpm,
+                ms(m.rise),
+                m.overshoot_percent,
+                ms(m.settling),
+                oxifoc_host::step::SETTLE_BAND_PERCENT
+            ),
+            None => tracing::warn!(
+                "The speed barely changed over {} samples; nothing to measure",
+                capture.after.len()
+            ),
         }
-        // Read DEFMT channel and decode
-        if let (Some(up_idx), Some(stream)) = (defmt_up_idx, defmt_stream.as_mut())
-            && let Some(channel) = rtt.up_channels().get_mut(up_idx)
-        {
-            let count = channel.read(&mut core, &mut defbuf)?;
-            if count > 0 {
-                stream.received(&defbuf[..count]);
-                loop {
-                    match stream.decode() {
-                        Ok(frame) => {
-                            println!("{}", frame.display(true));
-                        }
-                        Err(DecodeError::UnexpectedEof) => break,
-                        Err(DecodeError::Malformed) => {
-                            error!("Malformed defmt frame");
-                            break;
+        if !interactive {
+            return Ok(());
+        }
+        // Next step: the same again, a new pair of duties, or done
+        loop {
+            println!(
+                "Enter: repeat {}% -> {}%; `<from> <to>`: new step; q: quit",
+                from, to
+            );
+            let Some(line) = lines.next_line().await? else {
+                return Ok(());
+            };
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                [] => break,
+                ["q"] | ["quit"] => return Ok(()),
+                [a, b] => match (a.parse::<u8>(), b.parse::<u8>()) {
+                    (Ok(a), Ok(b)) => {
+                        match MotorCommand::set_speed(a).and(MotorCommand::set_speed(b)) {
+                            Ok(_) => {
+                                (from, to) = (a, b);
+                                break;
+                            }
+                            Err(e) => println!("{}", e),
                         }
                     }
-                }
+                    _ => println!("Duties are whole percent"),
+                },
+                _ => println!("Expected two duties, an empty line or q"),
             }
         }
-        // Flush any pending outbound ergot frames from queue to RTT down channel
-        if let Some(di) = down_idx
-            && let Some(channel) = rtt.down_channels().get_mut(di)
-        {
-            // Drain as many frames as available without blocking too long
-            for _ in 0..8 {
-                match tokio::time::timeout(Duration::from_millis(1), tx_consumer.wait_read()).await
-                {
-                    Ok(frame) => {
-                        let len = frame.len();
-                        if len == 0 {
-                            break;
-                        }
-                        let data = &frame[..len];
-                        let _ = channel.write(&mut core, data);
-                        frame.release(len);
-                    }
-                    Err(_) => break,
+    }
+}
+
+async fn run_freewheel(
+    device: &OxifocDevice,
+    path: &str,
+    duty: u8,
+    spin_ms: u16,
+    coast_ms: u16,
+) -> Result<()> {
+    info!(
+        "Freewheel test at {}% for {}ms, coasting for up to {}ms",
+        duty, spin_ms, coast_ms
+    );
+    let decay = device.freewheel(duty, spin_ms, coast_ms).await?;
+    let mut csv = String::from("t_ms,rpm\n");
+    for (t, rpm) in decay.samples() {
+        csv.push_str(&format!("{:.1},{}\n", t.as_secs_f64() * 1000.0, rpm));
+    }
+    std::fs::write(path, csv).with_context(|| format!("Failed to write {}", path))?;
+    if let (Some((_, from)), Some((t, to))) = (decay.samples().first(), decay.samples().last()) {
+        info!("Coasted from {} to {} rpm in {}ms", from, to, t.as_millis());
+    }
+    match decay.time_constant() {
+        Some(tau) => info!("Decay time constant {:.0}ms", tau.as_secs_f64() * 1000.0),
+        None => tracing::warn!("Too few samples to fit the decay; is rpm in the telemetry mask?"),
+    }
+    info!("Wrote {} samples to {}", decay.samples().len(), path);
+    Ok(())
+}
+
+async fn run_bench(device: &OxifocDevice, duration_ms: u16, payload: u16) -> Result<()> {
+    info!(
+        "Benchmarking the link with {}-byte frames for {}ms",
+        payload, duration_ms
+    );
+    let (report, tally) = device
+        .bench_throughput(&BenchRequest {
+            duration_ms,
+            payload,
+        })
+        .await?;
+    let (sent, elapsed_ms, queue_full) = match report {
+        BenchReport::Done {
+            frames,
+            elapsed_ms,
+            queue_full,
+        } => (frames, elapsed_ms, queue_full),
+        BenchReport::Disarmed => anyhow::bail!("Device is not armed; run `arm` first"),
+        BenchReport::MotorRunning => {
+            anyhow::bail!("The benchmark needs the motor stopped; run `stop` first")
+        }
+    };
+    let elapsed = std::time::Duration::from_millis(elapsed_ms as u64);
+    info!(
+        "Received {} of {} frames in {}ms: {:.1} KiB/s payload, {:.0} frames/s",
+        tally.frames,
+        sent,
+        elapsed_ms,
+        tally.bytes_per_sec(elapsed) / 1024.0,
+        tally.frames as f64 / elapsed.as_secs_f64().max(1e-3)
+    );
+    info!(
+        "Lost {} frames ({:.2}%) in {} gaps; {} out of order, {} corrupt; device queue full {} times",
+        tally.lost(sent),
+        tally.loss_percent(sent),
+        tally.gaps,
+        tally.out_of_order,
+        tally.corrupt,
+        queue_full
+    );
+    Ok(())
+}
+
+async fn run_self_test(device: &OxifocDevice, req: &SelfTestRequest) -> Result<()> {
+    info!(
+        "Running direction self-test (duty={}%, persist={}, learn={})",
+        req.duty, req.persist, req.learn
+    );
+    let report = device.self_test(req).await?;
+    info!(
+        "Self-test: {:?} (observed={:?}, hall transitions={}, phase map={:?})",
+        report.result, report.observed, report.transitions, report.phase_map.channels
+    );
+    match report.result {
+        SelfTestResult::Ok => Ok(()),
+        SelfTestResult::Corrected => {
+            tracing::warn!(
+                "Rotation was reversed; device swapped phases B/C{}",
+                if req.persist {
+                    " and saved the phase map"
+                } else {
+                    " (not persisted)"
                 }
+            );
+            Ok(())
+        }
+        SelfTestResult::Disarmed => anyhow::bail!("Device is not armed; run `arm` first"),
+        SelfTestResult::Inconclusive => {
+            anyhow::bail!("Self-test inconclusive; check hall wiring and test duty")
+        }
+        SelfTestResult::Learned => {
+            info!("Hall order stored; later self-tests judge the direction against it");
+            Ok(())
+        }
+        SelfTestResult::Uncalibrated => anyhow::bail!(
+            "No hall order learned yet; run `self-test --learn` while the motor turns forward"
+        ),
+        SelfTestResult::Aborted => anyhow::bail!("Self-test aborted on the device"),
+    }
+}
+
+fn profile_name(name: &str) -> Result<heapless::String<16>> {
+    heapless::String::try_from(name)
+        .map_err(|_| anyhow::anyhow!("Profile name '{}' is longer than 16 bytes", name))
+}
+
+async fn run_profile(device: &OxifocDevice, action: &ProfileAction) -> Result<()> {
+    let cmd = match action {
+        ProfileAction::List => ProfileCommand::List,
+        ProfileAction::Save(name) => ProfileCommand::Save {
+            name: profile_name(name)?,
+        },
+        ProfileAction::Load(name) => ProfileCommand::Load {
+            name: profile_name(name)?,
+        },
+    };
+    let resp = device.profile(&cmd).await?;
+    match resp {
+        ProfileResponse::Saved => info!("Profile saved"),
+        ProfileResponse::Loaded(p) => info!(
+            "Profile '{}' loaded: pole_pairs={} kv={} pwm={}Hz dead_time={}ns max_duty={}%",
+            p.name, p.pole_pairs, p.kv, p.pwm_freq, p.dead_time_ns, p.max_duty
+        ),
+        ProfileResponse::List(profiles) => {
+            if profiles.is_empty() {
+                info!("No profiles stored");
+            }
+            for p in profiles {
+                println!(
+                    "{:<16} pole_pairs={} kv={} pwm={}Hz dead_time={}ns max_duty={}%",
+                    p.name.as_str(),
+                    p.pole_pairs,
+                    p.kv,
+                    p.pwm_freq,
+                    p.dead_time_ns,
+                    p.max_duty
+                );
             }
         }
-        tokio::time::sleep(Duration::from_millis(10)).await;
+        ProfileResponse::NotFound => anyhow::bail!("Profile not found on device"),
+        ProfileResponse::Full => anyhow::bail!("Profile table full on device"),
+        ProfileResponse::StorageError => anyhow::bail!("Device flash storage error"),
     }
+    Ok(())
 }
 
 // (no manual COBS or ad-hoc frame decoding here; ergot handles it)
@@ -0,0 +1,114 @@
+//! RTT poll pacing for the dedicated pump thread (`--rtt-thread`)
+//!
+//! By default the pump shares its runtime with the endpoint servers and the
+//! handshake, and waits between I/O rounds with `tokio::time::sleep`. That
+//! wait is at least a timer tick late and longer whenever another task runs
+//! first, which shows as uneven gaps in high-rate telemetry. A dedicated pump
+//! moves every task onto the caller's runtime, keeps only the RTT reads and
+//! writes on its thread, and paces them with `Pacer`: a plain sleep for most
+//! of the interval, then a spin over the last `spin` of it, which the OS
+//! scheduler cannot make late. The thread can also be pinned to one core.
+
+use std::time::{Duration, Instant};
+
+/// How the pump paces its I/O rounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PollConfig {
+    /// Run only RTT I/O on the pump thread, paced by a `Pacer`
+    pub dedicated: bool,
+    /// Time from the start of one round to the start of the next
+    pub interval: Duration,
+    /// Busy-wait for this final part of each interval instead of sleeping
+    pub spin: Duration,
+    /// Pin the pump thread to this CPU core
+    pub core: Option<usize>,
+}
+
+/// Fixed-rate round schedule with a hybrid sleep/spin wait
+pub(crate) struct Pacer {
+    interval: Duration,
+    spin: Duration,
+    next: Instant,
+    worst_late: Duration,
+}
+
+impl Pacer {
+    pub fn new(cfg: &PollConfig, now: Instant) -> Self {
+        Self {
+            interval: cfg.interval,
+            spin: cfg.spin.min(cfg.interval),
+            next: now + cfg.interval,
+            worst_late: Duration::ZERO,
+        }
+    }
+
+    /// Block until the next round is due; returns how late it starts
+    ///
+    /// A round that overran a whole interval restarts the schedule from now
+    /// rather than running the missed rounds back to back.
+    pub fn wait(&mut self) -> Duration {
+        let now = Instant::now();
+        if let Some(ahead) = self.next.checked_duration_since(now)
+            && ahead > self.spin
+        {
+            std::thread::sleep(ahead - self.spin);
+        }
+        while Instant::now() < self.next {
+            std::hint::spin_loop();
+        }
+        let now = Instant::now();
+        let late = now - self.next;
+        self.worst_late = self.worst_late.max(late);
+        self.next = if late >= self.interval {
+            now + self.interval
+        } else {
+            self.next + self.interval
+        };
+        late
+    }
+
+    /// Latest a round has started so far
+    pub fn worst_late(&self) -> Duration {
+        self.worst_late
+    }
+}
+
+/// Pin the calling thread to CPU `core`; false if there is no such core or the OS refused
+pub(crate) fn pin_current_thread(core: usize) -> bool {
+    let Some(id) =
+        core_affinity::get_core_ids().and_then(|ids| ids.into_iter().find(|c| c.id == core))
+    else {
+        return false;
+    };
+    core_affinity::set_for_current(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_keep_their_schedule_and_resync_after_an_overrun() {
+        let cfg = PollConfig {
+            dedicated: true,
+            interval: Duration::from_millis(5),
+            spin: Duration::from_millis(1),
+            core: None,
+        };
+        let start = Instant::now();
+        let mut pacer = Pacer::new(&cfg, start);
+        pacer.wait();
+        pacer.wait();
+        // Never early: two rounds take at least two intervals
+        assert!(start.elapsed() >= Duration::from_millis(10));
+
+        // A round longer than the interval: the next wait returns at once, then the
+        // schedule continues from there
+        std::thread::sleep(Duration::from_millis(12));
+        assert!(pacer.wait() >= Duration::from_millis(5));
+        let resynced = Instant::now();
+        pacer.wait();
+        assert!(resynced.elapsed() >= Duration::from_millis(4));
+        assert!(pacer.worst_late() >= Duration::from_millis(5));
+    }
+}
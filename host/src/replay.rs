@@ -0,0 +1,153 @@
+//! Offline decoding of raw RTT channel captures
+//!
+//! `--raw-capture-ergot` / `--raw-capture-defmt` copy the exact bytes read
+//! from an up channel to a file. `decode_file` replays such a file through the
+//! decoders a live link uses, with no probe attached: defmt bytes through the
+//! stream decoder of the device ELF's table, ergot bytes through the COBS
+//! framer into a fresh host stack serving the device's event endpoints. The
+//! events go to the sinks as usual, stamped at decode time.
+//!
+//! Responses the device was waiting for cannot reach it and are dropped, and
+//! replies to host requests find no request to complete, so only
+//! device-originated events (logs, buttons, telemetry, keepalives, command
+//! logs) come out of a replay.
+
+use anyhow::{Context, Result};
+use ergot::interface_manager::profiles::direct_edge::process_frame as ergot_edge_process_frame;
+use std::path::Path;
+use std::str::FromStr;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::config::{Addressing, HostConfig};
+use crate::device;
+use crate::link::{self, ErgotFrames};
+use crate::sink::{EventBus, HostEvent};
+
+/// Bytes handed to the decoders per step, matching the live read buffers
+const CHUNK: usize = 2048;
+
+/// Which up channel a capture was taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawChannel {
+    /// COBS-framed ergot packets
+    Ergot,
+    /// defmt log frames, decoded with the device ELF
+    Defmt,
+}
+
+impl FromStr for RawChannel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "ergot" => Ok(Self::Ergot),
+            "defmt" => Ok(Self::Defmt),
+            other => Err(format!(
+                "unknown channel '{}' (expected ergot or defmt)",
+                other
+            )),
+        }
+    }
+}
+
+/// Decode the raw `channel` capture at `path` and publish its events on `bus`
+///
+/// The defmt table and the ergot addressing come from `cfg`, so they must
+/// match the firmware that produced the capture.
+pub async fn decode_file(
+    cfg: &HostConfig,
+    channel: RawChannel,
+    path: impl AsRef<Path>,
+    bus: &EventBus,
+) -> Result<()> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)
+        .with_context(|| format!("Failed to read raw capture {}", path.display()))?;
+    match channel {
+        RawChannel::Defmt => {
+            let table = device::load_defmt_table(&device::elf_path(cfg))?;
+            let mut stream = table.new_stream_decoder();
+            for chunk in bytes.chunks(CHUNK) {
+                link::decode_defmt(stream.as_mut(), chunk, bus);
+            }
+            Ok(())
+        }
+        RawChannel::Ergot => {
+            let addressing = cfg.addressing;
+            let bus = bus.clone();
+            // Servers and the replay share one thread, so every frame is
+            // handled before the next is fed and event order is reproducible
+            tokio::task::spawn_blocking(move || {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .context("Failed to start the replay runtime")?
+                    .block_on(replay_ergot(bytes, addressing, &bus))
+            })
+            .await
+            .context("Replay thread failed")?
+        }
+    }
+}
+
+/// Feed captured ergot bytes through a host stack, one frame at a time
+async fn replay_ergot(mut bytes: Vec<u8>, addressing: Addressing, bus: &EventBus) -> Result<()> {
+    let (stack, _queue) = link::new_stack(addressing);
+    let (telemetry, _) = broadcast::channel(1);
+    let (bemf, _) = broadcast::channel(1);
+    let (ka_tx, mut ka_rx) = mpsc::unbounded_channel();
+    let rt = tokio::runtime::Handle::current();
+    let tasks = link::spawn_event_servers(&rt, &stack, bus, telemetry, bemf, ka_tx);
+    // Let every server attach before the first frame arrives
+    tokio::task::yield_now().await;
+
+    let mut frames = Vec::new();
+    let mut splitter = ErgotFrames::new();
+    for chunk in bytes.chunks_mut(CHUNK) {
+        splitter.feed(chunk, |frame| frames.push(frame.to_vec()));
+    }
+
+    // As a live controller would, we own the network id
+    let mut net_id = Some(addressing.network_id);
+    for frame in &mut frames {
+        ergot_edge_process_frame(&mut net_id, frame, &stack, ());
+        tokio::task::yield_now().await;
+        while let Ok(ka) = ka_rx.try_recv() {
+            bus.publish(HostEvent::KeepAlive(ka));
+        }
+    }
+
+    for task in tasks {
+        task.abort();
+    }
+    tracing::info!("Replayed {} ergot frames", frames.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture_frames_do_not_depend_on_read_size() {
+        // COBS encodings of [0x11, 0x22] and [0x11, 0x00, 0x22], each ending in a 0 delimiter
+        let capture = [0x03, 0x11, 0x22, 0x00, 0x02, 0x11, 0x02, 0x22, 0x00];
+        let expected = vec![vec![0x11, 0x22], vec![0x11, 0x00, 0x22]];
+        for read_size in 1..=capture.len() {
+            let mut frames = Vec::new();
+            let mut splitter = ErgotFrames::new();
+            let mut bytes = capture;
+            for chunk in bytes.chunks_mut(read_size) {
+                splitter.feed(chunk, |frame| frames.push(frame.to_vec()));
+            }
+            assert_eq!(frames, expected, "read size {}", read_size);
+        }
+    }
+
+    #[test]
+    fn channel_names_parse() {
+        assert_eq!("ergot".parse(), Ok(RawChannel::Ergot));
+        assert_eq!("defmt".parse(), Ok(RawChannel::Defmt));
+        assert!("rtt".parse::<RawChannel>().is_err());
+    }
+}
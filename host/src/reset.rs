@@ -0,0 +1,193 @@
+//! Device reset detection from the keepalive stream
+//!
+//! The device numbers its keepalives from 0 at every boot. A sequence that does
+//! not move forward means the device restarted; once keepalives have been seen,
+//! prolonged silence means it restarted and is waiting for the host to talk to
+//! it again (a target stays inactive until it receives a frame).
+//!
+//! A wedged device does neither: it stops sending without restarting.
+//! `HangWatchdog` notices that from the absence of any ergot traffic, so the
+//! host can reset the core through the probe (`--reset-on-hang`).
+
+use std::time::{Duration, Instant};
+
+/// Why a reset is suspected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetSignal {
+    /// Keepalive sequence restarted
+    SequenceRestart { last: u32, seq: u32 },
+    /// No keepalive within the timeout after the link was up
+    Silence(Duration),
+}
+
+impl std::fmt::Display for ResetSignal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SequenceRestart { last, seq } => {
+                write!(f, "keepalive sequence restarted ({} -> {})", last, seq)
+            }
+            Self::Silence(d) => write!(f, "no keepalive for {:.1}s", d.as_secs_f32()),
+        }
+    }
+}
+
+pub struct ResetDetector {
+    timeout: Duration,
+    last_seq: Option<u32>,
+    last_seen: Option<Instant>,
+}
+
+impl ResetDetector {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            last_seq: None,
+            last_seen: None,
+        }
+    }
+
+    /// Feed a received keepalive
+    pub fn observe(&mut self, seq: u32, now: Instant) -> Option<ResetSignal> {
+        let signal = match self.last_seq {
+            Some(last) if seq <= last => Some(ResetSignal::SequenceRestart { last, seq }),
+            _ => None,
+        };
+        self.last_seq = Some(seq);
+        self.last_seen = Some(now);
+        signal
+    }
+
+    /// Check for silence; only armed once a keepalive has been received
+    pub fn poll(&self, now: Instant) -> Option<ResetSignal> {
+        let since = now.duration_since(self.last_seen?);
+        (since > self.timeout).then_some(ResetSignal::Silence(since))
+    }
+}
+
+/// Keepalives lost in transit, counted over the whole session
+///
+/// Within one link a forward jump in `seq` means keepalives went missing.
+/// After a rebuild the device numbers from 0 again, so `rebase` makes the next
+/// keepalive a fresh baseline instead of a gap or a restart.
+#[derive(Default)]
+pub struct KeepaliveGaps {
+    last_seq: Option<u32>,
+    missed: u64,
+}
+
+impl KeepaliveGaps {
+    /// Feed a received keepalive; returns how many were skipped before it
+    pub fn observe(&mut self, seq: u32) -> u32 {
+        let skipped = match self.last_seq {
+            Some(last) if seq > last => seq - last - 1,
+            _ => 0,
+        };
+        self.last_seq = Some(seq);
+        self.missed += skipped as u64;
+        skipped
+    }
+
+    /// Start a new baseline (a new link)
+    pub fn rebase(&mut self) {
+        self.last_seq = None;
+    }
+
+    /// Keepalives missed so far, across all links
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+}
+
+/// Decides when a silent device should be reset through the probe
+pub struct HangWatchdog {
+    timeout: Duration,
+    cooldown: Duration,
+    last_activity: Instant,
+    last_reset: Option<Instant>,
+}
+
+impl HangWatchdog {
+    pub fn new(timeout: Duration, cooldown: Duration, now: Instant) -> Self {
+        Self {
+            timeout,
+            cooldown,
+            last_activity: now,
+            last_reset: None,
+        }
+    }
+
+    /// Record traffic from the device, or the start of a new link
+    pub fn activity(&mut self, now: Instant) {
+        self.last_activity = now;
+    }
+
+    /// Silence that warrants a reset now, if any
+    ///
+    /// Returning `Some` counts as resetting: the next reset waits for a new
+    /// timeout and at least the cooldown.
+    pub fn poll(&mut self, now: Instant) -> Option<Duration> {
+        let silence = now.duration_since(self.last_activity);
+        if silence <= self.timeout {
+            return None;
+        }
+        if let Some(t) = self.last_reset
+            && now.duration_since(t) < self.cooldown
+        {
+            return None;
+        }
+        self.last_reset = Some(now);
+        self.last_activity = now;
+        Some(silence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_restart_and_silence() {
+        let t0 = Instant::now();
+        let mut d = ResetDetector::new(Duration::from_secs(3));
+        assert_eq!(d.poll(t0 + Duration::from_secs(60)), None);
+        assert_eq!(d.observe(5, t0), None);
+        assert_eq!(d.observe(6, t0), None);
+        assert_eq!(
+            d.observe(0, t0),
+            Some(ResetSignal::SequenceRestart { last: 6, seq: 0 })
+        );
+        assert_eq!(d.poll(t0 + Duration::from_secs(2)), None);
+        assert!(matches!(
+            d.poll(t0 + Duration::from_secs(4)),
+            Some(ResetSignal::Silence(_))
+        ));
+    }
+
+    #[test]
+    fn keepalive_gaps_survive_rebase() {
+        let mut g = KeepaliveGaps::default();
+        assert_eq!(g.observe(3), 0);
+        assert_eq!(g.observe(4), 0);
+        assert_eq!(g.observe(7), 2);
+        // The device restarted and the link was rebuilt
+        g.rebase();
+        assert_eq!(g.observe(0), 0);
+        assert_eq!(g.observe(2), 1);
+        assert_eq!(g.missed(), 3);
+    }
+
+    #[test]
+    fn hang_watchdog_resets_after_silence_with_cooldown() {
+        let s = Duration::from_secs;
+        let t0 = Instant::now();
+        let mut w = HangWatchdog::new(s(10), s(30), t0);
+        assert_eq!(w.poll(t0 + s(9)), None);
+        w.activity(t0 + s(9));
+        assert_eq!(w.poll(t0 + s(18)), None);
+        assert_eq!(w.poll(t0 + s(20)), Some(s(11)));
+        // Still silent, but within the cooldown of the last reset
+        assert_eq!(w.poll(t0 + s(31)), None);
+        assert_eq!(w.poll(t0 + s(49)), None);
+        assert_eq!(w.poll(t0 + s(51)), Some(s(31)));
+    }
+}
@@ -0,0 +1,343 @@
+//! Schema report for all oxifoc endpoints (`oxifoc-host schema`)
+//!
+//! Walks the `postcard_schema::Schema` description of each request/response
+//! type and prints it with its key and encoded size bounds, so host and device
+//! builds can be compared and message sizes checked against the link MTU.
+
+use std::fmt::Write as _;
+
+use ergot::traits::{Endpoint, Topic};
+use oxifoc_protocol::constants::{MAX_PACKET_SIZE, MAX_PAYLOAD_SIZE, varint_len};
+use oxifoc_protocol::{
+    BemfEndpoint, BenchEndpoint, BenchTopic, ButtonEndpoint, CoggingEndpoint, CommandLogEndpoint,
+    CommutationPeriodEndpoint, CommutationTableEndpoint, ConfigEndpoint,
+    CurrentCalibrationEndpoint, ElectricalParamsEndpoint, ExtremesEndpoint, FullStatusEndpoint,
+    IdentifyEndpoint, InfoEndpoint, LogLevelEndpoint, MotorEndpoint, ProfileEndpoint,
+    ProtocolVersionEndpoint, ResetExtremesEndpoint, SelfTestEndpoint, TaskStatsEndpoint,
+    TelemetryEndpoint,
+};
+use postcard_schema::Schema;
+use postcard_schema::schema::{DataModelType, DataModelVariant, NamedType, NamedValue};
+
+/// Encoded size range in bytes; `max` is `None` for unbounded types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeBounds {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl SizeBounds {
+    const fn fixed(n: usize) -> Self {
+        Self {
+            min: n,
+            max: Some(n),
+        }
+    }
+
+    const fn varint(max: usize) -> Self {
+        Self {
+            min: 1,
+            max: Some(max),
+        }
+    }
+
+    /// Length-prefixed collection with unknown capacity
+    const fn unbounded() -> Self {
+        Self { min: 1, max: None }
+    }
+
+    fn then(self, other: Self) -> Self {
+        Self {
+            min: self.min + other.min,
+            max: self.max.zip(other.max).map(|(a, b)| a + b),
+        }
+    }
+}
+
+/// Encoded size bounds of a schema
+pub fn size_bounds(ty: &NamedType) -> SizeBounds {
+    match ty.ty {
+        DataModelType::Bool | DataModelType::I8 | DataModelType::U8 => SizeBounds::fixed(1),
+        DataModelType::I16 | DataModelType::U16 => SizeBounds::varint(3),
+        DataModelType::I32 | DataModelType::U32 => SizeBounds::varint(5),
+        DataModelType::I64 | DataModelType::U64 => SizeBounds::varint(10),
+        DataModelType::I128 | DataModelType::U128 => SizeBounds::varint(19),
+        DataModelType::Usize | DataModelType::Isize => SizeBounds::varint(10),
+        DataModelType::F32 => SizeBounds::fixed(4),
+        DataModelType::F64 => SizeBounds::fixed(8),
+        // Length byte plus up to 4 bytes of UTF-8
+        DataModelType::Char => SizeBounds {
+            min: 2,
+            max: Some(5),
+        },
+        DataModelType::Unit | DataModelType::UnitStruct => SizeBounds::fixed(0),
+        DataModelType::Option(inner) => SizeBounds {
+            min: 1,
+            max: size_bounds(inner).max.map(|m| m + 1),
+        },
+        DataModelType::NewtypeStruct(inner) => size_bounds(inner),
+        // Arrays are tuples in the schema
+        DataModelType::Tuple(items) | DataModelType::TupleStruct(items) => items_bounds(items),
+        DataModelType::Struct(fields) => fields_bounds(fields),
+        DataModelType::Enum(variants) => {
+            let tag = SizeBounds::varint(varint_len(variants.len().saturating_sub(1)));
+            let mut body: Option<SizeBounds> = None;
+            for v in variants.iter() {
+                let b = variant_bounds(v.ty);
+                body = Some(match body {
+                    None => b,
+                    Some(acc) => SizeBounds {
+                        min: acc.min.min(b.min),
+                        max: acc.max.zip(b.max).map(|(a, b)| a.max(b)),
+                    },
+                });
+            }
+            tag.then(body.unwrap_or(SizeBounds::fixed(0)))
+        }
+        // Strings, byte arrays, sequences, maps and nested schemas carry a length
+        // prefix; heapless capacities are not part of the schema
+        _ => SizeBounds::unbounded(),
+    }
+}
+
+fn variant_bounds(variant: &DataModelVariant) -> SizeBounds {
+    match variant {
+        DataModelVariant::UnitVariant => SizeBounds::fixed(0),
+        DataModelVariant::NewtypeVariant(ty) => size_bounds(ty),
+        DataModelVariant::TupleVariant(items) => items_bounds(items),
+        DataModelVariant::StructVariant(fields) => fields_bounds(fields),
+    }
+}
+
+fn items_bounds(items: &[&NamedType]) -> SizeBounds {
+    items
+        .iter()
+        .fold(SizeBounds::fixed(0), |acc, t| acc.then(size_bounds(t)))
+}
+
+fn fields_bounds(fields: &[&NamedValue]) -> SizeBounds {
+    fields
+        .iter()
+        .fold(SizeBounds::fixed(0), |acc, f| acc.then(size_bounds(f.ty)))
+}
+
+/// Rust-like one-line rendering of a schema
+pub fn describe(ty: &NamedType) -> String {
+    let mut out = String::new();
+    write_type(&mut out, ty);
+    out
+}
+
+fn write_type(out: &mut String, ty: &NamedType) {
+    let _ = match ty.ty {
+        DataModelType::Option(inner) => {
+            out.push_str("Option<");
+            write_type(out, inner);
+            out.push('>');
+            Ok(())
+        }
+        DataModelType::Seq(inner) => {
+            out.push_str("Seq<");
+            write_type(out, inner);
+            out.push('>');
+            Ok(())
+        }
+        // An array is a tuple of one item type
+        DataModelType::Tuple(items) if ty.name == "[T; N]" && !items.is_empty() => {
+            out.push('[');
+            write_type(out, items[0]);
+            write!(out, "; {}]", items.len())
+        }
+        DataModelType::Tuple(items) => {
+            out.push('(');
+            write_list(out, items.iter().map(|t| (None, *t)));
+            out.push(')');
+            Ok(())
+        }
+        DataModelType::Map { key, val } => {
+            out.push_str("Map<");
+            write_type(out, key);
+            out.push_str(", ");
+            write_type(out, val);
+            out.push('>');
+            Ok(())
+        }
+        DataModelType::Struct(fields) => {
+            out.push_str(ty.name);
+            write_variant(out, &DataModelVariant::StructVariant(fields));
+            Ok(())
+        }
+        DataModelType::TupleStruct(items) => {
+            out.push_str(ty.name);
+            write_variant(out, &DataModelVariant::TupleVariant(items));
+            Ok(())
+        }
+        DataModelType::NewtypeStruct(inner) => {
+            out.push_str(ty.name);
+            write_variant(out, &DataModelVariant::NewtypeVariant(inner));
+            Ok(())
+        }
+        DataModelType::UnitStruct => {
+            out.push_str(ty.name);
+            Ok(())
+        }
+        DataModelType::Enum(variants) => {
+            let _ = write!(out, "enum {} {{ ", ty.name);
+            for (i, v) in variants.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(v.name);
+                write_variant(out, v.ty);
+            }
+            out.push_str(" }");
+            Ok(())
+        }
+        DataModelType::Unit => {
+            out.push_str("()");
+            Ok(())
+        }
+        // Primitives: bool, u8, ..., string, bytearray
+        other => {
+            out.push_str(&format!("{:?}", other).to_lowercase());
+            Ok(())
+        }
+    };
+}
+
+fn write_variant(out: &mut String, variant: &DataModelVariant) {
+    match variant {
+        DataModelVariant::UnitVariant => {}
+        DataModelVariant::NewtypeVariant(ty) => {
+            out.push('(');
+            write_type(out, ty);
+            out.push(')');
+        }
+        DataModelVariant::TupleVariant(items) => {
+            out.push('(');
+            write_list(out, items.iter().map(|t| (None, *t)));
+            out.push(')');
+        }
+        DataModelVariant::StructVariant(fields) => {
+            out.push_str(" { ");
+            write_list(out, fields.iter().map(|f| (Some(f.name), f.ty)));
+            out.push_str(" }");
+        }
+    }
+}
+
+fn write_list<'a>(out: &mut String, items: impl Iterator<Item = (Option<&'a str>, &'a NamedType)>) {
+    for (i, (name, ty)) in items.enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        if let Some(name) = name {
+            out.push_str(name);
+            out.push_str(": ");
+        }
+        write_type(out, ty);
+    }
+}
+
+fn format_bounds(b: SizeBounds) -> String {
+    match b.max {
+        Some(max) if max == b.min => format!("{} B", max),
+        Some(max) => format!("{}..={} B", b.min, max),
+        None => format!("{}.. B (unbounded)", b.min),
+    }
+}
+
+fn endpoint_report<E: Endpoint>(out: &mut String, name: &str) {
+    let _ = writeln!(out, "{} ({})", E::PATH, name);
+    for (dir, ty, key) in [
+        ("request ", E::Request::SCHEMA, E::REQ_KEY),
+        ("response", E::Response::SCHEMA, E::RESP_KEY),
+    ] {
+        let bounds = size_bounds(ty);
+        let warn = match bounds.max {
+            Some(max) if max > MAX_PAYLOAD_SIZE => "  ! exceeds packet payload size",
+            _ => "",
+        };
+        let _ = writeln!(
+            out,
+            "  {}  {}  key={:?}{}\n            {}",
+            dir,
+            format_bounds(bounds),
+            key,
+            warn,
+            describe(ty)
+        );
+    }
+}
+
+fn topic_report<T: Topic>(out: &mut String, name: &str) {
+    let _ = writeln!(out, "{} ({})", T::PATH, name);
+    let bounds = size_bounds(T::Message::SCHEMA);
+    let warn = match bounds.max {
+        Some(max) if max > MAX_PAYLOAD_SIZE => "  ! exceeds packet payload size",
+        _ => "",
+    };
+    let _ = writeln!(
+        out,
+        "  message   {}  key={:?}{}\n            {}",
+        format_bounds(bounds),
+        T::TOPIC_KEY,
+        warn,
+        describe(T::Message::SCHEMA)
+    );
+}
+
+/// Schema report for every endpoint in `oxifoc-protocol`
+pub fn report() -> String {
+    let mut out = String::new();
+    endpoint_report::<ButtonEndpoint>(&mut out, "ButtonEndpoint");
+    endpoint_report::<InfoEndpoint>(&mut out, "InfoEndpoint");
+    endpoint_report::<IdentifyEndpoint>(&mut out, "IdentifyEndpoint");
+    endpoint_report::<LogLevelEndpoint>(&mut out, "LogLevelEndpoint");
+    endpoint_report::<ProtocolVersionEndpoint>(&mut out, "ProtocolVersionEndpoint");
+    endpoint_report::<MotorEndpoint>(&mut out, "MotorEndpoint");
+    endpoint_report::<ProfileEndpoint>(&mut out, "ProfileEndpoint");
+    endpoint_report::<SelfTestEndpoint>(&mut out, "SelfTestEndpoint");
+    endpoint_report::<ElectricalParamsEndpoint>(&mut out, "ElectricalParamsEndpoint");
+    endpoint_report::<CurrentCalibrationEndpoint>(&mut out, "CurrentCalibrationEndpoint");
+    endpoint_report::<ConfigEndpoint>(&mut out, "ConfigEndpoint");
+    endpoint_report::<FullStatusEndpoint>(&mut out, "FullStatusEndpoint");
+    endpoint_report::<CommutationTableEndpoint>(&mut out, "CommutationTableEndpoint");
+    endpoint_report::<CommutationPeriodEndpoint>(&mut out, "CommutationPeriodEndpoint");
+    endpoint_report::<ExtremesEndpoint>(&mut out, "ExtremesEndpoint");
+    endpoint_report::<ResetExtremesEndpoint>(&mut out, "ResetExtremesEndpoint");
+    endpoint_report::<TelemetryEndpoint>(&mut out, "TelemetryEndpoint");
+    endpoint_report::<BemfEndpoint>(&mut out, "BemfEndpoint");
+    endpoint_report::<CoggingEndpoint>(&mut out, "CoggingEndpoint");
+    endpoint_report::<CommandLogEndpoint>(&mut out, "CommandLogEndpoint");
+    endpoint_report::<BenchEndpoint>(&mut out, "BenchEndpoint");
+    endpoint_report::<TaskStatsEndpoint>(&mut out, "TaskStatsEndpoint");
+    topic_report::<BenchTopic>(&mut out, "BenchTopic");
+    let _ = writeln!(
+        out,
+        "Sizes are postcard payload bounds; packets are limited to {} B including the ergot header ({} B payload).",
+        MAX_PACKET_SIZE, MAX_PAYLOAD_SIZE
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oxifoc_protocol::{MotorStatus, PhaseMap};
+
+    #[test]
+    fn fixed_size_types_have_exact_bounds() {
+        // state tag + duty + step + rejection (None, or tag + u16 varint)
+        assert_eq!(
+            size_bounds(MotorStatus::SCHEMA),
+            SizeBounds {
+                min: 4,
+                max: Some(8)
+            }
+        );
+        assert_eq!(size_bounds(PhaseMap::SCHEMA), SizeBounds::fixed(3));
+        assert_eq!(varint_len(127), 1);
+        assert_eq!(varint_len(128), 2);
+    }
+}
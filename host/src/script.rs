@@ -0,0 +1,135 @@
+//! Command scripts (`oxifoc-host run-script <file>`)
+//!
+//! A script lists CLI commands one per line, in the CLI's own syntax
+//! (`start 20`, `speed 40`, `start 8 --step 3`), with `wait <ms>` pausing
+//! between them and `#` starting a comment. The whole file is parsed before
+//! the device is touched, so a typo near the end cannot leave the motor
+//! running after the lines before it. Options that apply to a whole run
+//! (`--csv`, `--force`, ...) go on the `run-script` command line.
+
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::cli::{Command, ConfigAction};
+
+/// What one script line does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Wait(Duration),
+    Run(Command),
+}
+
+/// One parsed script line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    /// Line number in the file, from 1
+    pub number: usize,
+    /// The line as written, without its comment
+    pub text: String,
+    pub step: Step,
+}
+
+/// A line that does not parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// Read and parse the script at `path`
+pub fn load(path: &str) -> anyhow::Result<Vec<Line>> {
+    let text =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read script {}", path))?;
+    parse(&text).with_context(|| format!("Invalid script {}", path))
+}
+
+/// Parse a script; blank and comment-only lines are skipped
+pub fn parse(text: &str) -> Result<Vec<Line>, ScriptError> {
+    let mut lines = Vec::new();
+    for (i, raw) in text.lines().enumerate() {
+        let text = raw.split('#').next().unwrap_or_default().trim();
+        if text.is_empty() {
+            continue;
+        }
+        let fail = |message: String| ScriptError {
+            line: i + 1,
+            message,
+        };
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let step = match words.as_slice() {
+            ["wait", ms] => Step::Wait(Duration::from_millis(
+                ms.parse()
+                    .map_err(|_| fail(format!("invalid wait '{}'", ms)))?,
+            )),
+            ["wait", ..] => return Err(fail("wait takes one time in milliseconds".into())),
+            _ => Step::Run(command(&words).map_err(fail)?),
+        };
+        lines.push(Line {
+            number: i + 1,
+            text: text.to_owned(),
+            step,
+        });
+    }
+    Ok(lines)
+}
+
+fn command(words: &[&str]) -> Result<Command, String> {
+    match Command::parse(words.iter().copied()) {
+        Ok(
+            Command::Monitor | Command::Schema | Command::Decode { .. } | Command::RunScript { .. },
+        )
+        | Ok(Command::Config(ConfigAction::Dump)) => {
+            Err(format!("'{}' cannot run in a script", words.join(" ")))
+        }
+        Ok(command) => Ok(command),
+        // An empty message is the CLI asking for its usage text
+        Err(e) if e.0.is_empty() => Err(format!("'{}' is not a command", words.join(" "))),
+        Err(e) => Err(e.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands_and_waits_and_points_at_bad_lines() {
+        let script = parse(
+            "# spin-up check\narm\nstart 20 --step 3\n\nwait 2000  # settle\nspeed 40\nstop\n",
+        )
+        .unwrap();
+        let steps: Vec<(usize, &Step)> = script.iter().map(|l| (l.number, &l.step)).collect();
+        assert_eq!(
+            steps,
+            [
+                (2, &Step::Run(Command::Arm)),
+                (
+                    3,
+                    &Step::Run(Command::Start {
+                        duty: 20,
+                        step: Some(3)
+                    })
+                ),
+                (5, &Step::Wait(Duration::from_millis(2000))),
+                (6, &Step::Run(Command::Speed { duty: 40 })),
+                (7, &Step::Run(Command::Stop)),
+            ]
+        );
+        assert_eq!(script[2].text, "wait 2000");
+
+        assert_eq!(parse("stop\nwait soon\n").unwrap_err().line, 2);
+        assert!(parse("start 20 --csv out.csv\n").is_err());
+        assert!(parse("run-script other.txt\n").is_err());
+        assert!(parse("spin\n").is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Requests to any device endpoint from JSON (`oxifoc-host send`)
+//!
+//! Every endpoint in `ENDPOINTS` is tied to its request and response types.
+//! The JSON argument is deserialized into the request with serde, sent as
+//! postcard like any other request, and the response is serialized back to
+//! JSON. An endpoint added to the protocol is reachable once it has a line in
+//! `ENDPOINTS`, without a command of its own.
+//!
+//! Enums are externally tagged and `()` is `null`:
+//!
+//! ```text
+//! oxifoc-host send req/full_status
+//! oxifoc-host send cmd/identify 5
+//! oxifoc-host send cmd/motor '{"cmd": {"Start": {"duty": 5, "step": null}}, "trace": null}'
+//! ```
+
+use std::future::Future;
+use std::pin::Pin;
+
+use anyhow::{Context, Result};
+use ergot::traits::Endpoint;
+use oxifoc_protocol::{
+    BenchEndpoint, CommutationPeriodEndpoint, CommutationTableEndpoint, ConfigEndpoint,
+    CurrentCalibrationEndpoint, ElectricalParamsEndpoint, ExtremesEndpoint, FullStatusEndpoint,
+    IdentifyEndpoint, InfoEndpoint, LogLevelEndpoint, MotorEndpoint, ProfileEndpoint,
+    ProtocolVersionEndpoint, ResetExtremesEndpoint, SelfTestEndpoint, TaskStatsEndpoint,
+};
+use postcard_schema::Schema;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::OxifocDevice;
+use crate::schema::describe;
+
+type Reply<'a> = Pin<Box<dyn Future<Output = Result<Value>> + 'a>>;
+
+/// An endpoint `send` can reach
+pub struct SendEntry {
+    /// Endpoint path, e.g. `req/full_status`
+    pub path: &'static str,
+    /// A request can move the motor, so a protocol mismatch refuses it
+    pub motion: bool,
+    send: for<'a> fn(&'a OxifocDevice, Value) -> Reply<'a>,
+}
+
+impl SendEntry {
+    /// Send `json` as this endpoint's request; returns the response as JSON
+    pub async fn send(&self, device: &OxifocDevice, json: Value) -> Result<Value> {
+        (self.send)(device, json).await
+    }
+}
+
+macro_rules! entry {
+    ($ep:ty, $motion:literal) => {
+        SendEntry {
+            path: <$ep as Endpoint>::PATH,
+            motion: $motion,
+            send: send_json::<$ep, $motion>,
+        }
+    };
+}
+
+/// Every endpoint the device serves; the `event/` ones go the other way
+pub const ENDPOINTS: &[SendEntry] = &[
+    entry!(InfoEndpoint, false),
+    entry!(IdentifyEndpoint, false),
+    entry!(LogLevelEndpoint, false),
+    entry!(ProtocolVersionEndpoint, false),
+    entry!(MotorEndpoint, true),
+    entry!(ProfileEndpoint, true),
+    entry!(SelfTestEndpoint, true),
+    entry!(ElectricalParamsEndpoint, false),
+    entry!(CurrentCalibrationEndpoint, false),
+    entry!(ConfigEndpoint, false),
+    entry!(FullStatusEndpoint, false),
+    entry!(CommutationTableEndpoint, false),
+    entry!(CommutationPeriodEndpoint, false),
+    entry!(ExtremesEndpoint, false),
+    entry!(ResetExtremesEndpoint, false),
+    entry!(BenchEndpoint, false),
+    entry!(TaskStatsEndpoint, false),
+];
+
+/// Entry for `name`: a full path (`req/full_status`) or its last segment (`full_status`)
+pub fn find(name: &str) -> Option<&'static SendEntry> {
+    ENDPOINTS
+        .iter()
+        .find(|e| e.path == name || e.path.rsplit('/').next() == Some(name))
+}
+
+/// Request of endpoint `E` from JSON; the error names the expected layout
+pub fn decode_request<E: Endpoint>(json: Value) -> Result<E::Request>
+where
+    E::Request: DeserializeOwned,
+{
+    serde_json::from_value(json)
+        .with_context(|| format!("{} expects {}", E::PATH, describe(E::Request::SCHEMA)))
+}
+
+fn send_json<E, const MOTION: bool>(device: &OxifocDevice, json: Value) -> Reply<'_>
+where
+    E: Endpoint,
+    E::Request: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    E::Response: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    Box::pin(async move {
+        let req = decode_request::<E>(json)?;
+        let resp = device.request::<E>(&req, MOTION).await?;
+        Ok(serde_json::to_value(resp)?)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oxifoc_protocol::{MotorCommand, MotorRequest};
+
+    #[test]
+    fn endpoints_resolve_by_path_or_name_and_decode_json() {
+        assert_eq!(find("req/full_status").unwrap().path, "req/full_status");
+        assert_eq!(find("motor").unwrap().path, "cmd/motor");
+        assert!(find("motor").unwrap().motion);
+        assert!(find("event/telemetry").is_none());
+        for (n, e) in ENDPOINTS.iter().enumerate() {
+            assert!(
+                ENDPOINTS[..n].iter().all(|o| o.path != e.path),
+                "{} listed twice",
+                e.path
+            );
+        }
+
+        let json =
+            serde_json::json!({ "cmd": { "Start": { "duty": 5, "step": null } }, "trace": 7 });
+        let req = decode_request::<MotorEndpoint>(json).unwrap();
+        assert!(matches!(
+            req,
+            MotorRequest {
+                cmd: MotorCommand::Start {
+                    duty: 5,
+                    step: None
+                },
+                trace: Some(7)
+            }
+        ));
+        assert!(decode_request::<FullStatusEndpoint>(Value::Null).is_ok());
+        let err = decode_request::<IdentifyEndpoint>(serde_json::json!("soon")).unwrap_err();
+        assert!(
+            err.to_string().starts_with("cmd/identify expects"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Simulated BLDC motor for tuning control logic without hardware
+//!
+//! A lumped model of a 6-step driven motor: the energized phase pair is one
+//! R-L winding whose field points at `step × 60°` electrical, and both torque
+//! and back-EMF follow `sin(field − rotor)`, so an aligned step holds the rotor
+//! and a leading step pulls it forward.
+//!
+//! - electrical: `L di/dt = duty·Vbus − R·i − Kt·ω·sin(δ)`
+//! - mechanical: `J dω/dt = Kt·i·sin(δ) − B·ω − Tc·sign(ω)`
+//!
+//! `Kt` follows from the KV rating; floating phases carry no current. The
+//! remaining constants default to the ZD2808 700KV motor on the B-G431B-ESC1.
+
+use std::f32::consts::{PI, TAU};
+
+use oxifoc_protocol::MotorProfile;
+
+/// Plant constants in SI units
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimParams {
+    pub pole_pairs: u8,
+    /// Torque constant (equal to the back-EMF constant), N·m/A
+    pub kt: f32,
+    /// Phase-to-phase resistance, Ω
+    pub resistance: f32,
+    /// Phase-to-phase inductance, H
+    pub inductance: f32,
+    /// Rotor inertia, kg·m²
+    pub inertia: f32,
+    /// Viscous friction, N·m·s/rad
+    pub viscous: f32,
+    /// Coulomb friction, N·m
+    pub coulomb: f32,
+    /// Bus voltage, V
+    pub vbus: f32,
+}
+
+impl SimParams {
+    /// Default plant for a motor with `pole_pairs` and `kv` (rpm/V)
+    pub fn for_motor(pole_pairs: u8, kv: u16) -> Self {
+        Self {
+            pole_pairs: pole_pairs.max(1),
+            kt: 60.0 / (TAU * kv.max(1) as f32),
+            ..Self::default()
+        }
+    }
+}
+
+impl From<&MotorProfile> for SimParams {
+    fn from(profile: &MotorProfile) -> Self {
+        Self::for_motor(profile.pole_pairs, profile.kv)
+    }
+}
+
+impl Default for SimParams {
+    fn default() -> Self {
+        Self {
+            pole_pairs: 7,
+            kt: 60.0 / (TAU * 700.0),
+            resistance: 0.3,
+            inductance: 30e-6,
+            inertia: 5e-6,
+            viscous: 2e-6,
+            coulomb: 1e-3,
+            vbus: 12.0,
+        }
+    }
+}
+
+/// Longest integration step, well below the L/R time constant
+const MAX_DT: f32 = 5e-6;
+
+/// Motor state driven by `set_drive` and integrated by `advance`
+pub struct MotorModel {
+    params: SimParams,
+    /// Energized 6-step index, `None` = all phases floating
+    step: Option<u8>,
+    /// Applied duty, 0.0-1.0
+    duty: f32,
+    /// Winding current, A
+    current: f32,
+    /// Mechanical speed, rad/s
+    omega: f32,
+    /// Mechanical angle since start, rad (unwrapped)
+    theta: f32,
+}
+
+impl MotorModel {
+    /// Motor at rest with the rotor at electrical angle 0
+    pub fn new(params: SimParams) -> Self {
+        Self {
+            params,
+            step: None,
+            duty: 0.0,
+            current: 0.0,
+            omega: 0.0,
+            theta: 0.0,
+        }
+    }
+
+    pub fn params(&self) -> &SimParams {
+        &self.params
+    }
+
+    /// Energize `step` (0-5) at `duty_percent`, or float all phases
+    pub fn set_drive(&mut self, step: Option<u8>, duty_percent: u8) {
+        self.step = step.map(|s| s % 6);
+        self.duty = duty_percent.min(100) as f32 / 100.0;
+        if self.step.is_none() {
+            self.current = 0.0;
+        }
+    }
+
+    /// Integrate the plant over `dt` seconds
+    pub fn advance(&mut self, dt: f32) {
+        let n = (dt / MAX_DT).ceil().max(1.0) as u32;
+        let h = dt / n as f32;
+        for _ in 0..n {
+            self.substep(h);
+        }
+    }
+
+    fn substep(&mut self, h: f32) {
+        let p = self.params;
+        let sin_delta = match self.step {
+            Some(step) => (step as f32 * PI / 3.0 - self.electrical_angle()).sin(),
+            None => 0.0,
+        };
+        if self.step.is_some() {
+            let emf = p.kt * self.omega * sin_delta;
+            let v = self.duty * p.vbus;
+            self.current += h * (v - p.resistance * self.current - emf) / p.inductance;
+            // Reverse current freewheels through the body diodes back to zero
+            self.current = self.current.max(0.0);
+        }
+        let drive = p.kt * self.current * sin_delta;
+        // Static friction holds the rotor until the drive torque exceeds it
+        if self.omega == 0.0 && drive.abs() <= p.coulomb {
+            return;
+        }
+        let torque = drive - p.viscous * self.omega - p.coulomb * self.omega.signum();
+        let omega = self.omega + h * torque / p.inertia;
+        // Friction alone stops the rotor rather than reversing it
+        let reversed = self.omega != 0.0 && omega.signum() != self.omega.signum();
+        self.omega = if reversed && drive.abs() <= p.coulomb {
+            0.0
+        } else {
+            omega
+        };
+        self.theta += h * self.omega;
+    }
+
+    /// Rotor electrical angle, 0..2π
+    pub fn electrical_angle(&self) -> f32 {
+        (self.theta * self.params.pole_pairs as f32).rem_euclid(TAU)
+    }
+
+    /// Mechanical angle travelled since start, rad
+    pub fn position(&self) -> f32 {
+        self.theta
+    }
+
+    /// Mechanical speed, rpm
+    pub fn rpm(&self) -> f32 {
+        self.omega * 60.0 / TAU
+    }
+
+    /// Winding current, A
+    pub fn current(&self) -> f32 {
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligned_step_holds_rotor_at_ohmic_current() {
+        let mut m = MotorModel::new(SimParams::default());
+        m.set_drive(Some(1), 5);
+        m.advance(0.5);
+        // Rotor pulled onto the 60° field, current limited by resistance alone
+        assert!((m.electrical_angle() - PI / 3.0).abs() < 0.05);
+        assert!(m.rpm().abs() < 1.0);
+        assert!((m.current() - 0.05 * 12.0 / 0.3).abs() < 0.05);
+    }
+
+    #[test]
+    fn open_loop_stepping_follows_commanded_rate() {
+        let mut m = MotorModel::new(SimParams::for_motor(7, 700));
+        let period = 0.01;
+        let ticks = 300;
+        let mut start = 0.0;
+        for i in 0..ticks {
+            if i == ticks / 2 {
+                start = m.position();
+            }
+            m.set_drive(Some((i % 6) as u8), 10);
+            m.advance(period);
+        }
+        // Six steps per electrical revolution, pole_pairs electrical per mechanical
+        let expected = 60.0 / (6.0 * 7.0 * period);
+        let elapsed = (ticks / 2) as f32 * period;
+        let measured = (m.position() - start) / elapsed * 60.0 / TAU;
+        assert!(
+            (measured - expected).abs() < 0.05 * expected,
+            "{} rpm vs {} rpm",
+            measured,
+            expected
+        );
+    }
+
+    #[test]
+    fn floating_phases_coast_down() {
+        let mut m = MotorModel::new(SimParams::default());
+        for i in 0..120 {
+            m.set_drive(Some((i % 6) as u8), 10);
+            m.advance(0.01);
+        }
+        m.set_drive(None, 0);
+        assert_eq!(m.current(), 0.0);
+        m.advance(2.0);
+        assert_eq!(m.rpm(), 0.0);
+    }
+}
@@ -0,0 +1,946 @@
+//! Host output pipeline: events fan out to every enabled sink
+//!
+//! Decode loops and endpoint servers publish `HostEvent`s to an `EventBus`;
+//! a dispatcher task owns the sinks (stdout, log file, CSVs, metrics) and hands
+//! each event to all of them in order.
+//!
+//! `EventBus::publish` stamps every event with `host_time()` as it is
+//! ingested, so defmt lines and ergot events printed by the same sink carry
+//! comparable timestamps and appear in the order they arrived.
+//!
+//! With a `SessionHeader` (`--label`), the log file and both CSVs start with
+//! `#` comment lines describing the run: the label, the wall-clock start,
+//! the host version and, once the device has identified itself, its
+//! hardware, firmware, unique ID, build hash and active profile. Output is
+//! held back until then (at most `HEADER_HOLD_LINES` lines), so the header
+//! comes first even though the files open before the device answers.
+//!
+//! With `--dedup`, runs of identical defmt lines are collapsed by the
+//! dispatcher before any sink sees them (see `Dedup`).
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
+
+use oxifoc_protocol::{
+    ButtonEvent, CoggingSample, CommandLog, DeviceConfig, DeviceInfo, KeepAlive, MotorCommand,
+    MotorStatus, RejectReason, Telemetry,
+};
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+static CLOCK_ORIGIN: OnceLock<Instant> = OnceLock::new();
+
+/// Host monotonic time since the first call (the start of the host, in practice)
+pub fn host_time() -> Duration {
+    CLOCK_ORIGIN.get_or_init(Instant::now).elapsed()
+}
+
+/// Render a `host_time` stamp as seconds, e.g. `   12.345s`
+pub fn format_stamp(at: Duration) -> String {
+    format!("{:>9.3}s", at.as_secs_f64())
+}
+
+/// Everything the host can report
+#[derive(Debug, Clone)]
+pub enum HostEvent {
+    /// Decoded defmt log line from the device
+    Defmt(String),
+    /// Device replied to the info handshake
+    DeviceInfo(DeviceInfo),
+    /// Button event received from the device
+    Button(ButtonEvent),
+    /// Motor status returned by a motor command
+    MotorStatus(MotorStatus),
+    /// Full device configuration snapshot
+    DeviceConfig(DeviceConfig),
+    /// Periodic device liveness message
+    KeepAlive(KeepAlive),
+    /// Decoded telemetry sample (only the fields in the device's mask)
+    Telemetry(Telemetry),
+    /// Device's record of what it did with a motor command
+    CommandLog(CommandLog),
+    /// One position of a cogging scan
+    Cogging(CoggingSample),
+    /// Motor command `trace` (`MotorCommand::id` `cmd`), sent at `sent_at` on
+    /// the `host_time` clock, was answered after `latency`; `status` is `None`
+    /// if the request failed or timed out
+    CommandReply {
+        trace: u16,
+        cmd: u8,
+        sent_at: Duration,
+        latency: Duration,
+        status: Option<MotorStatus>,
+    },
+    /// The link was rebuilt (device reset, probe reconnect); events before and
+    /// after this are from different connections
+    Discontinuity(String),
+}
+
+impl HostEvent {
+    /// Short kind name, used by the metrics sink
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Defmt(_) => "defmt",
+            Self::DeviceInfo(_) => "device_info",
+            Self::Button(_) => "button",
+            Self::MotorStatus(_) => "motor_status",
+            Self::DeviceConfig(_) => "device_config",
+            Self::KeepAlive(_) => "keepalive",
+            Self::Telemetry(_) => "telemetry",
+            Self::CommandLog(_) => "command_log",
+            Self::Cogging(_) => "cogging",
+            Self::CommandReply { .. } => "command_reply",
+            Self::Discontinuity(_) => "discontinuity",
+        }
+    }
+
+    /// Human-readable one-line rendering
+    pub fn to_text(&self) -> String {
+        match self {
+            Self::Defmt(line) => line.clone(),
+            Self::DeviceInfo(info) => {
+                format!(
+                    "Device connected: hw='{}' sw='{}' uid={} build={}",
+                    info.hw, info.sw, info.uid, info.build
+                )
+            }
+            Self::Button(ev) => format!("Button: {:?}", ev),
+            Self::MotorStatus(s) => match &s.rejected {
+                None => format!(
+                    "Motor status: state={:?}, duty={}%, step={}",
+                    s.state, s.duty, s.step
+                ),
+                Some(reason) => format!(
+                    "Motor status: state={:?}, duty={}%, step={} (command rejected: {:?})",
+                    s.state, s.duty, s.step, reason
+                ),
+            },
+            Self::DeviceConfig(c) => format!(
+                "Device config: profile='{}' pole_pairs={} kv={} pwm={}Hz dead_time={}ns \
+                 max_duty={}% mode={:?} scheme={:?} period={}ms blanking={}us self_test_max={}% \
+                 min_off={}ms min_rpm={} ({}ms) max_phase={}mV regen={}mA phase_map={:?} addr={}.{} peer={} \
+                 current_offsets={:?} current_gain={}/{}",
+                c.profile,
+                c.pole_pairs,
+                c.kv,
+                c.pwm_freq,
+                c.dead_time_ns,
+                c.max_duty,
+                c.mode,
+                c.scan_scheme,
+                c.commutation_period_ms,
+                c.blanking_us,
+                c.self_test_max_duty,
+                c.min_off_ms,
+                c.min_rpm,
+                c.min_rpm_timeout_ms,
+                c.max_phase_voltage_mv,
+                c.regen_current_limit_ma,
+                c.phase_map.channels,
+                c.addressing.network_id,
+                c.addressing.node_id,
+                c.addressing.peer_node_id,
+                c.current_calibration.offset,
+                c.current_calibration.gain_num,
+                c.current_calibration.gain_den
+            ),
+            Self::KeepAlive(ka) => {
+                format!("KeepAlive: seq={} uptime={}ms", ka.seq, ka.uptime_ms)
+            }
+            Self::Telemetry(t) => {
+                let mut fields = Vec::new();
+                if let Some(v) = t.rpm {
+                    fields.push(format!("rpm={}", v));
+                }
+                if let Some(v) = t.duty {
+                    fields.push(format!("duty={}%", v));
+                }
+                if let Some(v) = t.step {
+                    fields.push(format!("step={}", v));
+                }
+                if let Some(v) = &t.state {
+                    fields.push(format!("state={:?}", v));
+                }
+                if let Some(v) = t.vbus_mv {
+                    fields.push(format!("vbus={:.2}V", v as f32 / 1000.0));
+                }
+                if let Some(v) = t.temp_dc {
+                    fields.push(format!("temp={:.1}C", v as f32 / 10.0));
+                }
+                if let Some(v) = t.current_ma {
+                    fields.push(format!("current={}mA", v));
+                }
+                if let Some(v) = t.limp {
+                    fields.push(format!("limp={}", v));
+                }
+                match t.fault {
+                    Some(Some(kind)) => fields.push(format!("fault={:?}", kind)),
+                    Some(None) => fields.push("fault=none".to_owned()),
+                    None => {}
+                }
+                if let Some(v) = t.fan {
+                    fields.push(format!("fan={}%", v));
+                }
+                if let Some(v) = t.adc_fault {
+                    fields.push(format!("adc_fault={}", v));
+                }
+                format!("Telemetry: {}", fields.join(" "))
+            }
+            Self::Discontinuity(reason) => format!("Link discontinuity: {}", reason),
+            Self::CommandLog(log) => match log.reason {
+                RejectReason::None => {
+                    format!("Command {}: accepted", MotorCommand::name_of(log.cmd_id))
+                }
+                reason => format!(
+                    "Command {}: rejected ({:?})",
+                    MotorCommand::name_of(log.cmd_id),
+                    reason
+                ),
+            },
+            Self::Cogging(s) => format!(
+                "Cogging: {}/{} step={} hall={:03b} duty={:.1}% current={}mA",
+                s.index + 1,
+                s.total,
+                s.step,
+                s.hall,
+                s.duty_permille as f32 / 10.0,
+                s.current_ma
+            ),
+            Self::CommandReply {
+                trace,
+                cmd,
+                latency,
+                status,
+                ..
+            } => {
+                let ms = latency.as_secs_f64() * 1000.0;
+                let name = MotorCommand::name_of(*cmd);
+                match status {
+                    Some(s) => format!(
+                        "Motor command [id={}]: {} answered after {:.1}ms (state={:?}{})",
+                        trace,
+                        name,
+                        ms,
+                        s.state,
+                        s.rejected
+                            .as_ref()
+                            .map_or(String::new(), |r| format!(", rejected: {:?}", r))
+                    ),
+                    None => format!(
+                        "Motor command [id={}]: {} got no answer in {:.1}ms",
+                        trace, name, ms
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// An output receiving host events
+pub trait Sink: Send {
+    /// `at` is the `host_time` at which the event was published
+    fn handle(&mut self, at: Duration, event: &HostEvent);
+
+    /// Called when the host shuts down
+    fn flush(&mut self) {}
+}
+
+/// Time a run of repeated defmt lines is counted before its count is printed
+pub const DEDUP_FLUSH: Duration = Duration::from_secs(5);
+
+/// Collapses consecutive identical defmt lines (`--dedup`)
+///
+/// The first line of a run is passed on at once and its repeats are only
+/// counted. Once a different line arrives, or `flush_after` has passed since
+/// the first repeat counted, a single `<line> [×N]` stands in for the N
+/// repeats, stamped with the last of them; a run that goes on is summed up
+/// again every `flush_after`. Other events pass through and do not end a run.
+pub struct Dedup {
+    flush_after: Duration,
+    last: Option<String>,
+    repeats: u32,
+    /// Stamps of the first and the latest repeat counted
+    first_repeat: Duration,
+    last_repeat: Duration,
+}
+
+impl Dedup {
+    pub fn new(flush_after: Duration) -> Self {
+        Self {
+            flush_after,
+            last: None,
+            repeats: 0,
+            first_repeat: Duration::ZERO,
+            last_repeat: Duration::ZERO,
+        }
+    }
+
+    /// Take `event` published at `at`
+    ///
+    /// Returns the summary of the run it ends, if any, and whether `event`
+    /// itself is to be passed on.
+    pub fn push(
+        &mut self,
+        at: Duration,
+        event: &HostEvent,
+    ) -> (Option<(Duration, HostEvent)>, bool) {
+        let HostEvent::Defmt(line) = event else {
+            return (None, true);
+        };
+        if self.last.as_ref() == Some(line) {
+            if self.repeats == 0 {
+                self.first_repeat = at;
+            }
+            self.repeats += 1;
+            self.last_repeat = at;
+            return (None, false);
+        }
+        let summary = self.drain();
+        self.last = Some(line.clone());
+        (summary, true)
+    }
+
+    /// When `expire` will have a summary, if repeats are being counted
+    pub fn deadline(&self) -> Option<Duration> {
+        (self.repeats > 0).then(|| self.first_repeat + self.flush_after)
+    }
+
+    /// The summary of the repeats counted so far, once `flush_after` has passed by `now`
+    pub fn expire(&mut self, now: Duration) -> Option<(Duration, HostEvent)> {
+        match self.deadline() {
+            Some(deadline) if now >= deadline => self.drain(),
+            _ => None,
+        }
+    }
+
+    /// The summary of the repeats counted so far, however recent
+    pub fn drain(&mut self) -> Option<(Duration, HostEvent)> {
+        if self.repeats == 0 {
+            return None;
+        }
+        let line = self.last.as_deref().unwrap_or_default();
+        let summary = HostEvent::Defmt(format!("{} [×{}]", line, self.repeats));
+        self.repeats = 0;
+        Some((self.last_repeat, summary))
+    }
+}
+
+/// Fan-out over all enabled sinks
+#[derive(Default)]
+pub struct Dispatcher {
+    sinks: Vec<Box<dyn Sink>>,
+    dedup: Option<Dedup>,
+}
+
+impl Dispatcher {
+    pub fn add(&mut self, sink: impl Sink + 'static) {
+        self.sinks.push(Box::new(sink));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// Collapse repeated defmt lines, printing their count at most `flush_after` late
+    pub fn set_dedup(&mut self, flush_after: Duration) {
+        self.dedup = Some(Dedup::new(flush_after));
+    }
+
+    pub fn publish(&mut self, at: Duration, event: &HostEvent) {
+        if let Some(dedup) = &mut self.dedup {
+            let (summary, forward) = dedup.push(at, event);
+            if let Some((t, summary)) = summary {
+                self.deliver(t, &summary);
+            }
+            if !forward {
+                return;
+            }
+        }
+        self.deliver(at, event);
+    }
+
+    /// Print the count of a run of repeats that has gone on for the flush time by `now`
+    pub fn expire(&mut self, now: Duration) {
+        if let Some((t, summary)) = self.dedup.as_mut().and_then(|d| d.expire(now)) {
+            self.deliver(t, &summary);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Some((t, summary)) = self.dedup.as_mut().and_then(Dedup::drain) {
+            self.deliver(t, &summary);
+        }
+        for sink in &mut self.sinks {
+            sink.flush();
+        }
+    }
+
+    fn deliver(&mut self, at: Duration, event: &HostEvent) {
+        for sink in &mut self.sinks {
+            sink.handle(at, event);
+        }
+    }
+
+    /// Move the dispatcher into a task and return the bus feeding it
+    ///
+    /// The task ends (flushing all sinks) once every `EventBus` clone is dropped.
+    pub fn spawn(mut self) -> (EventBus, tokio::task::JoinHandle<()>) {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let handle = tokio::spawn(async move {
+            loop {
+                // A pending repeat count is printed on time even if nothing else arrives
+                let msg = match self.dedup.as_ref().and_then(Dedup::deadline) {
+                    Some(deadline) => {
+                        let wait = deadline.saturating_sub(host_time());
+                        match tokio::time::timeout(wait, rx.recv()).await {
+                            Ok(msg) => msg,
+                            Err(_) => {
+                                self.expire(host_time());
+                                continue;
+                            }
+                        }
+                    }
+                    None => rx.recv().await,
+                };
+                let Some(msg) = msg else { break };
+                match msg {
+                    BusMsg::Event(at, event) => self.publish(at, &event),
+                    BusMsg::Flush(done) => {
+                        self.flush();
+                        let _ = done.send(());
+                    }
+                }
+            }
+            self.flush();
+        });
+        (EventBus { tx, tap: None }, handle)
+    }
+}
+
+enum BusMsg {
+    Event(Duration, HostEvent),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Cloneable handle for publishing events from any task
+#[derive(Clone)]
+pub struct EventBus {
+    tx: mpsc::UnboundedSender<BusMsg>,
+    /// Copy of every event for `DeviceEvents` receivers
+    tap: Option<broadcast::Sender<HostEvent>>,
+}
+
+impl EventBus {
+    /// Stamp `event` with the current `host_time` and queue it for the sinks
+    pub fn publish(&self, event: HostEvent) {
+        if let Some(tap) = &self.tap
+            && tap.receiver_count() > 0
+        {
+            let _ = tap.send(event.clone());
+        }
+        // Dispatcher gone means we're shutting down; nothing left to report to
+        let _ = self.tx.send(BusMsg::Event(host_time(), event));
+    }
+
+    /// This bus, also copying each event to `tap`
+    pub(crate) fn with_tap(&self, tap: broadcast::Sender<HostEvent>) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            tap: Some(tap),
+        }
+    }
+
+    /// Flush all sinks after every event published so far has been handled
+    pub async fn flush(&self) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.tx.send(BusMsg::Flush(done_tx)).is_ok() {
+            let _ = done_rx.await;
+        }
+    }
+}
+
+/// Timestamped text lines on stdout
+pub struct StdoutSink;
+
+impl Sink for StdoutSink {
+    fn handle(&mut self, at: Duration, event: &HostEvent) {
+        println!("{} {}", format_stamp(at), event.to_text());
+    }
+}
+
+/// Render `t` as a UTC timestamp, e.g. `2026-10-14T09:05:00Z`
+pub fn format_utc(t: SystemTime) -> String {
+    let secs = t
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Days since the epoch to a civil date (proleptic Gregorian)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+/// Lines an output file holds back at most while waiting for the device's identity
+pub const HEADER_HOLD_LINES: usize = 256;
+
+/// Description of a capture session, written at the top of output files
+#[derive(Debug, Clone)]
+pub struct SessionHeader {
+    label: String,
+    started: SystemTime,
+    device: Option<String>,
+    profile: Option<String>,
+}
+
+impl SessionHeader {
+    pub fn new(label: &str, started: SystemTime) -> Self {
+        Self {
+            // One comment line, whatever the label contains
+            label: label.replace(['\r', '\n'], " "),
+            started,
+            device: None,
+            profile: None,
+        }
+    }
+
+    /// Take in the device's identity and configuration; true once both are known
+    fn observe(&mut self, event: &HostEvent) -> bool {
+        match event {
+            HostEvent::DeviceInfo(info) => {
+                self.device = Some(format!(
+                    "hw='{}' sw='{}' uid={} build={}",
+                    info.hw, info.sw, info.uid, info.build
+                ));
+            }
+            HostEvent::DeviceConfig(c) => self.profile = Some(c.profile.to_string()),
+            _ => {}
+        }
+        self.device.is_some() && self.profile.is_some()
+    }
+
+    /// `# key: value` comment lines
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("# label: {}", self.label),
+            format!("# started: {}", format_utc(self.started)),
+            format!("# host: oxifoc-host {}", env!("CARGO_PKG_VERSION")),
+        ];
+        lines.extend(self.device.as_ref().map(|d| format!("# device: {}", d)));
+        lines.extend(self.profile.as_ref().map(|p| format!("# profile: {}", p)));
+        lines
+    }
+}
+
+/// Output file that may start with a `SessionHeader`
+struct HeaderedOut<W: Write> {
+    out: W,
+    /// Header and the lines held back behind it, until it is written
+    pending: Option<(SessionHeader, Vec<String>)>,
+}
+
+impl<W: Write> HeaderedOut<W> {
+    /// `first` (a CSV column line) goes out first, or right after the header
+    fn new(mut out: W, header: Option<SessionHeader>, first: Option<&str>) -> io::Result<Self> {
+        let pending = match header {
+            Some(header) => Some((header, first.map(str::to_owned).into_iter().collect())),
+            None => {
+                if let Some(first) = first {
+                    writeln!(out, "{}", first)?;
+                }
+                None
+            }
+        };
+        Ok(Self { out, pending })
+    }
+
+    /// Note `event` for the header, then write `line`, or hold it while the header waits
+    fn write(&mut self, event: &HostEvent, line: Option<String>) {
+        match &mut self.pending {
+            Some((header, held)) => {
+                let complete = header.observe(event);
+                held.extend(line);
+                if complete || held.len() >= HEADER_HOLD_LINES {
+                    self.release();
+                }
+            }
+            None => {
+                if let Some(line) = line {
+                    let _ = writeln!(self.out, "{}", line);
+                }
+            }
+        }
+    }
+
+    /// Write the header and the held lines, with whatever the header has by now
+    fn release(&mut self) {
+        if let Some((header, held)) = self.pending.take() {
+            for line in header.lines().iter().chain(&held) {
+                let _ = writeln!(self.out, "{}", line);
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        self.release();
+        let _ = self.out.flush();
+    }
+
+    #[cfg(test)]
+    fn into_inner(mut self) -> W {
+        self.release();
+        self.out
+    }
+}
+
+/// Timestamped text lines appended to a log file
+pub struct LogFileSink {
+    out: HeaderedOut<BufWriter<File>>,
+}
+
+impl LogFileSink {
+    pub fn create(path: impl AsRef<Path>, header: Option<SessionHeader>) -> io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Self {
+            out: HeaderedOut::new(BufWriter::new(file), header, None)?,
+        })
+    }
+}
+
+impl Sink for LogFileSink {
+    fn handle(&mut self, at: Duration, event: &HostEvent) {
+        self.out.write(
+            event,
+            Some(format!("{} {}", format_stamp(at), event.to_text())),
+        );
+    }
+
+    fn flush(&mut self) {
+        self.out.flush();
+    }
+}
+
+/// Motor status samples as CSV (`t_ms,state,duty,step,board`), `t_ms` on the `host_time` clock
+///
+/// `board` is the unique ID from the latest `DeviceInfo`, empty until one arrives.
+/// A link rebuild is marked by a `t_ms,discontinuity,,,board` row, so the file
+/// stays one series across reconnects with the gap visible. A `SessionHeader`
+/// goes above the column line.
+pub struct CsvSink<W: Write + Send> {
+    out: HeaderedOut<W>,
+    board: String,
+}
+
+impl CsvSink<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>, header: Option<SessionHeader>) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), header)
+    }
+}
+
+impl<W: Write + Send> CsvSink<W> {
+    pub fn new(out: W, header: Option<SessionHeader>) -> io::Result<Self> {
+        let out = HeaderedOut::new(out, header, Some("t_ms,state,duty,step,board"))?;
+        Ok(Self {
+            out,
+            board: String::new(),
+        })
+    }
+}
+
+impl<W: Write + Send> Sink for CsvSink<W> {
+    fn handle(&mut self, at: Duration, event: &HostEvent) {
+        let row = match event {
+            HostEvent::DeviceInfo(info) => {
+                self.board = info.uid.to_string();
+                None
+            }
+            HostEvent::MotorStatus(s) => Some(format!(
+                "{},{:?},{},{},{}",
+                at.as_millis(),
+                s.state,
+                s.duty,
+                s.step,
+                self.board
+            )),
+            HostEvent::Discontinuity(_) => {
+                Some(format!("{},discontinuity,,,{}", at.as_millis(), self.board))
+            }
+            _ => None,
+        };
+        self.out.write(event, row);
+    }
+
+    fn flush(&mut self) {
+        self.out.flush();
+    }
+}
+
+/// Device command log as CSV (`t_ms,cmd,accepted,reason,board`), `t_ms` on the `host_time` clock
+///
+/// `board`, the `t_ms,discontinuity,,,board` marker row and the header are as for `CsvSink`.
+pub struct CommandCsvSink<W: Write + Send> {
+    out: HeaderedOut<W>,
+    board: String,
+}
+
+impl CommandCsvSink<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>, header: Option<SessionHeader>) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), header)
+    }
+}
+
+impl<W: Write + Send> CommandCsvSink<W> {
+    pub fn new(out: W, header: Option<SessionHeader>) -> io::Result<Self> {
+        let out = HeaderedOut::new(out, header, Some("t_ms,cmd,accepted,reason,board"))?;
+        Ok(Self {
+            out,
+            board: String::new(),
+        })
+    }
+}
+
+impl<W: Write + Send> Sink for CommandCsvSink<W> {
+    fn handle(&mut self, at: Duration, event: &HostEvent) {
+        let row = match event {
+            HostEvent::DeviceInfo(info) => {
+                self.board = info.uid.to_string();
+                None
+            }
+            HostEvent::CommandLog(log) => Some(format!(
+                "{},{},{},{:?},{}",
+                at.as_millis(),
+                MotorCommand::name_of(log.cmd_id),
+                log.accepted,
+                log.reason,
+                self.board
+            )),
+            HostEvent::Discontinuity(_) => {
+                Some(format!("{},discontinuity,,,{}", at.as_millis(), self.board))
+            }
+            _ => None,
+        };
+        self.out.write(event, row);
+    }
+
+    fn flush(&mut self) {
+        self.out.flush();
+    }
+}
+
+/// Per-kind event counters, summarized on shutdown
+#[derive(Default)]
+pub struct MetricsSink {
+    counts: BTreeMap<&'static str, u64>,
+}
+
+impl Sink for MetricsSink {
+    fn handle(&mut self, _at: Duration, event: &HostEvent) {
+        *self.counts.entry(event.kind()).or_default() += 1;
+    }
+
+    fn flush(&mut self) {
+        for (kind, n) in &self.counts {
+            tracing::info!("metrics: {} events={}", kind, n);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Records every event it receives
+    struct RecordingSink(Arc<Mutex<Vec<String>>>);
+
+    impl Sink for RecordingSink {
+        fn handle(&mut self, _at: Duration, event: &HostEvent) {
+            self.0.lock().unwrap().push(event.to_text());
+        }
+    }
+
+    /// Records the stamp of every event it receives
+    struct StampSink(Arc<Mutex<Vec<Duration>>>);
+
+    impl Sink for StampSink {
+        fn handle(&mut self, at: Duration, _event: &HostEvent) {
+            self.0.lock().unwrap().push(at);
+        }
+    }
+
+    #[test]
+    fn dispatcher_fans_out_to_all_sinks() {
+        let a = Arc::new(Mutex::new(Vec::new()));
+        let b = Arc::new(Mutex::new(Vec::new()));
+        let mut d = Dispatcher::default();
+        d.add(RecordingSink(a.clone()));
+        d.add(RecordingSink(b.clone()));
+
+        d.publish(Duration::ZERO, &HostEvent::Defmt("hello".into()));
+        d.publish(Duration::ZERO, &HostEvent::Button(ButtonEvent::Hold));
+
+        let expected = vec!["hello".to_string(), "Button: Hold".to_string()];
+        assert_eq!(*a.lock().unwrap(), expected);
+        assert_eq!(*b.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn dedup_collapses_repeats_into_one_counted_line() {
+        let rec = Arc::new(Mutex::new(Vec::new()));
+        let mut d = Dispatcher::default();
+        d.add(RecordingSink(rec.clone()));
+        d.set_dedup(Duration::from_secs(5));
+        let ms = Duration::from_millis;
+        for t in [0, 10, 20, 30] {
+            d.publish(ms(t), &HostEvent::Defmt("waiting".into()));
+        }
+        d.publish(ms(40), &HostEvent::Button(ButtonEvent::Hold));
+        d.publish(ms(50), &HostEvent::Defmt("waiting".into()));
+        d.publish(ms(60), &HostEvent::Defmt("ready".into()));
+        // A single line is not followed by a count
+        d.publish(ms(70), &HostEvent::Defmt("waiting".into()));
+        d.publish(ms(80), &HostEvent::Defmt("ready".into()));
+        assert_eq!(
+            *rec.lock().unwrap(),
+            vec![
+                "waiting",
+                "Button: Hold",
+                "waiting [×4]",
+                "ready",
+                "waiting",
+                "ready"
+            ]
+        );
+
+        // A run that does not end is summed up once the flush time has passed
+        rec.lock().unwrap().clear();
+        d.publish(ms(1000), &HostEvent::Defmt("ready".into()));
+        d.expire(ms(5999));
+        assert!(rec.lock().unwrap().is_empty());
+        d.expire(ms(6000));
+        d.publish(ms(6100), &HostEvent::Defmt("ready".into()));
+        d.flush();
+        assert_eq!(*rec.lock().unwrap(), vec!["ready [×1]", "ready [×1]"]);
+    }
+
+    #[tokio::test]
+    async fn bus_delivers_in_order_before_flush() {
+        let rec = Arc::new(Mutex::new(Vec::new()));
+        let mut d = Dispatcher::default();
+        d.add(RecordingSink(rec.clone()));
+        let (bus, handle) = d.spawn();
+        bus.publish(HostEvent::Defmt("one".into()));
+        bus.publish(HostEvent::Defmt("two".into()));
+        bus.flush().await;
+        assert_eq!(*rec.lock().unwrap(), vec!["one", "two"]);
+        drop(bus);
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn events_are_stamped_at_publish() {
+        let stamps = Arc::new(Mutex::new(Vec::new()));
+        let mut d = Dispatcher::default();
+        d.add(StampSink(stamps.clone()));
+        let (bus, _handle) = d.spawn();
+        let before = host_time();
+        bus.publish(HostEvent::Defmt("device log".into()));
+        std::thread::sleep(Duration::from_millis(5));
+        bus.publish(HostEvent::Button(ButtonEvent::Hold));
+        let after = host_time();
+        bus.flush().await;
+        let stamps = stamps.lock().unwrap();
+        assert!(before <= stamps[0] && stamps[0] < stamps[1] && stamps[1] <= after);
+        assert!(stamps[1] - stamps[0] >= Duration::from_millis(5));
+    }
+
+    #[test]
+    fn command_csv_writes_only_command_logs() {
+        let mut sink = CommandCsvSink::new(Vec::new(), None).unwrap();
+        let log = CommandLog {
+            cmd_id: 5,
+            accepted: false,
+            reason: RejectReason::Disarmed,
+        };
+        sink.handle(
+            Duration::from_millis(1500),
+            &HostEvent::CommandLog(log.clone()),
+        );
+        sink.handle(
+            Duration::from_millis(1600),
+            &HostEvent::Defmt("ignored".into()),
+        );
+        let info = DeviceInfo {
+            hw: "B-G431B-ESC1".try_into().unwrap(),
+            sw: "oxifoc-0.1.0".try_into().unwrap(),
+            uid: "0123456789ABCDEF01234567".try_into().unwrap(),
+            build: "0011223344556677".try_into().unwrap(),
+        };
+        sink.handle(Duration::from_millis(1700), &HostEvent::DeviceInfo(info));
+        sink.handle(Duration::from_millis(1800), &HostEvent::CommandLog(log));
+        let csv = String::from_utf8(sink.out.into_inner()).unwrap();
+        assert_eq!(
+            csv,
+            "t_ms,cmd,accepted,reason,board\n1500,nudge,false,Disarmed,\n\
+             1800,nudge,false,Disarmed,0123456789ABCDEF01234567\n"
+        );
+    }
+
+    #[test]
+    fn header_waits_for_the_device_and_goes_above_the_columns() {
+        let started = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let header = SessionHeader::new("motor A, no load\nrun 3", started);
+        let mut sink = CommandCsvSink::new(Vec::new(), Some(header)).unwrap();
+        let log = CommandLog {
+            cmd_id: 1,
+            accepted: true,
+            reason: RejectReason::None,
+        };
+        sink.handle(Duration::from_millis(100), &HostEvent::CommandLog(log));
+        let info = DeviceInfo {
+            hw: "B-G431B-ESC1".try_into().unwrap(),
+            sw: "oxifoc-0.1.0".try_into().unwrap(),
+            uid: "0123456789ABCDEF01234567".try_into().unwrap(),
+            build: "0011223344556677".try_into().unwrap(),
+        };
+        sink.handle(Duration::from_millis(200), &HostEvent::DeviceInfo(info));
+        // No config before the end: the header goes out with what it has
+        let csv = String::from_utf8(sink.out.into_inner()).unwrap();
+        assert_eq!(
+            csv,
+            format!(
+                "# label: motor A, no load run 3\n# started: 2023-11-14T22:13:20Z\n# host: oxifoc-host {}\n\
+                 # device: hw='B-G431B-ESC1' sw='oxifoc-0.1.0' uid=0123456789ABCDEF01234567 build=0011223344556677\n\
+                 t_ms,cmd,accepted,reason,board\n100,start,true,None,\n",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        assert_eq!(format_utc(SystemTime::UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            format_utc(SystemTime::UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29T00:00:00Z"
+        );
+    }
+
+    #[test]
+    fn stamp_format_is_fixed_width_seconds() {
+        assert_eq!(format_stamp(Duration::from_millis(12_345)), "   12.345s");
+    }
+}
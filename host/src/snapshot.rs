@@ -0,0 +1,116 @@
+//! Device configuration snapshots (`oxifoc-host config dump|diff`)
+//!
+//! A snapshot is the `DeviceConfig` from `req/config` written as TOML, one
+//! key per field, so it can be kept next to a test setup and reviewed like
+//! any other file. Comparing works on the parsed TOML rather than on
+//! `DeviceConfig` itself: a baseline taken by an older host, with a field
+//! since added or dropped, still compares, and the difference shows up as a
+//! missing or extra key instead of a parse error.
+
+use std::fmt;
+
+use anyhow::{Context, Result};
+use oxifoc_protocol::DeviceConfig;
+use toml::{Table, Value};
+
+/// `config` as snapshot TOML
+pub fn to_toml(config: &DeviceConfig) -> Result<String> {
+    toml::to_string(config).context("Serializing the device config")
+}
+
+/// Parse snapshot TOML (a saved baseline)
+pub fn parse(text: &str) -> Result<Table> {
+    text.parse().context("Parsing the config snapshot")
+}
+
+/// One field that differs between a baseline and the device
+#[derive(Debug, Clone, PartialEq)]
+pub enum Drift {
+    /// In both, with different values
+    Changed {
+        key: String,
+        baseline: Value,
+        device: Value,
+    },
+    /// In the baseline only
+    Missing { key: String, baseline: Value },
+    /// On the device only
+    Extra { key: String, device: Value },
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Changed {
+                key,
+                baseline,
+                device,
+            } => write!(f, "{}: {} -> {}", key, baseline, device),
+            Self::Missing { key, baseline } => write!(f, "{}: {} -> (not reported)", key, baseline),
+            Self::Extra { key, device } => write!(f, "{}: (not in baseline) -> {}", key, device),
+        }
+    }
+}
+
+/// Every field of `device` that differs from `baseline`, with dotted keys for nested tables
+pub fn diff(baseline: &Table, device: &Table) -> Vec<Drift> {
+    let mut out = Vec::new();
+    diff_tables("", baseline, device, &mut out);
+    out
+}
+
+fn diff_tables(prefix: &str, baseline: &Table, device: &Table, out: &mut Vec<Drift>) {
+    for (name, base) in baseline {
+        let key = format!("{}{}", prefix, name);
+        match (base, device.get(name)) {
+            (Value::Table(b), Some(Value::Table(d))) => {
+                diff_tables(&format!("{}.", key), b, d, out)
+            }
+            (_, Some(dev)) if dev != base => out.push(Drift::Changed {
+                key,
+                baseline: base.clone(),
+                device: dev.clone(),
+            }),
+            (_, Some(_)) => {}
+            (_, None) => out.push(Drift::Missing {
+                key,
+                baseline: base.clone(),
+            }),
+        }
+    }
+    for (name, dev) in device {
+        if !baseline.contains_key(name) {
+            out.push(Drift::Extra {
+                key: format!("{}{}", prefix, name),
+                device: dev.clone(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_changed_missing_and_extra_fields() {
+        let baseline =
+            parse("kv = 700\nmax_duty = 50\nold = 1\n[addressing]\nnode_id = 2\n").unwrap();
+        let device =
+            parse("kv = 700\nmax_duty = 40\nnew = true\n[addressing]\nnode_id = 3\n").unwrap();
+        let drift: Vec<String> = diff(&baseline, &device)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            drift,
+            [
+                "addressing.node_id: 2 -> 3",
+                "max_duty: 50 -> 40",
+                "old: 1 -> (not reported)",
+                "new: (not in baseline) -> true",
+            ]
+        );
+        assert!(diff(&device, &device).is_empty());
+    }
+}
@@ -0,0 +1,145 @@
+//! Speed step response (`oxifoc-host step-test`)
+//!
+//! `OxifocDevice::step_response` holds the running motor at one duty, steps
+//! it to another and records the telemetry rpm on both sides of the step.
+//! `StepCapture::metrics` reduces that to the usual step-response figures,
+//! so a change to the profile or the controller can be judged by numbers
+//! rather than by eye:
+//! - initial and final speed: the mean of the second half of the hold
+//!   before the step and of the last quarter of the one after it
+//! - rise time: from 10% to 90% of the way from the initial to the final
+//!   speed
+//! - overshoot: how far the speed went past the final one, as a share of
+//!   the change
+//! - settling time: from the step to the last sample outside
+//!   `SETTLE_BAND_PERCENT` of the change around the final speed
+//!
+//! A step down is measured the same way, mirrored.
+
+use std::time::Duration;
+
+/// Band around the final speed the response has to stay in, in percent of the change
+pub const SETTLE_BAND_PERCENT: f64 = 5.0;
+/// Smallest change between the initial and final speed that is measured, in rpm
+const MIN_CHANGE_RPM: f64 = 20.0;
+
+/// Telemetry rpm around one duty step
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StepCapture {
+    /// Speeds during the hold before the step, oldest first
+    pub before: Vec<u16>,
+    /// (time since the step was sent, rpm) after it
+    pub after: Vec<(Duration, u16)>,
+}
+
+/// Step-response figures of a `StepCapture`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepMetrics {
+    pub initial_rpm: f64,
+    pub final_rpm: f64,
+    /// 10% to 90% of the change; `None` if the speed never got to 90%
+    pub rise: Option<Duration>,
+    /// Peak past the final speed, in percent of the change (0 without one)
+    pub overshoot_percent: f64,
+    /// Last time the speed was outside the settling band; `None` if it still was at the end
+    pub settling: Option<Duration>,
+}
+
+fn mean(values: impl Iterator<Item = u16>) -> Option<f64> {
+    let (sum, n) = values.fold((0.0, 0u32), |(sum, n), v| (sum + v as f64, n + 1));
+    (n > 0).then(|| sum / n as f64)
+}
+
+impl StepCapture {
+    /// Figures of the response; `None` without samples on either side or
+    /// when the speed changed by less than `MIN_CHANGE_RPM`
+    pub fn metrics(&self) -> Option<StepMetrics> {
+        let initial_rpm = mean(self.before[self.before.len() / 2..].iter().copied())?;
+        let final_rpm = mean(self.after[self.after.len() * 3 / 4..].iter().map(|s| s.1))?;
+        let change = final_rpm - initial_rpm;
+        if change.abs() < MIN_CHANGE_RPM {
+            return None;
+        }
+        // Progress from the initial (0) to the final speed (1), mirrored for a step down
+        let progress = |rpm: u16| (rpm as f64 - initial_rpm) / change;
+        let first_past = |level: f64| {
+            self.after
+                .iter()
+                .find(|(_, rpm)| progress(*rpm) >= level)
+                .map(|s| s.0)
+        };
+        let rise = match (first_past(0.1), first_past(0.9)) {
+            (Some(t10), Some(t90)) => Some(t90.saturating_sub(t10)),
+            _ => None,
+        };
+        let peak = self
+            .after
+            .iter()
+            .map(|(_, rpm)| progress(*rpm))
+            .fold(f64::MIN, f64::max);
+        let band = SETTLE_BAND_PERCENT / 100.0;
+        let outside = |rpm: u16| (progress(rpm) - 1.0).abs() > band;
+        let settling = match self.after.iter().rposition(|(_, rpm)| outside(*rpm)) {
+            None => Some(Duration::ZERO),
+            Some(i) if i + 1 == self.after.len() => None,
+            Some(i) => Some(self.after[i + 1].0),
+        };
+        Some(StepMetrics {
+            initial_rpm,
+            final_rpm,
+            rise,
+            overshoot_percent: ((peak - 1.0) * 100.0).max(0.0),
+            settling,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_underdamped_step_up_gives_its_rise_overshoot_and_settling() {
+        let ms = Duration::from_millis;
+        // 1000 -> 3000 rpm: up at 40 ms, peaking 20% over at 60 ms, inside 5% from 90 ms
+        let after: Vec<(Duration, u16)> = [
+            1000, 1000, 1400, 2200, 2900, 3300, 3400, 3200, 2850, 2950, 3080, 3040, 2990, 3010,
+            3000, 3000,
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, rpm)| (ms(i as u64 * 10), rpm))
+        .collect();
+        let capture = StepCapture {
+            before: vec![900, 950, 1010, 990],
+            after,
+        };
+        let m = capture.metrics().unwrap();
+        assert_eq!((m.initial_rpm, m.final_rpm), (1000.0, 3000.0));
+        // 10% (1200 rpm) at 20 ms, 90% (2800 rpm) at 40 ms
+        assert_eq!(m.rise, Some(ms(20)));
+        assert!((m.overshoot_percent - 20.0).abs() < 1e-9);
+        assert_eq!(m.settling, Some(ms(90)));
+
+        // A step down mirrors it; a speed that did not move has no figures
+        let down = StepCapture {
+            before: vec![3000, 3000],
+            after: vec![
+                (ms(0), 3000),
+                (ms(10), 2000),
+                (ms(20), 1000),
+                (ms(30), 1000),
+            ],
+        };
+        let m = down.metrics().unwrap();
+        assert_eq!(
+            (m.rise, m.overshoot_percent, m.settling),
+            (Some(ms(10)), 0.0, Some(ms(20)))
+        );
+        let flat = StepCapture {
+            before: vec![1000],
+            after: vec![(ms(0), 1005)],
+        };
+        assert_eq!(flat.metrics(), None);
+    }
+}
@@ -0,0 +1,57 @@
+//! Pacing of outgoing motor commands (`--max-command-rate`)
+//!
+//! The device queues motor commands in a four-deep control channel and drops
+//! what does not fit, so a script or a loop over `OxifocDevice::motor` can
+//! lose commands by sending them faster than the control task takes them.
+//! With a rate set, `CommandThrottle` hands out send slots at least one
+//! interval apart; a command that comes early waits for its slot instead of
+//! being sent, in the order the commands were issued. `Stop` and `Estop` are
+//! never held back.
+
+use std::time::{Duration, Instant};
+
+/// Send schedule for motor commands at a fixed maximum rate
+pub(crate) struct CommandThrottle {
+    interval: Duration,
+    next: Option<Instant>,
+}
+
+impl CommandThrottle {
+    /// At most `per_s` commands per second; `None` for 0 (unlimited)
+    pub fn new(per_s: u32) -> Option<Self> {
+        (per_s > 0).then(|| Self {
+            interval: Duration::from_secs(1) / per_s,
+            next: None,
+        })
+    }
+
+    /// Take the next free slot at or after `now`; returns how long to wait for it
+    pub fn reserve(&mut self, now: Instant) -> Duration {
+        let slot = self.next.map_or(now, |next| next.max(now));
+        self.next = Some(slot + self.interval);
+        slot - now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_are_an_interval_apart_and_idle_time_is_not_banked() {
+        assert!(CommandThrottle::new(0).is_none());
+        let mut throttle = CommandThrottle::new(10).unwrap();
+        let t0 = Instant::now();
+        assert_eq!(throttle.reserve(t0), Duration::ZERO);
+        // The ones right behind it queue up
+        assert_eq!(throttle.reserve(t0), Duration::from_millis(100));
+        assert_eq!(
+            throttle.reserve(t0 + Duration::from_millis(50)),
+            Duration::from_millis(150)
+        );
+        // After a quiet spell the next goes at once, but only one
+        let later = t0 + Duration::from_secs(5);
+        assert_eq!(throttle.reserve(later), Duration::ZERO);
+        assert_eq!(throttle.reserve(later), Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,338 @@
+//! Timeline of a run as a Chrome trace (`--trace <path>`)
+//!
+//! `TraceSink` turns host events into Trace Event Format JSON, which
+//! `chrome://tracing` and Perfetto (ui.perfetto.dev) open as a timeline.
+//! Timestamps are `host_time` in microseconds. Each kind of event gets its
+//! own track of the `oxifoc` process:
+//! - `host commands`: one slice per motor command, from sending it to the
+//!   device's answer, with its id and outcome
+//! - `device command log`: the device's `CommandLog` verdicts, as instants
+//! - `motor state`: one slice per `MotorState` while it lasts, as seen in
+//!   motor status replies and telemetry; a state entered after a command
+//!   carries that command's id and the latency from sending it, and a flow
+//!   arrow links the two
+//! - `faults`: protection faults as instants, and limp mode as slices
+//! - `device log`: defmt lines, as instants
+//! - `link`: device identity, configuration and buttons, and link rebuilds
+//!   as instants across all tracks
+//!
+//! Telemetry fields also go to counter tracks (rpm, duty, current, bus
+//! voltage, temperature). The file is one JSON array. It is completed on
+//! every flush, and the closing bracket is overwritten when more events
+//! follow, so a file of a run that is still going can be opened too.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use oxifoc_protocol::{FaultKind, MotorCommand, MotorState, RejectReason};
+use serde_json::{Value, json};
+
+use crate::sink::{HostEvent, Sink};
+
+/// Tracks of the timeline, as (thread id, name)
+const COMMANDS: (u32, &str) = (1, "host commands");
+const COMMAND_LOG: (u32, &str) = (2, "device command log");
+const STATE: (u32, &str) = (3, "motor state");
+const FAULTS: (u32, &str) = (4, "faults");
+const DEVICE_LOG: (u32, &str) = (5, "device log");
+const LINK: (u32, &str) = (6, "link");
+const PID: u32 = 1;
+
+/// Closes the array; overwritten by the next event
+const TERMINATOR: &[u8] = b"\n]\n";
+
+fn micros(t: Duration) -> u64 {
+    t.as_micros() as u64
+}
+
+/// Motor command answered by the device, waiting for the state it causes
+struct LastCommand {
+    trace: u16,
+    name: &'static str,
+    sent_at: Duration,
+}
+
+/// Host events as Chrome trace JSON
+pub struct TraceSink<W: Write + Seek + Send> {
+    out: W,
+    /// Events written so far
+    written: usize,
+    /// `TERMINATOR` is the last thing in `out`
+    terminated: bool,
+    state: Option<MotorState>,
+    limp: bool,
+    fault: Option<FaultKind>,
+    last_command: Option<LastCommand>,
+}
+
+impl TraceSink<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Seek + Send> TraceSink<W> {
+    pub fn new(out: W) -> io::Result<Self> {
+        let mut sink = Self {
+            out,
+            written: 0,
+            terminated: false,
+            state: None,
+            limp: false,
+            fault: None,
+            last_command: None,
+        };
+        sink.out.write_all(b"[")?;
+        sink.emit(
+            json!({"name": "process_name", "ph": "M", "pid": PID, "args": {"name": "oxifoc"}}),
+        );
+        for (tid, name) in [COMMANDS, COMMAND_LOG, STATE, FAULTS, DEVICE_LOG, LINK] {
+            sink.emit(json!({"name": "thread_name", "ph": "M", "pid": PID, "tid": tid, "args": {"name": name}}));
+            sink.emit(json!({"name": "thread_sort_index", "ph": "M", "pid": PID, "tid": tid, "args": {"sort_index": tid}}));
+        }
+        Ok(sink)
+    }
+
+    fn emit(&mut self, event: Value) {
+        if self.terminated {
+            let _ = self.out.seek(SeekFrom::Current(-(TERMINATOR.len() as i64)));
+            self.terminated = false;
+        }
+        let sep: &[u8] = if self.written == 0 { b"\n" } else { b",\n" };
+        let _ = self.out.write_all(sep);
+        let _ = serde_json::to_writer(&mut self.out, &event);
+        self.written += 1;
+    }
+
+    fn instant(&mut self, at: Duration, track: (u32, &str), name: &str, args: Value) {
+        self.emit(json!({
+            "name": name, "ph": "i", "s": "t", "ts": micros(at), "pid": PID, "tid": track.0, "args": args
+        }));
+    }
+
+    /// Start or end a slice on `track`
+    fn edge(&mut self, at: Duration, track: (u32, &str), ph: &str, name: &str, args: Value) {
+        self.emit(json!({"name": name, "ph": ph, "ts": micros(at), "pid": PID, "tid": track.0, "args": args}));
+    }
+
+    /// End the current state's slice and start one for `state`, if it changed
+    fn observe_state(&mut self, at: Duration, state: &MotorState) {
+        if self.state.as_ref() == Some(state) {
+            return;
+        }
+        if let Some(prev) = self.state.take() {
+            self.edge(at, STATE, "E", &format!("{:?}", prev), json!({}));
+        }
+        let args = match self.last_command.take() {
+            Some(cmd) => {
+                // Flow arrow from the command's slice to the state it caused
+                let flow = |ph: &str, ts: Duration, tid: u32| {
+                    json!({"name": "effect", "cat": "command", "ph": ph, "bp": "e", "id": cmd.trace,
+                        "ts": micros(ts), "pid": PID, "tid": tid})
+                };
+                self.emit(flow("s", cmd.sent_at, COMMANDS.0));
+                self.emit(flow("f", at, STATE.0));
+                let latency_ms = at.saturating_sub(cmd.sent_at).as_secs_f64() * 1000.0;
+                json!({"after": format!("{} [id={}]", cmd.name, cmd.trace), "latency_ms": latency_ms})
+            }
+            None => json!({}),
+        };
+        self.edge(at, STATE, "B", &format!("{:?}", state), args);
+        self.state = Some(state.clone());
+    }
+
+    fn counters(&mut self, at: Duration, values: [(&str, Option<f64>); 5]) {
+        for (name, value) in values {
+            if let Some(v) = value {
+                self.emit(json!({"name": name, "ph": "C", "ts": micros(at), "pid": PID, "args": {"value": v}}));
+            }
+        }
+    }
+}
+
+impl<W: Write + Seek + Send> Sink for TraceSink<W> {
+    fn handle(&mut self, at: Duration, event: &HostEvent) {
+        match event {
+            HostEvent::CommandReply {
+                trace,
+                cmd,
+                sent_at,
+                latency,
+                status,
+            } => {
+                let name = MotorCommand::name_of(*cmd);
+                let outcome = match status {
+                    Some(s) => s
+                        .rejected
+                        .as_ref()
+                        .map_or("accepted".to_owned(), |r| format!("rejected: {:?}", r)),
+                    None => "no answer".to_owned(),
+                };
+                self.emit(json!({
+                    "name": name, "cat": "command", "ph": "X", "ts": micros(*sent_at), "dur": micros(*latency),
+                    "pid": PID, "tid": COMMANDS.0, "args": {"id": trace, "outcome": outcome}
+                }));
+                if let Some(s) = status {
+                    if s.rejected.is_none() {
+                        self.last_command = Some(LastCommand {
+                            trace: *trace,
+                            name,
+                            sent_at: *sent_at,
+                        });
+                    }
+                    self.observe_state(at, &s.state);
+                }
+            }
+            HostEvent::MotorStatus(s) => self.observe_state(at, &s.state),
+            HostEvent::Telemetry(t) => {
+                self.counters(
+                    at,
+                    [
+                        ("rpm", t.rpm.map(f64::from)),
+                        ("duty %", t.duty.map(f64::from)),
+                        ("current mA", t.current_ma.map(f64::from)),
+                        ("vbus V", t.vbus_mv.map(|v| v as f64 / 1000.0)),
+                        ("temp °C", t.temp_dc.map(|v| v as f64 / 10.0)),
+                    ],
+                );
+                if let Some(state) = &t.state {
+                    self.observe_state(at, state);
+                }
+                if let Some(Some(fault)) = t.fault
+                    && self.fault != Some(fault)
+                {
+                    self.instant(at, FAULTS, &format!("{:?}", fault), json!({}));
+                }
+                if let Some(fault) = t.fault {
+                    self.fault = fault;
+                }
+                if let Some(limp) = t.limp
+                    && limp != self.limp
+                {
+                    self.edge(at, FAULTS, if limp { "B" } else { "E" }, "limp", json!({}));
+                    self.limp = limp;
+                }
+            }
+            HostEvent::CommandLog(log) => {
+                let name = MotorCommand::name_of(log.cmd_id);
+                let verdict = match log.reason {
+                    RejectReason::None => format!("{} accepted", name),
+                    reason => format!("{} rejected ({:?})", name, reason),
+                };
+                self.instant(at, COMMAND_LOG, &verdict, json!({}));
+            }
+            HostEvent::Defmt(line) => self.instant(at, DEVICE_LOG, line, json!({})),
+            HostEvent::DeviceInfo(info) => {
+                let args = json!({"hw": info.hw.as_str(), "sw": info.sw.as_str(), "uid": info.uid.to_string()});
+                self.instant(at, LINK, "device info", args);
+            }
+            HostEvent::DeviceConfig(c) => {
+                self.instant(
+                    at,
+                    LINK,
+                    "device config",
+                    json!({"profile": c.profile.as_str()}),
+                );
+            }
+            HostEvent::Button(ev) => self.instant(at, LINK, &format!("button {:?}", ev), json!({})),
+            HostEvent::Discontinuity(reason) => {
+                // Nothing from before carries over to the new connection
+                if let Some(prev) = self.state.take() {
+                    self.edge(at, STATE, "E", &format!("{:?}", prev), json!({}));
+                }
+                if self.limp {
+                    self.edge(at, FAULTS, "E", "limp", json!({}));
+                    self.limp = false;
+                }
+                self.fault = None;
+                self.last_command = None;
+                self.emit(json!({
+                    "name": "link rebuilt", "ph": "i", "s": "g", "ts": micros(at), "pid": PID, "tid": LINK.0,
+                    "args": {"reason": reason}
+                }));
+            }
+            HostEvent::KeepAlive(_) | HostEvent::Cogging(_) => {}
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.terminated {
+            let _ = self.out.write_all(TERMINATOR);
+            self.terminated = true;
+        }
+        let _ = self.out.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oxifoc_protocol::{MotorStatus, Telemetry};
+    use std::io::Cursor;
+
+    fn events(sink: &TraceSink<Cursor<Vec<u8>>>) -> Vec<Value> {
+        let json: Value = serde_json::from_slice(sink.out.get_ref()).unwrap();
+        json.as_array()
+            .unwrap()
+            .iter()
+            .filter(|e| e["ph"] != "M")
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn commands_and_the_states_they_cause_form_a_timeline() {
+        let ms = Duration::from_millis;
+        let mut sink = TraceSink::new(Cursor::new(Vec::new())).unwrap();
+        let stopped = Telemetry {
+            state: Some(MotorState::Stopped),
+            rpm: Some(0),
+            ..Default::default()
+        };
+        sink.handle(ms(100), &HostEvent::Telemetry(stopped));
+        let status = MotorStatus {
+            state: MotorState::Stopped,
+            duty: 8,
+            step: 0,
+            rejected: None,
+        };
+        let start = MotorCommand::Start {
+            duty: 8,
+            step: None,
+        };
+        sink.handle(
+            ms(203),
+            &HostEvent::CommandReply {
+                trace: 7,
+                cmd: start.id(),
+                sent_at: ms(200),
+                latency: ms(3),
+                status: Some(status),
+            },
+        );
+        sink.flush();
+        assert_eq!(events(&sink).len(), 3);
+
+        // Written on after a flush; the file is whole again at the next one
+        let running = Telemetry {
+            state: Some(MotorState::Running),
+            ..Default::default()
+        };
+        sink.handle(ms(700), &HostEvent::Telemetry(running));
+        sink.flush();
+
+        let ev = events(&sink);
+        let phases: Vec<_> = ev.iter().map(|e| e["ph"].as_str().unwrap()).collect();
+        assert_eq!(phases, ["C", "B", "X", "E", "s", "f", "B"]);
+        assert_eq!(
+            (ev[2]["ts"].as_u64(), ev[2]["dur"].as_u64()),
+            (Some(200_000), Some(3_000))
+        );
+        assert_eq!(ev[2]["args"]["id"], 7);
+        assert_eq!(ev[6]["name"], "Running");
+        assert_eq!(ev[6]["args"]["after"], "start [id=7]");
+        assert_eq!(ev[6]["args"]["latency_ms"], 500.0);
+    }
+}
@@ -0,0 +1,402 @@
+//! Validated construction of `MotorCommand`s
+//!
+//! The device clamps what it receives, so an out-of-range field is harmless
+//! but silently changed into something else. The constructors here, and
+//! `MotorCommand::validate` for a command built by hand, reject such values
+//! before serialization, by the same rules on the host and in the firmware.
+//! Commands not listed accept every value of their fields.
+
+use core::fmt;
+
+use crate::constants::{
+    MAX_COGGING_CURRENT_MA, MAX_COMMUTATION_PERIOD_US, MAX_DUTY_PERCENT, MAX_FREEWHEEL_COAST_MS,
+    MAX_NUDGE_DUTY, MAX_NUDGE_STEPS, MAX_TEST_PATTERN_DUTY, MIN_COGGING_SETTLE_MS,
+    MIN_COMMUTATION_PERIOD_US, MIN_REVERSE_DWELL_MS, MIN_TEST_PATTERN_PERIOD_MS,
+};
+use crate::{BEMF_MAX_SAMPLES, MotorCommand, TestPattern, telemetry_field};
+
+/// Why a command was refused before sending
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandError {
+    /// Duty above `MAX_DUTY_PERCENT`
+    Duty(u8),
+    /// Start step outside 0-5
+    Step(u8),
+    /// Nudge of more than `MAX_NUDGE_STEPS` either way
+    NudgeSteps(i8),
+    /// Nudge duty above `MAX_NUDGE_DUTY`
+    NudgeDuty(u8),
+    /// BEMF capture of 0 or more than `BEMF_MAX_SAMPLES` samples
+    BemfSamples(u16),
+    /// Period outside `MIN/MAX_COMMUTATION_PERIOD_US`
+    CommutationPeriod(u32),
+    /// Telemetry mask with bits outside `telemetry_field::ALL`
+    TelemetryMask(u16),
+    /// Test pattern duty above `MAX_TEST_PATTERN_DUTY`
+    TestPatternDuty(u8),
+    /// Test pattern dwell or ramp period below `MIN_TEST_PATTERN_PERIOD_MS`
+    TestPatternPeriod(u16),
+    /// Reverse dwell below `MIN_REVERSE_DWELL_MS`
+    ReverseDwell(u16),
+    /// Current gain with a zero denominator
+    CurrentGain(u16, u16),
+    /// Cogging scan current of 0 or above `MAX_COGGING_CURRENT_MA`
+    CoggingCurrent(u16),
+    /// Cogging scan settle time below `MIN_COGGING_SETTLE_MS`
+    CoggingSettle(u16),
+    /// Freewheel coast-down of 0 or above `MAX_FREEWHEEL_COAST_MS`
+    FreewheelCoast(u16),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Duty(duty) => write!(f, "duty {}% is above {}%", duty, MAX_DUTY_PERCENT),
+            Self::Step(step) => write!(f, "start step {} is outside 0-5", step),
+            Self::NudgeSteps(steps) => {
+                write!(
+                    f,
+                    "nudge of {} steps is more than {}",
+                    steps, MAX_NUDGE_STEPS
+                )
+            }
+            Self::NudgeDuty(duty) => write!(f, "nudge duty {}% is above {}%", duty, MAX_NUDGE_DUTY),
+            Self::BemfSamples(n) => write!(
+                f,
+                "BEMF capture of {} samples is outside 1-{}",
+                n, BEMF_MAX_SAMPLES
+            ),
+            Self::CommutationPeriod(us) => write!(
+                f,
+                "commutation period {}us is outside {}-{}us",
+                us, MIN_COMMUTATION_PERIOD_US, MAX_COMMUTATION_PERIOD_US
+            ),
+            Self::TelemetryMask(mask) => {
+                write!(f, "telemetry mask {:#06x} has unknown fields", mask)
+            }
+            Self::TestPatternDuty(duty) => {
+                write!(
+                    f,
+                    "test pattern duty {}% is above {}%",
+                    duty, MAX_TEST_PATTERN_DUTY
+                )
+            }
+            Self::TestPatternPeriod(ms) => {
+                write!(
+                    f,
+                    "test pattern period {}ms is below {}ms",
+                    ms, MIN_TEST_PATTERN_PERIOD_MS
+                )
+            }
+            Self::ReverseDwell(ms) => write!(
+                f,
+                "reverse dwell {}ms is below {}ms",
+                ms, MIN_REVERSE_DWELL_MS
+            ),
+            Self::CurrentGain(num, den) => {
+                write!(f, "current gain {}/{} has a zero denominator", num, den)
+            }
+            Self::CoggingCurrent(ma) => {
+                write!(
+                    f,
+                    "cogging scan current {}mA is outside 1-{}mA",
+                    ma, MAX_COGGING_CURRENT_MA
+                )
+            }
+            Self::CoggingSettle(ms) => {
+                write!(
+                    f,
+                    "cogging scan settle time {}ms is below {}ms",
+                    ms, MIN_COGGING_SETTLE_MS
+                )
+            }
+            Self::FreewheelCoast(ms) => {
+                write!(
+                    f,
+                    "freewheel coast-down of {}ms is outside 1-{}ms",
+                    ms, MAX_FREEWHEEL_COAST_MS
+                )
+            }
+        }
+    }
+}
+
+impl core::error::Error for CommandError {}
+
+fn check_duty(duty: u8) -> Result<(), CommandError> {
+    if duty > MAX_DUTY_PERCENT {
+        Err(CommandError::Duty(duty))
+    } else {
+        Ok(())
+    }
+}
+
+impl MotorCommand {
+    /// `Start` at `duty` percent, aligning at the device's default step
+    pub fn start(duty: u8) -> Result<Self, CommandError> {
+        Self::Start { duty, step: None }.validated()
+    }
+
+    /// `Start` at `duty` percent, aligning at commutation step `step` (0-5)
+    pub fn start_at(duty: u8, step: u8) -> Result<Self, CommandError> {
+        Self::Start {
+            duty,
+            step: Some(step),
+        }
+        .validated()
+    }
+
+    /// `SetSpeed` to `duty` percent
+    pub fn set_speed(duty: u8) -> Result<Self, CommandError> {
+        Self::SetSpeed { duty }.validated()
+    }
+
+    /// `Nudge` of `steps` (negative = reverse) at `duty` percent
+    pub fn nudge(steps: i8, duty: u8) -> Result<Self, CommandError> {
+        Self::Nudge { steps, duty }.validated()
+    }
+
+    /// `CaptureBemf` of `samples` readings
+    pub fn capture_bemf(samples: u16) -> Result<Self, CommandError> {
+        Self::CaptureBemf { samples }.validated()
+    }
+
+    /// `SetCommutationPeriodUs` to `us` per step
+    pub fn set_commutation_period_us(us: u32) -> Result<Self, CommandError> {
+        Self::SetCommutationPeriodUs { us }.validated()
+    }
+
+    /// `SetTelemetryMask` selecting the `telemetry_field` bits in `mask`
+    pub fn set_telemetry_mask(mask: u16) -> Result<Self, CommandError> {
+        Self::SetTelemetryMask { mask }.validated()
+    }
+
+    /// `TestPattern` driving `pattern`
+    pub fn test_pattern(pattern: TestPattern) -> Result<Self, CommandError> {
+        Self::TestPattern { pattern }.validated()
+    }
+
+    /// `Reverse`, coasting `dwell_ms` at standstill before driving the other way
+    pub fn reverse(dwell_ms: u16) -> Result<Self, CommandError> {
+        Self::Reverse { dwell_ms }.validated()
+    }
+
+    /// `SetPhaseDuties` holding phases A, B and C at `a`, `b` and `c` percent
+    pub fn set_phase_duties(a: u8, b: u8, c: u8) -> Result<Self, CommandError> {
+        Self::SetPhaseDuties { a, b, c }.validated()
+    }
+
+    /// `CalibrateCurrent`, also setting the gain to `num / den` if given, storing the result if `persist`
+    pub fn calibrate_current(
+        gain: Option<(u16, u16)>,
+        persist: bool,
+    ) -> Result<Self, CommandError> {
+        let (gain_num, gain_den) = gain.unwrap_or((0, 0));
+        Self::CalibrateCurrent {
+            gain_num,
+            gain_den,
+            persist,
+        }
+        .validated()
+    }
+
+    /// `CoggingScan` holding `current_ma` at each position for `settle_ms`
+    pub fn cogging_scan(current_ma: u16, settle_ms: u16) -> Result<Self, CommandError> {
+        Self::CoggingScan {
+            current_ma,
+            settle_ms,
+        }
+        .validated()
+    }
+
+    /// `Freewheel`: run at `duty` percent for `spin_ms`, then coast for up to `coast_ms`
+    pub fn freewheel(duty: u8, spin_ms: u16, coast_ms: u16) -> Result<Self, CommandError> {
+        Self::Freewheel {
+            duty,
+            spin_ms,
+            coast_ms,
+        }
+        .validated()
+    }
+
+    /// Check every field against the bounds the device would clamp to
+    pub fn validate(&self) -> Result<(), CommandError> {
+        match *self {
+            Self::Start { duty, step } => {
+                check_duty(duty)?;
+                match step {
+                    Some(step) if step > 5 => Err(CommandError::Step(step)),
+                    _ => Ok(()),
+                }
+            }
+            Self::SetSpeed { duty } => check_duty(duty),
+            Self::SetPhaseDuties { a, b, c } => check_duty(a).and(check_duty(b)).and(check_duty(c)),
+            Self::Nudge { steps, duty } => {
+                if steps.unsigned_abs() > MAX_NUDGE_STEPS {
+                    Err(CommandError::NudgeSteps(steps))
+                } else if duty > MAX_NUDGE_DUTY {
+                    Err(CommandError::NudgeDuty(duty))
+                } else {
+                    Ok(())
+                }
+            }
+            Self::CaptureBemf { samples } if samples == 0 || samples > BEMF_MAX_SAMPLES => {
+                Err(CommandError::BemfSamples(samples))
+            }
+            Self::SetCommutationPeriodUs { us }
+                if !(MIN_COMMUTATION_PERIOD_US..=MAX_COMMUTATION_PERIOD_US).contains(&us) =>
+            {
+                Err(CommandError::CommutationPeriod(us))
+            }
+            Self::SetTelemetryMask { mask } if mask & !telemetry_field::ALL != 0 => {
+                Err(CommandError::TelemetryMask(mask))
+            }
+            Self::TestPattern { pattern } => {
+                if pattern.duty() > MAX_TEST_PATTERN_DUTY {
+                    Err(CommandError::TestPatternDuty(pattern.duty()))
+                } else {
+                    match pattern.period_ms() {
+                        Some(ms) if ms < MIN_TEST_PATTERN_PERIOD_MS => {
+                            Err(CommandError::TestPatternPeriod(ms))
+                        }
+                        _ => Ok(()),
+                    }
+                }
+            }
+            Self::Reverse { dwell_ms } if dwell_ms < MIN_REVERSE_DWELL_MS => {
+                Err(CommandError::ReverseDwell(dwell_ms))
+            }
+            Self::CalibrateCurrent {
+                gain_num, gain_den, ..
+            } if gain_num != 0 && gain_den == 0 => {
+                Err(CommandError::CurrentGain(gain_num, gain_den))
+            }
+            Self::CoggingScan {
+                current_ma,
+                settle_ms,
+            } => {
+                if current_ma == 0 || current_ma > MAX_COGGING_CURRENT_MA {
+                    Err(CommandError::CoggingCurrent(current_ma))
+                } else if settle_ms < MIN_COGGING_SETTLE_MS {
+                    Err(CommandError::CoggingSettle(settle_ms))
+                } else {
+                    Ok(())
+                }
+            }
+            Self::Freewheel { duty, coast_ms, .. } => {
+                check_duty(duty)?;
+                if coast_ms == 0 || coast_ms > MAX_FREEWHEEL_COAST_MS {
+                    Err(CommandError::FreewheelCoast(coast_ms))
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn validated(self) -> Result<Self, CommandError> {
+        self.validate().map(|()| self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duty_and_step_bounds() {
+        assert!(MotorCommand::start(MAX_DUTY_PERCENT).is_ok());
+        assert_eq!(
+            MotorCommand::start(101).unwrap_err(),
+            CommandError::Duty(101)
+        );
+        assert!(MotorCommand::start_at(10, 5).is_ok());
+        assert_eq!(
+            MotorCommand::start_at(10, 6).unwrap_err(),
+            CommandError::Step(6)
+        );
+        assert_eq!(
+            MotorCommand::set_speed(200).unwrap_err(),
+            CommandError::Duty(200)
+        );
+        assert!(MotorCommand::set_phase_duties(0, 50, MAX_DUTY_PERCENT).is_ok());
+        assert_eq!(
+            MotorCommand::set_phase_duties(10, 10, 101).unwrap_err(),
+            CommandError::Duty(101)
+        );
+    }
+
+    #[test]
+    fn nudge_bounds_are_symmetric() {
+        assert!(MotorCommand::nudge(-6, MAX_NUDGE_DUTY).is_ok());
+        assert_eq!(
+            MotorCommand::nudge(7, 5).unwrap_err(),
+            CommandError::NudgeSteps(7)
+        );
+        assert_eq!(
+            MotorCommand::nudge(i8::MIN, 5).unwrap_err(),
+            CommandError::NudgeSteps(i8::MIN)
+        );
+        assert_eq!(
+            MotorCommand::nudge(1, 11).unwrap_err(),
+            CommandError::NudgeDuty(11)
+        );
+    }
+
+    #[test]
+    fn range_bounds_are_inclusive() {
+        assert!(MotorCommand::capture_bemf(1).is_ok());
+        assert!(MotorCommand::capture_bemf(BEMF_MAX_SAMPLES).is_ok());
+        assert!(MotorCommand::capture_bemf(0).is_err());
+        assert!(MotorCommand::capture_bemf(BEMF_MAX_SAMPLES + 1).is_err());
+        assert!(MotorCommand::set_commutation_period_us(MIN_COMMUTATION_PERIOD_US).is_ok());
+        assert!(MotorCommand::set_commutation_period_us(MAX_COMMUTATION_PERIOD_US).is_ok());
+        assert!(MotorCommand::set_commutation_period_us(MIN_COMMUTATION_PERIOD_US - 1).is_err());
+        assert!(MotorCommand::set_telemetry_mask(telemetry_field::ALL).is_ok());
+        assert!(MotorCommand::set_telemetry_mask(1 << 15).is_err());
+        let walk =
+            |duty, dwell_ms| MotorCommand::test_pattern(TestPattern::WalkPhase { duty, dwell_ms });
+        assert!(walk(MAX_TEST_PATTERN_DUTY, MIN_TEST_PATTERN_PERIOD_MS).is_ok());
+        assert_eq!(
+            walk(11, 100).unwrap_err(),
+            CommandError::TestPatternDuty(11)
+        );
+        assert_eq!(walk(5, 9).unwrap_err(), CommandError::TestPatternPeriod(9));
+        assert!(MotorCommand::reverse(MIN_REVERSE_DWELL_MS).is_ok());
+        assert_eq!(
+            MotorCommand::reverse(0).unwrap_err(),
+            CommandError::ReverseDwell(0)
+        );
+        assert!(MotorCommand::calibrate_current(None, true).is_ok());
+        assert!(MotorCommand::calibrate_current(Some((1010, 1000)), false).is_ok());
+        assert_eq!(
+            MotorCommand::calibrate_current(Some((3, 0)), false).unwrap_err(),
+            CommandError::CurrentGain(3, 0)
+        );
+        assert!(MotorCommand::cogging_scan(MAX_COGGING_CURRENT_MA, MIN_COGGING_SETTLE_MS).is_ok());
+        assert_eq!(
+            MotorCommand::cogging_scan(0, 100).unwrap_err(),
+            CommandError::CoggingCurrent(0)
+        );
+        assert_eq!(
+            MotorCommand::cogging_scan(500, 19).unwrap_err(),
+            CommandError::CoggingSettle(19)
+        );
+        assert!(MotorCommand::freewheel(20, 2000, MAX_FREEWHEEL_COAST_MS).is_ok());
+        assert_eq!(
+            MotorCommand::freewheel(20, 2000, 0).unwrap_err(),
+            CommandError::FreewheelCoast(0)
+        );
+        assert_eq!(
+            MotorCommand::freewheel(101, 2000, 5000).unwrap_err(),
+            CommandError::Duty(101)
+        );
+        // Commands without bounds pass as given
+        assert!(
+            MotorCommand::SetMaxPhaseVoltage { mv: u16::MAX }
+                .validate()
+                .is_ok()
+        );
+    }
+}
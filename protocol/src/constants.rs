@@ -0,0 +1,224 @@
+//! Values the device and the host must agree on
+//!
+//! Both ends import these instead of keeping their own literals, so a change
+//! here reaches the firmware and the host together. The assertions at the end
+//! check at compile time that the fixed-size messages fit in a packet, using
+//! their `Schema` descriptions.
+
+use postcard_schema::Schema;
+use postcard_schema::schema::{DataModelType, DataModelVariant, NamedType, NamedValue};
+
+use crate::{
+    BEMF_CHUNK_SAMPLES, BENCH_MAX_PAYLOAD, BemfSample, BenchReport, BenchRequest, CoggingSample,
+    CommandLog, CommutationPeriod, CommutationTable, FullStatus, KeepAlive, MotorCommand,
+    MotorRequest, MotorStatus, SelfTestReport, SelfTestRequest, TaskStats, TelemetryExtremes,
+};
+
+/// Wire protocol revision; bump on any change to a message layout or endpoint
+pub const PROTOCOL_VERSION: u32 = 38;
+
+/// Largest ergot packet on the RTT link, header included
+///
+/// The device sizes its stack and receive buffer with it and the host uses it
+/// as the MTU of its sink, so neither sends what the other cannot take.
+pub const MAX_PACKET_SIZE: usize = 512;
+
+/// Bytes of a packet set aside for the ergot header (addresses, key, sequence)
+pub const HEADER_RESERVE: usize = 32;
+
+/// Largest postcard payload that always fits in one packet
+pub const MAX_PAYLOAD_SIZE: usize = MAX_PACKET_SIZE - HEADER_RESERVE;
+
+/// Network id of both ends unless the firmware is built with `OXIFOC_NETWORK_ID`
+pub const DEFAULT_NETWORK_ID: u16 = 1;
+
+/// Node id of the DirectEdge controller (network owner; the host by default)
+pub const CONTROLLER_NODE_ID: u8 = 1;
+
+/// Node id of the DirectEdge target (the device by default)
+pub const TARGET_NODE_ID: u8 = 2;
+
+/// Highest duty in percent any command may request; the device clamps to it
+pub const MAX_DUTY_PERCENT: u8 = 100;
+
+/// Shortest open-loop step period a `SetCommutationPeriodUs` may set
+///
+/// 200 µs is about 7100 rpm on the default 7 pole-pair motor, past what it
+/// reaches on 3S open loop.
+pub const MIN_COMMUTATION_PERIOD_US: u32 = 200;
+
+/// Longest open-loop step period a `SetCommutationPeriodUs` may set
+pub const MAX_COMMUTATION_PERIOD_US: u32 = 1_000_000;
+
+/// Most steps a single `Nudge` may move (one electrical revolution)
+pub const MAX_NUDGE_STEPS: u8 = 6;
+
+/// Highest duty in percent a `Nudge` may request
+pub const MAX_NUDGE_DUTY: u8 = 10;
+
+/// Shortest coast between slowing down and coming back up in a `Reverse`
+///
+/// The rotor has to be at a standstill before the other direction is driven,
+/// or the first steps fight its momentum through the bridge.
+pub const MIN_REVERSE_DWELL_MS: u16 = 200;
+
+/// Highest duty in percent a `TestPattern` may drive
+///
+/// Plenty to check edges and dead time on a scope, little enough that a
+/// motor left connected by mistake only twitches.
+pub const MAX_TEST_PATTERN_DUTY: u8 = 10;
+
+/// Shortest dwell or ramp period a `TestPattern` may use, in milliseconds
+pub const MIN_TEST_PATTERN_PERIOD_MS: u16 = 10;
+
+/// Highest winding current a `CoggingScan` may hold, in milliamps
+///
+/// A held step dissipates all of it in two windings with the rotor still,
+/// so this stays well below what the motor takes running.
+pub const MAX_COGGING_CURRENT_MA: u16 = 3000;
+
+/// Shortest time a `CoggingScan` may settle at each position, in milliseconds
+pub const MIN_COGGING_SETTLE_MS: u16 = 20;
+
+/// Longest coast-down a `Freewheel` tracks, in milliseconds
+pub const MAX_FREEWHEEL_COAST_MS: u16 = 20_000;
+
+/// Longest throughput benchmark flood, in milliseconds
+///
+/// The flood can crowd keepalives off the up channel, so it stays under the
+/// host's default 3 s of keepalive silence taken for a device reset.
+pub const MAX_BENCH_DURATION_MS: u16 = 2500;
+
+/// Largest postcard encoding of a schema, `None` if it holds a sequence or string
+///
+/// heapless capacities are not part of a schema, so those bounds have to be
+/// worked out by hand (see `BEMF_CHUNK_MAX_BYTES`).
+pub const fn max_encoded_size(ty: &NamedType) -> Option<usize> {
+    match ty.ty {
+        DataModelType::Bool | DataModelType::I8 | DataModelType::U8 => Some(1),
+        DataModelType::I16 | DataModelType::U16 => Some(3),
+        DataModelType::I32 | DataModelType::U32 => Some(5),
+        DataModelType::I64 | DataModelType::U64 => Some(10),
+        DataModelType::Usize | DataModelType::Isize => Some(10),
+        DataModelType::I128 | DataModelType::U128 => Some(19),
+        DataModelType::F32 => Some(4),
+        DataModelType::F64 => Some(8),
+        DataModelType::Char => Some(5),
+        DataModelType::Unit | DataModelType::UnitStruct => Some(0),
+        DataModelType::Option(inner) => match max_encoded_size(inner) {
+            Some(n) => Some(n + 1),
+            None => None,
+        },
+        DataModelType::NewtypeStruct(inner) => max_encoded_size(inner),
+        // Arrays are tuples in the schema
+        DataModelType::Tuple(items) | DataModelType::TupleStruct(items) => sum_max(items),
+        DataModelType::Struct(fields) => fields_max(fields),
+        DataModelType::Enum(variants) => {
+            let mut body = 0;
+            let mut i = 0;
+            while i < variants.len() {
+                match variant_max(variants[i].ty) {
+                    Some(n) if n > body => body = n,
+                    Some(_) => {}
+                    None => return None,
+                }
+                i += 1;
+            }
+            Some(varint_len(variants.len().saturating_sub(1)) + body)
+        }
+        _ => None,
+    }
+}
+
+const fn variant_max(variant: &DataModelVariant) -> Option<usize> {
+    match variant {
+        DataModelVariant::UnitVariant => Some(0),
+        DataModelVariant::NewtypeVariant(ty) => max_encoded_size(ty),
+        DataModelVariant::TupleVariant(items) => sum_max(items),
+        DataModelVariant::StructVariant(fields) => fields_max(fields),
+    }
+}
+
+const fn fields_max(fields: &[&NamedValue]) -> Option<usize> {
+    let mut total = 0;
+    let mut i = 0;
+    while i < fields.len() {
+        match max_encoded_size(fields[i].ty) {
+            Some(n) => total += n,
+            None => return None,
+        }
+        i += 1;
+    }
+    Some(total)
+}
+
+const fn sum_max(items: &[&NamedType]) -> Option<usize> {
+    let mut total = 0;
+    let mut i = 0;
+    while i < items.len() {
+        match max_encoded_size(items[i]) {
+            Some(n) => total += n,
+            None => return None,
+        }
+        i += 1;
+    }
+    Some(total)
+}
+
+/// Bytes needed for `n` as a postcard varint
+pub const fn varint_len(n: usize) -> usize {
+    let bits = (usize::BITS - n.leading_zeros()) as usize;
+    if bits == 0 { 1 } else { bits.div_ceil(7) }
+}
+
+/// Largest `BemfChunk`: offset and total, the sample count, then full samples
+pub const BEMF_CHUNK_MAX_BYTES: usize = 3
+    + 3
+    + varint_len(BEMF_CHUNK_SAMPLES)
+    + BEMF_CHUNK_SAMPLES * {
+        match max_encoded_size(BemfSample::SCHEMA) {
+            Some(n) => n,
+            None => panic!("BemfSample must be fixed-size"),
+        }
+    };
+
+/// Largest `BenchFrame`: the sequence number, the payload length, then a full payload
+pub const BENCH_FRAME_MAX_BYTES: usize = 5 + varint_len(BENCH_MAX_PAYLOAD) + BENCH_MAX_PAYLOAD;
+
+/// Fail the build if a message of type `ty` could exceed one packet
+const fn assert_fits(ty: &NamedType) {
+    match max_encoded_size(ty) {
+        Some(n) => assert!(
+            n <= MAX_PAYLOAD_SIZE,
+            "message larger than MAX_PAYLOAD_SIZE"
+        ),
+        None => panic!("message size is unbounded"),
+    }
+}
+
+const _: () = {
+    assert!(HEADER_RESERVE < MAX_PACKET_SIZE);
+    assert!(
+        BEMF_CHUNK_MAX_BYTES <= MAX_PAYLOAD_SIZE,
+        "BEMF_CHUNK_SAMPLES too large for one packet"
+    );
+    assert!(
+        BENCH_FRAME_MAX_BYTES <= MAX_PAYLOAD_SIZE,
+        "BENCH_MAX_PAYLOAD too large for one packet"
+    );
+    assert_fits(MotorCommand::SCHEMA);
+    assert_fits(MotorRequest::SCHEMA);
+    assert_fits(MotorStatus::SCHEMA);
+    assert_fits(KeepAlive::SCHEMA);
+    assert_fits(CommandLog::SCHEMA);
+    assert_fits(CommutationTable::SCHEMA);
+    assert_fits(CommutationPeriod::SCHEMA);
+    assert_fits(FullStatus::SCHEMA);
+    assert_fits(TelemetryExtremes::SCHEMA);
+    assert_fits(TaskStats::SCHEMA);
+    assert_fits(CoggingSample::SCHEMA);
+    assert_fits(SelfTestRequest::SCHEMA);
+    assert_fits(SelfTestReport::SCHEMA);
+    assert_fits(BenchRequest::SCHEMA);
+    assert_fits(BenchReport::SCHEMA);
+};
@@ -1,12 +1,19 @@
 #![no_std]
 
-use ergot::endpoint;
+use ergot::{endpoint, topic};
 use heapless::String;
 use postcard_schema::Schema;
 use serde::{Deserialize, Serialize};
 
+pub mod command;
+pub use command::CommandError;
+pub mod constants;
+pub use constants::PROTOCOL_VERSION;
+pub mod telemetry_delta;
+pub use telemetry_delta::{TelemetryDecoder, TelemetryEncoder};
+
 /// Button events from the B-G431B-ESC1 board
-#[derive(Clone, Schema, Serialize, Deserialize, Debug)]
+#[derive(Clone, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub enum ButtonEvent {
     SingleClick,
     DoubleClick,
@@ -21,17 +28,193 @@ endpoint!(ButtonEndpoint, ButtonEvent, (), "event/button");
 pub struct DeviceInfo {
     pub hw: String<32>,
     pub sw: String<32>,
+    /// The MCU's 96-bit factory unique ID in hex, telling boards of one type apart
+    pub uid: String<24>,
+    /// Hash of the firmware's build inputs in hex, also stored in its ELF
+    pub build: String<16>,
 }
 
 // Host -> Device info query endpoint (unit request, returns DeviceInfo)
 endpoint!(InfoEndpoint, (), DeviceInfo, "req/device_info");
 
+// Host -> Device: blink the LED in the identify pattern for this many seconds (0 ends it)
+endpoint!(IdentifyEndpoint, u16, (), "cmd/identify");
+
+/// Device log verbosity: frames below it are not sent at all
+///
+/// Ordered from most to least verbose. It acts on top of the firmware's
+/// compile-time `DEFMT_LOG` filter, so it can only hide what was built in.
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    /// Inverse of `level as u8`; values past `Error` map to `Error`
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            0 => Self::Trace,
+            1 => Self::Debug,
+            2 => Self::Info,
+            3 => Self::Warn,
+            _ => Self::Error,
+        }
+    }
+}
+
+// Host -> Device: set the device's runtime log level (lasts until reset)
+endpoint!(LogLevelEndpoint, LogLevel, (), "cmd/log_level");
+
+// Host -> Device protocol revision query; its types must never change
+endpoint!(ProtocolVersionEndpoint, (), u32, "req/protocol_version");
+
+/// Periodic liveness message from the device
+///
+/// `seq` counts from 0 at every boot, so a sequence that goes backwards means
+/// the device has reset.
+#[derive(Clone, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct KeepAlive {
+    pub seq: u32,
+    pub uptime_ms: u32,
+}
+
+// Device -> Host keepalive (host serves, unit response)
+endpoint!(KeepAliveEndpoint, KeepAlive, (), "event/keepalive");
+
 /// Motor control commands
 #[derive(Clone, Schema, Serialize, Deserialize, Debug)]
 pub enum MotorCommand {
     Stop,
-    Start { duty: u8 },      // duty: 0-100%
-    SetSpeed { duty: u8 },   // duty: 0-100% (adjust while running)
+    Start {
+        duty: u8,
+        step: Option<u8>,
+    }, // duty: 0-100%; step (0-5) to align at, None = device default
+    SetSpeed {
+        duty: u8,
+    }, // duty: 0-100% (adjust while running)
+    Arm,    // allow test/diagnostic motion
+    Disarm, // stop and block test/diagnostic motion
+    Nudge {
+        steps: i8,
+        duty: u8,
+    }, // one-shot: advance (or reverse if negative) a few steps, then stop
+    SetTelemetryMask {
+        mask: u16,
+    }, // select `telemetry_field` bits streamed on `TelemetryEndpoint`
+    Estop,  // stop and disarm; motion stays refused until the next Arm
+    CaptureBemf {
+        samples: u16,
+    }, // record the floating phase voltage while running, sent on `BemfEndpoint`
+    SetScanScheme {
+        scheme: ScanScheme,
+    }, // stop the motor and switch the commutation step table
+    SetLowSpeedCutoff {
+        min_rpm: u16,
+        timeout_ms: u16,
+    }, // halve duty, then stop, while slower than min_rpm (0 = off)
+    SetMaxPhaseVoltage {
+        mv: u16,
+    }, // cap duty at mv / VBUS on top of max_duty (0 = off)
+    SetCommutationPeriodUs {
+        us: u32,
+    }, // open-loop run step period, clamped to MIN/MAX_COMMUTATION_PERIOD_US
+    TestPattern {
+        pattern: TestPattern,
+    }, // bench PWM pattern until the next command; needs arming
+    Reverse {
+        dwell_ms: u16,
+    }, // while running: ramp to 0, coast dwell_ms, ramp back up the other way
+    MeasureRL, // from standstill: measure phase R and L, read on `ElectricalParamsEndpoint`; needs arming
+    SetPhaseDuties {
+        a: u8,
+        b: u8,
+        c: u8,
+    }, // duty per phase (0-100%), bypassing the sequencer until the next command; needs arming
+    CalibrateCurrent {
+        gain_num: u16,
+        gain_den: u16,
+        persist: bool,
+    }, // while stopped: measure the shunt offsets, set the gain (gain_num 0 = keep), optionally store
+    CoggingScan {
+        current_ma: u16,
+        settle_ms: u16,
+    }, // from standstill: hold each step of one mechanical turn at current_ma, sent on `CoggingEndpoint`; needs arming
+    SetCommutationMode {
+        mode: CommutationMode,
+    }, // what times the running steps: the run period or the external trigger input
+    Freewheel {
+        duty: u8,
+        spin_ms: u16,
+        coast_ms: u16,
+    }, // run at duty for spin_ms (starting if stopped), then float every phase and report the BEMF-measured coast-down speed as telemetry rpm for up to coast_ms; needs arming
+    SetTelemetryDelta {
+        key_interval: u8,
+    }, // stream a key frame every key_interval samples and deltas from it in between (0 = full samples only)
+}
+
+impl MotorCommand {
+    /// Names of the commands, indexed by `id()`
+    pub const NAMES: [&'static str; 22] = [
+        "stop",
+        "start",
+        "set_speed",
+        "arm",
+        "disarm",
+        "nudge",
+        "set_telemetry_mask",
+        "estop",
+        "capture_bemf",
+        "set_scan_scheme",
+        "set_low_speed_cutoff",
+        "set_max_phase_voltage",
+        "set_commutation_period_us",
+        "test_pattern",
+        "reverse",
+        "measure_rl",
+        "set_phase_duties",
+        "calibrate_current",
+        "cogging_scan",
+        "set_commutation_mode",
+        "freewheel",
+        "set_telemetry_delta",
+    ];
+
+    /// Compact command identifier used by `CommandLog`
+    pub fn id(&self) -> u8 {
+        match self {
+            Self::Stop => 0,
+            Self::Start { .. } => 1,
+            Self::SetSpeed { .. } => 2,
+            Self::Arm => 3,
+            Self::Disarm => 4,
+            Self::Nudge { .. } => 5,
+            Self::SetTelemetryMask { .. } => 6,
+            Self::Estop => 7,
+            Self::CaptureBemf { .. } => 8,
+            Self::SetScanScheme { .. } => 9,
+            Self::SetLowSpeedCutoff { .. } => 10,
+            Self::SetMaxPhaseVoltage { .. } => 11,
+            Self::SetCommutationPeriodUs { .. } => 12,
+            Self::TestPattern { .. } => 13,
+            Self::Reverse { .. } => 14,
+            Self::MeasureRL => 15,
+            Self::SetPhaseDuties { .. } => 16,
+            Self::CalibrateCurrent { .. } => 17,
+            Self::CoggingScan { .. } => 18,
+            Self::SetCommutationMode { .. } => 19,
+            Self::Freewheel { .. } => 20,
+            Self::SetTelemetryDelta { .. } => 21,
+        }
+    }
+
+    /// Name of the command with identifier `id`
+    pub fn name_of(id: u8) -> &'static str {
+        Self::NAMES.get(id as usize).copied().unwrap_or("unknown")
+    }
 }
 
 /// Motor operational state
@@ -42,13 +225,1019 @@ pub enum MotorState {
     Error,
 }
 
+impl MotorState {
+    /// Inverse of `state as u8`; unknown values map to `Error`
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            0 => Self::Stopped,
+            1 => Self::Running,
+            _ => Self::Error,
+        }
+    }
+}
+
+/// Why the device refused a motor command
+#[derive(Clone, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum CommandRejection {
+    MinOffTime { remaining_ms: u16 }, // start too soon after the last stop
+    StartDebounced,                   // repeated start while already running
+    EstopLatched,                     // motion refused until re-armed after an estop
+    Disarmed,                         // test/diagnostic motion refused until armed
+    AdcFault,                         // needs current or bus voltage sensing, which is untrusted
+    MotorRunning,                     // needs the motor stopped
+    NotBuilt,                         // left out of this firmware (its `diagnostics` feature)
+}
+
+/// Outcome class of a processed motor command
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum RejectReason {
+    None,        // accepted
+    Disarmed,    // motion refused until armed
+    RateLimited, // start debounce, min-off dwell, or control queue full
+    Fault,       // motion refused after an estop, or for untrusted ADC readings
+    Busy,        // needs the motor stopped
+    Unsupported, // not built into the firmware
+}
+
+impl From<&CommandRejection> for RejectReason {
+    fn from(rejection: &CommandRejection) -> Self {
+        match rejection {
+            CommandRejection::MinOffTime { .. } | CommandRejection::StartDebounced => {
+                Self::RateLimited
+            }
+            CommandRejection::EstopLatched | CommandRejection::AdcFault => Self::Fault,
+            CommandRejection::Disarmed => Self::Disarmed,
+            CommandRejection::MotorRunning => Self::Busy,
+            CommandRejection::NotBuilt => Self::Unsupported,
+        }
+    }
+}
+
+/// Device record of what it did with one motor command
+#[derive(Clone, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct CommandLog {
+    pub cmd_id: u8, // `MotorCommand::id`
+    pub accepted: bool,
+    pub reason: RejectReason, // `None` when accepted
+}
+
+impl CommandLog {
+    /// Log entry for `cmd` with its admission verdict
+    pub fn new(cmd: &MotorCommand, verdict: &Result<(), CommandRejection>) -> Self {
+        Self {
+            cmd_id: cmd.id(),
+            accepted: verdict.is_ok(),
+            reason: verdict
+                .as_ref()
+                .err()
+                .map_or(RejectReason::None, RejectReason::from),
+        }
+    }
+}
+
+// Device -> Host command log, one per processed motor command (host serves, unit response)
+endpoint!(CommandLogEndpoint, CommandLog, (), "event/command_log");
+
 /// Motor status response
 #[derive(Clone, Schema, Serialize, Deserialize, Debug)]
 pub struct MotorStatus {
     pub state: MotorState,
-    pub duty: u8,           // Current duty cycle (0-100%)
-    pub step: u8,           // Current commutation step (0-5)
+    pub duty: u8,                           // Current duty cycle (0-100%)
+    pub step: u8,                           // Current commutation step (0-5)
+    pub rejected: Option<CommandRejection>, // set when the command was refused
+}
+
+/// A `MotorCommand` as sent over `MotorEndpoint`
+///
+/// `trace` is a correlation id picked by the host. The device tags the defmt
+/// lines it logs while handling the command with it (`[id=42]`), so a host log
+/// and the device log can be matched up line by line.
+#[derive(Clone, Schema, Serialize, Deserialize, Debug)]
+pub struct MotorRequest {
+    pub cmd: MotorCommand,
+    pub trace: Option<u16>,
 }
 
 // Host -> Device motor control endpoint (command in, status out)
-endpoint!(MotorEndpoint, MotorCommand, MotorStatus, "cmd/motor");
+endpoint!(MotorEndpoint, MotorRequest, MotorStatus, "cmd/motor");
+
+/// Telemetry field selection bits, in wire order
+pub mod telemetry_field {
+    pub const RPM: u16 = 1 << 0; // mechanical rpm, u16 (open-loop estimate, or BEMF-measured in a freewheel coast)
+    pub const DUTY: u16 = 1 << 1; // 0-100%, u8
+    pub const STEP: u16 = 1 << 2; // commutation step 0-5, u8
+    pub const STATE: u16 = 1 << 3; // MotorState discriminant, u8
+    pub const VBUS: u16 = 1 << 4; // bus voltage in mV, u16
+    pub const TEMP: u16 = 1 << 5; // board temperature in 0.1 °C, i16
+    pub const CURRENT: u16 = 1 << 6; // winding current in mA, u16
+    pub const LIMP: u16 = 1 << 7; // duty clamped by a soft fault, u8 (0/1)
+    pub const FAULT: u16 = 1 << 8; // last protection fault, u8 (0 = none, see `FaultKind::code`)
+    pub const FAN: u16 = 1 << 9; // cooling fan duty 0-100%, u8 (absent without a fan)
+    pub const ADC_FAULT: u16 = 1 << 10; // ADC readings untrusted, fixed limits in use, u8 (0/1)
+    pub const ALL: u16 = (1 << 11) - 1;
+
+    // Not selectable: frame kind of the delta coding (see `telemetry_delta`)
+    pub const KEY_SEQ: u16 = 0b111 << 11; // sequence number of the key frame, mod 8
+    pub const KEY: u16 = 1 << 14; // key frame: every streamed field, deltas refer to it
+    pub const DELTA: u16 = 1 << 15; // the fields that differ from the key frame, delta coded
+}
+
+/// Encoded size of a telemetry sample with every field enabled
+pub const TELEMETRY_MAX_BYTES: usize = 15;
+
+/// One telemetry sample; fields outside the active mask are `None`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Telemetry {
+    pub rpm: Option<u16>,
+    pub duty: Option<u8>,
+    pub step: Option<u8>,
+    pub state: Option<MotorState>,
+    pub vbus_mv: Option<u16>,
+    pub temp_dc: Option<i16>,
+    pub current_ma: Option<u16>,
+    pub limp: Option<bool>,
+    /// `Some(None)`: sampled, and no fault since the last start
+    pub fault: Option<Option<FaultKind>>,
+    pub fan: Option<u8>,
+    pub adc_fault: Option<bool>,
+}
+
+/// Compact telemetry payload: the field mask, then each enabled field
+/// little-endian in `telemetry_field` bit order
+#[derive(Clone, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct TelemetryFrame {
+    pub mask: u16,
+    pub data: heapless::Vec<u8, TELEMETRY_MAX_BYTES>,
+}
+
+impl Telemetry {
+    /// Encode the fields selected by `mask`; a selected field that is `None`
+    /// is dropped from the mask
+    pub fn encode(&self, mask: u16) -> TelemetryFrame {
+        use telemetry_field::*;
+        let mut frame = TelemetryFrame {
+            mask: 0,
+            data: heapless::Vec::new(),
+        };
+        let state = self.state.as_ref().map(|s| s.clone() as u8);
+        let fault = self.fault.map(|f| f.map_or(0, FaultKind::code));
+        let fields: [(u16, Option<[u8; 2]>, usize); 11] = [
+            (RPM, self.rpm.map(u16::to_le_bytes), 2),
+            (DUTY, self.duty.map(|v| [v, 0]), 1),
+            (STEP, self.step.map(|v| [v, 0]), 1),
+            (STATE, state.map(|v| [v, 0]), 1),
+            (VBUS, self.vbus_mv.map(u16::to_le_bytes), 2),
+            (TEMP, self.temp_dc.map(i16::to_le_bytes), 2),
+            (CURRENT, self.current_ma.map(u16::to_le_bytes), 2),
+            (LIMP, self.limp.map(|v| [v as u8, 0]), 1),
+            (FAULT, fault.map(|v| [v, 0]), 1),
+            (FAN, self.fan.map(|v| [v, 0]), 1),
+            (ADC_FAULT, self.adc_fault.map(|v| [v as u8, 0]), 1),
+        ];
+        for (bit, bytes, len) in fields {
+            if mask & bit == 0 {
+                continue;
+            }
+            if let Some(bytes) = bytes {
+                // Capacity covers every field, so this cannot fail
+                let _ = frame.data.extend_from_slice(&bytes[..len]);
+                frame.mask |= bit;
+            }
+        }
+        frame
+    }
+}
+
+impl TelemetryFrame {
+    /// Decode the payload; `None` if its length does not match the mask, or
+    /// for a delta frame, which needs its key frame (see `TelemetryDecoder`)
+    pub fn decode(&self) -> Option<Telemetry> {
+        use telemetry_field::*;
+        if self.mask & DELTA != 0 {
+            return None;
+        }
+        let mut rest = self.data.as_slice();
+        let mut take = |bit: u16, len: usize| -> Option<Option<&[u8]>> {
+            if self.mask & bit == 0 {
+                return Some(None);
+            }
+            let (head, tail) = rest.split_at_checked(len)?;
+            rest = tail;
+            Some(Some(head))
+        };
+        let u16_le = |b: &[u8]| u16::from_le_bytes([b[0], b[1]]);
+        let t = Telemetry {
+            rpm: take(RPM, 2)?.map(u16_le),
+            duty: take(DUTY, 1)?.map(|b| b[0]),
+            step: take(STEP, 1)?.map(|b| b[0]),
+            state: take(STATE, 1)?.map(|b| MotorState::from_u8(b[0])),
+            vbus_mv: take(VBUS, 2)?.map(u16_le),
+            temp_dc: take(TEMP, 2)?.map(|b| i16::from_le_bytes([b[0], b[1]])),
+            current_ma: take(CURRENT, 2)?.map(u16_le),
+            limp: take(LIMP, 1)?.map(|b| b[0] != 0),
+            fault: take(FAULT, 1)?.map(|b| FaultKind::from_code(b[0])),
+            fan: take(FAN, 1)?.map(|b| b[0]),
+            adc_fault: take(ADC_FAULT, 1)?.map(|b| b[0] != 0),
+        };
+        rest.is_empty().then_some(t)
+    }
+}
+
+// Device -> Host telemetry stream (host serves, unit response)
+endpoint!(TelemetryEndpoint, TelemetryFrame, (), "event/telemetry");
+
+/// Largest capture `MotorCommand::CaptureBemf` buffers on the device
+pub const BEMF_MAX_SAMPLES: u16 = 512;
+
+/// Samples per `BemfChunk`
+pub const BEMF_CHUNK_SAMPLES: usize = 32;
+
+/// One reading of the phase left floating by the applied commutation step
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct BemfSample {
+    pub t_us: u32, // since the first sample of the capture
+    pub step: u8,  // commutation step applied (0-5)
+    pub phase: u8, // floating logical phase (0 = A, 1 = B, 2 = C)
+    pub mv: u16,   // phase voltage to ground
+}
+
+/// Part of a BEMF capture; chunks are sent in order once the capture is full
+#[derive(Clone, Schema, Serialize, Deserialize, Debug)]
+pub struct BemfChunk {
+    pub offset: u16, // index of `samples[0]` in the capture
+    pub total: u16,  // samples in the whole capture
+    pub samples: heapless::Vec<BemfSample, BEMF_CHUNK_SAMPLES>,
+}
+
+// Device -> Host BEMF capture upload (host serves, unit response)
+endpoint!(BemfEndpoint, BemfChunk, (), "event/bemf");
+
+/// One held position of a `MotorCommand::CoggingScan`, sent as soon as it is measured
+///
+/// Each position is a commutation step held at the scan current; `total`
+/// positions make one mechanical revolution.
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct CoggingSample {
+    pub index: u16,
+    pub total: u16,
+    pub step: u8,           // commutation step held (0-5)
+    pub hall: u8,           // raw hall state H3 H2 H1, 0b111 without sensors fitted
+    pub duty_permille: u16, // duty that held the scan current, at the end of the settle time
+    pub current_ma: u16,    // filtered winding current at that point
+}
+
+// Device -> Host cogging scan samples (host serves, unit response)
+endpoint!(CoggingEndpoint, CoggingSample, (), "event/cogging");
+
+/// Maximum number of motor profiles stored on the device
+pub const MAX_PROFILES: usize = 4;
+
+/// Named motor parameter preset persisted in device flash
+#[derive(Clone, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct MotorProfile {
+    pub name: String<16>,
+    pub pole_pairs: u8,
+    pub kv: u16,           // RPM per volt
+    pub pwm_freq: u32,     // Hz
+    pub dead_time_ns: u32, // nanoseconds
+    pub max_duty: u8,      // 0-100%
+}
+
+/// Motor profile management commands
+#[derive(Clone, Schema, Serialize, Deserialize, Debug)]
+pub enum ProfileCommand {
+    /// Store the active parameter set under `name` (overwrites a profile with the same name)
+    Save { name: String<16> },
+    /// Stop the motor and apply the named profile
+    Load { name: String<16> },
+    /// List all stored profiles
+    List,
+}
+
+/// Result of a profile command
+#[derive(Clone, Schema, Serialize, Deserialize, Debug)]
+pub enum ProfileResponse {
+    Saved,
+    Loaded(MotorProfile),
+    List(heapless::Vec<MotorProfile, MAX_PROFILES>),
+    NotFound,
+    Full,
+    StorageError,
+}
+
+// Host -> Device profile management endpoint
+endpoint!(
+    ProfileEndpoint,
+    ProfileCommand,
+    ProfileResponse,
+    "cmd/profile"
+);
+
+/// Rotation direction
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Reverse,
+}
+
+impl Direction {
+    /// The other way round
+    pub fn opposite(self) -> Self {
+        match self {
+            Self::Forward => Self::Reverse,
+            Self::Reverse => Self::Forward,
+        }
+    }
+}
+
+/// Mapping of logical phases (A, B, C) to TIM1 outputs (0 = CH1, 1 = CH2, 2 = CH3)
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct PhaseMap {
+    pub channels: [u8; 3],
+}
+
+impl PhaseMap {
+    /// A→CH1, B→CH2, C→CH3
+    pub const IDENTITY: Self = Self {
+        channels: [0, 1, 2],
+    };
+
+    /// True if `channels` is a permutation of 0..3
+    pub fn is_valid(&self) -> bool {
+        let mut seen = [false; 3];
+        for &c in &self.channels {
+            match seen.get_mut(c as usize) {
+                Some(s) if !*s => *s = true,
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Swap the outputs driving phases B and C (reverses rotation)
+    pub fn swapped_bc(self) -> Self {
+        let [a, b, c] = self.channels;
+        Self {
+            channels: [a, c, b],
+        }
+    }
+}
+
+/// Direction self-test parameters
+#[derive(Clone, Schema, Serialize, Deserialize, Debug)]
+pub struct SelfTestRequest {
+    /// Test duty (0-100%, clamped to the device's self-test ceiling)
+    pub duty: u8,
+    /// Persist a corrected phase map to flash
+    pub persist: bool,
+    /// The motor is known to turn forward: store the hall order seen as the
+    /// forward one (always persisted) instead of judging the direction
+    pub learn: bool,
+}
+
+/// Outcome of the direction self-test
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum SelfTestResult {
+    /// Rotation matched the commanded direction
+    Ok,
+    /// Rotation was reversed; two phases were swapped in the phase map
+    Corrected,
+    /// Not enough valid hall transitions to decide
+    Inconclusive,
+    /// No hall order learned yet, so the rotation seen cannot be judged
+    Uncalibrated,
+    /// The hall order seen was stored as forward (`SelfTestRequest::learn`)
+    Learned,
+    /// Motor is not armed
+    Disarmed,
+    /// Aborted by a stop command
+    Aborted,
+}
+
+/// Direction self-test report
+#[derive(Clone, Schema, Serialize, Deserialize, Debug)]
+pub struct SelfTestReport {
+    pub result: SelfTestResult,
+    pub observed: Option<Direction>,
+    /// Number of valid hall transitions seen during the test
+    pub transitions: u8,
+    /// Phase map in effect after the test
+    pub phase_map: PhaseMap,
+}
+
+// Host -> Device direction self-test (spins briefly, may correct phase order)
+endpoint!(
+    SelfTestEndpoint,
+    SelfTestRequest,
+    SelfTestReport,
+    "cmd/self_test"
+);
+
+/// Per-phase winding resistance and inductance, from `MotorCommand::MeasureRL`
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct MotorElectricalParams {
+    /// Phase resistance in milliohms
+    pub r_mohm: u16,
+    /// Phase inductance in microhenries
+    pub l_uh: u16,
+}
+
+/// Why a resistance/inductance measurement produced no result
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum RlFailure {
+    /// Motor is not armed
+    Disarmed,
+    /// Aborted by an incoming command
+    Aborted,
+    /// The current stayed below the target at the duty ceiling (open winding?)
+    NoCurrent,
+    /// The current passed the abort level
+    OverCurrent,
+    /// The current rise was not seen in time, or a value does not fit
+    OutOfRange,
+}
+
+/// State of the latest resistance/inductance measurement
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum RlMeasurement {
+    /// None since boot
+    Idle,
+    /// In progress
+    Measuring,
+    Done(MotorElectricalParams),
+    Failed(RlFailure),
+}
+
+// Host -> Device: state of the latest `MeasureRL`
+endpoint!(
+    ElectricalParamsEndpoint,
+    (),
+    RlMeasurement,
+    "req/electrical_params"
+);
+
+/// Shunt current-sense calibration, from `MotorCommand::CalibrateCurrent`
+///
+/// A phase current is (reading - offset) converted at the nominal amplifier
+/// gain, then scaled by `gain_num / gain_den` for the board's actual shunt
+/// and gain tolerance.
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct CurrentCalibration {
+    /// ADC reading at zero current for phases A, B and C, in counts
+    pub offset: [i16; 3],
+    pub gain_num: u16,
+    pub gain_den: u16,
+}
+
+impl Default for CurrentCalibration {
+    /// Mid-rail bias and the nominal gain
+    fn default() -> Self {
+        Self {
+            offset: [2048; 3],
+            gain_num: 1,
+            gain_den: 1,
+        }
+    }
+}
+
+/// Why a current calibration produced no usable result
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum CalibrationFailure {
+    /// The offsets failed the ADC health check; the current stays untrusted
+    BadOffsets,
+    /// Measured and applied, but not written to flash
+    PersistFailed,
+}
+
+/// State of the latest current calibration
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum CalibrationRun {
+    /// None since boot
+    Idle,
+    /// In progress
+    Measuring,
+    Done(CurrentCalibration),
+    Failed(CalibrationFailure),
+}
+
+// Host -> Device: state of the latest `CalibrateCurrent`
+endpoint!(
+    CurrentCalibrationEndpoint,
+    (),
+    CalibrationRun,
+    "req/current_calibration"
+);
+
+/// How the commutation sequencer advances
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum CommutationMode {
+    /// Fixed-period open-loop 6-step
+    OpenLoop,
+    /// Open-loop 6-step, one step per edge on the external trigger input
+    External,
+}
+
+/// Energization pattern of the six commutation steps
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub enum ScanScheme {
+    /// Two phases conduct, the third floats (classic 6-step, BEMF observable)
+    #[default]
+    Conduction120,
+    /// All three phases conduct; no floating phase
+    Conduction180,
+}
+
+/// Deterministic PWM output for checking the bridge without a motor
+///
+/// Duties are in percent, capped at `MAX_TEST_PATTERN_DUTY`; periods are in
+/// milliseconds, at least `MIN_TEST_PATTERN_PERIOD_MS`.
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum TestPattern {
+    /// One phase switches at `duty` while the other two float, moving A, B, C every `dwell_ms`
+    WalkPhase { duty: u8, dwell_ms: u16 },
+    /// All three phases switch at `duty`, in step with each other
+    AllPhases { duty: u8 },
+    /// All three phases ramp from 0 up to `duty` and back down once every `period_ms`
+    RampDuty { duty: u8, period_ms: u16 },
+}
+
+impl TestPattern {
+    /// Peak duty of the pattern, in percent
+    pub fn duty(&self) -> u8 {
+        match *self {
+            Self::WalkPhase { duty, .. }
+            | Self::AllPhases { duty }
+            | Self::RampDuty { duty, .. } => duty,
+        }
+    }
+
+    /// Dwell or ramp period, if the pattern changes over time
+    pub fn period_ms(&self) -> Option<u16> {
+        match *self {
+            Self::WalkPhase { dwell_ms, .. } => Some(dwell_ms),
+            Self::AllPhases { .. } => None,
+            Self::RampDuty { period_ms, .. } => Some(period_ms),
+        }
+    }
+}
+
+/// What one phase does during a commutation step
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum PhaseDrive {
+    /// High side switching at the commanded duty, low side complementary
+    Pwm,
+    /// Low side held on (0% duty)
+    Low,
+    /// Neither switch driven (tristated, or clamped at 0% depending on firmware setup)
+    Float,
+}
+
+/// Step table the device is commutating with
+#[derive(Clone, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct CommutationTable {
+    pub scheme: ScanScheme,
+    pub steps: [[PhaseDrive; 3]; 6], // drives of logical phases A, B, C, indexed by step
+    pub phase_map: PhaseMap,         // TIM1 output driving each logical phase
+}
+
+// Host -> Device query of the active commutation step table
+endpoint!(
+    CommutationTableEndpoint,
+    (),
+    CommutationTable,
+    "req/comm_table"
+);
+
+/// Open-loop step period: what the motor runs at, and the dwell it is at now
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct CommutationPeriod {
+    pub period_us: u32, // run period, as applied after clamping
+    pub dwell_us: u32,  // dwell of the current step while accelerating to it, 0 unless running
+}
+
+// Host -> Device query of the open-loop commutation period
+endpoint!(
+    CommutationPeriodEndpoint,
+    (),
+    CommutationPeriod,
+    "req/comm_period"
+);
+
+/// Ergot addressing of a node and its link peer
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Addressing {
+    pub network_id: u16,
+    pub node_id: u8,
+    pub peer_node_id: u8,
+}
+
+/// Complete device configuration snapshot (mirrors what is persisted to flash)
+#[derive(Clone, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct DeviceConfig {
+    /// Name of the applied motor profile
+    pub profile: String<16>,
+    pub pole_pairs: u8,
+    pub kv: u16,
+    pub pwm_freq: u32, // Hz
+    pub dead_time_ns: u32,
+    pub max_duty: u8, // 0-100%, absolute PWM duty ceiling
+    pub mode: CommutationMode,
+    pub scan_scheme: ScanScheme,
+    pub commutation_period_ms: u32,
+    pub blanking_us: u16,
+    pub self_test_max_duty: u8,      // 0-100%
+    pub min_off_ms: u16,             // minimum stop-to-start dwell
+    pub min_rpm: u16,                // low-speed cutoff threshold, 0 = disabled
+    pub min_rpm_timeout_ms: u16,     // time allowed below min_rpm before each reduction
+    pub max_phase_voltage_mv: u16,   // VBUS-scaled duty ceiling, 0 = disabled
+    pub regen_current_limit_ma: u16, // braking current of a controlled stop, 0 = coast as any other stop
+    pub phase_map: PhaseMap,
+    pub addressing: Addressing,
+    pub current_calibration: CurrentCalibration,
+}
+
+// Host -> Device full configuration query
+endpoint!(ConfigEndpoint, (), DeviceConfig, "req/config");
+
+/// Fault reported by the device's protection logic
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum FaultKind {
+    Overcurrent,
+    Overtemperature,
+    Brownout,
+    /// The PWM timer's break input tripped (gate driver fault line)
+    GateDriver,
+    /// The motor stayed stalled through every reverse-and-retry attempt
+    Stall,
+    /// ADC readings implausible; the limits they feed are replaced by fixed ones
+    Adc,
+    /// The external commutation trigger went missing for several steps in a row
+    LostSync,
+}
+
+impl FaultKind {
+    /// Non-zero telemetry code; 0 stands for no fault
+    pub fn code(self) -> u8 {
+        self as u8 + 1
+    }
+
+    /// Inverse of `code`; 0 and unknown codes are `None`
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Self::Overcurrent),
+            2 => Some(Self::Overtemperature),
+            3 => Some(Self::Brownout),
+            4 => Some(Self::GateDriver),
+            5 => Some(Self::Stall),
+            6 => Some(Self::Adc),
+            7 => Some(Self::LostSync),
+            _ => None,
+        }
+    }
+}
+
+/// Every piece of runtime state, captured at one instant
+///
+/// Separate queries can straddle a state change (a duty from before a stop
+/// next to the stopped state); the device fills this with interrupts masked,
+/// so all fields agree.
+#[derive(Clone, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct FullStatus {
+    pub uptime_ms: u32,
+    pub state: MotorState,
+    pub rpm: u16, // open-loop estimate, 0 unless running or in a freewheel coast
+    pub duty: u8, // 0-100%, as applied to the bridge
+    pub step: u8,
+    pub vbus_mv: u16, // 0 until the first reading
+    pub temp_dc: i16, // 0.1 °C
+    pub current_ma: u16,
+    pub armed: bool,
+    pub estop: bool,              // emergency stop latched until the next arm
+    pub limp: bool,               // duty clamped by a soft fault
+    pub adc_fault: bool,          // ADC readings untrusted, fixed limits in use
+    pub fault: Option<FaultKind>, // cause of the limp, or of the last protection stop since start
+    pub mode: CommutationMode,
+    pub scan_scheme: ScanScheme,
+    pub commutation_period_us: u32, // run period
+}
+
+// Host -> Device coherent snapshot of all runtime state
+endpoint!(FullStatusEndpoint, (), FullStatus, "req/full_status");
+
+/// Lowest and highest reading of each quantity since the last reset
+///
+/// The device folds in every control-loop reading, so a run summary needs no
+/// telemetry capture. Every range is 0..0 while `samples` is 0.
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct TelemetryExtremes {
+    pub since_ms: u32, // uptime at the last reset, 0 if none since boot
+    pub samples: u32,
+    pub rpm_min: u16,
+    pub rpm_max: u16,
+    pub vbus_min_mv: u16,
+    pub vbus_max_mv: u16,
+    pub temp_min_dc: i16, // 0.1 °C
+    pub temp_max_dc: i16,
+    pub current_min_ma: u16,
+    pub current_max_ma: u16,
+}
+
+impl TelemetryExtremes {
+    /// Start over at `now_ms` of uptime
+    pub const fn reset(now_ms: u32) -> Self {
+        Self {
+            since_ms: now_ms,
+            samples: 0,
+            rpm_min: 0,
+            rpm_max: 0,
+            vbus_min_mv: 0,
+            vbus_max_mv: 0,
+            temp_min_dc: 0,
+            temp_max_dc: 0,
+            current_min_ma: 0,
+            current_max_ma: 0,
+        }
+    }
+
+    /// Widen the ranges to take in one reading
+    pub fn record(&mut self, rpm: u16, vbus_mv: u16, temp_dc: i16, current_ma: u16) {
+        if self.samples == 0 {
+            (self.rpm_min, self.rpm_max) = (rpm, rpm);
+            (self.vbus_min_mv, self.vbus_max_mv) = (vbus_mv, vbus_mv);
+            (self.temp_min_dc, self.temp_max_dc) = (temp_dc, temp_dc);
+            (self.current_min_ma, self.current_max_ma) = (current_ma, current_ma);
+        }
+        self.samples = self.samples.saturating_add(1);
+        self.rpm_min = self.rpm_min.min(rpm);
+        self.rpm_max = self.rpm_max.max(rpm);
+        self.vbus_min_mv = self.vbus_min_mv.min(vbus_mv);
+        self.vbus_max_mv = self.vbus_max_mv.max(vbus_mv);
+        self.temp_min_dc = self.temp_min_dc.min(temp_dc);
+        self.temp_max_dc = self.temp_max_dc.max(temp_dc);
+        self.current_min_ma = self.current_min_ma.min(current_ma);
+        self.current_max_ma = self.current_max_ma.max(current_ma);
+    }
+}
+
+// Host -> Device query of the extremes since the last reset
+endpoint!(ExtremesEndpoint, (), TelemetryExtremes, "req/extremes");
+
+// Host -> Device: start the extremes over; replies with the ones it ended
+endpoint!(
+    ResetExtremesEndpoint,
+    (),
+    TelemetryExtremes,
+    "cmd/reset_extremes"
+);
+
+/// Iterations of one device task loop and the longest gap between two
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+pub struct TaskTiming {
+    pub iterations: u32,
+    pub max_interval_us: u32, // 0 until the loop has run twice
+}
+
+/// Scheduling of the busiest device task loops since the last query
+///
+/// Each query starts the figures over. A `max_interval_us` well past a loop's
+/// period means it was starved by other tasks or held up by its own work.
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct TaskStats {
+    pub since_ms: u32, // uptime at the last query, 0 if none since boot
+    pub uptime_ms: u32,
+    pub control: TaskTiming,   // once per commutation step
+    pub telemetry: TaskTiming, // once per telemetry period
+    pub rx: TaskTiming,        // once per poll of the RTT down channel
+    pub tx: TaskTiming,        // once per write to the RTT up channel
+}
+
+// Host -> Device query of the task scheduling since the last one
+endpoint!(TaskStatsEndpoint, (), TaskStats, "req/task_stats");
+
+/// Largest `BenchFrame` payload, in bytes
+pub const BENCH_MAX_PAYLOAD: usize = 256;
+
+/// Start an RTT throughput benchmark: flood `BenchTopic` for `duration_ms`
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct BenchRequest {
+    pub duration_ms: u16, // clamped to `constants::MAX_BENCH_DURATION_MS`
+    pub payload: u16,     // bytes per frame, clamped to `BENCH_MAX_PAYLOAD`
+}
+
+/// Device side of a benchmark, sent once the flood is over
+#[derive(Clone, Copy, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum BenchReport {
+    Done {
+        frames: u32,     // frames handed to the link, sequence numbers 0..frames
+        elapsed_ms: u32, // time the flood took
+        queue_full: u32, // attempts that found the outgoing queue full (not sent, not counted in `frames`)
+    },
+    /// Refused: the device is not armed
+    Disarmed,
+    /// Refused: the motor is not stopped
+    MotorRunning,
+}
+
+/// One frame of a benchmark flood
+///
+/// Broadcast without acknowledgement, so the link runs as fast as the
+/// device can queue frames; a receiver finds drops as gaps in `seq`.
+#[derive(Clone, Schema, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct BenchFrame {
+    pub seq: u32,
+    pub data: heapless::Vec<u8, BENCH_MAX_PAYLOAD>, // `seq as u8`, counting up and wrapping
+}
+
+impl BenchFrame {
+    /// Frame `seq` with `len` bytes of its pattern (at most `BENCH_MAX_PAYLOAD`)
+    pub fn new(seq: u32, len: usize) -> Self {
+        let data = (0..len.min(BENCH_MAX_PAYLOAD))
+            .map(|i| (seq as u8).wrapping_add(i as u8))
+            .collect();
+        Self { seq, data }
+    }
+
+    /// Whether `data` still holds the pattern for `seq`
+    pub fn is_intact(&self) -> bool {
+        self.data
+            .iter()
+            .enumerate()
+            .all(|(i, &b)| b == (self.seq as u8).wrapping_add(i as u8))
+    }
+}
+
+// Host -> Device benchmark start; the response comes after the flood
+endpoint!(BenchEndpoint, BenchRequest, BenchReport, "cmd/bench");
+// Device -> Host benchmark frames, unacknowledged
+topic!(BenchTopic, BenchFrame, "event/bench");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_ids_index_names() {
+        let cmds = [
+            MotorCommand::Stop,
+            MotorCommand::Start {
+                duty: 10,
+                step: None,
+            },
+            MotorCommand::SetSpeed { duty: 10 },
+            MotorCommand::Arm,
+            MotorCommand::Disarm,
+            MotorCommand::Nudge { steps: 1, duty: 5 },
+            MotorCommand::SetTelemetryMask { mask: 0 },
+            MotorCommand::Estop,
+            MotorCommand::CaptureBemf { samples: 1 },
+            MotorCommand::SetScanScheme {
+                scheme: ScanScheme::Conduction120,
+            },
+            MotorCommand::SetLowSpeedCutoff {
+                min_rpm: 0,
+                timeout_ms: 0,
+            },
+            MotorCommand::SetMaxPhaseVoltage { mv: 0 },
+            MotorCommand::SetCommutationPeriodUs { us: 0 },
+            MotorCommand::TestPattern {
+                pattern: TestPattern::AllPhases { duty: 0 },
+            },
+            MotorCommand::Reverse { dwell_ms: 500 },
+            MotorCommand::MeasureRL,
+            MotorCommand::SetPhaseDuties { a: 0, b: 0, c: 0 },
+            MotorCommand::CalibrateCurrent {
+                gain_num: 0,
+                gain_den: 0,
+                persist: false,
+            },
+            MotorCommand::CoggingScan {
+                current_ma: 500,
+                settle_ms: 100,
+            },
+            MotorCommand::SetCommutationMode {
+                mode: CommutationMode::External,
+            },
+            MotorCommand::Freewheel {
+                duty: 20,
+                spin_ms: 2000,
+                coast_ms: 5000,
+            },
+            MotorCommand::SetTelemetryDelta { key_interval: 10 },
+        ];
+        assert_eq!(cmds.len(), MotorCommand::NAMES.len());
+        for (n, cmd) in cmds.iter().enumerate() {
+            assert_eq!(cmd.id() as usize, n);
+        }
+        assert_eq!(MotorCommand::name_of(7), "estop");
+        assert_eq!(MotorCommand::name_of(200), "unknown");
+
+        let log = CommandLog::new(
+            &cmds[1],
+            &Err(CommandRejection::MinOffTime { remaining_ms: 5 }),
+        );
+        assert_eq!(
+            log,
+            CommandLog {
+                cmd_id: 1,
+                accepted: false,
+                reason: RejectReason::RateLimited
+            }
+        );
+    }
+
+    #[test]
+    fn extremes_widen_from_the_first_reading() {
+        let mut ext = TelemetryExtremes::reset(5000);
+        ext.record(0, 12_100, 251, 40);
+        assert_eq!(ext.rpm_min, 0);
+        assert_eq!(ext.vbus_min_mv, 12_100);
+        assert_eq!(ext.temp_max_dc, 251);
+        ext.record(3000, 10_900, -15, 8200);
+        ext.record(1500, 11_500, 100, 900);
+        assert_eq!(
+            ext,
+            TelemetryExtremes {
+                since_ms: 5000,
+                samples: 3,
+                rpm_min: 0,
+                rpm_max: 3000,
+                vbus_min_mv: 10_900,
+                vbus_max_mv: 12_100,
+                temp_min_dc: -15,
+                temp_max_dc: 251,
+                current_min_ma: 40,
+                current_max_ma: 8200,
+            }
+        );
+    }
+
+    #[test]
+    fn schema_bounds_are_worst_case_encodings() {
+        use constants::{BEMF_CHUNK_MAX_BYTES, max_encoded_size};
+        // state tag + duty + step + rejection (None, or tag + u16 varint)
+        assert_eq!(max_encoded_size(MotorStatus::SCHEMA), Some(8));
+        assert_eq!(max_encoded_size(PhaseMap::SCHEMA), Some(3));
+        // heapless::Vec is a plain sequence in the schema
+        assert_eq!(max_encoded_size(BemfChunk::SCHEMA), None);
+        assert_eq!(BEMF_CHUNK_MAX_BYTES, 3 + 3 + 1 + BEMF_CHUNK_SAMPLES * 10);
+    }
+
+    #[test]
+    fn bench_frames_carry_a_checkable_pattern() {
+        let frame = BenchFrame::new(300, 4);
+        assert_eq!(frame.data.as_slice(), &[44, 45, 46, 47]);
+        assert!(frame.is_intact());
+        assert_eq!(BenchFrame::new(0, 1000).data.len(), BENCH_MAX_PAYLOAD);
+
+        let mut bad = BenchFrame::new(7, 8);
+        bad.data[3] ^= 1;
+        assert!(!bad.is_intact());
+    }
+
+    #[test]
+    fn telemetry_round_trips_through_mask() {
+        let sample = Telemetry {
+            rpm: Some(1234),
+            duty: Some(20),
+            step: Some(3),
+            state: Some(MotorState::Running),
+            vbus_mv: Some(12_100),
+            temp_dc: Some(-52),
+            current_ma: Some(1500),
+            limp: Some(true),
+            fault: Some(Some(FaultKind::Brownout)),
+            fan: Some(45),
+            adc_fault: Some(true),
+        };
+        let full = sample.encode(telemetry_field::ALL);
+        assert_eq!(full.data.len(), TELEMETRY_MAX_BYTES);
+        assert_eq!(full.decode(), Some(sample.clone()));
+
+        let mask = telemetry_field::RPM | telemetry_field::TEMP;
+        let partial = sample.encode(mask);
+        assert_eq!(partial.data.as_slice(), &[0xd2, 0x04, 0xcc, 0xff]);
+        assert_eq!(
+            partial.decode(),
+            Some(Telemetry {
+                rpm: Some(1234),
+                temp_dc: Some(-52),
+                ..Default::default()
+            })
+        );
+
+        let healthy = Telemetry {
+            fault: Some(None),
+            ..Default::default()
+        };
+        assert_eq!(
+            healthy.encode(telemetry_field::FAULT).decode(),
+            Some(healthy)
+        );
+
+        let truncated = TelemetryFrame {
+            mask,
+            data: heapless::Vec::from_slice(&[1, 2]).unwrap(),
+        };
+        assert_eq!(truncated.decode(), None);
+    }
+}
@@ -0,0 +1,290 @@
+//! Delta coding of the telemetry stream (`MotorCommand::SetTelemetryDelta`)
+//!
+//! From one sample to the next most fields stay put and the rest move a
+//! little, yet a plain frame carries every one at full width. With a key
+//! interval of `n`, `TelemetryEncoder` sends a key frame every `n` samples:
+//! the plain encoding, flagged `telemetry_field::KEY` and numbered in
+//! `KEY_SEQ`. The samples in between go as delta frames against it, flagged
+//! `DELTA` with the same number. Their mask holds only the fields that differ
+//! from the key frame; a one-byte field follows as is, a two-byte one as the
+//! zigzag varint of its difference (one byte within ±63, two within ±8191,
+//! three beyond).
+//!
+//! Deltas refer to the key frame, not to the sample before, so a frame the
+//! link drops costs only itself. A dropped key frame costs the deltas that
+//! refer to it: `TelemetryDecoder` tells them apart by their number and
+//! skips them until the next key frame. A key frame also goes out early when
+//! the mask changes, a field comes or goes, or a delta would be no shorter
+//! than the plain frame, so no frame is ever longer than one. An interval of
+//! 0 sends plain frames only.
+
+use crate::telemetry_field::{ALL, DELTA, KEY, KEY_SEQ};
+use crate::{TELEMETRY_MAX_BYTES, Telemetry, TelemetryFrame};
+
+/// Fields in `telemetry_field` bit order
+const FIELDS: usize = 11;
+/// Encoded width of each field, in bytes
+const WIDTHS: [usize; FIELDS] = [2, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1];
+/// Position of `KEY_SEQ` in the mask
+const SEQ_SHIFT: u16 = 11;
+
+/// Wire value of each field of a sample, `None` outside the mask
+type Values = [Option<u16>; FIELDS];
+
+/// Field values of a plain or key frame; `None` if its length does not match the mask
+fn unpack(frame: &TelemetryFrame) -> Option<Values> {
+    let mut values = [None; FIELDS];
+    let mut rest = frame.data.as_slice();
+    for (i, width) in WIDTHS.iter().enumerate() {
+        if frame.mask & (1 << i) == 0 {
+            continue;
+        }
+        let (head, tail) = rest.split_at_checked(*width)?;
+        values[i] = Some(head.iter().rev().fold(0, |v, b| v << 8 | *b as u16));
+        rest = tail;
+    }
+    rest.is_empty().then_some(values)
+}
+
+/// Plain frame of `values`
+fn pack(values: &Values) -> TelemetryFrame {
+    let mut frame = TelemetryFrame {
+        mask: 0,
+        data: heapless::Vec::new(),
+    };
+    for (i, (value, width)) in values.iter().zip(WIDTHS).enumerate() {
+        if let Some(v) = value {
+            // Capacity covers every field, so this cannot fail
+            let _ = frame.data.extend_from_slice(&v.to_le_bytes()[..width]);
+            frame.mask |= 1 << i;
+        }
+    }
+    frame
+}
+
+/// Append `new - old` as a zigzag varint; `None` if it does not fit
+fn push_delta(data: &mut heapless::Vec<u8, TELEMETRY_MAX_BYTES>, old: u16, new: u16) -> Option<()> {
+    let d = new.wrapping_sub(old) as i16;
+    let mut zig = ((d << 1) ^ (d >> 15)) as u16;
+    while zig >= 0x80 {
+        data.push(zig as u8 | 0x80).ok()?;
+        zig >>= 7;
+    }
+    data.push(zig as u8).ok()
+}
+
+/// Take a difference written by `push_delta` off the front of `rest`
+fn take_delta(rest: &mut &[u8]) -> Option<u16> {
+    let mut zig = 0u32;
+    for shift in [0, 7, 14] {
+        let (&b, tail) = rest.split_first()?;
+        *rest = tail;
+        zig |= ((b & 0x7f) as u32) << shift;
+        if b & 0x80 == 0 {
+            let zig = u16::try_from(zig).ok()?;
+            return Some(((zig >> 1) as i16 ^ -((zig & 1) as i16)) as u16);
+        }
+    }
+    None
+}
+
+/// Key frame the deltas refer to
+#[derive(Clone, Copy)]
+struct Key {
+    seq: u16,
+    /// Field bits of the key frame
+    mask: u16,
+    values: Values,
+}
+
+/// Device side of the delta coding: turns samples into key and delta frames
+#[derive(Default)]
+pub struct TelemetryEncoder {
+    key_interval: u8,
+    key: Option<Key>,
+    /// Frames sent since the key frame, the key frame included
+    since_key: u8,
+    seq: u16,
+}
+
+impl TelemetryEncoder {
+    /// Send a key frame every `key_interval` samples and deltas in between; 0 = plain frames only
+    pub fn set_key_interval(&mut self, key_interval: u8) {
+        if key_interval != self.key_interval {
+            self.key_interval = key_interval;
+            self.key = None;
+        }
+    }
+
+    pub fn key_interval(&self) -> u8 {
+        self.key_interval
+    }
+
+    /// Encode the fields of `sample` selected by `mask`, as `Telemetry::encode` does
+    pub fn encode(&mut self, sample: &Telemetry, mask: u16) -> TelemetryFrame {
+        let plain = sample.encode(mask);
+        if self.key_interval == 0 {
+            return plain;
+        }
+        if let Some(delta) = self.delta(&plain) {
+            self.since_key += 1;
+            return delta;
+        }
+        self.seq = (self.seq + 1) % 8;
+        self.since_key = 1;
+        let seq = self.seq << SEQ_SHIFT;
+        self.key = unpack(&plain).map(|values| Key {
+            seq,
+            mask: plain.mask,
+            values,
+        });
+        TelemetryFrame {
+            mask: plain.mask | KEY | seq,
+            data: plain.data,
+        }
+    }
+
+    /// `plain` as a delta frame, if one is due and shorter
+    fn delta(&self, plain: &TelemetryFrame) -> Option<TelemetryFrame> {
+        let key = self
+            .key
+            .filter(|k| k.mask == plain.mask && self.since_key < self.key_interval)?;
+        let values = unpack(plain)?;
+        let mut frame = TelemetryFrame {
+            mask: DELTA | key.seq,
+            data: heapless::Vec::new(),
+        };
+        for (i, ((old, new), width)) in key.values.into_iter().zip(values).zip(WIDTHS).enumerate() {
+            // The masks match, so both or neither are present
+            let (Some(old), Some(new)) = (old, new) else {
+                continue;
+            };
+            if old == new {
+                continue;
+            }
+            frame.mask |= 1 << i;
+            if width == 1 {
+                frame.data.push(new as u8).ok()?;
+            } else {
+                push_delta(&mut frame.data, old, new)?;
+            }
+        }
+        (frame.data.len() < plain.data.len()).then_some(frame)
+    }
+}
+
+/// Why `TelemetryDecoder` could not return a sample
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DeltaError {
+    /// The payload does not match the mask
+    Malformed,
+    /// A delta frame whose key frame was never received
+    MissingKey,
+}
+
+/// Host side of the delta coding: rebuilds full samples from any frame
+#[derive(Default)]
+pub struct TelemetryDecoder {
+    key: Option<Key>,
+}
+
+impl TelemetryDecoder {
+    pub fn decode(&mut self, frame: &TelemetryFrame) -> Result<Telemetry, DeltaError> {
+        let seq = frame.mask & KEY_SEQ;
+        if frame.mask & DELTA == 0 {
+            let sample = frame.decode().ok_or(DeltaError::Malformed)?;
+            self.key = match frame.mask & KEY {
+                0 => None,
+                _ => unpack(frame).map(|values| Key {
+                    seq,
+                    mask: frame.mask & ALL,
+                    values,
+                }),
+            };
+            return Ok(sample);
+        }
+        let Some(mut key) = self.key.filter(|k| k.seq == seq) else {
+            return Err(DeltaError::MissingKey);
+        };
+        let mut rest = frame.data.as_slice();
+        for (i, width) in WIDTHS.iter().enumerate() {
+            if frame.mask & (1 << i) == 0 {
+                continue;
+            }
+            let old = key.values[i].ok_or(DeltaError::Malformed)?;
+            let new = if *width == 1 {
+                let (&b, tail) = rest.split_first().ok_or(DeltaError::Malformed)?;
+                rest = tail;
+                b as u16
+            } else {
+                old.wrapping_add(take_delta(&mut rest).ok_or(DeltaError::Malformed)?)
+            };
+            key.values[i] = Some(new);
+        }
+        if !rest.is_empty() {
+            return Err(DeltaError::Malformed);
+        }
+        pack(&key.values).decode().ok_or(DeltaError::Malformed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MotorState;
+    use crate::telemetry_field::{CURRENT, RPM, TEMP};
+
+    #[test]
+    fn deltas_rebuild_every_sample_and_a_lost_key_frame_is_skipped() {
+        let mut sample = Telemetry {
+            rpm: Some(3000),
+            duty: Some(20),
+            step: Some(0),
+            state: Some(MotorState::Running),
+            vbus_mv: Some(12_000),
+            temp_dc: Some(-5),
+            current_ma: Some(1500),
+            limp: Some(false),
+            fault: Some(None),
+            fan: Some(0),
+            adc_fault: Some(false),
+        };
+        let mut enc = TelemetryEncoder::default();
+        enc.set_key_interval(4);
+        let mut dec = TelemetryDecoder::default();
+        let mut frames = [0usize; 8];
+        for (i, len) in frames.iter_mut().enumerate() {
+            sample.rpm = Some(3000 + 10 * i as u16);
+            sample.step = Some(i as u8 % 6);
+            sample.temp_dc = Some(-5 + i as i16);
+            sample.current_ma = Some(if i == 6 { 30_000 } else { 1500 });
+            let frame = enc.encode(&sample, ALL);
+            assert_eq!(frame.mask & KEY != 0, i % 4 == 0);
+            *len = frame.data.len();
+            assert_eq!(dec.decode(&frame), Ok(sample.clone()));
+        }
+        // Key frames are plain; rpm, step and temp cost a byte each, the current jump three
+        assert_eq!(frames, [15, 3, 3, 3, 15, 3, 6, 3]);
+
+        // A delta with no key frame before it is not guessed at
+        let lost = enc.encode(&sample, ALL);
+        assert_ne!(lost.mask & KEY, 0);
+        sample.rpm = Some(2900);
+        let orphan = enc.encode(&sample, ALL);
+        assert_eq!(dec.decode(&orphan), Err(DeltaError::MissingKey));
+        assert_eq!(orphan.decode(), None);
+
+        // A new mask starts over with a key frame; interval 0 is the plain coding
+        let mask = RPM | TEMP | CURRENT;
+        let frame = enc.encode(&sample, mask);
+        assert_eq!(frame.mask, mask | KEY | 4 << SEQ_SHIFT);
+        assert_eq!(dec.decode(&frame).unwrap().rpm, Some(2900));
+        enc.set_key_interval(0);
+        assert_eq!(enc.encode(&sample, mask), sample.encode(mask));
+        let truncated = TelemetryFrame {
+            mask: DELTA | 4 << SEQ_SHIFT | RPM,
+            data: heapless::Vec::new(),
+        };
+        assert_eq!(dec.decode(&truncated), Err(DeltaError::Malformed));
+    }
+}